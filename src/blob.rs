@@ -1,4 +1,4 @@
-use std::{fmt::Display, str::from_utf8};
+use std::fmt::Display;
 
 use crate::{bytes_reader::BytesReader, codec, hash::Hash, object::Header};
 
@@ -33,6 +33,6 @@ impl Blob {
 
 impl Display for Blob {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", from_utf8(&self.content).unwrap())
+        write!(f, "{}", String::from_utf8_lossy(&self.content))
     }
 }
@@ -1,3 +1,5 @@
+use crate::error::{Error, Result};
+
 pub struct BytesReader<'a> {
     bytes: &'a [u8],
 }
@@ -15,28 +17,38 @@ impl<'a> BytesReader<'a> {
         self.len() == 0
     }
 
-    pub fn skip(&mut self) {
-        self.read();
+    pub fn skip(&mut self) -> Result<()> {
+        self.read()?;
+        Ok(())
     }
 
-    pub fn read(&mut self) -> u8 {
-        self.read_n(1)[0]
+    pub fn read(&mut self) -> Result<u8> {
+        Ok(self.read_n(1)?[0])
     }
 
-    pub fn read_n(&mut self, len: usize) -> &'a [u8] {
+    pub fn read_n(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.len() {
+            return Err(Error::corrupt("unexpected end of data"));
+        }
         let res = &self.bytes[..len];
         self.bytes = &self.bytes[len..];
-        res
+        Ok(res)
     }
 
     pub fn read_all(&mut self) -> &'a [u8] {
-        self.read_n(self.len())
+        let res = self.bytes;
+        self.bytes = &[];
+        res
     }
 
-    pub fn read_until(&mut self, byte: u8) -> &'a [u8] {
-        let pos = self.bytes.iter().position(|x| *x == byte).unwrap();
+    pub fn read_until(&mut self, byte: u8) -> Result<&'a [u8]> {
+        let pos = self
+            .bytes
+            .iter()
+            .position(|x| *x == byte)
+            .ok_or_else(|| Error::corrupt("unexpected end of data"))?;
         let (front, back) = self.bytes.split_at(pos);
         self.bytes = back;
-        front
+        Ok(front)
     }
 }
@@ -0,0 +1,429 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    hash::Hash,
+    ignore::Ignore,
+    index::Index,
+    tree,
+    tree_diff::{self, DiffEntry},
+    worktree,
+};
+
+/// How `checkout` and `switch` move to another branch or commit.
+pub struct Options {
+    /// A branch to create at the target and switch to.
+    pub new_branch: Option<String>,
+    /// Detach HEAD even when the target is a branch.
+    pub detach: bool,
+    /// Throw away local changes instead of refusing to lose them.
+    pub force: bool,
+    pub quiet: bool,
+    /// Whether this is `switch`, which only detaches HEAD when told to.
+    pub switch: bool,
+}
+
+/// The command moving the working tree, which its errors name.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operation {
+    Checkout,
+    Merge,
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Checkout => "checkout",
+            Self::Merge => "merge",
+        }
+    }
+
+    /// What the user is told to tidy up before trying again.
+    fn doing(&self) -> &'static str {
+        match self {
+            Self::Checkout => "switch branches",
+            Self::Merge => "merge",
+        }
+    }
+}
+
+/// The paths whose local state stops the working tree from being switched.
+#[derive(Default)]
+struct Conflicts {
+    /// Tracked files with changes that would be lost.
+    modified: Vec<String>,
+    /// Untracked files that would be overwritten.
+    untracked: Vec<String>,
+}
+
+impl Conflicts {
+    fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.untracked.is_empty()
+    }
+
+    fn into_error(self, operation: Operation) -> Error {
+        let mut message = vec![];
+        let list = |paths: Vec<String>| -> String {
+            paths.iter().map(|path| format!("\t{}\n", path)).collect()
+        };
+        if !self.modified.is_empty() {
+            message.push(format!(
+                "Your local changes to the following files would be overwritten by {}:\n\
+                 {}Please commit your changes or stash them before you {}.",
+                operation.name(),
+                list(self.modified),
+                operation.doing()
+            ));
+        }
+        if !self.untracked.is_empty() {
+            message.push(format!(
+                "The following untracked working tree files would be overwritten by {}:\n\
+                 {}Please move or remove them before you {}.",
+                operation.name(),
+                list(self.untracked),
+                operation.doing()
+            ));
+        }
+        Error::failed(format!("{}\nAborting", message.join("\nerror: ")))
+    }
+}
+
+type Key = Option<(u32, Hash)>;
+
+fn key(entry: Option<&DiffEntry>) -> Key {
+    entry.map(|entry| (entry.mode, entry.hash))
+}
+
+/// Moves the index and the working tree from tree `old` to tree `new` the
+/// way git's two-way merge does. Paths the two trees agree on are left
+/// alone along with their local changes, and the others are updated as
+/// long as that loses nothing; otherwise nothing is touched. With `force`,
+/// both end up matching `new` whatever they held. `operation` is what
+/// errors say would have overwritten the files. A `new` tree with a path
+/// that `tree::verify_tree` rejects is refused before anything is written.
+pub fn switch_trees(
+    root: impl AsRef<Path>,
+    index: &mut Index,
+    old: Option<Hash>,
+    new: Option<Hash>,
+    force: bool,
+    operation: Operation,
+) -> Result<()> {
+    let root = root.as_ref();
+    if let Some(new) = new {
+        tree::verify_tree(root, &new)?;
+    }
+    let by_path = |entries: Vec<DiffEntry>| -> HashMap<String, DiffEntry> {
+        entries
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect()
+    };
+    let old_entries = by_path(tree_diff::flatten_tree(root, old)?);
+    let new_entries = by_path(tree_diff::flatten_tree(root, new)?);
+    let (worktree_entries, _) = worktree::tracked_entries(root, index)?;
+    let worktree_entries = by_path(worktree_entries);
+    let ignore = Ignore::load(root)?;
+
+    let mut paths: BTreeSet<&str> = old_entries.keys().map(String::as_str).collect();
+    paths.extend(new_entries.keys().map(String::as_str));
+    paths.extend(index.entries().iter().map(|entry| entry.path.as_str()));
+
+    let mut writes = vec![];
+    let mut removals = vec![];
+    let mut conflicts = Conflicts::default();
+    for path in paths {
+        let old = key(old_entries.get(path));
+        let new = key(new_entries.get(path));
+        let current = index
+            .get(path)
+            .filter(|entry| entry.stage == 0 && !entry.intent_to_add)
+            .map(|entry| (entry.mode, entry.hash));
+        let on_disk = key(worktree_entries.get(path));
+
+        if force {
+            match new {
+                Some(new) if current != Some(new) || on_disk != Some(new) => {
+                    writes.push((path.to_string(), new))
+                }
+                Some(_) => {}
+                None if index.get(path).is_some() || old.is_some() => {
+                    removals.push(path.to_string())
+                }
+                None => {}
+            }
+            continue;
+        }
+
+        if index.get(path).is_some() && current.is_none() {
+            // An intent-to-add entry, which the trees know nothing of.
+            if new.is_some() {
+                conflicts.modified.push(path.to_string());
+            }
+            continue;
+        }
+        match current {
+            Some(_) if old == new || current == new => {}
+            Some(_)
+                if current == old && on_disk.map_or(true, |on_disk| Some(on_disk) == current) =>
+            {
+                match new {
+                    Some(new) => writes.push((path.to_string(), new)),
+                    None => removals.push(path.to_string()),
+                }
+            }
+            Some(_) => conflicts.modified.push(path.to_string()),
+            None => match new {
+                // A staged deletion, which stands as long as the trees
+                // agree on the path.
+                Some(_) if old == new => {}
+                Some(_) if old.is_some() => conflicts.modified.push(path.to_string()),
+                Some(new) => {
+                    let in_the_way = untracked_in_the_way(root, index, &ignore, path)?;
+                    if in_the_way.is_empty() {
+                        writes.push((path.to_string(), new));
+                    }
+                    for path in in_the_way {
+                        if !conflicts.untracked.contains(&path) {
+                            conflicts.untracked.push(path);
+                        }
+                    }
+                }
+                None => {}
+            },
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(conflicts.into_error(operation));
+    }
+
+    for path in removals {
+        worktree::remove_file(root, &path);
+        index.remove(&path);
+    }
+    for (path, (mode, hash)) in writes {
+        index.remove(&path);
+        index.add(worktree::checkout_file(root, &path, mode, hash)?);
+    }
+    Ok(())
+}
+
+/// Returns the untracked, not ignored files that writing a new file at
+/// `path` would overwrite: the file itself, a file where one of its
+/// directories should be, or whatever is in a directory in its place.
+fn untracked_in_the_way(
+    root: &Path,
+    index: &Index,
+    ignore: &Ignore,
+    path: &str,
+) -> Result<Vec<String>> {
+    let untracked = |path: &str| index.get(path).is_none();
+    let mut end = 0;
+    while let Some(i) = path[end..].find('/') {
+        end += i;
+        let dir = &path[..end];
+        let metadata = fs::symlink_metadata(worktree::get_path(root, dir));
+        if metadata.is_ok_and(|m| !m.is_dir()) && untracked(dir) && !ignore.is_excluded(dir, false)
+        {
+            return Ok(vec![dir.to_string()]);
+        }
+        end += 1;
+    }
+
+    let Ok(metadata) = fs::symlink_metadata(worktree::get_path(root, path)) else {
+        return Ok(vec![]);
+    };
+    if !metadata.is_dir() {
+        return Ok(match ignore.is_excluded(path, false) {
+            true => vec![],
+            false => vec![path.to_string()],
+        });
+    }
+    let files = worktree::list_files(root, path, index, Some(ignore))?;
+    Ok(files.into_iter().filter(|file| untracked(file)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        blob::Blob,
+        checkout::{switch_trees, Operation},
+        file_mode::FileMode,
+        hash::Hash,
+        index::Index,
+        input_output,
+        object::Object,
+        repo::Repo,
+        test_utils, tree,
+        tree_node::{TreeNode, TreeNodeEntry},
+    };
+
+    /// Writes `files` to the working tree, stages everything and returns
+    /// the tree of the index.
+    fn stage(repo: &Repo, root: &std::path::Path, files: &[(&str, &str)]) -> Hash {
+        for (path, content) in files {
+            input_output::write(root.join(path), content).unwrap();
+        }
+        repo.add(vec![], true, false, false).unwrap();
+        tree::write_tree(root).unwrap()
+    }
+
+    #[test]
+    fn test_switch_trees_local_changes() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+        let old = stage(&repo, &root, &[("a", "one\n"), ("b", "b\n")]);
+        let new = stage(&repo, &root, &[("a", "two\n")]);
+        let mut index = tree::checkout_tree(&root, &old).unwrap();
+        let read = |path: &str| fs::read_to_string(root.join(path)).unwrap();
+
+        // A change to a file the trees disagree on stops everything.
+        input_output::write(root.join("a"), "local\n").unwrap();
+        let error = switch_trees(
+            &root,
+            &mut index,
+            Some(old),
+            Some(new),
+            false,
+            Operation::Checkout,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.report().unwrap(),
+            "error: Your local changes to the following files would be overwritten by checkout:\n\
+             \ta\n\
+             Please commit your changes or stash them before you switch branches.\n\
+             Aborting"
+        );
+        assert_eq!(read("a"), "local\n");
+        let one = Object::Blob(Blob::new(b"one\n".to_vec())).hash();
+        assert_eq!(index.get("a").unwrap().hash, one);
+
+        // One to a file they agree on is carried over.
+        input_output::write(root.join("a"), "one\n").unwrap();
+        input_output::write(root.join("b"), "local\n").unwrap();
+        switch_trees(
+            &root,
+            &mut index,
+            Some(old),
+            Some(new),
+            false,
+            Operation::Checkout,
+        )
+        .unwrap();
+        assert_eq!(read("a"), "two\n");
+        assert_eq!(read("b"), "local\n");
+
+        // Forcing throws local changes away.
+        input_output::write(root.join("a"), "local\n").unwrap();
+        switch_trees(
+            &root,
+            &mut index,
+            Some(new),
+            Some(old),
+            true,
+            Operation::Checkout,
+        )
+        .unwrap();
+        assert_eq!(read("a"), "one\n");
+        assert_eq!(read("b"), "b\n");
+    }
+
+    #[test]
+    fn test_switch_trees_untracked() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+        let new = stage(
+            &repo,
+            &root,
+            &[("a", "a\n"), ("dir/file", "file\n"), ("new", "new\n")],
+        );
+        fs::remove_dir_all(root.join("dir")).unwrap();
+        fs::remove_file(root.join("new")).unwrap();
+        fs::remove_file(Index::get_path(&root)).unwrap();
+        let old = stage(&repo, &root, &[]);
+        let mut index = Index::read(&root).unwrap();
+
+        // Untracked files where the new files go, or where one of their
+        // directories goes, would be lost.
+        input_output::write(root.join("dir"), "untracked\n").unwrap();
+        input_output::write(root.join("new"), "untracked\n").unwrap();
+        let error = switch_trees(
+            &root,
+            &mut index,
+            Some(old),
+            Some(new),
+            false,
+            Operation::Merge,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.report().unwrap(),
+            "error: The following untracked working tree files would be overwritten by merge:\n\
+             \tdir\n\
+             \tnew\n\
+             Please move or remove them before you merge.\n\
+             Aborting"
+        );
+        assert_eq!(fs::read_to_string(root.join("dir")).unwrap(), "untracked\n");
+        assert_eq!(fs::read_to_string(root.join("new")).unwrap(), "untracked\n");
+
+        // Ignored files are not worth keeping.
+        fs::remove_file(root.join("dir")).unwrap();
+        input_output::write(root.join(".git/info/exclude"), "new\n").unwrap();
+        switch_trees(
+            &root,
+            &mut index,
+            Some(old),
+            Some(new),
+            false,
+            Operation::Merge,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(root.join("new")).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(root.join("dir/file")).unwrap(), "file\n");
+    }
+
+    #[test]
+    fn test_switch_trees_invalid_path() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+        let old = stage(&repo, &root, &[("a", "a\n")]);
+        let mut index = Index::read(&root).unwrap();
+
+        let write = |mode, name: &str, hash| {
+            let entry = TreeNodeEntry::new(mode, name.to_string(), hash);
+            Object::TreeNode(TreeNode::new(vec![entry]))
+                .write(&root)
+                .unwrap()
+        };
+        let hook = Object::Blob(Blob::new(b"#!/bin/sh\n".to_vec()))
+            .write(&root)
+            .unwrap();
+        let hooks = write(FileMode::Executable, "post-checkout", hook);
+        let git_dir = write(FileMode::Tree, "hooks", hooks);
+        let new = write(FileMode::Tree, ".git", git_dir);
+
+        let error = switch_trees(
+            &root,
+            &mut index,
+            Some(old),
+            Some(new),
+            false,
+            Operation::Checkout,
+        )
+        .unwrap_err();
+        assert_eq!(error.report().unwrap(), "fatal: invalid path '.git'");
+        assert!(!root.join(".git/hooks/post-checkout").exists());
+        assert!(root.join("a").exists());
+    }
+}
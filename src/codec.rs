@@ -1,21 +1,47 @@
-use std::io::Read;
+use std::io::{BufRead, Read};
 
 use flate2::{
     bufread::{ZlibDecoder, ZlibEncoder},
     Compression,
 };
 
-pub fn decompress(compressed: &[u8]) -> Vec<u8> {
+use crate::error::{Error, Result};
+
+fn inflate_error(e: std::io::Error) -> Error {
+    Error::corrupt(format!("unable to inflate object: {}", e))
+}
+
+pub fn decompress(compressed: &[u8]) -> Result<Vec<u8>> {
     let mut bytes = vec![];
-    ZlibDecoder::new(&compressed[..])
+    ZlibDecoder::new(compressed)
         .read_to_end(&mut bytes)
-        .unwrap();
-    bytes
+        .map_err(inflate_error)?;
+    Ok(bytes)
 }
 
 pub fn compress(bytes: &[u8]) -> Vec<u8> {
-    let mut z = ZlibEncoder::new(&bytes[..], Compression::fast());
+    let mut z = ZlibEncoder::new(bytes, Compression::fast());
     let mut encoded = vec![];
+    // Reading from an in-memory buffer cannot fail.
     z.read_to_end(&mut encoded).unwrap();
     encoded
 }
+
+/// Inflates a single zlib stream from `reader`, leaving whatever follows the
+/// stream unread.
+pub fn decompress_from(reader: impl BufRead) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    ZlibDecoder::new(reader)
+        .read_to_end(&mut bytes)
+        .map_err(inflate_error)?;
+    Ok(bytes)
+}
+
+/// Inflates the zlib stream at the start of `compressed` and returns it along
+/// with the number of compressed bytes it took up.
+pub fn decompress_prefix(compressed: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut decoder = ZlibDecoder::new(compressed);
+    let mut bytes = vec![];
+    decoder.read_to_end(&mut bytes).map_err(inflate_error)?;
+    Ok((bytes, decoder.total_in() as usize))
+}
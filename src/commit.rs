@@ -1,62 +1,94 @@
-use chrono::Local;
+use std::{fmt::Display, str::from_utf8};
 
-use crate::{bytes_reader::BytesReader, codec, hash::Hash, object::Header};
+use crate::{
+    bytes_reader::BytesReader,
+    codec,
+    error::{Error, Result},
+    hash::Hash,
+    object::Header,
+    signature::Signature,
+};
 
 #[derive(Debug)]
 pub struct Commit {
-    tree: Hash,
-    parents: Vec<Hash>,
-    name: String,
-    email: String,
-    timestamp: String,
-    timezone: String,
-    message: String,
+    pub tree: Hash,
+    pub parents: Vec<Hash>,
+    pub author: Signature,
+    pub committer: Signature,
+    /// Headers following `committer` (`encoding`, `mergetag`, `gpgsig`, ...)
+    /// in their original order. Multi-line values are stored with plain `\n`
+    /// separators, the leading space of continuation lines removed.
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    /// The message as stored, in whatever `encoding` names.
+    pub message: Vec<u8>,
 }
 
 impl Commit {
     pub fn new(
         tree: Hash,
         parents: Vec<Hash>,
-        timestamp: String,
-        timezone: String,
+        author: Signature,
+        committer: Signature,
         message: String,
     ) -> Self {
         Self {
             tree,
             parents,
-            name: String::from("Name"),
-            email: String::from("name@example.com"),
-            timestamp,
-            timezone,
-            message,
+            author,
+            committer,
+            extra_headers: vec![],
+            message: complete_line(message).into_bytes(),
         }
     }
 
-    pub fn new_current_time(tree: Hash, parents: Vec<Hash>, message: String) -> Self {
-        let now = Local::now();
-        let timestamp = now.timestamp().to_string();
-        let offset = now.offset().to_string().replace(":", "");
-        Self::new(tree, parents, timestamp, offset, message)
-    }
+    pub fn parse(reader: &mut BytesReader) -> Result<Self> {
+        let corrupt = |message: &str| Error::corrupt(format!("corrupt commit: {}", message));
+        let (fields, message) = parse_headers(reader.read_all(), "commit")?;
 
-    pub fn parse(_reader: &mut BytesReader) -> Self {
-        unimplemented!()
-    }
+        let mut tree = None;
+        let mut parents = vec![];
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = vec![];
+        for (key, value) in fields {
+            match key {
+                "tree" => tree = Some(parse_hash(&value)?),
+                "parent" => parents.push(parse_hash(&value)?),
+                "author" => author = Some(Signature::parse(&value)?),
+                "committer" => committer = Some(Signature::parse(&value)?),
+                _ => extra_headers.push((key.to_string(), value)),
+            }
+        }
 
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut payload = vec![];
+        Ok(Self {
+            tree: tree.ok_or_else(|| corrupt("missing tree"))?,
+            parents,
+            author: author.ok_or_else(|| corrupt("missing author"))?,
+            committer: committer.ok_or_else(|| corrupt("missing committer"))?,
+            extra_headers,
+            message: message.to_vec(),
+        })
+    }
 
-        payload.push(format!("tree {}", self.tree));
+    /// The commit as stored, without the object header.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = format!("tree {}\n", self.tree).into_bytes();
         for parent in &self.parents {
-            payload.push(format!("parent {}", parent));
+            payload.extend_from_slice(format!("parent {}\n", parent).as_bytes());
         }
-        let user = format!("{} <{}>", self.name, self.email);
-        let dt = format!("{} {}", self.timestamp, self.timezone);
-        payload.push(format!("author {} {}", user, dt));
-        payload.push(format!("committer {} {}", user, dt));
+        push_header(&mut payload, "author", &self.author.to_bytes());
+        push_header(&mut payload, "committer", &self.committer.to_bytes());
+        for (key, value) in &self.extra_headers {
+            push_header(&mut payload, key, value);
+        }
+
+        payload.push(b'\n');
+        payload.extend_from_slice(&self.message);
+        payload
+    }
 
-        let payload = format!("{}\n\n{}\n", payload.join("\n"), self.message);
-        let payload: Vec<_> = payload.bytes().collect();
+    pub fn serialize(&self) -> Vec<u8> {
+        let payload = self.payload();
 
         let mut bytes = Header::new("commit", payload.len()).encode();
         bytes.extend_from_slice(&payload);
@@ -71,11 +103,91 @@ impl Commit {
     }
 }
 
+/// Header names and values of a commit or tag, in order.
+pub type Headers<'a> = Vec<(&'a str, Vec<u8>)>;
+
+/// Splits the payload of a `kind` object (commit or tag) into its headers
+/// and message. Values continued on lines starting with a space are joined
+/// with `\n`. Only the names need to be UTF-8: values and the message are
+/// kept as they are, since an `encoding` header may say they are not.
+pub fn parse_headers<'a>(payload: &'a [u8], kind: &str) -> Result<(Headers<'a>, &'a [u8])> {
+    let corrupt = |message: &str| Error::corrupt(format!("corrupt {}: {}", kind, message));
+    let (headers, message) = match payload.windows(2).position(|pair| pair == b"\n\n") {
+        Some(end) => (&payload[..end], &payload[end + 2..]),
+        None => (payload, &b""[..]),
+    };
+
+    let mut fields: Headers = vec![];
+    // Split on `\n` alone so a `\r` stays part of the line it ends.
+    for line in headers
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+    {
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = fields
+                .last_mut()
+                .ok_or_else(|| corrupt("continuation without header"))?;
+            value.push(b'\n');
+            value.extend_from_slice(continuation);
+        } else {
+            let space = line
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or_else(|| corrupt("header without value"))?;
+            let key = from_utf8(&line[..space]).map_err(|_| corrupt("invalid header name"))?;
+            fields.push((key, line[space + 1..].to_vec()));
+        }
+    }
+    Ok((fields, message))
+}
+
+/// Appends a `key value` header line, continuing each line of a multi-line
+/// value with a space.
+pub fn push_header(payload: &mut Vec<u8>, key: &str, value: &[u8]) {
+    payload.extend_from_slice(key.as_bytes());
+    payload.push(b' ');
+    for &b in value {
+        payload.push(b);
+        if b == b'\n' {
+            payload.push(b' ');
+        }
+    }
+    payload.push(b'\n');
+}
+
+/// Parses the object name a `tree`, `parent` or `object` header holds.
+pub fn parse_hash(value: &[u8]) -> Result<Hash> {
+    from_utf8(value)
+        .map_err(|_| {
+            let value = String::from_utf8_lossy(value);
+            Error::corrupt(format!("invalid object id '{}'", value))
+        })?
+        .parse()
+}
+
+/// Terminates a non-empty message with a newline, like git does for
+/// messages given on the command line.
+fn complete_line(mut message: String) -> String {
+    if !message.is_empty() && !message.ends_with('\n') {
+        message.push('\n');
+    }
+    message
+}
+
+impl Display for Commit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.payload()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
 
-    use crate::{object::Object, reference_impl, repo::Repo, test_utils, tree::write_tree};
+    use crate::{
+        codec, input_output, object::Object, reference_impl, repo::Repo, signature::Signature,
+        test_utils, tree::write_tree,
+    };
 
     use super::Commit;
 
@@ -83,17 +195,25 @@ mod tests {
     fn test_create_commit() {
         let root = test_utils::create_test_dir();
         let repo = Repo::new(&root);
-        repo.init();
+        repo.init().unwrap();
 
         let filename = "hello.txt";
         let contents = "Hello World!";
         fs::write(root.join(filename), contents).unwrap();
+        repo.add(vec![String::from(filename)], false, false, false)
+            .unwrap();
 
         let tree_want = write_tree(&root).unwrap();
         let parents_want = vec![];
         let message_want = String::from("msg");
-        let commit = Commit::new_current_time(tree_want, parents_want, message_want.clone());
-        let hash = Object::Commit(commit).write(&root);
+        let commit = Commit::new(
+            tree_want,
+            parents_want,
+            Signature::author(&root).unwrap(),
+            Signature::committer(&root).unwrap(),
+            message_want.clone(),
+        );
+        let hash = Object::Commit(commit).write(&root).unwrap();
 
         let (tree_got, parents_got, message_got) =
             reference_impl::read_commit(root, &hash.to_string());
@@ -101,4 +221,82 @@ mod tests {
         assert_eq!(parents_got.len(), 0);
         assert_eq!(message_got.unwrap(), format!("{}\n", message_want));
     }
+
+    #[test]
+    fn test_read_commit() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+
+        input_output::write(root.join("hello.txt"), "Hello World!").unwrap();
+        reference_impl::git_add_all(&repository);
+        let first = reference_impl::git_commit(&repository, "first", &[]);
+        let second = reference_impl::git_commit(&repository, "second", &[]);
+        let merge = reference_impl::git_commit(
+            &repository,
+            "merge\n\nbody\n",
+            &[first.as_str(), second.as_str()],
+        );
+
+        let commit = Object::read(&root, &merge).unwrap().into_commit();
+        assert_eq!(
+            commit.tree.to_string(),
+            reference_impl::git_write_tree(&repository)
+        );
+        let parents: Vec<_> = commit.parents.iter().map(|p| p.to_string()).collect();
+        assert_eq!(parents, [first, second]);
+        assert_eq!(commit.author.name, b"Author Name");
+        assert_eq!(commit.author.email, b"author@example.com");
+        assert_eq!(commit.author.timestamp, 1700000000);
+        assert_eq!(commit.author.timezone, "+0130");
+        assert_eq!(commit.committer.name, b"Committer Name");
+        assert_eq!(commit.committer.timezone, "-0800");
+        assert_eq!(commit.message, b"merge\n\nbody\n");
+    }
+
+    #[test]
+    fn test_read_commit_round_trip() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+
+        input_output::write(root.join("hello.txt"), "Hello World!").unwrap();
+        reference_impl::git_add_all(&repository);
+        let hash = reference_impl::git_commit_signed(&repository, "signed");
+
+        let want = codec::decompress(&input_output::read_obj(&root, &hash).unwrap()).unwrap();
+        let commit = Object::read(&root, &hash).unwrap().into_commit();
+        assert_eq!(commit.extra_headers.len(), 1);
+        assert_eq!(commit.extra_headers[0].0, "gpgsig");
+        assert_eq!(commit.serialize(), want);
+    }
+
+    #[test]
+    fn test_read_commit_not_utf8() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+
+        let tree = reference_impl::git_write_tree(&repository);
+        let mut payload = format!("tree {}\n", tree).into_bytes();
+        payload.extend_from_slice(
+            b"author Jos\xe9 <j@x> 1700000000 +0000\n\
+              committer C <c@x> 1700000000 +0000\n\
+              encoding ISO-8859-1\n\
+              x-note one\r\n two\n\
+              \n\
+              Caf\xe9\r\n",
+        );
+        let hash = reference_impl::git_write_object(&repository, "commit", &payload);
+
+        let commit = Object::read(&root, &hash).unwrap().into_commit();
+        assert_eq!(commit.author.name, b"Jos\xe9");
+        assert_eq!(
+            commit.extra_headers,
+            [
+                (String::from("encoding"), b"ISO-8859-1".to_vec()),
+                (String::from("x-note"), b"one\r\ntwo".to_vec()),
+            ]
+        );
+        assert_eq!(commit.message, b"Caf\xe9\r\n");
+        assert_eq!(commit.payload(), payload);
+        assert!(commit.to_string().ends_with("\n\nCaf\u{fffd}\r\n"));
+    }
 }
@@ -0,0 +1,989 @@
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    io::ErrorKind,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::from_utf8,
+};
+
+use crate::{
+    error::{Error, Result},
+    lockfile::Lock,
+    refs::{self, Ref},
+    wildmatch::wildmatch,
+};
+
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    System,
+    Global,
+    Local,
+    Command,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Scope::System => "system",
+            Scope::Global => "global",
+            Scope::Local => "local",
+            Scope::Command => "command",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    /// `section[.subsection].name`, with the section and name lowercased.
+    pub key: String,
+    /// `None` for a name without `=`, which is a boolean true.
+    pub value: Option<String>,
+    pub scope: Scope,
+    /// Where the entry came from, as printed by `--show-origin`.
+    pub origin: String,
+}
+
+impl Entry {
+    pub fn as_str(&self) -> &str {
+        self.value.as_deref().unwrap_or_default()
+    }
+
+    pub fn as_bool(&self) -> Result<bool> {
+        match &self.value {
+            None => Ok(true),
+            Some(value) => parse_bool(value).ok_or_else(|| {
+                Error::fatal(format!(
+                    "bad boolean config value '{}' for '{}'",
+                    value, self.key
+                ))
+            }),
+        }
+    }
+
+    pub fn as_int(&self) -> Result<i64> {
+        parse_int(self.as_str()).ok_or_else(|| {
+            Error::fatal(format!(
+                "bad numeric config value '{}' for '{}': invalid unit",
+                self.as_str(),
+                self.key
+            ))
+        })
+    }
+}
+
+/// Configuration variables merged from the system, global, repository and
+/// command scopes, in that order. Later entries override earlier ones.
+#[derive(Debug, Default)]
+pub struct Config {
+    entries: Vec<Entry>,
+}
+
+impl Config {
+    pub fn read(root: impl AsRef<Path>) -> Result<Self> {
+        let mut config = Self::default();
+        for scope in [Scope::System, Scope::Global, Scope::Local, Scope::Command] {
+            config.load_scope(root.as_ref(), scope)?;
+        }
+        Ok(config)
+    }
+
+    /// Reads a single scope, following includes.
+    pub fn read_scope(root: impl AsRef<Path>, scope: Scope) -> Result<Self> {
+        let mut config = Self::default();
+        config.load_scope(root.as_ref(), scope)?;
+        Ok(config)
+    }
+
+    /// Reads a single file given with `--file`, which does not follow
+    /// includes.
+    pub fn read_file(path: impl AsRef<Path>) -> Result<Self> {
+        let mut config = Self::default();
+        config.load_file(path.as_ref(), Scope::Command, None, 0)?;
+        Ok(config)
+    }
+
+    fn load_scope(&mut self, root: &Path, scope: Scope) -> Result<()> {
+        match scope {
+            Scope::Command => self.load_env(),
+            scope => {
+                for path in scope_files(root, scope) {
+                    self.load_file(&path, scope, Some(root), 0)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Loads `GIT_CONFIG_KEY_<n>`/`GIT_CONFIG_VALUE_<n>` pairs for `n` below
+    /// `GIT_CONFIG_COUNT`.
+    fn load_env(&mut self) -> Result<()> {
+        let Ok(count) = env::var("GIT_CONFIG_COUNT") else {
+            return Ok(());
+        };
+        let count: usize = count
+            .parse()
+            .map_err(|_| Error::fatal("bogus count in GIT_CONFIG_COUNT"))?;
+        for i in 0..count {
+            let key = env::var(format!("GIT_CONFIG_KEY_{}", i))
+                .map_err(|_| Error::fatal(format!("missing config key GIT_CONFIG_KEY_{}", i)))?;
+            let value = env::var(format!("GIT_CONFIG_VALUE_{}", i)).map_err(|_| {
+                Error::fatal(format!("missing config value GIT_CONFIG_VALUE_{}", i))
+            })?;
+            self.entries.push(Entry {
+                key: canonical_key(&key),
+                value: Some(value),
+                scope: Scope::Command,
+                origin: String::from("command line:"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Loads the file at `path` if it exists. Includes are followed when the
+    /// file belongs to the repository at `root`.
+    fn load_file(
+        &mut self,
+        path: &Path,
+        scope: Scope,
+        root: Option<&Path>,
+        depth: usize,
+    ) -> Result<()> {
+        let Ok(content) = fs::read_to_string(path) else {
+            return Ok(());
+        };
+        let origin = format!("file:{}", path.display());
+        for event in parse(&content, &origin)? {
+            let Event::Entry { key, value, .. } = event else {
+                continue;
+            };
+            let include = match root {
+                Some(root) => include_path(&key, value.as_deref(), path, root),
+                None => None,
+            };
+            self.entries.push(Entry {
+                key,
+                value,
+                scope,
+                origin: origin.clone(),
+            });
+            if let Some(include) = include {
+                if depth == MAX_INCLUDE_DEPTH {
+                    return Err(Error::fatal(format!(
+                        "exceeded maximum include depth ({}) while including\n\t{}\nfrom\n\t{}",
+                        MAX_INCLUDE_DEPTH,
+                        include.display(),
+                        path.display()
+                    )));
+                }
+                self.load_file(&include, scope, root, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn get_all<'a>(&'a self, key: &str) -> impl Iterator<Item = &'a Entry> {
+        let key = canonical_key(key);
+        self.entries.iter().filter(move |entry| entry.key == key)
+    }
+
+    pub fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.get_all(key).last()
+    }
+
+    /// Returns the last value of `key` (`section[.subsection].name`).
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_entry(key).map(Entry::as_str)
+    }
+
+    /// Returns the last value of `key` as a path, with a leading `~/`
+    /// standing for the home directory.
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        self.get(key).map(expand_home)
+    }
+}
+
+/// What `config` does with a key.
+pub enum Action {
+    Get {
+        key: String,
+        all: bool,
+    },
+    Set {
+        key: String,
+        value: String,
+        add: bool,
+    },
+    Unset {
+        key: String,
+        all: bool,
+    },
+    List,
+}
+
+/// The file or scope `config` reads or writes; all scopes are read and the
+/// repository file written when it is not given.
+pub enum Location {
+    Scope(Scope),
+    File(PathBuf),
+}
+
+/// The type `config --type` checks and canonicalizes values as.
+#[derive(Clone, Copy)]
+pub enum Type {
+    Bool,
+    Int,
+}
+
+impl Type {
+    pub fn format(&self, entry: &Entry) -> Result<String> {
+        Ok(match self {
+            Type::Bool => entry.as_bool()?.to_string(),
+            Type::Int => entry.as_int()?.to_string(),
+        })
+    }
+
+    pub fn normalize(&self, value: &str) -> Result<String> {
+        match self {
+            Type::Bool => parse_bool(value)
+                .map(|value| value.to_string())
+                .ok_or_else(|| Error::fatal(format!("invalid boolean value '{}'", value))),
+            Type::Int => parse_int(value)
+                .map(|value| value.to_string())
+                .ok_or_else(|| Error::fatal(format!("invalid integer value '{}'", value))),
+        }
+    }
+}
+
+/// The files making up `scope`, lowest precedence first.
+fn scope_files(root: &Path, scope: Scope) -> Vec<PathBuf> {
+    match scope {
+        Scope::System if env::var_os("GIT_CONFIG_NOSYSTEM").is_some() => vec![],
+        Scope::System => vec![system_path()],
+        Scope::Global => match env::var_os("GIT_CONFIG_GLOBAL") {
+            Some(global) => vec![PathBuf::from(global)],
+            None => [xdg_path("config"), home_path()]
+                .into_iter()
+                .flatten()
+                .collect(),
+        },
+        Scope::Local => vec![root.join(".git/config")],
+        Scope::Command => vec![],
+    }
+}
+
+fn system_path() -> PathBuf {
+    env::var_os("GIT_CONFIG_SYSTEM")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/etc/gitconfig"))
+}
+
+/// The file `name` in git's directory under `$XDG_CONFIG_HOME`, which
+/// defaults to `~/.config`.
+pub fn xdg_path(name: &str) -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("git").join(name))
+}
+
+fn home_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| Path::new(&home).join(".gitconfig"))
+}
+
+/// The file written by `config` for `scope`. Like git, global settings go
+/// to `~/.gitconfig` unless only the XDG file exists.
+pub fn scope_path(root: impl AsRef<Path>, scope: Scope) -> Result<PathBuf> {
+    Ok(match scope {
+        Scope::System => system_path(),
+        Scope::Global => {
+            if let Some(global) = env::var_os("GIT_CONFIG_GLOBAL") {
+                return Ok(PathBuf::from(global));
+            }
+            let home = home_path().ok_or_else(|| Error::fatal("$HOME not set"))?;
+            match xdg_path("config") {
+                Some(xdg) if !home.exists() && xdg.exists() => xdg,
+                _ => home,
+            }
+        }
+        Scope::Local | Scope::Command => root.as_ref().join(".git/config"),
+    })
+}
+
+/// The file to include for an `include.path` or a matching
+/// `includeIf.<condition>.path` entry read from `file`.
+fn include_path(key: &str, value: Option<&str>, file: &Path, root: &Path) -> Option<PathBuf> {
+    if key != "include.path" {
+        let condition = key.strip_prefix("includeif.")?.strip_suffix(".path")?;
+        if !include_condition(condition, file, root) {
+            return None;
+        }
+    }
+    let value = value?;
+    let path = expand_home(value);
+    Some(match file.parent() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    })
+}
+
+fn include_condition(condition: &str, file: &Path, root: &Path) -> bool {
+    if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        matches_gitdir(pattern, file, root, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        matches_gitdir(pattern, file, root, true)
+    } else if let Some(pattern) = condition.strip_prefix("onbranch:") {
+        let Ok(Some(Ref::Symbolic(head))) = refs::read_ref(root, "HEAD") else {
+            return false;
+        };
+        let Some(branch) = head.strip_prefix("refs/heads/") else {
+            return false;
+        };
+        let pattern = match pattern.ends_with('/') {
+            true => format!("{}**", pattern),
+            false => pattern.to_string(),
+        };
+        wildmatch(&pattern, branch, true, false)
+    } else {
+        false
+    }
+}
+
+/// Matches the repository's `.git` directory against an `includeIf
+/// "gitdir:..."` pattern: `~/` is the home directory, `./` the directory of
+/// the including file, other relative patterns match at any depth and a
+/// trailing `/` matches everything below.
+fn matches_gitdir(pattern: &str, file: &Path, root: &Path, casefold: bool) -> bool {
+    let mut pattern = match pattern.strip_prefix("./") {
+        Some(rest) => match file.parent() {
+            Some(dir) => format!("{}/{}", dir.display(), rest),
+            None => return false,
+        },
+        None => expand_home(pattern).display().to_string(),
+    };
+    if !pattern.starts_with('/') {
+        pattern = format!("**/{}", pattern);
+    }
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    let gitdir = root.join(".git");
+    let real = fs::canonicalize(&gitdir).unwrap_or_else(|_| gitdir.clone());
+    [gitdir, real]
+        .iter()
+        .any(|dir| wildmatch(&pattern, &dir.display().to_string(), true, casefold))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// A config file kept verbatim so that `set` and `unset` only touch the
+/// lines they change.
+pub struct ConfigFile {
+    path: PathBuf,
+    content: String,
+}
+
+impl ConfigFile {
+    pub fn read(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(Error::io("could not read", path, e)),
+        };
+        Ok(Self { path, content })
+    }
+
+    fn events(&self) -> Result<Vec<Event>> {
+        parse(&self.content, &format!("file:{}", self.path.display()))
+    }
+
+    fn spans_of(&self, key: &str) -> Result<Vec<Range<usize>>> {
+        Ok(self
+            .events()?
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Entry { key: k, span, .. } if k == key => Some(span),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Replaces the value of `key`, or adds it if it isn't set yet.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = validate_key(key)?;
+        let spans = self.spans_of(&key)?;
+        match spans.as_slice() {
+            [] => self.add(&key, value),
+            [span] => {
+                let (_, name) = split_key(&key);
+                self.content
+                    .replace_range(span.clone(), &format_entry(name, value));
+                Ok(())
+            }
+            _ => Err(Error::MultipleValues { key, replace: true }),
+        }
+    }
+
+    /// Adds a value for `key` after the last entry of its section, keeping
+    /// existing values.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = validate_key(key)?;
+        let (section, name) = split_key(&key);
+        let end = self
+            .events()?
+            .into_iter()
+            .filter(|event| event.section() == section)
+            .map(|event| event.span().end)
+            .next_back();
+        let entry = format_entry(name, value);
+        match end {
+            Some(end) => {
+                let entry = match self.content[..end].ends_with('\n') {
+                    true => entry,
+                    false => format!("\n{}", entry),
+                };
+                self.content.insert_str(end, &entry);
+            }
+            None => {
+                if !self.content.is_empty() && !self.content.ends_with('\n') {
+                    self.content.push('\n');
+                }
+                self.content.push_str(&format_section(section));
+                self.content.push_str(&entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes `key`, or every value of it with `all`. Returns whether
+    /// anything was removed. A section left without entries is removed too.
+    pub fn unset(&mut self, key: &str, all: bool) -> Result<bool> {
+        let key = validate_key(key)?;
+        let spans = self.spans_of(&key)?;
+        if spans.len() > 1 && !all {
+            return Err(Error::MultipleValues {
+                key,
+                replace: false,
+            });
+        }
+        for span in spans.iter().rev() {
+            self.content.replace_range(span.clone(), "");
+        }
+
+        let (section, _) = split_key(&key);
+        let events = self.events()?;
+        let empty_headers: Vec<_> = events
+            .iter()
+            .enumerate()
+            .filter_map(|(i, event)| match event {
+                Event::Section { name, span } if name == section => {
+                    let body_end = events[i + 1..]
+                        .iter()
+                        .find(|event| matches!(event, Event::Section { .. }))
+                        .map(|event| event.span().start)
+                        .unwrap_or(self.content.len());
+                    let body = &self.content[span.end..body_end];
+                    body.trim().is_empty().then(|| span.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        for span in empty_headers.iter().rev() {
+            self.content.replace_range(span.clone(), "");
+        }
+
+        Ok(!spans.is_empty())
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let lock = Lock::acquire(self.path.clone())?;
+        lock.commit(&self.content)
+    }
+}
+
+#[derive(Debug)]
+enum Event {
+    Section {
+        name: String,
+        span: Range<usize>,
+    },
+    Entry {
+        key: String,
+        value: Option<String>,
+        span: Range<usize>,
+    },
+}
+
+impl Event {
+    fn span(&self) -> &Range<usize> {
+        match self {
+            Event::Section { span, .. } | Event::Entry { span, .. } => span,
+        }
+    }
+
+    fn section(&self) -> &str {
+        match self {
+            Event::Section { name, .. } => name,
+            Event::Entry { key, .. } => key.rsplit_once('.').unwrap().0,
+        }
+    }
+}
+
+/// Parses git's INI dialect into section headers and entries, each with the
+/// byte range it occupies including its line terminator.
+fn parse(content: &str, origin: &str) -> Result<Vec<Event>> {
+    let mut parser = Parser {
+        bytes: content.as_bytes(),
+        pos: 0,
+        origin,
+    };
+    let mut events = vec![];
+    let mut section: Option<String> = None;
+    while parser.pos < parser.bytes.len() {
+        let start = parser.pos;
+        parser.skip_blanks();
+        match parser.peek() {
+            None => break,
+            Some(b'\n') => parser.pos += 1,
+            Some(b'#' | b';') => parser.skip_line(),
+            Some(b'[') => {
+                let name = parser.parse_section()?;
+                parser.skip_blanks();
+                if matches!(parser.peek(), None | Some(b'\n' | b'#' | b';')) {
+                    parser.skip_line();
+                }
+                events.push(Event::Section {
+                    name: name.clone(),
+                    span: start..parser.pos,
+                });
+                section = Some(name);
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let Some(section) = &section else {
+                    return Err(parser.fail());
+                };
+                let (name, value) = parser.parse_entry()?;
+                events.push(Event::Entry {
+                    key: format!("{}.{}", section, name),
+                    value,
+                    span: start..parser.pos,
+                });
+            }
+            Some(_) => return Err(parser.fail()),
+        }
+    }
+    Ok(events)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    origin: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn fail(&self) -> Error {
+        let line = self.bytes[..self.pos.min(self.bytes.len())]
+            .iter()
+            .filter(|&&c| c == b'\n')
+            .count()
+            + 1;
+        Error::ConfigSyntax {
+            line,
+            origin: self.origin.replacen("file:", "file ", 1),
+        }
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next() {
+            if c == b'\n' {
+                return;
+            }
+        }
+        self.pos = self.bytes.len();
+    }
+
+    fn take_while(&mut self, pred: impl Fn(u8) -> bool) -> &str {
+        let start = self.pos;
+        while self.peek().is_some_and(&pred) {
+            self.pos += 1;
+        }
+        from_utf8(&self.bytes[start..self.pos]).unwrap()
+    }
+
+    /// Parses `[section]`, `[section "subsection"]` or the deprecated
+    /// `[section.subsection]`. Section names are case-insensitive,
+    /// subsection names are not, except in the deprecated syntax.
+    fn parse_section(&mut self) -> Result<String> {
+        self.pos += 1;
+        let name = self
+            .take_while(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'.')
+            .to_lowercase();
+        if name.is_empty() {
+            return Err(self.fail());
+        }
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(name);
+        }
+        self.skip_blanks();
+        if name.contains('.') || self.next() != Some(b'"') {
+            return Err(self.fail());
+        }
+        let mut subsection = vec![];
+        loop {
+            match self.next() {
+                None | Some(b'\n') => return Err(self.fail()),
+                Some(b'"') => break,
+                Some(b'\\') => match self.next() {
+                    None | Some(b'\n') => return Err(self.fail()),
+                    Some(c) => subsection.push(c),
+                },
+                Some(c) => subsection.push(c),
+            }
+        }
+        if self.next() != Some(b']') {
+            return Err(self.fail());
+        }
+        let subsection = String::from_utf8(subsection).map_err(|_| self.fail())?;
+        Ok(format!("{}.{}", name, subsection))
+    }
+
+    fn parse_entry(&mut self) -> Result<(String, Option<String>)> {
+        let name = self
+            .take_while(|c| c.is_ascii_alphanumeric() || c == b'-')
+            .to_lowercase();
+        self.skip_blanks();
+        match self.peek() {
+            Some(b'=') => {
+                self.pos += 1;
+                Ok((name, Some(self.parse_value()?)))
+            }
+            None | Some(b'\n' | b'#' | b';') => {
+                self.skip_line();
+                Ok((name, None))
+            }
+            Some(_) => Err(self.fail()),
+        }
+    }
+
+    /// Parses a value up to the end of its line: outside quotes, comments
+    /// are dropped and whitespace runs are kept only between words; a
+    /// backslash before the newline continues the value on the next line.
+    fn parse_value(&mut self) -> Result<String> {
+        let mut value = vec![];
+        let mut quoted = false;
+        let mut comment = false;
+        let mut spaces = 0;
+        loop {
+            let Some(c) = self.next() else {
+                if quoted {
+                    return Err(self.fail());
+                }
+                self.pos = self.bytes.len();
+                break;
+            };
+            if c == b'\n' {
+                if quoted {
+                    return Err(self.fail());
+                }
+                break;
+            }
+            if comment {
+                continue;
+            }
+            if !quoted {
+                if c == b';' || c == b'#' {
+                    comment = true;
+                    continue;
+                }
+                if c.is_ascii_whitespace() {
+                    if !value.is_empty() {
+                        spaces += 1;
+                    }
+                    continue;
+                }
+            }
+            value.resize(value.len() + spaces, b' ');
+            spaces = 0;
+            match c {
+                b'"' => quoted = !quoted,
+                b'\\' => match self.next() {
+                    Some(b'\n') => {}
+                    Some(b'n') => value.push(b'\n'),
+                    Some(b't') => value.push(b'\t'),
+                    Some(b'b') => value.push(0x08),
+                    Some(c @ (b'"' | b'\\')) => value.push(c),
+                    _ => return Err(self.fail()),
+                },
+                c => value.push(c),
+            }
+        }
+        String::from_utf8(value).map_err(|_| self.fail())
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        value => value.parse::<i64>().ok().map(|n| n != 0),
+    }
+}
+
+/// Parses an integer with an optional `k`, `m` or `g` suffix (powers of
+/// 1024).
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (number, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    number.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// Lowercases the section and name of a key, leaving the subsection alone.
+fn canonical_key(key: &str) -> String {
+    let (section, rest) = key.split_once('.').unwrap_or((key, ""));
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => format!(
+            "{}.{}.{}",
+            section.to_lowercase(),
+            subsection,
+            name.to_lowercase()
+        ),
+        None => format!("{}.{}", section.to_lowercase(), rest.to_lowercase()),
+    }
+}
+
+fn validate_key(key: &str) -> Result<String> {
+    let Some((section, name)) = key.rsplit_once('.') else {
+        return Err(Error::MissingSection(key.to_string()));
+    };
+    let section_name = section.split('.').next().unwrap();
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let valid_section = !section_name.is_empty()
+        && section_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid_name || !valid_section || key.contains('\n') {
+        return Err(Error::InvalidKey(key.to_string()));
+    }
+    Ok(canonical_key(key))
+}
+
+fn split_key(key: &str) -> (&str, &str) {
+    key.rsplit_once('.').unwrap()
+}
+
+fn format_section(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => format!(
+            "[{} \"{}\"]\n",
+            name,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]\n", section),
+    }
+}
+
+/// Formats `name = value`, quoting values that would otherwise lose
+/// leading or trailing spaces or be cut at a comment character.
+fn format_entry(name: &str, value: &str) -> String {
+    let quote = value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']);
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    match quote {
+        true => format!("\t{} = \"{}\"\n", name, escaped),
+        false => format!("\t{} = {}\n", name, escaped),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{config::ConfigFile, input_output, reference_impl, repo::Repo, test_utils};
+
+    use super::{parse_int, Config, Scope};
+
+    #[test]
+    fn test_parse() {
+        let root = test_utils::create_test_dir();
+        Repo::new(&root).init().unwrap();
+        input_output::write(
+            root.join(".git/config"),
+            concat!(
+                "# comment\n",
+                "[User]\n",
+                "\tName = \"Jane  Doe\" ; trailing comment\n",
+                "\temail = jane@example.com\n",
+                "[remote \"Origin\"]\n",
+                "\turl = https://example.com/a\\\n",
+                "b.git  \n",
+                "\tfetch = one\n",
+                "\tfetch = \"two\\tthree\"\n",
+                "[core] bare\n",
+                "\tbigFileThreshold = 512m\n",
+                "[user]\n",
+                "\temail = doe@example.com\n",
+            ),
+        )
+        .unwrap();
+
+        let config = Config::read_scope(&root, Scope::Local).unwrap();
+        assert_eq!(config.get("user.name"), Some("Jane  Doe"));
+        assert_eq!(config.get("USER.EMAIL"), Some("doe@example.com"));
+        assert_eq!(
+            config.get("remote.Origin.url"),
+            Some("https://example.com/ab.git")
+        );
+        assert_eq!(config.get("remote.origin.url"), None);
+        let fetch: Vec<_> = config
+            .get_all("remote.Origin.fetch")
+            .map(|e| e.as_str())
+            .collect();
+        assert_eq!(fetch, ["one", "two\tthree"]);
+        assert!(config.get_entry("core.bare").unwrap().as_bool().unwrap());
+        assert_eq!(
+            config
+                .get_entry("core.bigfilethreshold")
+                .unwrap()
+                .as_int()
+                .unwrap(),
+            512 << 20
+        );
+
+        let repository = git2::Repository::open(&root).unwrap();
+        let git = repository.config().unwrap();
+        assert_eq!(
+            git.get_string("remote.Origin.url").unwrap(),
+            "https://example.com/ab.git"
+        );
+        assert_eq!(git.get_i64("core.bigfilethreshold").unwrap(), 512 << 20);
+    }
+
+    #[test]
+    fn test_parse_int() {
+        assert_eq!(parse_int("42"), Some(42));
+        assert_eq!(parse_int("1k"), Some(1024));
+        assert_eq!(parse_int("2G"), Some(2 << 30));
+        assert_eq!(parse_int("-3m"), Some(-3 << 20));
+        assert_eq!(parse_int("1x"), None);
+    }
+
+    #[test]
+    fn test_include() {
+        let root = test_utils::create_test_dir();
+        Repo::new(&root).init().unwrap();
+        let gitdir = fs::canonicalize(root.join(".git")).unwrap();
+        input_output::write(root.join("a.inc"), "[user]\n\tname = From Include\n").unwrap();
+        input_output::write(root.join("b.inc"), "[user]\n\temail = if@example.com\n").unwrap();
+        input_output::write(root.join("c.inc"), "[user]\n\temail = other@example.com\n").unwrap();
+        input_output::write(
+            root.join(".git/config"),
+            format!(
+                "[include]\n\tpath = ../a.inc\n\
+                 [includeIf \"gitdir:{}/\"]\n\tpath = ../b.inc\n\
+                 [includeIf \"gitdir:/nonexistent/\"]\n\tpath = ../c.inc\n\
+                 [includeIf \"onbranch:main\"]\n\tpath = onbranch.inc\n",
+                gitdir.parent().unwrap().display()
+            ),
+        )
+        .unwrap();
+        input_output::write(root.join(".git/onbranch.inc"), "[core]\n\tonbranch = yes\n").unwrap();
+
+        let config = Config::read_scope(&root, Scope::Local).unwrap();
+        assert_eq!(config.get("user.name"), Some("From Include"));
+        assert_eq!(config.get("user.email"), Some("if@example.com"));
+        assert!(config
+            .get_entry("core.onbranch")
+            .unwrap()
+            .as_bool()
+            .unwrap());
+        assert!(Config::read_file(root.join(".git/config"))
+            .unwrap()
+            .get("user.name")
+            .is_none());
+    }
+
+    #[test]
+    fn test_write() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let path = root.join(".git/config");
+        let original = fs::read_to_string(&path).unwrap();
+        input_output::write(
+            &path,
+            format!(
+                "{}# keep me\n[remote \"origin\"]\n    url = old ; comment\n\tfetch = a\n",
+                original
+            ),
+        )
+        .unwrap();
+
+        let mut file = ConfigFile::read(&path).unwrap();
+        file.set("remote.origin.url", "new").unwrap();
+        file.add("remote.origin.fetch", "b").unwrap();
+        file.set("user.name", " Spaced; Name ").unwrap();
+        file.set("core.bare", "false").unwrap();
+        file.write().unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(&original));
+        assert!(content
+            .contains("# keep me\n[remote \"origin\"]\n\turl = new\n\tfetch = a\n\tfetch = b\n"));
+        let config = repository.config().unwrap().snapshot().unwrap();
+        assert_eq!(config.get_str("user.name").unwrap(), " Spaced; Name ");
+        assert_eq!(config.get_str("remote.origin.url").unwrap(), "new");
+
+        let mut file = ConfigFile::read(&path).unwrap();
+        assert!(file.unset("user.name", false).unwrap());
+        assert!(!file.unset("user.name", false).unwrap());
+        assert!(file.unset("remote.origin.fetch", true).unwrap());
+        assert!(file.unset("remote.origin.url", false).unwrap());
+        file.write().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}# keep me\n", original)
+        );
+    }
+}
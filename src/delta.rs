@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use crate::{
+    bytes_reader::BytesReader,
+    error::{Error, Result},
+};
+
+/// The size of the blocks of the base that copies are looked up by.
+const BLOCK: usize = 16;
+/// How many places in the base are remembered for the same block.
+const MAX_CANDIDATES: usize = 64;
+const MAX_COPY: usize = 0x10000;
+const MAX_INSERT: usize = 0x7f;
+
+/// Reads the little-endian base-128 size used in delta headers.
+fn read_size(reader: &mut BytesReader) -> Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        if shift >= usize::BITS {
+            return Err(Error::corrupt("bad delta size"));
+        }
+        let byte = reader.read()?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            delta.push(byte);
+            return;
+        }
+        delta.push(byte | 0x80);
+    }
+}
+
+fn write_insert(delta: &mut Vec<u8>, bytes: &[u8]) {
+    for chunk in bytes.chunks(MAX_INSERT) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+fn write_copy(delta: &mut Vec<u8>, mut offset: usize, mut size: usize) {
+    while size > 0 {
+        let len = size.min(MAX_COPY);
+        let mut op = 0x80;
+        let mut args = vec![];
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 1 << i;
+                args.push(byte);
+            }
+        }
+        // A size of zero stands for the largest copy.
+        for i in 0..3 {
+            let byte = ((len & 0xffff) >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 0x10 << i;
+                args.push(byte);
+            }
+        }
+        delta.push(op);
+        delta.extend_from_slice(&args);
+        offset += len;
+        size -= len;
+    }
+}
+
+/// Encodes `target` as a git delta against `base`: copies of the runs that
+/// start at a block of the base and inserts of everything else. Returns
+/// `None` if the delta grows beyond `max_size`, when that is not zero.
+pub fn create(base: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (i, block) in base.chunks_exact(BLOCK).enumerate() {
+        let offsets = blocks.entry(block).or_default();
+        if offsets.len() < MAX_CANDIDATES {
+            offsets.push(i * BLOCK);
+        }
+    }
+
+    let mut delta = vec![];
+    write_size(&mut delta, base.len());
+    write_size(&mut delta, target.len());
+    let too_big = |delta: &Vec<u8>| max_size != 0 && delta.len() > max_size;
+    let mut inserted = 0;
+    let mut pos = 0;
+    while pos < target.len() {
+        let mut best = (0, 0);
+        if let Some(offsets) = target
+            .get(pos..pos + BLOCK)
+            .and_then(|block| blocks.get(block))
+        {
+            for &offset in offsets {
+                let len = base[offset..]
+                    .iter()
+                    .zip(&target[pos..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best.1 {
+                    best = (offset, len);
+                }
+            }
+        }
+        let (mut offset, mut len) = best;
+        if len < BLOCK {
+            pos += 1;
+            continue;
+        }
+        // The match may also cover the end of what would be inserted.
+        while pos > inserted && offset > 0 && base[offset - 1] == target[pos - 1] {
+            pos -= 1;
+            offset -= 1;
+            len += 1;
+        }
+        write_insert(&mut delta, &target[inserted..pos]);
+        write_copy(&mut delta, offset, len);
+        pos += len;
+        inserted = pos;
+        if too_big(&delta) {
+            return None;
+        }
+    }
+    write_insert(&mut delta, &target[inserted..]);
+    match too_big(&delta) {
+        true => None,
+        false => Some(delta),
+    }
+}
+
+/// Applies a git delta (as stored in OFS_DELTA and REF_DELTA pack entries) to
+/// `base`.
+pub fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = BytesReader::new(delta);
+    let base_size = read_size(&mut reader)?;
+    if base_size != base.len() {
+        return Err(Error::corrupt("delta base size mismatch"));
+    }
+    let target_size = read_size(&mut reader)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    while !reader.is_at_end() {
+        let op = reader.read()?;
+        if op & 0x80 != 0 {
+            let mut offset = 0;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (reader.read()? as usize) << (8 * i);
+                }
+            }
+            let mut size = 0;
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    size |= (reader.read()? as usize) << (8 * i);
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let copy = base
+                .get(offset..offset + size)
+                .ok_or_else(|| Error::corrupt("delta copies beyond the base object"))?;
+            target.extend_from_slice(copy);
+        } else if op != 0 {
+            target.extend_from_slice(reader.read_n(op as usize)?);
+        } else {
+            return Err(Error::corrupt("invalid delta opcode 0"));
+        }
+    }
+    if target.len() != target_size {
+        return Err(Error::corrupt("delta target size mismatch"));
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, create};
+
+    #[test]
+    fn test_apply() {
+        let base = b"Hello World!";
+        let delta = [
+            12, 13, // base size, target size
+            0x91, 6, 5, // copy "World" from offset 6
+            2, b',', b' ', // insert ", "
+            0x90, 6, // copy "Hello " from offset 0
+        ];
+        assert_eq!(apply(base, &delta).unwrap(), b"World, Hello ");
+    }
+
+    #[test]
+    fn test_create() {
+        let base: Vec<u8> = (0..300)
+            .flat_map(|i| format!("line {}\n", i).into_bytes())
+            .collect();
+        let mut target = b"new first line\n".to_vec();
+        target.extend_from_slice(&base[100..1500]);
+        target.extend_from_slice(b"in the middle\n");
+        target.extend_from_slice(&base[1200..]);
+        let delta = create(&base, &target, 0).unwrap();
+        assert!(delta.len() < 100);
+        assert_eq!(apply(&base, &delta).unwrap(), target);
+
+        // Copies longer than the largest one are split.
+        let base = vec![b'x'; 0x30000];
+        let delta = create(&base, &base, 0).unwrap();
+        assert_eq!(apply(&base, &delta).unwrap(), base);
+
+        assert_eq!(
+            apply(b"", &create(b"", b"abc", 0).unwrap()).unwrap(),
+            b"abc"
+        );
+        assert_eq!(create(b"abc", b"something else entirely", 10), None);
+    }
+}
@@ -0,0 +1,158 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{action} '{}': {}", path.display(), strerror(source))]
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error(transparent)]
+    RawIo(#[from] io::Error),
+    #[error(
+        "Unable to create '{}': File exists.\n\n\
+         Another git process seems to be running in this repository.\n\
+         If it still fails, a git process may have crashed in this repository earlier:\n\
+         remove the file manually to continue.",
+        .0.display()
+    )]
+    LockExists(PathBuf),
+    #[error("Not a valid object name {0}")]
+    InvalidObjectName(String),
+    /// A revision `rev-parse` could not resolve.
+    #[error(
+        "ambiguous argument '{0}': unknown revision or path not in the working tree.\n\
+         Use '--' to separate paths from revisions, like this:\n\
+         'git <command> [<revision>...] -- [<file>...]'"
+    )]
+    UnknownRevision(String),
+    #[error("{0}")]
+    Corrupt(String),
+    #[error("bad config line {line} in {origin}")]
+    ConfigSyntax { line: usize, origin: String },
+    #[error("key does not contain a section: {0}")]
+    MissingSection(String),
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    /// `config` was asked to replace or remove one of several values.
+    #[error("{key} has multiple values")]
+    MultipleValues { key: String, replace: bool },
+    #[error("unable to access '{url}': {message}")]
+    Remote { url: String, message: String },
+    /// A failure git reports with `error:` and exit code 1.
+    #[error("{0}")]
+    Failed(String),
+    /// Wrong command line usage, exit code 129.
+    #[error("{0}")]
+    Usage(String),
+    /// An unsuccessful outcome that isn't reported, only signalled through
+    /// the exit code, like `config --get` of a missing key.
+    #[error("exit status {0}")]
+    Exit(u8),
+    #[error("{0}")]
+    Fatal(String),
+}
+
+impl Error {
+    pub fn io(action: &'static str, path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io {
+            action,
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn corrupt(message: impl Into<String>) -> Self {
+        Self::Corrupt(message.into())
+    }
+
+    pub fn fatal(message: impl Into<String>) -> Self {
+        Self::Fatal(message.into())
+    }
+
+    pub fn failed(message: impl Into<String>) -> Self {
+        Self::Failed(message.into())
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Failed(_) | Error::InvalidKey(_) => 1,
+            Error::MissingSection(_) => 2,
+            Error::Usage(_) => 129,
+            Error::MultipleValues { .. } => 5,
+            Error::Exit(code) => *code,
+            _ => 128,
+        }
+    }
+
+    /// The message printed on stderr, prefixed like git's `die()`,
+    /// `error()` and `warning()`.
+    pub fn report(&self) -> Option<String> {
+        match self {
+            Error::Exit(_) => None,
+            Error::Failed(_)
+            | Error::Usage(_)
+            | Error::MissingSection(_)
+            | Error::InvalidKey(_) => Some(format!("error: {}", self)),
+            Error::MultipleValues { key, replace } => {
+                let mut report = format!("warning: {}", self);
+                if *replace {
+                    report.push_str(&format!(
+                        "\nerror: cannot overwrite multiple values with a single value\n       \
+                         Use a regexp, --add or --replace-all to change {}.",
+                        key
+                    ));
+                }
+                Some(report)
+            }
+            _ => Some(format!("fatal: {}", self)),
+        }
+    }
+}
+
+/// Formats an I/O error like C's `strerror`, without the `(os error N)`
+/// suffix Rust appends.
+fn strerror(error: &io::Error) -> String {
+    let message = error.to_string();
+    match error.raw_os_error() {
+        Some(code) => message
+            .trim_end_matches(&format!(" (os error {})", code))
+            .to_string(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::PathBuf};
+
+    use super::Error;
+
+    #[test]
+    fn test_report() {
+        let error = Error::io(
+            "could not open",
+            PathBuf::from("missing"),
+            io::Error::from_raw_os_error(2),
+        );
+        assert_eq!(
+            error.report().unwrap(),
+            "fatal: could not open 'missing': No such file or directory"
+        );
+        assert_eq!(error.exit_code(), 128);
+
+        let error = Error::MultipleValues {
+            key: String::from("a.b"),
+            replace: false,
+        };
+        assert_eq!(error.report().unwrap(), "warning: a.b has multiple values");
+        assert_eq!(error.exit_code(), 5);
+        assert_eq!(Error::failed("oops").report().unwrap(), "error: oops");
+        assert_eq!(Error::Exit(1).report(), None);
+    }
+}
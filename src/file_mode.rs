@@ -0,0 +1,139 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::error::{Error, Result};
+
+/// The bits of a mode that give its type of file, `S_IFMT`.
+const TYPE_MASK: u32 = 0o170000;
+
+/// The kinds of entries a tree holds, each with the mode git gives it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileMode {
+    Tree,
+    Blob,
+    /// `100664`, which early versions of git wrote for group-writable files.
+    /// It is a plain blob everywhere but in the tree that holds it, which
+    /// must keep it to keep its hash.
+    GroupWritable,
+    Executable,
+    Symlink,
+    Gitlink,
+}
+
+impl FileMode {
+    /// Maps a mode as stored in trees and the index.
+    pub fn from_bits(bits: u32) -> Result<Self> {
+        match bits {
+            0o40000 => Ok(Self::Tree),
+            0o100644 => Ok(Self::Blob),
+            0o100664 => Ok(Self::GroupWritable),
+            0o100755 => Ok(Self::Executable),
+            0o120000 => Ok(Self::Symlink),
+            0o160000 => Ok(Self::Gitlink),
+            bits => Err(Error::corrupt(format!("invalid file mode {:o}", bits))),
+        }
+    }
+
+    /// The type of file any mode stands for, even one git does not write,
+    /// from its `S_IFMT` bits alone: a regular file with any permissions is
+    /// a [`FileMode::Blob`].
+    pub fn type_of(bits: u32) -> Option<Self> {
+        match bits & TYPE_MASK {
+            0o040000 => Some(Self::Tree),
+            0o100000 => Some(Self::Blob),
+            0o120000 => Some(Self::Symlink),
+            0o160000 => Some(Self::Gitlink),
+            _ => None,
+        }
+    }
+
+    /// Whether two modes, valid or not, have the same type of file.
+    pub fn same_type(a: u32, b: u32) -> bool {
+        a & TYPE_MASK == b & TYPE_MASK
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::Tree => 0o40000,
+            Self::Blob => 0o100644,
+            Self::GroupWritable => 0o100664,
+            Self::Executable => 0o100755,
+            Self::Symlink => 0o120000,
+            Self::Gitlink => 0o160000,
+        }
+    }
+
+    /// The mode git uses for this one outside of trees: in the index, in
+    /// diffs and when showing trees.
+    pub fn canonical(self) -> Self {
+        match self {
+            Self::GroupWritable => Self::Blob,
+            mode => mode,
+        }
+    }
+
+    /// The type of the object an entry of this mode points to.
+    pub fn object_type(self) -> &'static str {
+        match self {
+            Self::Tree => "tree",
+            Self::Gitlink => "commit",
+            _ => "blob",
+        }
+    }
+}
+
+impl FromStr for FileMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        u32::from_str_radix(s, 8)
+            .ok()
+            .filter(|_| !s.starts_with('+'))
+            .ok_or_else(|| Error::corrupt(format!("invalid file mode {}", s)))
+            .and_then(Self::from_bits)
+    }
+}
+
+/// Shows the canonical mode in octal, without leading zeros, the way git
+/// shows trees.
+impl Display for FileMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&format!("{:o}", self.canonical().bits()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file_mode::FileMode;
+
+    #[test]
+    fn test_parse() {
+        for (mode, want) in [
+            ("40000", FileMode::Tree),
+            ("040000", FileMode::Tree),
+            ("100644", FileMode::Blob),
+            ("100664", FileMode::GroupWritable),
+            ("100755", FileMode::Executable),
+            ("120000", FileMode::Symlink),
+            ("160000", FileMode::Gitlink),
+        ] {
+            assert_eq!(mode.parse::<FileMode>().unwrap(), want);
+        }
+        for mode in ["", "100600", "123456", "10064x", "+100644", "100644 "] {
+            assert!(mode.parse::<FileMode>().is_err(), "{}", mode);
+        }
+
+        assert_eq!(FileMode::Tree.to_string(), "40000");
+        assert_eq!(format!("{:0>6}", FileMode::Tree), "040000");
+        let legacy = "100664".parse::<FileMode>().unwrap();
+        assert_eq!(legacy.bits(), 0o100664);
+        assert_eq!(legacy.canonical(), FileMode::Blob);
+        assert_eq!(legacy.to_string(), "100644");
+        assert_eq!(FileMode::Gitlink.object_type(), "commit");
+
+        assert_eq!(FileMode::type_of(0o100600), Some(FileMode::Blob));
+        assert_eq!(FileMode::type_of(0o40755), Some(FileMode::Tree));
+        assert_eq!(FileMode::type_of(0o170000), None);
+        assert!(FileMode::same_type(0o100644, 0o100755));
+        assert!(!FileMode::same_type(0o100644, 0o120000));
+    }
+}
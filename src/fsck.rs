@@ -0,0 +1,1225 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use flate2::Crc;
+
+use crate::{
+    bytes_reader::BytesReader,
+    codec,
+    error::{Error, Result},
+    file_mode::FileMode,
+    hash::Hash,
+    index::{CacheTree, Index},
+    input_output,
+    object::Header,
+    pack::{self, Pack},
+    refs::{self, Ref},
+};
+
+/// The bits of fsck's exit code, like git's.
+pub const ERROR_OBJECT: u8 = 1;
+pub const ERROR_REACHABLE: u8 = 2;
+pub const ERROR_PACK: u8 = 4;
+pub const ERROR_REFS: u8 = 8;
+
+pub struct Options {
+    /// Lists every unreachable object, not only the dangling ones.
+    pub unreachable: bool,
+    pub dangling: bool,
+    /// Treats warnings as errors and `100664` modes as bad.
+    pub strict: bool,
+}
+
+/// What fsck found: the lines for standard output and standard error, in
+/// order, and the bits of the exit code.
+#[derive(Default)]
+pub struct Report {
+    pub out: Vec<String>,
+    pub err: Vec<String>,
+    pub errors: u8,
+    /// What stopped the check before it was done, like git dying when it
+    /// needs an object that is corrupt.
+    pub fatal: Option<Error>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+/// An object of the store that could be parsed, with the objects it links
+/// to and the type it expects each of them to have.
+struct Found {
+    kind: &'static str,
+    links: Vec<(Hash, &'static str)>,
+}
+
+struct Checker<'a> {
+    options: &'a Options,
+    report: Report,
+    found: HashMap<Hash, Found>,
+    /// The objects that are linked to, with the type of the first link.
+    used: HashMap<Hash, &'static str>,
+    reachable: HashSet<Hash>,
+    /// The trees that could not be walked, with why.
+    bad_trees: HashMap<Hash, &'static str>,
+    /// The packed objects that could not be read, with their pack.
+    bad_packed: HashMap<Hash, String>,
+    /// The order objects were checked in.
+    order: Vec<Hash>,
+}
+
+/// Checks every loose and packed object, then which objects are reachable
+/// from the refs, HEAD, the reflogs and the index, like `git fsck`.
+pub fn check(root: impl AsRef<Path>, options: &Options) -> Result<Report> {
+    let root = root.as_ref();
+    let mut checker = Checker {
+        options,
+        report: Report::default(),
+        found: HashMap::new(),
+        used: HashMap::new(),
+        reachable: HashSet::new(),
+        bad_trees: HashMap::new(),
+        bad_packed: HashMap::new(),
+        order: vec![],
+    };
+    for hex in input_output::list_objs(root)? {
+        checker.check_loose(root, &hex)?;
+    }
+    for pack in pack::packs(root)? {
+        checker.check_pack(root, &pack)?;
+    }
+    checker.check_link_types();
+    match checker.mark_reachable(root) {
+        Ok(()) => checker.list_unreachable(),
+        Err(error @ Error::Fatal(_)) => checker.report.fatal = Some(error),
+        Err(error) => return Err(error),
+    }
+    Ok(checker.report)
+}
+
+impl Checker<'_> {
+    fn error(&mut self, message: String) {
+        self.report.err.push(format!("error: {}", message));
+    }
+
+    fn report(
+        &mut self,
+        kind: &str,
+        hash: &Hash,
+        severity: Severity,
+        id: &str,
+        message: &str,
+    ) -> bool {
+        let is_error =
+            severity == Severity::Error || (severity == Severity::Warning && self.options.strict);
+        let level = match is_error {
+            true => "error",
+            false => "warning",
+        };
+        self.report.err.push(format!(
+            "{} in {} {}: {}: {}",
+            level, kind, hash, id, message
+        ));
+        if is_error {
+            self.report.errors |= ERROR_OBJECT;
+        }
+        is_error
+    }
+
+    fn check_loose(&mut self, root: &Path, hex: &str) -> Result<()> {
+        let path = input_output::get_obj_path(root, hex);
+        let display = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        let bytes = input_output::read_obj(root, hex)?;
+        let parsed = codec::decompress(&bytes).ok().and_then(|bytes| {
+            let mut reader = BytesReader::new(&bytes);
+            let header = Header::parse(&mut reader).ok()?;
+            let kind = ["blob", "tree", "commit", "tag"]
+                .into_iter()
+                .find(|kind| *kind == header.kind)?;
+            let content = reader.read_all();
+            (content.len() == header.size).then(|| (Hash::hash(&bytes), kind, content.to_vec()))
+        });
+        let Some((hash, kind, content)) = parsed else {
+            self.report.errors |= ERROR_OBJECT;
+            self.error(format!("{}: object corrupt or missing: {}", hex, display));
+            return Ok(());
+        };
+        if hash.to_string() != hex {
+            self.report.errors |= ERROR_OBJECT;
+            self.error(format!(
+                "{}: hash-path mismatch, found at: {}",
+                hash, display
+            ));
+            return Ok(());
+        }
+        self.check_object(hash, kind, &content, &display);
+        Ok(())
+    }
+
+    fn check_pack(&mut self, root: &Path, pack: &Pack) -> Result<()> {
+        let path = pack.path();
+        let display = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .display()
+            .to_string();
+        let bytes = fs::read(path).map_err(|e| Error::io("could not read", path, e))?;
+        let split = bytes.len().saturating_sub(20);
+        if bytes.len() < 32 || Hash::hash(&bytes[..split]).bytes() != bytes[split..] {
+            self.report.errors |= ERROR_PACK;
+            self.error(format!("{} pack checksum mismatch", display));
+        }
+        // Entries are checked in the order they are stored, each ending
+        // where the next starts.
+        let mut entries = vec![];
+        for (i, hash) in pack.index.hashes().into_iter().enumerate() {
+            entries.push((pack.index.offset(i)?, i, hash));
+        }
+        entries.sort();
+        let ends: Vec<_> = entries
+            .iter()
+            .skip(1)
+            .map(|(offset, _, _)| *offset as usize)
+            .chain([split])
+            .collect();
+        for ((offset, i, hash), end) in entries.into_iter().zip(ends) {
+            if let Some(entry) = bytes.get(offset as usize..end) {
+                let mut crc = Crc::new();
+                crc.update(entry);
+                if crc.sum() != pack.index.crc32(i) {
+                    self.report.errors |= ERROR_PACK;
+                    self.error(format!(
+                        "index CRC mismatch for object {} from {} at offset {}",
+                        hash, display, offset
+                    ));
+                }
+            }
+            if self.found.contains_key(&hash) {
+                continue;
+            }
+            let Ok((kind, content)) = pack.read_at(root, offset) else {
+                self.report.errors |= ERROR_PACK;
+                self.error(format!(
+                    "cannot unpack {} from {} at offset {}",
+                    hash, display, offset
+                ));
+                self.bad_packed.insert(hash, display.clone());
+                continue;
+            };
+            let mut object = Header::new(kind, content.len()).encode();
+            object.extend_from_slice(&content);
+            if Hash::hash(&object) != hash {
+                self.report.errors |= ERROR_PACK;
+                self.error(format!("packed {} from {} is corrupt", hash, display));
+                self.bad_packed.insert(hash, display.clone());
+                continue;
+            }
+            self.check_object(hash, kind, &content, &display);
+        }
+        Ok(())
+    }
+
+    /// Parses an object the way git does before checking it, then runs the
+    /// checks for its type. Objects that cannot be parsed are not recorded,
+    /// so links to them count as missing.
+    fn check_object(&mut self, hash: Hash, kind: &'static str, content: &[u8], display: &str) {
+        let links = match kind {
+            "blob" => Some(vec![]),
+            "tree" => Some(self.check_tree(&hash, content)),
+            "commit" => self.parse_commit(&hash, content),
+            _ => self.parse_tag(&hash, content),
+        };
+        let Some(links) = links else {
+            self.report.errors |= ERROR_OBJECT;
+            self.error(format!("{}: object could not be parsed: {}", hash, display));
+            return;
+        };
+        match kind {
+            "commit" => self.check_commit(&hash, content),
+            "tag" => self.check_tag(&hash, content),
+            _ => {}
+        }
+        for (link, link_kind) in &links {
+            self.used.entry(*link).or_insert(link_kind);
+        }
+        self.found.insert(hash, Found { kind, links });
+        self.order.push(hash);
+    }
+
+    fn check_tree(&mut self, hash: &Hash, content: &[u8]) -> Vec<(Hash, &'static str)> {
+        let entries = match parse_tree(content) {
+            Ok(entries) => entries,
+            Err(message) => {
+                // Like git, once for walking the links and once for the
+                // checks.
+                self.error(message.to_string());
+                self.report
+                    .err
+                    .push(format!("error in tree {}: broken links", hash));
+                self.error(message.to_string());
+                self.report(
+                    "tree",
+                    hash,
+                    Severity::Error,
+                    "badTree",
+                    "cannot be parsed as a tree",
+                );
+                self.bad_trees.insert(*hash, message);
+                return vec![];
+            }
+        };
+
+        let mut links = vec![];
+        let (mut null_hash, mut full_path, mut empty_name) = (false, false, false);
+        let (mut dot, mut dotdot, mut dotgit) = (false, false, false);
+        let (mut zero_padded, mut bad_mode, mut bad_link) = (false, false, false);
+        let (mut duplicates, mut unsorted) = (false, false);
+        let mut files = HashSet::new();
+        let mut dirs = HashSet::new();
+        for (i, entry) in entries.iter().enumerate() {
+            null_hash |= entry.hash.is_zero();
+            full_path |= entry.name.contains(&b'/');
+            empty_name |= entry.name.is_empty();
+            dot |= entry.name == b".";
+            dotdot |= entry.name == b"..";
+            dotgit |= is_dotgit(entry.name);
+            zero_padded |= entry.mode_text.starts_with(b"0");
+            bad_mode |= match FileMode::from_bits(entry.mode) {
+                Ok(FileMode::GroupWritable) => self.options.strict,
+                Ok(_) => false,
+                Err(_) => true,
+            };
+            if i > 0 {
+                match verify_ordered(&entries[i - 1], entry) {
+                    Ordered::Yes => {}
+                    Ordered::No => unsorted = true,
+                    Ordered::Duplicate => duplicates = true,
+                }
+            }
+            // A file and a directory of the same name need not be next to
+            // each other.
+            match FileMode::type_of(entry.mode) == Some(FileMode::Tree) {
+                true => duplicates |= files.contains(entry.name) || !dirs.insert(entry.name),
+                false => duplicates |= dirs.contains(entry.name) || !files.insert(entry.name),
+            }
+
+            match FileMode::type_of(entry.mode) {
+                Some(FileMode::Gitlink) => {}
+                Some(mode) => links.push((entry.hash, mode.object_type())),
+                None => {
+                    bad_link = true;
+                    self.error(format!(
+                        "in tree {}: entry {} has bad mode {:06o}",
+                        hash,
+                        String::from_utf8_lossy(entry.name),
+                        entry.mode
+                    ));
+                }
+            }
+        }
+        if bad_link {
+            self.report.errors |= ERROR_OBJECT;
+            self.report
+                .err
+                .push(format!("error in tree {}: broken links", hash));
+        }
+
+        let checks = [
+            (
+                null_hash,
+                Severity::Warning,
+                "nullSha1",
+                "contains entries pointing to null sha1",
+            ),
+            (
+                full_path,
+                Severity::Warning,
+                "fullPathname",
+                "contains full pathnames",
+            ),
+            (
+                empty_name,
+                Severity::Warning,
+                "emptyName",
+                "contains empty pathname",
+            ),
+            (dot, Severity::Warning, "hasDot", "contains '.'"),
+            (dotdot, Severity::Warning, "hasDotdot", "contains '..'"),
+            (dotgit, Severity::Warning, "hasDotgit", "contains '.git'"),
+            (
+                zero_padded,
+                Severity::Warning,
+                "zeroPaddedFilemode",
+                "contains zero-padded file modes",
+            ),
+            (
+                bad_mode,
+                Severity::Info,
+                "badFilemode",
+                "contains bad file modes",
+            ),
+            (
+                duplicates,
+                Severity::Error,
+                "duplicateEntries",
+                "contains duplicate file entries",
+            ),
+            (
+                unsorted,
+                Severity::Error,
+                "treeNotSorted",
+                "not properly sorted",
+            ),
+        ];
+        for (found, severity, id, message) in checks {
+            if found {
+                self.report("tree", hash, severity, id, message);
+            }
+        }
+        links
+    }
+
+    /// Reads the tree and parents like git's commit parser, which rejects
+    /// a commit before fsck gets to look at it.
+    fn parse_commit(&mut self, hash: &Hash, content: &[u8]) -> Option<Vec<(Hash, &'static str)>> {
+        let mut links = vec![];
+        let Some(rest) = content
+            .strip_prefix(b"tree ")
+            .filter(|rest| rest.get(40) == Some(&b'\n'))
+        else {
+            self.error(format!("bogus commit object {}", hash));
+            return None;
+        };
+        let Some(tree) = parse_hex(&rest[..40]) else {
+            self.error(format!("bad tree pointer in commit {}", hash));
+            return None;
+        };
+        links.push((tree, "tree"));
+        let mut rest = &rest[41..];
+        while let Some(line) = rest.strip_prefix(b"parent ") {
+            let parent = match line.get(40) == Some(&b'\n') {
+                true => parse_hex(&line[..40]),
+                false => None,
+            };
+            let Some(parent) = parent else {
+                self.error(format!("bad parents in commit {}", hash));
+                return None;
+            };
+            links.push((parent, "commit"));
+            rest = &line[41..];
+        }
+        Some(links)
+    }
+
+    /// Reads the tagged object and its type like git's tag parser.
+    fn parse_tag(&mut self, hash: &Hash, content: &[u8]) -> Option<Vec<(Hash, &'static str)>> {
+        if content.len() < 40 + 24 {
+            return None;
+        }
+        let rest = content.strip_prefix(b"object ")?;
+        let object = parse_hex(rest.get(..40)?)?;
+        let rest = rest[40..].strip_prefix(b"\n")?.strip_prefix(b"type ")?;
+        let end = rest.iter().position(|&c| c == b'\n')?;
+        let kind = match &rest[..end] {
+            b"blob" => "blob",
+            b"tree" => "tree",
+            b"commit" => "commit",
+            b"tag" => "tag",
+            kind => {
+                self.error(format!(
+                    "unknown tag type '{}' in {}",
+                    String::from_utf8_lossy(kind),
+                    hash
+                ));
+                return None;
+            }
+        };
+        let rest = &rest[end + 1..];
+        if rest.len() <= 4 || !rest.starts_with(b"tag ") {
+            return None;
+        }
+        Some(vec![(object, kind)])
+    }
+
+    fn check_commit(&mut self, hash: &Hash, content: &[u8]) {
+        let report = |checker: &mut Self, severity, id, message: &str| {
+            checker.report("commit", hash, severity, id, message)
+        };
+        if self.check_headers("commit", hash, content) {
+            return;
+        }
+        let mut rest = content;
+        match rest.strip_prefix(b"tree ") {
+            None => {
+                report(
+                    self,
+                    Severity::Error,
+                    "missingTree",
+                    "invalid format - expected 'tree' line",
+                );
+                return;
+            }
+            Some(line) => rest = self.skip_hash_line("commit", hash, line, "badTreeSha1", "tree"),
+        }
+        while let Some(line) = rest.strip_prefix(b"parent ") {
+            rest = self.skip_hash_line("commit", hash, line, "badParentSha1", "parent");
+        }
+        let mut authors = 0;
+        while let Some(line) = rest.strip_prefix(b"author ") {
+            authors += 1;
+            match self.check_ident("commit", hash, line) {
+                Some(next) => rest = next,
+                None => return,
+            }
+        }
+        if authors == 0 {
+            report(
+                self,
+                Severity::Error,
+                "missingAuthor",
+                "invalid format - expected 'author' line",
+            );
+            return;
+        }
+        if authors > 1 {
+            report(
+                self,
+                Severity::Error,
+                "multipleAuthors",
+                "invalid format - multiple 'author' lines",
+            );
+            return;
+        }
+        let Some(line) = rest.strip_prefix(b"committer ") else {
+            report(
+                self,
+                Severity::Error,
+                "missingCommitter",
+                "invalid format - expected 'committer' line",
+            );
+            return;
+        };
+        if self.check_ident("commit", hash, line).is_none() {
+            return;
+        }
+        if content.contains(&0) {
+            report(
+                self,
+                Severity::Warning,
+                "nulInCommit",
+                "NUL byte in the commit object body",
+            );
+        }
+    }
+
+    fn check_tag(&mut self, hash: &Hash, content: &[u8]) {
+        if self.check_headers("tag", hash, content) {
+            return;
+        }
+        // The parser already made sure of the object, type and tag lines.
+        let mut lines = content.splitn(4, |&c| c == b'\n');
+        let name = lines
+            .nth(2)
+            .and_then(|line| line.strip_prefix(b"tag "))
+            .unwrap_or_default();
+        let name = String::from_utf8_lossy(name);
+        if !refs::is_valid_name(&format!("refs/tags/{}", name))
+            && self.report(
+                "tag",
+                hash,
+                Severity::Info,
+                "badTagName",
+                &format!("invalid 'tag' name: {}", name),
+            )
+        {
+            return;
+        }
+        match lines.next().unwrap_or_default().strip_prefix(b"tagger ") {
+            Some(line) => {
+                self.check_ident("tag", hash, line);
+            }
+            None => {
+                self.report(
+                    "tag",
+                    hash,
+                    Severity::Info,
+                    "missingTaggerEntry",
+                    "invalid format - expected 'tagger' line",
+                );
+            }
+        }
+    }
+
+    /// Checks that the headers end with a newline and hold no NUL byte.
+    /// Returns whether an error was reported.
+    fn check_headers(&mut self, kind: &str, hash: &Hash, content: &[u8]) -> bool {
+        for (i, &c) in content.iter().enumerate() {
+            match c {
+                0 => {
+                    let message = format!("unterminated header: NUL at offset {}", i);
+                    return self.report(kind, hash, Severity::Error, "nulInHeader", &message);
+                }
+                b'\n' if content.get(i + 1) == Some(&b'\n') => return false,
+                _ => {}
+            }
+        }
+        if content.last() == Some(&b'\n') {
+            return false;
+        }
+        self.report(
+            kind,
+            hash,
+            Severity::Error,
+            "unterminatedHeader",
+            "unterminated header",
+        )
+    }
+
+    /// Checks a `tree` or `parent` line and returns what follows it.
+    fn skip_hash_line<'b>(
+        &mut self,
+        kind: &str,
+        hash: &Hash,
+        line: &'b [u8],
+        id: &str,
+        name: &str,
+    ) -> &'b [u8] {
+        let valid = line.get(..40).and_then(parse_hex).is_some() && line.get(40) == Some(&b'\n');
+        if !valid {
+            let message = format!("invalid '{}' line format - bad sha1", name);
+            self.report(kind, hash, Severity::Error, id, &message);
+        }
+        let end = line
+            .iter()
+            .position(|&c| c == b'\n')
+            .map_or(line.len(), |end| end + 1);
+        &line[end..]
+    }
+
+    /// Checks an author, committer or tagger line like git's fsck_ident and
+    /// returns what follows it, or `None` if it has an error.
+    fn check_ident<'b>(&mut self, kind: &str, hash: &Hash, line: &'b [u8]) -> Option<&'b [u8]> {
+        let end = line.iter().position(|&c| c == b'\n');
+        let rest = &line[end.map_or(line.len(), |end| end + 1)..];
+        if let Some((id, message)) = ident_error(line) {
+            let message = format!("invalid author/committer line - {}", message);
+            self.report(kind, hash, Severity::Error, id, &message);
+            return None;
+        }
+        Some(rest)
+    }
+
+    /// Reports links to objects of another type than the link says.
+    fn check_link_types(&mut self) {
+        for hash in self.order.clone() {
+            let found = &self.found[&hash];
+            let kind = found.kind;
+            let mut broken = vec![];
+            for (link, expected) in &found.links {
+                if let Some(target) = self.found.get(link) {
+                    if target.kind != *expected {
+                        broken.push(format!(
+                            "object {} is a {}, not a {}",
+                            link, target.kind, expected
+                        ));
+                    }
+                }
+            }
+            if !broken.is_empty() {
+                for message in broken {
+                    self.error(message);
+                }
+                self.report.errors |= ERROR_OBJECT;
+                self.report
+                    .err
+                    .push(format!("error in {} {}: broken links", kind, hash));
+            }
+        }
+    }
+
+    /// Adds the object a ref points to to `pending`, returning whether it
+    /// exists.
+    fn mark_ref(&mut self, name: &str, hash: Hash, pending: &mut Vec<Hash>) -> Result<bool> {
+        let Some(found) = self.found.get(&hash) else {
+            self.check_readable(&hash)?;
+            self.report.errors |= ERROR_REACHABLE;
+            self.error(format!("{}: invalid sha1 pointer {}", name, hash));
+            return Ok(false);
+        };
+        if found.kind != "commit" && name.starts_with("refs/heads/") {
+            self.report.errors |= ERROR_REFS;
+            self.error(format!("{}: not a commit", name));
+        }
+        pending.push(hash);
+        Ok(true)
+    }
+
+    /// Fails like git does when it has to read a packed object that is
+    /// corrupt, unless a good copy was found elsewhere.
+    fn check_readable(&self, hash: &Hash) -> Result<()> {
+        match self.bad_packed.get(hash) {
+            Some(pack) if !self.found.contains_key(hash) => Err(Error::fatal(format!(
+                "packed object {} (stored in {}) is corrupt",
+                hash, pack
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Marks what the refs, HEAD, the reflogs and the index point to as
+    /// reachable, then everything those objects link to.
+    fn mark_reachable(&mut self, root: &Path) -> Result<()> {
+        let mut pending = vec![];
+        let mut default_refs = 0;
+        for (name, hash) in refs::list(root, "refs/")? {
+            default_refs += self.mark_ref(&name, hash, &mut pending)? as usize;
+        }
+        match refs::read_ref(root, "HEAD")? {
+            Some(Ref::Symbolic(target)) => match refs::resolve(root, &target)? {
+                Some(head) => default_refs += self.mark_ref("HEAD", head, &mut pending)? as usize,
+                None if target.starts_with("refs/heads/") => self.report.err.push(format!(
+                    "notice: HEAD points to an unborn branch ({})",
+                    refs::shorten(&target)
+                )),
+                None => {
+                    self.report.errors |= ERROR_REFS;
+                    self.error(format!("HEAD points to something strange ({})", target));
+                }
+            },
+            Some(Ref::Direct(head)) => {
+                default_refs += self.mark_ref("HEAD", head, &mut pending)? as usize
+            }
+            None => {
+                self.report.errors |= ERROR_REFS;
+                self.error(String::from("invalid HEAD"));
+            }
+        }
+        for name in refs::list_reflogs(root)? {
+            for hash in refs::read_reflog(root, &name)? {
+                if hash.is_zero() {
+                    continue;
+                }
+                match self.found.contains_key(&hash) {
+                    true => pending.push(hash),
+                    false => {
+                        self.report.errors |= ERROR_REACHABLE;
+                        self.error(format!("{}: invalid reflog entry {}", name, hash));
+                    }
+                }
+            }
+        }
+        if default_refs == 0 {
+            self.report
+                .err
+                .push(String::from("notice: No default references"));
+        }
+        let index = Index::read(root)?;
+        for entry in index.entries() {
+            if entry.mode != FileMode::Gitlink.bits() {
+                self.used.entry(entry.hash).or_insert("blob");
+                pending.push(entry.hash);
+            }
+        }
+        if let Some(cache_tree) = index.cache_tree() {
+            self.mark_cache_tree(cache_tree, &mut pending)?;
+        }
+
+        for hash in &pending {
+            self.reachable.insert(*hash);
+        }
+        while let Some(hash) = pending.pop() {
+            let Some(found) = self.found.get(&hash) else {
+                continue;
+            };
+            let (kind, links) = (found.kind, found.links.clone());
+            if let Some(message) = self.bad_trees.get(&hash) {
+                self.error(message.to_string());
+            }
+            for (link, expected) in links {
+                match self.found.get(&link) {
+                    Some(target) if target.kind != expected => {
+                        let message =
+                            format!("object {} is a {}, not a {}", link, target.kind, expected);
+                        self.error(message);
+                        self.report.errors |= ERROR_REACHABLE;
+                        self.report
+                            .out
+                            .push(format!("broken link from {:>7} {}", kind, hash));
+                        self.report
+                            .out
+                            .push(format!("broken link from {:>7} unknown", expected));
+                    }
+                    Some(_) => {
+                        if self.reachable.insert(link) {
+                            pending.push(link);
+                        }
+                    }
+                    // A packed object that cannot be read is there, if
+                    // broken, and was reported as such.
+                    None if self.bad_packed.contains_key(&link) => {
+                        self.reachable.insert(link);
+                    }
+                    None => {
+                        if self.reachable.insert(link) {
+                            self.report.errors |= ERROR_REACHABLE;
+                            self.report.out.push(format!(
+                                "broken link from {:>7} {}\n              to {:>7} {}",
+                                kind, hash, self.used[&link], link
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the trees the index's cache tree records as reachable. Those
+    /// that are missing are reported, and so are their subtrees.
+    fn mark_cache_tree(&mut self, cache_tree: &CacheTree, pending: &mut Vec<Hash>) -> Result<()> {
+        if let Some(hash) = cache_tree.hash {
+            let Some(found) = self.found.get(&hash) else {
+                self.check_readable(&hash)?;
+                self.report.errors |= ERROR_REFS;
+                self.error(format!("{}: invalid sha1 pointer in cache-tree", hash));
+                return Ok(());
+            };
+            if found.kind != "tree" {
+                self.report.errors |= ERROR_OBJECT;
+                self.report.err.push(format!(
+                    "error in {} {}: non-tree in cache-tree",
+                    found.kind, hash
+                ));
+            }
+            self.used.entry(hash).or_insert("tree");
+            pending.push(hash);
+        }
+        for subtree in &cache_tree.subtrees {
+            self.mark_cache_tree(subtree, pending)?;
+        }
+        Ok(())
+    }
+
+    /// Lists the missing objects that are reachable, and the objects that
+    /// are not, or only the dangling ones: those no other object links to.
+    fn list_unreachable(&mut self) {
+        let mut hashes: Vec<Hash> = self.found.keys().chain(self.used.keys()).copied().collect();
+        hashes.sort();
+        hashes.dedup();
+        for hash in hashes {
+            let found = self.found.get(&hash);
+            match (found, self.reachable.contains(&hash)) {
+                (None, true) if !self.bad_packed.contains_key(&hash) => {
+                    self.report.errors |= ERROR_REACHABLE;
+                    self.report
+                        .out
+                        .push(format!("missing {} {}", self.used[&hash], hash));
+                }
+                (Some(found), false) if self.options.unreachable => {
+                    self.report
+                        .out
+                        .push(format!("unreachable {} {}", found.kind, hash));
+                }
+                (Some(found), false) if self.options.dangling && !self.used.contains_key(&hash) => {
+                    self.report
+                        .out
+                        .push(format!("dangling {} {}", found.kind, hash));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+struct TreeEntry<'a> {
+    mode_text: &'a [u8],
+    mode: u32,
+    name: &'a [u8],
+    hash: Hash,
+}
+
+/// Splits a tree into its entries like git's tree walker, which only
+/// cares that each entry has an octal mode, a name and a hash.
+fn parse_tree(mut content: &[u8]) -> std::result::Result<Vec<TreeEntry<'_>>, &'static str> {
+    let mut entries = vec![];
+    while !content.is_empty() {
+        if content.len() < 23 || content[content.len() - 21] != 0 {
+            return Err("too-short tree object");
+        }
+        let space = content.iter().position(|&c| c == b' ');
+        let mode_text = &content[..space.unwrap_or(0)];
+        if mode_text.is_empty() || !mode_text.iter().all(|c| (b'0'..=b'7').contains(c)) {
+            return Err("malformed mode in tree entry");
+        }
+        let mode = mode_text
+            .iter()
+            .fold(0u32, |mode, c| (mode << 3).wrapping_add((c - b'0') as u32));
+        let rest = &content[mode_text.len() + 1..];
+        let name_len = rest.iter().position(|&c| c == 0).unwrap_or(rest.len());
+        if name_len == 0 {
+            return Err("empty filename in tree entry");
+        }
+        let Some(hash) = rest.get(name_len + 1..name_len + 21) else {
+            return Err("too-short tree file");
+        };
+        entries.push(TreeEntry {
+            mode_text,
+            mode,
+            name: &rest[..name_len],
+            hash: Hash::new(hash.try_into().unwrap()),
+        });
+        content = &rest[name_len + 21..];
+    }
+    Ok(entries)
+}
+
+enum Ordered {
+    Yes,
+    No,
+    Duplicate,
+}
+
+/// Compares two consecutive tree entries, with the name of a directory
+/// sorting as if it ended with a slash.
+fn verify_ordered(first: &TreeEntry, second: &TreeEntry) -> Ordered {
+    let len = first.name.len().min(second.name.len());
+    match first.name[..len].cmp(&second.name[..len]) {
+        std::cmp::Ordering::Less => return Ordered::Yes,
+        std::cmp::Ordering::Greater => return Ordered::No,
+        std::cmp::Ordering::Equal => {}
+    }
+    let next = |entry: &TreeEntry| match entry.name.get(len) {
+        Some(&c) => c,
+        None if FileMode::type_of(entry.mode) == Some(FileMode::Tree) => b'/',
+        None => 0,
+    };
+    if first.name.len() == second.name.len() {
+        return Ordered::Duplicate;
+    }
+    match next(first) < next(second) {
+        true => Ordered::Yes,
+        false => Ordered::No,
+    }
+}
+
+/// Whether a tree entry would be taken for `.git` by a case-insensitive
+/// file system, on Windows or macOS.
+fn is_dotgit(name: &[u8]) -> bool {
+    let end = name
+        .iter()
+        .rposition(|&c| c != b' ' && c != b'.')
+        .map_or(0, |end| end + 1);
+    let name = &name[..end];
+    name.eq_ignore_ascii_case(b".git") || name.eq_ignore_ascii_case(b"git~1")
+}
+
+fn parse_hex(hex: &[u8]) -> Option<Hash> {
+    std::str::from_utf8(hex).ok()?.parse().ok()
+}
+
+/// Finds what is wrong with an ident line, if anything, following the
+/// order of git's checks.
+fn ident_error(line: &[u8]) -> Option<(&'static str, &'static str)> {
+    let at = |i: usize| line.get(i).copied().unwrap_or(0);
+    let scan = |mut i: usize| {
+        while !matches!(at(i), b'<' | b'>' | b'\n' | 0) {
+            i += 1;
+        }
+        i
+    };
+    if at(0) == b'<' {
+        return Some(("missingNameBeforeEmail", "missing space before email"));
+    }
+    let mut p = scan(0);
+    if at(p) == b'>' {
+        return Some(("badName", "bad name"));
+    }
+    if at(p) != b'<' {
+        return Some(("missingEmail", "missing email"));
+    }
+    if at(p - 1) != b' ' {
+        return Some(("missingSpaceBeforeEmail", "missing space before email"));
+    }
+    p = scan(p + 1);
+    if at(p) != b'>' {
+        return Some(("badEmail", "bad email"));
+    }
+    p += 1;
+    if at(p) != b' ' {
+        return Some(("missingSpaceBeforeDate", "missing space before date"));
+    }
+    p += 1;
+    if at(p) == b'0' && at(p + 1) != b' ' {
+        return Some(("zeroPaddedDate", "zero-padded date"));
+    }
+    let digits = line[p.min(line.len())..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    let date = std::str::from_utf8(&line[p..p + digits]).unwrap_or_default();
+    if digits > 0 && date.parse::<u64>().map_or(true, |date| date == u64::MAX) {
+        return Some(("badDateOverflow", "date causes integer overflow"));
+    }
+    p += digits;
+    if digits == 0 || at(p) != b' ' {
+        return Some(("badDate", "bad date"));
+    }
+    p += 1;
+    if !matches!(at(p), b'+' | b'-')
+        || !(1..=4).all(|i| at(p + i).is_ascii_digit())
+        || at(p + 5) != b'\n'
+    {
+        return Some(("badTimezone", "bad time zone"));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use git2::Repository;
+
+    use crate::{
+        error::Error,
+        fsck::{self, Options},
+        hash::Hash,
+        input_output, object, pack, reference_impl, refs,
+        repo::Repo,
+        revision, test_utils,
+    };
+
+    fn options() -> Options {
+        Options {
+            unreachable: false,
+            dangling: true,
+            strict: false,
+        }
+    }
+
+    /// A repository with one commit on master, of `file` and `dir/other`.
+    fn committed() -> (PathBuf, Repository, Hash) {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        input_output::write(root.join("file"), "file\n").unwrap();
+        input_output::write(root.join("dir/other"), "other\n").unwrap();
+        reference_impl::git_add_all(&repository);
+        let commit = reference_impl::git_commit(&repository, "first", &[]);
+        let commit = commit.parse().unwrap();
+        refs::update(&root, "refs/heads/master", &commit, None).unwrap();
+        (root, repository, commit)
+    }
+
+    #[test]
+    fn test_check() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        input_output::write(root.join("file"), "file\n").unwrap();
+        input_output::write(root.join("dir/other"), "other\n").unwrap();
+        reference_impl::git_add_all(&repository);
+        let commit = reference_impl::git_commit(&repository, "first", &[]);
+        refs::update(&root, "refs/heads/master", &commit.parse().unwrap(), None).unwrap();
+
+        let report = fsck::check(&root, &options()).unwrap();
+        assert!(report.out.is_empty());
+        assert!(report.err.is_empty());
+        assert_eq!(report.errors, 0);
+
+        // A blob nothing points to is dangling.
+        let dangling = object::write_raw(&root, "blob", b"dangling\n").unwrap();
+        let report = fsck::check(&root, &options()).unwrap();
+        assert_eq!(report.out, [format!("dangling blob {}", dangling)]);
+        assert_eq!(report.errors, 0);
+
+        // A tree with unsorted entries and a dotdot name, and a committed
+        // file whose content does not match its name anymore.
+        let file = revision::resolve(&root, "master:file").unwrap();
+        let mut tree = vec![];
+        for name in ["b", ".."] {
+            tree.extend_from_slice(format!("100644 {}\0", name).as_bytes());
+            tree.extend_from_slice(&file.bytes());
+        }
+        let tree = object::write_raw(&root, "tree", &tree).unwrap();
+        let path = input_output::get_obj_path(&root, &file.to_string());
+        fs::remove_file(&path).unwrap();
+        fs::copy(
+            input_output::get_obj_path(&root, &dangling.to_string()),
+            &path,
+        )
+        .unwrap();
+
+        let report = fsck::check(&root, &options()).unwrap();
+        let mut err = report.err.clone();
+        err.sort();
+        assert_eq!(
+            err,
+            [
+                format!(
+                    "error in tree {}: treeNotSorted: not properly sorted",
+                    tree
+                ),
+                format!(
+                    "error: {}: hash-path mismatch, found at: .git/objects/f7/3f3093ff865c514c6c51f867e35f693487d0d3",
+                    dangling
+                ),
+                format!("warning in tree {}: hasDotdot: contains '..'", tree),
+            ]
+        );
+        let mut expected = vec![
+            format!("dangling blob {}", dangling),
+            format!("dangling tree {}", tree),
+            format!("missing blob {}", file),
+        ];
+        expected.sort_by_key(|line| line.rsplit(' ').next().unwrap().to_string());
+        assert_eq!(report.out, expected);
+        assert_eq!(report.errors, fsck::ERROR_OBJECT | fsck::ERROR_REACHABLE);
+    }
+
+    #[test]
+    fn test_check_hash_mismatch() {
+        let (root, _repository, _) = committed();
+        let one = object::write_raw(&root, "blob", b"one\n").unwrap();
+        let two = object::write_raw(&root, "blob", b"two\n").unwrap();
+        let path = input_output::get_obj_path(&root, &one.to_string());
+        fs::remove_file(&path).unwrap();
+        fs::copy(input_output::get_obj_path(&root, &two.to_string()), &path).unwrap();
+
+        // git fsck exits with 1.
+        let report = fsck::check(&root, &options()).unwrap();
+        assert_eq!(
+            report.err,
+            [format!(
+                "error: {}: hash-path mismatch, found at: {}",
+                two,
+                path.strip_prefix(&root).unwrap().display()
+            )]
+        );
+        assert_eq!(report.out, [format!("dangling blob {}", two)]);
+        assert_eq!(report.errors, fsck::ERROR_OBJECT);
+    }
+
+    #[test]
+    fn test_check_missing() {
+        // A blob of the commit's tree; git fsck exits with 2.
+        let (root, _repository, _) = committed();
+        let file = revision::resolve(&root, "master:file").unwrap();
+        fs::remove_file(input_output::get_obj_path(&root, &file.to_string())).unwrap();
+        let report = fsck::check(&root, &options()).unwrap();
+        assert!(report.err.is_empty());
+        assert_eq!(report.out, [format!("missing blob {}", file)]);
+        assert_eq!(report.errors, fsck::ERROR_REACHABLE);
+
+        // The commit's tree, which the index's cache tree also records; git
+        // fsck exits with 10.
+        let (root, repository, commit) = committed();
+        let mut index = repository.index().unwrap();
+        index.write_tree().unwrap();
+        index.write().unwrap();
+        let tree = revision::resolve(&root, "master^{tree}").unwrap();
+        let dir = revision::resolve(&root, "master:dir").unwrap();
+        fs::remove_file(input_output::get_obj_path(&root, &tree.to_string())).unwrap();
+        let report = fsck::check(&root, &options()).unwrap();
+        assert_eq!(
+            report.err,
+            [format!(
+                "error: {}: invalid sha1 pointer in cache-tree",
+                tree
+            )]
+        );
+        let mut out = report.out.clone();
+        out.sort();
+        assert_eq!(
+            out,
+            [
+                format!(
+                    "broken link from  commit {}\n              to    tree {}",
+                    commit, tree
+                ),
+                format!("dangling tree {}", dir),
+                format!("missing tree {}", tree),
+            ]
+        );
+        assert_eq!(report.errors, fsck::ERROR_REACHABLE | fsck::ERROR_REFS);
+    }
+
+    #[test]
+    fn test_check_dangling_commit() {
+        // git fsck exits with 0.
+        let (root, repository, commit) = committed();
+        let dangling = reference_impl::git_commit(&repository, "second", &[&commit.to_string()]);
+        let report = fsck::check(&root, &options()).unwrap();
+        assert!(report.err.is_empty());
+        assert_eq!(report.out, [format!("dangling commit {}", dangling)]);
+        assert_eq!(report.errors, 0);
+    }
+
+    #[test]
+    fn test_check_corrupt_pack() {
+        let (origin, repository, commit) = committed();
+        let root = origin.join("packed");
+        Repo::new(&root).init().unwrap();
+        reference_impl::git_pack_commit(&repository, &commit.to_string(), &root);
+        refs::update(&root, "refs/heads/main", &commit, None).unwrap();
+        let pack = &pack::packs(&root).unwrap()[0];
+        let display = pack
+            .path()
+            .strip_prefix(&root)
+            .unwrap()
+            .display()
+            .to_string();
+        let corrupt = |hash: &Hash| {
+            let offset = pack.index.find(hash).unwrap().unwrap();
+            let mut bytes = fs::read(pack.path()).unwrap();
+            bytes[offset as usize + 4] ^= 0xff;
+            fs::write(pack.path(), bytes).unwrap();
+            offset
+        };
+        let pack_errors = |offset: u64, hash: &Hash| {
+            [
+                format!("error: {} pack checksum mismatch", display),
+                format!(
+                    "error: index CRC mismatch for object {} from {} at offset {}",
+                    hash, display, offset
+                ),
+                format!(
+                    "error: cannot unpack {} from {} at offset {}",
+                    hash, display, offset
+                ),
+            ]
+        };
+
+        // A blob only the tree links to is there, if broken; git fsck exits
+        // with 4.
+        let file = revision::resolve(&origin, "master:file").unwrap();
+        let offset = corrupt(&file);
+        let report = fsck::check(&root, &options()).unwrap();
+        assert_eq!(report.err, pack_errors(offset, &file));
+        assert!(report.out.is_empty());
+        assert_eq!(report.errors, fsck::ERROR_PACK);
+        assert!(report.fatal.is_none());
+
+        // git dies reading the commit a branch points to, with 128.
+        let offset = corrupt(&commit);
+        let report = fsck::check(&root, &options()).unwrap();
+        assert!(pack_errors(offset, &commit)[1..]
+            .iter()
+            .all(|line| report.err.contains(line)));
+        assert!(matches!(
+            report.fatal,
+            Some(Error::Fatal(message)) if message == format!(
+                "packed object {} (stored in {}) is corrupt",
+                commit, display
+            )
+        ));
+    }
+}
@@ -1,8 +1,13 @@
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    str::FromStr,
+};
 
 use sha1::{Digest, Sha1};
 
-#[derive(Clone, Copy)]
+use crate::error::Error;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Hash([u8; 20]);
 
 impl Hash {
@@ -10,10 +15,19 @@ impl Hash {
         Self(hash)
     }
 
+    pub fn zero() -> Self {
+        Self([0; 20])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 20]
+    }
+
     pub fn bytes(&self) -> [u8; 20] {
         self.0
     }
 
+    #[allow(clippy::self_named_constructors)]
     pub fn hash(bytes: &[u8]) -> Self {
         let mut hasher = Sha1::new();
         hasher.update(bytes);
@@ -22,9 +36,15 @@ impl Hash {
     }
 }
 
-impl From<String> for Hash {
-    fn from(value: String) -> Self {
-        Self::new(hex::decode(value).unwrap().try_into().unwrap())
+impl FromStr for Hash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode(s)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Self::new)
+            .ok_or_else(|| Error::corrupt(format!("invalid object id '{}'", s)))
     }
 }
 
@@ -0,0 +1,284 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{config, config::Config, error::Result, wildmatch::wildmatch, worktree};
+
+/// One line of an ignore file.
+#[derive(Clone)]
+pub struct Pattern {
+    pattern: String,
+    /// The directory of the ignore file, which patterns with a `/` are
+    /// relative to.
+    base: String,
+    /// A `!` pattern, which brings back what an earlier one ignores.
+    pub negated: bool,
+    /// A pattern with a trailing `/`, which only matches directories.
+    dir_only: bool,
+    /// A pattern without a `/`, which matches the last component of a
+    /// path at any depth.
+    basename_only: bool,
+    /// The line as written, without the `!` and trailing spaces.
+    pub text: String,
+    /// The ignore file the pattern comes from, as `check-ignore` shows it.
+    pub source: Rc<str>,
+    pub line: usize,
+}
+
+impl Pattern {
+    /// Parses a line, or returns `None` for blank lines and comments.
+    fn parse(line: &str, base: &str, source: &Rc<str>, number: usize) -> Option<Self> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let text = line.to_string();
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+        Some(Self {
+            pattern: line.strip_prefix('/').unwrap_or(line).to_string(),
+            base: base.to_string(),
+            negated,
+            dir_only,
+            basename_only: !line.contains('/'),
+            text,
+            source: source.clone(),
+            line: number,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.basename_only {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            return wildmatch(&self.pattern, name, true, false);
+        }
+        let relative = match self.base.is_empty() {
+            true => Some(path),
+            false => path
+                .strip_prefix(self.base.as_str())
+                .and_then(|rest| rest.strip_prefix('/')),
+        };
+        relative.is_some_and(|relative| wildmatch(&self.pattern, relative, true, false))
+    }
+}
+
+/// Drops trailing spaces unless they are escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    match trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        true => &line[..trimmed.len() + 1],
+        false => trimmed,
+    }
+}
+
+/// The patterns of one ignore file, in file order.
+struct PatternList {
+    patterns: Vec<Pattern>,
+}
+
+impl PatternList {
+    fn parse(content: &str, base: &str, source: &str) -> Self {
+        let source = Rc::from(source);
+        let patterns = content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| Pattern::parse(line, base, &source, i + 1))
+            .collect();
+        Self { patterns }
+    }
+
+    /// Reads an ignore file, where a missing one has no patterns.
+    fn read(path: impl AsRef<Path>, base: &str, source: &str) -> Self {
+        let content = fs::read(path).unwrap_or_default();
+        Self::parse(&String::from_utf8_lossy(&content), base, source)
+    }
+
+    /// The last pattern that matches, which decides.
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<&Pattern> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+    }
+}
+
+/// The rules that decide which untracked files are ignored. The
+/// `.gitignore` of the directory a path is in takes precedence, then the
+/// ones of the directories above it, then `.git/info/exclude`, then
+/// `core.excludesFile`.
+pub struct Ignore {
+    root: PathBuf,
+    /// `.git/info/exclude`, then `core.excludesFile`.
+    excludes: Vec<PatternList>,
+    /// The `.gitignore` of each directory looked at so far, by directory.
+    dirs: RefCell<HashMap<String, Rc<PatternList>>>,
+}
+
+impl Ignore {
+    pub fn load(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        let mut excludes = vec![PatternList::read(
+            root.join(".git/info/exclude"),
+            "",
+            ".git/info/exclude",
+        )];
+        let excludes_file = Config::read(root)?
+            .get_path("core.excludesFile")
+            .or_else(|| config::xdg_path("ignore"));
+        if let Some(path) = excludes_file {
+            excludes.push(PatternList::read(&path, "", &path.display().to_string()));
+        }
+        Ok(Self {
+            root: root.to_path_buf(),
+            excludes,
+            dirs: RefCell::new(HashMap::new()),
+        })
+    }
+
+    fn dir_list(&self, dir: &str) -> Rc<PatternList> {
+        let mut dirs = self.dirs.borrow_mut();
+        let list = dirs.entry(dir.to_string()).or_insert_with(|| {
+            let source = worktree::join_path(dir, ".gitignore");
+            let path = worktree::get_path(&self.root, &source);
+            Rc::new(PatternList::read(path, dir, &source))
+        });
+        list.clone()
+    }
+
+    /// The pattern that decides about a path by itself, not counting the
+    /// directories above it.
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<Pattern> {
+        let mut dir = path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            dir = parent;
+            if let Some(pattern) = self.dir_list(dir).last_match(path, is_dir) {
+                return Some(pattern.clone());
+            }
+        }
+        self.dir_list("")
+            .last_match(path, is_dir)
+            .or_else(|| {
+                self.excludes
+                    .iter()
+                    .find_map(|list| list.last_match(path, is_dir))
+            })
+            .cloned()
+    }
+
+    /// Whether a path is ignored by itself, not counting the directories
+    /// above it.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.last_match(path, is_dir)
+            .is_some_and(|pattern| !pattern.negated)
+    }
+
+    /// The pattern that decides about a path, which is the one that
+    /// ignores a directory above it if there is one, since nothing in an
+    /// ignored directory can be brought back.
+    pub fn check(&self, path: &str, is_dir: bool) -> Option<Pattern> {
+        let mut end = 0;
+        while let Some(i) = path[end..].find('/') {
+            end += i;
+            if let Some(pattern) = self.last_match(&path[..end], true) {
+                if !pattern.negated {
+                    return Some(pattern);
+                }
+            }
+            end += 1;
+        }
+        self.last_match(path, is_dir)
+    }
+
+    /// Whether a path is ignored, by itself or by a directory above it.
+    pub fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        self.check(path, is_dir)
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ignore, PatternList};
+    use crate::{input_output, reference_impl, test_utils};
+
+    fn ignored(patterns: &str, base: &str, path: &str, is_dir: bool) -> bool {
+        let list = PatternList::parse(patterns, base, ".gitignore");
+        list.last_match(path, is_dir)
+            .is_some_and(|pattern| !pattern.negated)
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert!(ignored("*.o", "", "src/main.o", false));
+        assert!(!ignored("# *.o\n", "", "main.o", false));
+        assert!(ignored("target/", "", "sub/target", true));
+        assert!(!ignored("target/", "", "target", false));
+        assert!(ignored("/build", "", "build", true));
+        assert!(!ignored("/build", "", "sub/build", true));
+        assert!(ignored("doc/*.txt", "", "doc/a.txt", false));
+        assert!(!ignored("doc/*.txt", "", "doc/sub/a.txt", false));
+        assert!(ignored("doc/**/*.txt", "", "doc/sub/a.txt", false));
+        assert!(ignored("*.log\n!keep.log", "", "debug.log", false));
+        assert!(!ignored("*.log\n!keep.log", "", "keep.log", false));
+        assert!(ignored("a/b", "sub", "sub/a/b", false));
+        assert!(!ignored("a/b", "sub", "a/b", false));
+        assert!(ignored("trailing\\ ", "", "trailing ", false));
+        assert!(ignored("spaces  ", "", "spaces", false));
+    }
+
+    #[test]
+    fn test_ignore() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        input_output::write(root.join(".gitignore"), "*.o\n!keep.o\nbuild/\n/top\n").unwrap();
+        input_output::write(root.join("sub/.gitignore"), "!*.o\nlocal\n").unwrap();
+        input_output::write(root.join(".git/info/exclude"), "*.tmp\n").unwrap();
+        input_output::write(root.join("build/out"), "").unwrap();
+        input_output::write(root.join("sub/build/out"), "").unwrap();
+
+        let ignore = Ignore::load(&root).unwrap();
+        for path in [
+            "a.o",
+            "keep.o",
+            "dir/b.o",
+            "sub/c.o",
+            "sub/local",
+            "local",
+            "top",
+            "sub/top",
+            "build/out",
+            "sub/build/out",
+            "x.tmp",
+            "sub/x.tmp",
+            "a.c",
+        ] {
+            assert_eq!(
+                ignore.is_excluded(path, false),
+                reference_impl::git_is_ignored(&repository, path),
+                "{}",
+                path
+            );
+        }
+        let pattern = ignore.check("sub/local", false).unwrap();
+        assert_eq!(
+            (&*pattern.source, pattern.line, pattern.text.as_str()),
+            ("sub/.gitignore", 2, "local")
+        );
+    }
+}
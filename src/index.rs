@@ -0,0 +1,535 @@
+use std::{
+    fs::{self, Metadata},
+    io::ErrorKind,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    str::from_utf8,
+};
+
+use crate::{
+    bytes_reader::BytesReader,
+    error::{Error, Result},
+    hash::Hash,
+    lockfile::Lock,
+};
+
+const SIGNATURE: &[u8] = b"DIRC";
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+const EXT_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+const EXT_FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash: Hash,
+    pub assume_valid: bool,
+    pub stage: u8,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    pub path: String,
+}
+
+impl IndexEntry {
+    pub fn new(path: String, hash: Hash, mode: u32) -> Self {
+        Self {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            assume_valid: false,
+            stage: 0,
+            skip_worktree: false,
+            intent_to_add: false,
+            path,
+        }
+    }
+
+    /// Creates an entry whose stat data is taken from `metadata`, so that
+    /// later comparisons can tell an unchanged file without rehashing it.
+    pub fn from_metadata(path: String, hash: Hash, mode: u32, metadata: &Metadata) -> Self {
+        let mut entry = Self::new(path, hash, mode);
+        entry.update_stat(metadata);
+        entry
+    }
+
+    pub fn update_stat(&mut self, metadata: &Metadata) {
+        self.ctime = (metadata.ctime() as u32, metadata.ctime_nsec() as u32);
+        self.mtime = (metadata.mtime() as u32, metadata.mtime_nsec() as u32);
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.size() as u32;
+    }
+
+    /// Whether the file's stat data is still what was recorded, which
+    /// means its content is unchanged unless the entry is racy.
+    pub fn matches_stat(&self, metadata: &Metadata) -> bool {
+        let mut current = self.clone();
+        current.update_stat(metadata);
+        current == *self
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+
+    fn parse(reader: &mut BytesReader, version: u32, previous_path: &str) -> Result<Self> {
+        let start = reader.len();
+        let mut read_u32 =
+            || -> Result<u32> { Ok(u32::from_be_bytes(reader.read_n(4)?.try_into().unwrap())) };
+        let ctime = (read_u32()?, read_u32()?);
+        let mtime = (read_u32()?, read_u32()?);
+        let dev = read_u32()?;
+        let ino = read_u32()?;
+        let mode = read_u32()?;
+        let uid = read_u32()?;
+        let gid = read_u32()?;
+        let size = read_u32()?;
+        let hash = Hash::new(reader.read_n(20)?.try_into().unwrap());
+        let flags = u16::from_be_bytes(reader.read_n(2)?.try_into().unwrap());
+        let extended_flags = if flags & FLAG_EXTENDED != 0 {
+            u16::from_be_bytes(reader.read_n(2)?.try_into().unwrap())
+        } else {
+            0
+        };
+
+        let path = if version >= 4 {
+            let strip = read_varint(reader)?;
+            let suffix = reader.read_until(0)?;
+            reader.skip()?;
+            let keep = previous_path
+                .len()
+                .checked_sub(strip)
+                .ok_or_else(|| Error::corrupt("index uses v4 compression incorrectly"))?;
+            [&previous_path.as_bytes()[..keep], suffix].concat()
+        } else {
+            let path = reader.read_until(0)?.to_vec();
+            // Entries are NUL-padded to a multiple of eight bytes.
+            let len = start - reader.len();
+            reader.read_n(8 - len % 8)?;
+            path
+        };
+        let path =
+            String::from_utf8(path).map_err(|_| Error::corrupt("index entry path is not UTF-8"))?;
+
+        Ok(Self {
+            ctime,
+            mtime,
+            dev,
+            ino,
+            mode,
+            uid,
+            gid,
+            size,
+            hash,
+            assume_valid: flags & FLAG_ASSUME_VALID != 0,
+            stage: ((flags >> FLAG_STAGE_SHIFT) & 0x3) as u8,
+            skip_worktree: extended_flags & EXT_FLAG_SKIP_WORKTREE != 0,
+            intent_to_add: extended_flags & EXT_FLAG_INTENT_TO_ADD != 0,
+            path,
+        })
+    }
+
+    fn encode(&self, version: u32, previous_path: &str) -> Vec<u8> {
+        let mut bytes = vec![];
+        for value in [
+            self.ctime.0,
+            self.ctime.1,
+            self.mtime.0,
+            self.mtime.1,
+            self.dev,
+            self.ino,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.size,
+        ] {
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes.extend(self.hash.bytes());
+
+        let mut flags = (self.path.len().min(FLAG_NAME_MASK as usize)) as u16;
+        flags |= (self.stage as u16) << FLAG_STAGE_SHIFT;
+        if self.assume_valid {
+            flags |= FLAG_ASSUME_VALID;
+        }
+        if self.is_extended() {
+            flags |= FLAG_EXTENDED;
+        }
+        bytes.extend(flags.to_be_bytes());
+        if self.is_extended() {
+            let mut extended_flags = 0;
+            if self.skip_worktree {
+                extended_flags |= EXT_FLAG_SKIP_WORKTREE;
+            }
+            if self.intent_to_add {
+                extended_flags |= EXT_FLAG_INTENT_TO_ADD;
+            }
+            bytes.extend(extended_flags.to_be_bytes());
+        }
+
+        if version >= 4 {
+            let common = common_prefix_len(previous_path, &self.path);
+            bytes.extend(encode_varint(previous_path.len() - common));
+            bytes.extend_from_slice(&self.path.as_bytes()[common..]);
+            bytes.push(0);
+        } else {
+            bytes.extend_from_slice(self.path.as_bytes());
+            let padding = 8 - bytes.len() % 8;
+            bytes.extend(vec![0; padding]);
+        }
+        bytes
+    }
+}
+
+/// A directory in git's cache of the trees of the index, the `TREE`
+/// extension.
+#[derive(Debug)]
+pub struct CacheTree {
+    /// The tree the directory was last written as, unless its entries have
+    /// changed since.
+    pub hash: Option<Hash>,
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    fn parse(reader: &mut BytesReader) -> Result<Self> {
+        let corrupt = || Error::corrupt("corrupt cache tree");
+        reader.read_until(0)?;
+        reader.skip()?;
+        let counts = reader.read_until(b'\n')?;
+        reader.skip()?;
+        let (entries, subtrees) = from_utf8(counts)
+            .ok()
+            .and_then(|counts| counts.split_once(' '))
+            .ok_or_else(corrupt)?;
+        let entries: i64 = entries.parse().map_err(|_| corrupt())?;
+        let subtrees: usize = subtrees.parse().map_err(|_| corrupt())?;
+        let hash = match entries >= 0 {
+            true => Some(Hash::new(reader.read_n(20)?.try_into().unwrap())),
+            false => None,
+        };
+        let subtrees = (0..subtrees)
+            .map(|_| Self::parse(reader))
+            .collect::<Result<_>>()?;
+        Ok(Self { hash, subtrees })
+    }
+}
+
+/// The staging area stored in `.git/index`.
+#[derive(Debug)]
+pub struct Index {
+    pub version: u32,
+    entries: Vec<IndexEntry>,
+    cache_tree: Option<CacheTree>,
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Self {
+            version: 2,
+            entries: vec![],
+            cache_tree: None,
+        }
+    }
+
+    pub fn get_path(root: impl AsRef<Path>) -> PathBuf {
+        root.as_ref().join(".git").join("index")
+    }
+
+    /// Reads the index of the repository, or returns an empty one if there is
+    /// none yet.
+    pub fn read(root: impl AsRef<Path>) -> Result<Self> {
+        let path = Self::get_path(root);
+        match fs::read(&path) {
+            Ok(bytes) => Self::parse(&bytes),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(Error::io("could not read", path, e)),
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 32 {
+            return Err(Error::corrupt("index file smaller than expected"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 20);
+        if Hash::hash(body).bytes() != checksum {
+            return Err(Error::corrupt("index file corrupt"));
+        }
+
+        let mut reader = BytesReader::new(body);
+        if reader.read_n(4)? != SIGNATURE {
+            return Err(Error::corrupt("index file corrupt"));
+        }
+        let mut read_u32 =
+            || -> Result<u32> { Ok(u32::from_be_bytes(reader.read_n(4)?.try_into().unwrap())) };
+        let version = read_u32()?;
+        if !(2..=4).contains(&version) {
+            return Err(Error::corrupt(format!(
+                "index file version {} is not supported",
+                version
+            )));
+        }
+        let count = read_u32()?;
+
+        let mut entries: Vec<IndexEntry> = vec![];
+        for _ in 0..count {
+            let previous_path = entries.last().map_or("", |e| e.path.as_str());
+            let entry = IndexEntry::parse(&mut reader, version, previous_path)?;
+            entries.push(entry);
+        }
+
+        // Extensions are caches that we do not maintain; dropping them makes
+        // git rebuild them. Mandatory ones (lowercase signature) change the
+        // meaning of the entries, so they cannot be ignored. The cache tree
+        // is read for fsck to check, and left out if it cannot be.
+        let mut cache_tree = None;
+        while !reader.is_at_end() {
+            let signature = reader.read_n(4)?;
+            if !signature[0].is_ascii_uppercase() {
+                return Err(Error::corrupt(format!(
+                    "index uses {} extension, which we do not understand",
+                    String::from_utf8_lossy(signature)
+                )));
+            }
+            let len = u32::from_be_bytes(reader.read_n(4)?.try_into().unwrap());
+            let data = reader.read_n(len as usize)?;
+            if signature == b"TREE" {
+                cache_tree = CacheTree::parse(&mut BytesReader::new(data)).ok();
+            }
+        }
+
+        Ok(Self {
+            version,
+            entries,
+            cache_tree,
+        })
+    }
+
+    /// The cache tree as the index file recorded it. Changes made to the
+    /// index since do not update it, and it is not written back.
+    pub fn cache_tree(&self) -> Option<&CacheTree> {
+        self.cache_tree.as_ref()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let version = if self.version == 2 && self.entries.iter().any(|e| e.is_extended()) {
+            3
+        } else {
+            self.version
+        };
+
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(version.to_be_bytes());
+        bytes.extend((self.entries.len() as u32).to_be_bytes());
+        let mut previous_path = "";
+        for entry in &self.entries {
+            bytes.extend(entry.encode(version, previous_path));
+            previous_path = &entry.path;
+        }
+        let checksum = Hash::hash(&bytes);
+        bytes.extend(checksum.bytes());
+        bytes
+    }
+
+    /// Writes the index through `index.lock` so that readers never observe a
+    /// partially written file.
+    pub fn write(&self, root: impl AsRef<Path>) -> Result<()> {
+        let lock = Lock::acquire(Self::get_path(root))?;
+        lock.commit(self.serialize())
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.find(path, 0).ok().map(|i| &self.entries[i])
+    }
+
+    fn find(&self, path: &str, stage: u8) -> std::result::Result<usize, usize> {
+        self.entries
+            .binary_search_by(|e| (e.path.as_str(), e.stage).cmp(&(path, stage)))
+    }
+
+    /// Adds or replaces the entry for its path and stage. Like git, a
+    /// resolved entry replaces the conflicting stages of its path.
+    pub fn add(&mut self, entry: IndexEntry) {
+        if entry.stage == 0 {
+            self.entries
+                .retain(|e| e.path != entry.path || e.stage == 0);
+        }
+        match self.find(&entry.path, entry.stage) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Removes all stages of `path`. Returns whether anything was removed.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.path != path);
+        self.entries.len() != before
+    }
+
+    /// Returns the tracked paths equal to or below `pathspec`, where the
+    /// empty pathspec matches everything.
+    pub fn matching(&self, pathspec: &str) -> Vec<String> {
+        let mut paths: Vec<_> = self
+            .entries
+            .iter()
+            .map(|e| e.path.clone())
+            .filter(|path| matches_pathspec(path, pathspec))
+            .collect();
+        paths.dedup();
+        paths
+    }
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn matches_pathspec(path: &str, pathspec: &str) -> bool {
+    pathspec.is_empty()
+        || path == pathspec
+        || path
+            .strip_prefix(pathspec)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count()
+}
+
+/// Reads the offset-style varint used for v4 path prefix lengths, where each
+/// continuation adds one before shifting so that encodings are unique.
+fn read_varint(reader: &mut BytesReader) -> Result<usize> {
+    let mut byte = reader.read()?;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = reader.read()?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+    Ok(value)
+}
+
+fn encode_varint(mut value: usize) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{file_mode::FileMode, input_output, reference_impl, test_utils};
+
+    use super::{Index, IndexEntry};
+
+    fn create_files(root: impl AsRef<std::path::Path>) {
+        let root = root.as_ref();
+        input_output::write(root.join("file1"), "one").unwrap();
+        input_output::write(root.join("dir1/file_in_dir_1"), "two").unwrap();
+        input_output::write(root.join("dir1/file_in_dir_2"), "three").unwrap();
+        input_output::write_with_mode(root.join("dir2/script.sh"), "four", FileMode::Executable)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_read_index() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        create_files(&root);
+        reference_impl::git_add_all(&repository);
+
+        let index = Index::read(&root).unwrap();
+        let paths: Vec<_> = index.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "dir1/file_in_dir_1",
+                "dir1/file_in_dir_2",
+                "dir2/script.sh",
+                "file1"
+            ]
+        );
+        let script = &index.entries()[2];
+        assert_eq!(script.mode, 0o100755);
+        assert_eq!(script.size, 4);
+        assert_eq!(
+            script.hash.to_string(),
+            reference_impl::git_index_hash(&repository, "dir2/script.sh")
+        );
+    }
+
+    #[test]
+    fn test_write_index_round_trip() {
+        for version in [2, 4] {
+            let root = test_utils::create_test_dir();
+            let repository = reference_impl::create_repository(&root);
+            create_files(&root);
+            reference_impl::git_add_all(&repository);
+            reference_impl::git_set_index_version(&repository, version);
+
+            let want = fs::read(Index::get_path(&root)).unwrap();
+            let index = Index::parse(&want).unwrap();
+            assert_eq!(index.version, version);
+            assert_eq!(index.serialize(), want);
+        }
+    }
+
+    #[test]
+    fn test_write_index_read_by_git() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        create_files(&root);
+        reference_impl::git_add_all(&repository);
+
+        let mut index = Index::read(&root).unwrap();
+        let hash = index.entries()[0].hash;
+        let mut entry = IndexEntry::new(String::from("dir1/added"), hash, 0o100644);
+        entry.intent_to_add = true;
+        index.add(entry);
+        index.write(&root).unwrap();
+
+        let paths = reference_impl::git_index_paths(&repository);
+        assert_eq!(
+            paths,
+            [
+                "dir1/added",
+                "dir1/file_in_dir_1",
+                "dir1/file_in_dir_2",
+                "dir2/script.sh",
+                "file1"
+            ]
+        );
+        assert_eq!(Index::read(&root).unwrap().version, 3);
+    }
+}
@@ -1,17 +1,55 @@
 use std::{
     fs,
-    os::unix::fs::PermissionsExt,
+    os::unix::fs::{symlink, PermissionsExt},
     path::{Path, PathBuf},
 };
 
-pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) {
-    if let Some(parent) = path.as_ref().parent() {
-        fs::create_dir_all(parent).unwrap();
+use crate::{
+    error::{Error, Result},
+    file_mode::FileMode,
+};
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| Error::io("could not create directory", parent, e))?;
+    }
+    Ok(())
+}
+
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+    let path = path.as_ref();
+    create_parent(path)?;
+    fs::write(path, contents).map_err(|e| Error::io("could not write", path, e))
+}
+
+/// Writes `contents` to `path` as a file with the given git mode, which for
+/// symlinks means `contents` is the link target.
+pub fn write_with_mode<P: AsRef<Path>, C: AsRef<[u8]>>(
+    path: P,
+    contents: C,
+    mode: FileMode,
+) -> Result<()> {
+    let path = path.as_ref();
+    create_parent(path)?;
+    let _ = fs::remove_file(path);
+    if mode == FileMode::Symlink {
+        let target = String::from_utf8_lossy(contents.as_ref()).into_owned();
+        return symlink(target, path).map_err(|e| Error::io("could not create symlink", path, e));
+    }
+    write(path, contents)?;
+    if mode == FileMode::Executable {
+        let mut permissions = fs::metadata(path)
+            .map_err(|e| Error::io("could not stat", path, e))?
+            .permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        fs::set_permissions(path, permissions)
+            .map_err(|e| Error::io("could not change mode of", path, e))?;
     }
-    fs::write(path, contents).unwrap()
+    Ok(())
 }
 
-fn get_obj_path(root: impl AsRef<Path>, hash: &str) -> PathBuf {
+pub fn get_obj_path(root: impl AsRef<Path>, hash: &str) -> PathBuf {
     root.as_ref()
         .join(".git")
         .join("objects")
@@ -19,46 +57,86 @@ fn get_obj_path(root: impl AsRef<Path>, hash: &str) -> PathBuf {
         .join(&hash[2..])
 }
 
-pub fn read_obj(root: impl AsRef<Path>, hash: &str) -> Vec<u8> {
-    fs::read(get_obj_path(root, hash)).unwrap()
+pub fn obj_exists(root: impl AsRef<Path>, hash: &str) -> bool {
+    hash.len() > 2 && get_obj_path(root, hash).exists()
 }
 
-pub fn write_obj(root: impl AsRef<Path>, hash: &str, contents: &[u8]) {
-    write(get_obj_path(root, hash), contents);
+/// Returns the loose objects whose hash starts with `prefix`, which must be
+/// at least two hex digits long.
+pub fn find_objs(root: impl AsRef<Path>, prefix: &str) -> Result<Vec<String>> {
+    let dir = root
+        .as_ref()
+        .join(".git")
+        .join("objects")
+        .join(&prefix[..2]);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    Ok(read_dir_sorted(&dir)?
+        .iter()
+        .map(|path| format!("{}{}", &prefix[..2], basename(path)))
+        .filter(|hash| hash.len() == 40 && hash.starts_with(prefix))
+        .collect())
 }
 
-pub fn read_dir_sorted(path: impl AsRef<Path>) -> Vec<PathBuf> {
-    let mut paths: Vec<_> = fs::read_dir(path)
-        .unwrap()
-        .map(|e| e.unwrap().path())
-        .collect();
+/// Returns all loose objects, sorted.
+pub fn list_objs(root: impl AsRef<Path>) -> Result<Vec<String>> {
+    let dir = root.as_ref().join(".git").join("objects");
+    let mut hashes = vec![];
+    for fanout in read_dir_sorted(&dir)? {
+        let prefix = basename(&fanout);
+        if prefix.len() != 2 || !fanout.is_dir() {
+            continue;
+        }
+        hashes.extend(find_objs(&root, &prefix)?);
+    }
+    Ok(hashes)
+}
+
+pub fn read_obj(root: impl AsRef<Path>, hash: &str) -> Result<Vec<u8>> {
+    let path = get_obj_path(root, hash);
+    fs::read(&path).map_err(|e| Error::io("could not read", path, e))
+}
+
+pub fn write_obj(root: impl AsRef<Path>, hash: &str, contents: &[u8]) -> Result<()> {
+    write(get_obj_path(root, hash), contents)
+}
+
+pub fn read_dir_sorted(path: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let error = |e| Error::io("could not open directory", path, e);
+    let mut paths = fs::read_dir(path)
+        .map_err(error)?
+        .map(|e| e.map(|e| e.path()).map_err(error))
+        .collect::<Result<Vec<_>>>()?;
     paths.sort();
-    paths
+    Ok(paths)
 }
 
 pub fn basename(path: impl AsRef<Path>) -> String {
     path.as_ref()
         .components()
-        .last()
-        .unwrap()
-        .as_os_str()
-        .to_string_lossy()
-        .into_owned()
+        .next_back()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .unwrap_or_default()
 }
 
-pub fn get_mode(path: impl AsRef<Path>) -> u32 {
-    let metadata = fs::symlink_metadata(&path).unwrap();
-    if metadata.is_symlink() {
-        0o120000
-    } else if is_executable(&path) {
-        0o100755
+pub fn get_mode(path: impl AsRef<Path>) -> Result<FileMode> {
+    let path = path.as_ref();
+    let metadata = fs::symlink_metadata(path).map_err(|e| Error::io("could not stat", path, e))?;
+    Ok(if metadata.is_symlink() {
+        FileMode::Symlink
+    } else if is_executable(path)? {
+        FileMode::Executable
     } else {
-        0o100644
-    }
+        FileMode::Blob
+    })
 }
 
-fn is_executable(path: impl AsRef<Path>) -> bool {
-    fs::symlink_metadata(path).unwrap().permissions().mode() & 0o100 != 0
+fn is_executable(path: impl AsRef<Path>) -> Result<bool> {
+    let path = path.as_ref();
+    let metadata = fs::symlink_metadata(path).map_err(|e| Error::io("could not stat", path, e))?;
+    Ok(metadata.permissions().mode() & 0o100 != 0)
 }
 
 #[cfg(test)]
@@ -82,7 +160,7 @@ mod tests {
         let path = root.join("file");
         let _f = File::create(&path).unwrap();
         // println!("{:o}", _f.metadata().unwrap().permissions().mode());
-        assert!(!is_executable(&path));
+        assert!(!is_executable(&path).unwrap());
     }
 
     #[test]
@@ -94,6 +172,6 @@ mod tests {
         p.set_mode(p.mode() | 0o100);
         f.set_permissions(p).unwrap();
         // println!("{:o}", f.metadata().unwrap().permissions().mode());
-        assert!(is_executable(&path));
+        assert!(is_executable(&path).unwrap());
     }
 }
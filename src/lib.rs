@@ -1,21 +1,54 @@
-use clap::{Parser, Subcommand};
+use std::{env, path::PathBuf, process::ExitCode};
 
+use clap::{Args, Parser, Subcommand};
+
+use error::{Error, Result};
 use repo::Repo;
 
 mod blob;
 mod bytes_reader;
+mod checkout;
 mod codec;
 mod commit;
+mod config;
+mod delta;
+mod error;
+mod file_mode;
+mod fsck;
 mod hash;
+mod ignore;
+mod index;
 mod input_output;
+mod line_diff;
+mod line_merge;
+mod lockfile;
+mod log;
+mod merge;
+mod merge_base;
 mod object;
+mod pack;
+mod pack_index;
+mod pack_writer;
+mod patch;
+mod pkt_line;
+mod reachable;
 #[cfg(test)]
 mod reference_impl;
+mod refs;
+mod remote;
 mod repo;
+mod revision;
+mod revwalk;
+mod signature;
+mod status;
+mod tag;
 #[cfg(test)]
 mod test_utils;
 mod tree;
+mod tree_diff;
 mod tree_node;
+mod wildmatch;
+mod worktree;
 
 #[derive(Parser)]
 struct Cli {
@@ -42,40 +75,857 @@ enum Commands {
         tree_ish: String,
     },
     WriteTree,
+    Add {
+        #[arg(short = 'A', long)]
+        all: bool,
+        #[arg(short, long)]
+        update: bool,
+        #[arg(short, long)]
+        force: bool,
+        pathspec: Vec<String>,
+    },
+    Rm {
+        #[arg(long)]
+        cached: bool,
+        #[arg(short)]
+        recursive: bool,
+        #[arg(short, long)]
+        force: bool,
+        #[arg(required = true)]
+        pathspec: Vec<String>,
+    },
+    LsFiles {
+        #[arg(short, long)]
+        stage: bool,
+    },
     CommitTree {
         #[arg(short)]
-        parent: String,
+        parent: Vec<String>,
         #[arg(short)]
-        message: String,
+        message: Vec<String>,
+        #[arg(short = 'F')]
+        file: Vec<String>,
         tree: String,
     },
+    Commit {
+        #[arg(short, long)]
+        message: Vec<String>,
+        #[arg(long)]
+        allow_empty: bool,
+    },
+    UpdateRef {
+        #[arg(short)]
+        delete: bool,
+        name: String,
+        #[arg(num_args = 0..=2)]
+        values: Vec<String>,
+    },
+    SymbolicRef {
+        name: String,
+        target: Option<String>,
+    },
+    ShowRef {
+        #[arg(long)]
+        head: bool,
+        patterns: Vec<String>,
+    },
+    Log {
+        #[arg(long)]
+        oneline: bool,
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        #[arg(long, alias = "pretty")]
+        format: Option<String>,
+        #[arg(long)]
+        graph: bool,
+        #[arg(long)]
+        first_parent: bool,
+        #[arg(long)]
+        author: Option<String>,
+        #[arg(long)]
+        grep: Option<String>,
+        #[arg(long, alias = "after")]
+        since: Option<String>,
+        #[arg(long, alias = "before")]
+        until: Option<String>,
+        #[command(flatten)]
+        diff: DiffArgs,
+        revisions: Vec<String>,
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    Diff {
+        #[arg(long, alias = "staged")]
+        cached: bool,
+        #[command(flatten)]
+        diff: DiffArgs,
+        revisions: Vec<String>,
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    Show {
+        #[command(flatten)]
+        diff: DiffArgs,
+        objects: Vec<String>,
+    },
+    Status {
+        #[arg(short, long, group = "status_format")]
+        short: bool,
+        #[arg(
+            long,
+            group = "status_format",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "v1"
+        )]
+        porcelain: Option<String>,
+        #[arg(short, long)]
+        branch: bool,
+        #[arg(
+            short = 'u',
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "all"
+        )]
+        untracked_files: Option<String>,
+    },
+    DiffTree {
+        #[arg(short)]
+        recursive: bool,
+        #[arg(long, group = "output")]
+        raw: bool,
+        #[arg(long, group = "output")]
+        name_status: bool,
+        #[arg(long, group = "output")]
+        name_only: bool,
+        #[arg(
+            short = 'M',
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "",
+            overrides_with = "find_copies"
+        )]
+        find_renames: Option<String>,
+        #[arg(
+            short = 'C',
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "",
+            overrides_with = "find_renames"
+        )]
+        find_copies: Option<String>,
+        #[arg(long)]
+        root: bool,
+        #[arg(required = true, num_args = 1..=2)]
+        trees: Vec<String>,
+    },
+    RevParse {
+        #[arg(long)]
+        verify: bool,
+        #[arg(short, long)]
+        quiet: bool,
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        short: Option<usize>,
+        #[arg(long)]
+        abbrev_ref: bool,
+        revs: Vec<String>,
+    },
+    Tag {
+        #[arg(short, long, group = "mode")]
+        list: bool,
+        #[arg(short, long, group = "mode")]
+        delete: bool,
+        #[arg(short)]
+        annotate: bool,
+        #[arg(short, long)]
+        force: bool,
+        #[arg(short)]
+        message: Vec<String>,
+        #[arg(short = 'F')]
+        file: Option<String>,
+        args: Vec<String>,
+    },
+    Clone {
+        repository: String,
+        directory: Option<String>,
+    },
+    Config {
+        #[arg(long, group = "action")]
+        get: bool,
+        #[arg(long, group = "action")]
+        get_all: bool,
+        #[arg(long, group = "action")]
+        set: bool,
+        #[arg(long, group = "action")]
+        add: bool,
+        #[arg(long, group = "action")]
+        unset: bool,
+        #[arg(long, group = "action")]
+        unset_all: bool,
+        #[arg(short, long, group = "action")]
+        list: bool,
+        #[arg(long, group = "location")]
+        system: bool,
+        #[arg(long, group = "location")]
+        global: bool,
+        #[arg(long, group = "location")]
+        local: bool,
+        #[arg(short, long, group = "location")]
+        file: Option<PathBuf>,
+        #[arg(long = "type", group = "type", value_parser = ["bool", "int"])]
+        value_type: Option<String>,
+        #[arg(long, group = "type")]
+        bool: bool,
+        #[arg(long, group = "type")]
+        int: bool,
+        #[arg(long)]
+        show_origin: bool,
+        #[arg(long)]
+        show_scope: bool,
+        name: Option<String>,
+        value: Option<String>,
+    },
+    CheckIgnore {
+        #[arg(short, long)]
+        verbose: bool,
+        #[arg(short, long)]
+        quiet: bool,
+        #[arg(short, long)]
+        non_matching: bool,
+        #[arg(long)]
+        no_index: bool,
+        paths: Vec<String>,
+    },
+    Checkout {
+        #[arg(short = 'b')]
+        new_branch: Option<String>,
+        #[arg(long)]
+        detach: bool,
+        #[arg(short, long)]
+        force: bool,
+        #[arg(short, long)]
+        quiet: bool,
+        target: Option<String>,
+    },
+    Switch {
+        #[arg(short, long)]
+        create: Option<String>,
+        #[arg(short, long)]
+        detach: bool,
+        #[arg(short, long, alias = "discard-changes")]
+        force: bool,
+        #[arg(short, long)]
+        quiet: bool,
+        target: Option<String>,
+    },
+    MergeBase {
+        #[arg(short, long)]
+        all: bool,
+        #[arg(long)]
+        octopus: bool,
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+    Merge {
+        #[arg(short, long)]
+        message: Vec<String>,
+        #[arg(long, conflicts_with = "ff_only")]
+        no_ff: bool,
+        #[arg(long)]
+        ff_only: bool,
+        #[arg(long)]
+        no_commit: bool,
+        #[arg(long, conflicts_with_all = ["message", "no_ff", "ff_only", "no_commit", "commit"])]
+        abort: bool,
+        commit: Option<String>,
+    },
+    PackObjects {
+        #[arg(long)]
+        stdout: bool,
+        #[arg(short, long)]
+        quiet: bool,
+        #[arg(long, default_value_t = pack_writer::DEFAULT_WINDOW)]
+        window: usize,
+        #[arg(long, default_value_t = pack_writer::DEFAULT_DEPTH)]
+        depth: usize,
+        #[arg(required_unless_present = "stdout", conflicts_with = "stdout")]
+        base_name: Option<String>,
+    },
+    Repack {
+        #[arg(short = 'a')]
+        all: bool,
+        #[arg(short = 'A')]
+        all_loosen: bool,
+        #[arg(short = 'd')]
+        delete: bool,
+        #[arg(short, long)]
+        quiet: bool,
+        #[arg(long, default_value_t = pack_writer::DEFAULT_WINDOW)]
+        window: usize,
+        #[arg(long, default_value_t = pack_writer::DEFAULT_DEPTH)]
+        depth: usize,
+    },
+    Prune {
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        #[arg(short, long)]
+        verbose: bool,
+        #[arg(long)]
+        expire: Option<String>,
+    },
+    PackRefs {
+        #[arg(long)]
+        all: bool,
+        #[arg(long)]
+        no_prune: bool,
+    },
+    Gc {
+        #[arg(long)]
+        auto: bool,
+        #[arg(long)]
+        aggressive: bool,
+        #[arg(
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "now",
+            conflicts_with = "no_prune"
+        )]
+        prune: Option<String>,
+        #[arg(long)]
+        no_prune: bool,
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    Fsck {
+        #[arg(long)]
+        unreachable: bool,
+        #[arg(long)]
+        no_dangling: bool,
+        #[arg(long)]
+        strict: bool,
+    },
 }
 
-pub fn run() {
-    let cli = Cli::parse();
-    let repo = Repo::new_current_dir();
+/// The options of `diff`, `show` and `log` that choose how changes are
+/// shown.
+#[derive(Args)]
+struct DiffArgs {
+    #[arg(short = 'p', long, group = "diff_output")]
+    patch: bool,
+    #[arg(long, group = "diff_output")]
+    stat: bool,
+    #[arg(long, group = "diff_output")]
+    numstat: bool,
+    #[arg(long, group = "diff_output")]
+    name_only: bool,
+    #[arg(long, group = "diff_output")]
+    name_status: bool,
+    #[arg(short = 'U', long)]
+    unified: Option<usize>,
+    #[arg(long, group = "algorithm")]
+    minimal: bool,
+    #[arg(long, group = "algorithm")]
+    patience: bool,
+    #[arg(long, group = "algorithm")]
+    histogram: bool,
+    #[arg(long, group = "algorithm")]
+    diff_algorithm: Option<String>,
+    #[arg(long)]
+    no_renames: bool,
+    #[arg(
+        short = 'M',
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    find_renames: Option<String>,
+}
 
-    match cli.command {
-        Commands::Init => {
-            repo.init();
-        }
-        Commands::CatFile { object, .. } => {
-            repo.cat_file(&object);
-        }
-        Commands::HashObject { file, .. } => {
-            repo.hash_object(&file);
+impl DiffArgs {
+    /// The options for showing changes, or `None` if none of the outputs
+    /// was asked for and patches are not shown by default. Like git, renames
+    /// are detected unless `--no-renames` is given.
+    fn into_options(self, patch_by_default: bool) -> Result<Option<patch::Options>> {
+        let output = if self.stat {
+            patch::Output::Stat
+        } else if self.numstat {
+            patch::Output::Numstat
+        } else if self.name_only {
+            patch::Output::NameOnly
+        } else if self.name_status {
+            patch::Output::NameStatus
+        } else if self.patch || self.unified.is_some() || patch_by_default {
+            patch::Output::Patch
+        } else {
+            return Ok(None);
+        };
+        let algorithm = if self.minimal {
+            line_diff::Algorithm::Minimal
+        } else if self.patience {
+            line_diff::Algorithm::Patience
+        } else if self.histogram {
+            line_diff::Algorithm::Histogram
+        } else if let Some(name) = self.diff_algorithm {
+            line_diff::Algorithm::parse(&name).ok_or_else(|| {
+                Error::Usage(String::from(
+                    "option diff-algorithm accepts \"myers\", \"minimal\", \"patience\" and \"histogram\"",
+                ))
+            })?
+        } else {
+            line_diff::Algorithm::default()
+        };
+        let renames = match self.no_renames {
+            true => None,
+            false => {
+                Some(parse_find_score(self.find_renames)?.unwrap_or(tree_diff::DEFAULT_MIN_SCORE))
+            }
+        };
+        Ok(Some(patch::Options {
+            output,
+            context: self.unified.unwrap_or(3),
+            algorithm,
+            renames,
+            prefix_width: 0,
+        }))
+    }
+}
+
+pub fn run() -> ExitCode {
+    let cli = Cli::parse_from(env::args().map(attach_optional_value));
+    match execute(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            if let Some(report) = error.report() {
+                eprintln!("{}", report);
+            }
+            ExitCode::from(error.exit_code())
         }
+    }
+}
+
+/// clap only takes the optional values of short options after `=`, while
+/// git writes them right after the flag, as in `-M90%` or `-uno`.
+fn attach_optional_value(arg: String) -> String {
+    let is_score = |rest: &str| rest.starts_with(|c: char| c.is_ascii_digit());
+    let is_untracked_mode = |rest: &str| status::Untracked::parse(rest).is_some();
+    match (arg.get(..2), arg.get(2..)) {
+        (Some("-M" | "-C"), Some(rest)) if is_score(rest) => format!("{}={}", &arg[..2], rest),
+        (Some("-u"), Some(rest)) if is_untracked_mode(rest) => format!("-u={}", rest),
+        _ => arg,
+    }
+}
+
+/// Parses the optional similarity of `-M` and `-C`, which defaults to
+/// git's 50%.
+fn parse_find_score(value: Option<String>) -> Result<Option<u32>> {
+    value
+        .map(|value| match value.as_str() {
+            "" => Ok(tree_diff::DEFAULT_MIN_SCORE),
+            _ => tree_diff::parse_score(&value)
+                .ok_or_else(|| Error::Usage(format!("invalid argument '{}'", value))),
+        })
+        .transpose()
+}
+
+fn execute(command: Commands) -> Result<()> {
+    let repo = Repo::new_current_dir()?;
+
+    match command {
+        Commands::Init => repo.init(),
+        Commands::CatFile { object, .. } => repo.cat_file(&object),
+        Commands::HashObject { file, .. } => repo.hash_object(&file),
         Commands::LsTree {
             name_only,
             tree_ish,
-        } => {
-            repo.ls_tree(name_only, &tree_ish);
-        }
+        } => repo.ls_tree(name_only, &tree_ish),
         Commands::WriteTree => repo.write_tree(),
+        Commands::Add {
+            all,
+            update,
+            force,
+            pathspec,
+        } => repo.add(pathspec, all, update, force),
+        Commands::Rm {
+            cached,
+            recursive,
+            force,
+            pathspec,
+        } => repo.rm(pathspec, cached, recursive, force),
+        Commands::LsFiles { stage } => repo.ls_files(stage),
         Commands::CommitTree {
             parent,
             message,
+            file,
             tree,
-        } => repo.commit_tree(tree, parent, message),
+        } => repo.commit_tree(tree, parent, message, file),
+        Commands::Commit {
+            message,
+            allow_empty,
+        } => repo.commit(message, allow_empty),
+        Commands::UpdateRef {
+            delete,
+            name,
+            values,
+        } => repo.update_ref(delete, &name, values),
+        Commands::SymbolicRef { name, target } => repo.symbolic_ref(&name, target),
+        Commands::ShowRef { head, patterns } => repo.show_ref(head, patterns),
+        Commands::Log {
+            oneline,
+            max_count,
+            format,
+            graph,
+            first_parent,
+            author,
+            grep,
+            since,
+            until,
+            diff,
+            revisions,
+            paths,
+        } => {
+            let format = match format {
+                Some(format) => log::Format::parse(&format),
+                None if oneline => log::Format::Oneline(String::from("%h %s")),
+                None => log::Format::Medium,
+            };
+            let date = |date: Option<String>| {
+                date.map(|date| {
+                    signature::parse_approxidate(&date)
+                        .ok_or_else(|| Error::fatal(format!("invalid date format: {}", date)))
+                })
+                .transpose()
+            };
+            repo.log(log::Options {
+                revisions,
+                paths,
+                format,
+                max_count,
+                graph,
+                first_parent,
+                author,
+                grep,
+                since: date(since)?,
+                until: date(until)?,
+                diff: diff.into_options(false)?,
+            })
+        }
+        Commands::Diff {
+            cached,
+            diff,
+            revisions,
+            paths,
+        } => {
+            let options = diff.into_options(true)?.unwrap();
+            repo.diff(revisions, cached, paths, options)
+        }
+        Commands::Show { diff, objects } => repo.show(objects, diff.into_options(true)?.unwrap()),
+        Commands::Status {
+            short,
+            porcelain,
+            branch,
+            untracked_files,
+        } => {
+            let format = match porcelain.as_deref() {
+                Some("v1") => status::Format::Porcelain,
+                Some("v2") => status::Format::PorcelainV2,
+                Some(version) => {
+                    return Err(Error::fatal(format!(
+                        "unsupported porcelain version '{}'",
+                        version
+                    )))
+                }
+                None if short => status::Format::Short,
+                None => status::Format::Long,
+            };
+            let untracked = match untracked_files {
+                Some(value) => status::Untracked::parse(&value).ok_or_else(|| {
+                    Error::fatal(format!("Invalid untracked files mode '{}'", value))
+                })?,
+                None => status::Untracked::Normal,
+            };
+            repo.status(status::Options {
+                format,
+                branch,
+                untracked,
+            })
+        }
+        Commands::DiffTree {
+            recursive,
+            raw: _,
+            name_status,
+            name_only,
+            find_renames,
+            find_copies,
+            root,
+            trees,
+        } => {
+            let output = if name_status {
+                tree_diff::Output::NameStatus
+            } else if name_only {
+                tree_diff::Output::NameOnly
+            } else {
+                tree_diff::Output::Raw
+            };
+            repo.diff_tree(tree_diff::Options {
+                trees,
+                recursive,
+                output,
+                renames: parse_find_score(find_renames)?,
+                copies: parse_find_score(find_copies)?,
+                root,
+            })
+        }
+        Commands::RevParse {
+            verify,
+            quiet,
+            short,
+            abbrev_ref,
+            revs,
+        } => repo.rev_parse(revs, verify, quiet, short, abbrev_ref),
+        Commands::Tag {
+            list,
+            delete,
+            annotate,
+            force,
+            message,
+            file,
+            mut args,
+        } => {
+            let action = if delete {
+                tag::Action::Delete { names: args }
+            } else if list || args.is_empty() {
+                tag::Action::List { patterns: args }
+            } else if args.len() > 2 {
+                return Err(Error::fatal("too many arguments"));
+            } else {
+                let target = args.get(1).cloned();
+                tag::Action::Create {
+                    name: args.swap_remove(0),
+                    target,
+                    annotate,
+                    messages: message,
+                    file,
+                    force,
+                }
+            };
+            repo.tag(action)
+        }
+        Commands::Clone {
+            repository,
+            directory,
+        } => {
+            let directory = directory.unwrap_or_else(|| remote::default_directory(&repository));
+            repo.clone_remote(&repository, &directory)
+        }
+        Commands::Config {
+            get,
+            get_all,
+            set,
+            add,
+            unset,
+            unset_all,
+            list,
+            system,
+            global,
+            local,
+            file,
+            value_type,
+            bool,
+            int,
+            show_origin,
+            show_scope,
+            name,
+            value,
+        } => {
+            let arguments = |count: usize| {
+                let given = [&name, &value].iter().filter(|arg| arg.is_some()).count();
+                if given != count {
+                    return Err(Error::Usage(format!(
+                        "wrong number of arguments, should be {}",
+                        count
+                    )));
+                }
+                Ok((
+                    name.clone().unwrap_or_default(),
+                    value.clone().unwrap_or_default(),
+                ))
+            };
+            let action = if list {
+                arguments(0)?;
+                config::Action::List
+            } else if get || get_all {
+                let (key, _) = arguments(1)?;
+                config::Action::Get { key, all: get_all }
+            } else if unset || unset_all {
+                let (key, _) = arguments(1)?;
+                config::Action::Unset {
+                    key,
+                    all: unset_all,
+                }
+            } else if set || add || value.is_some() {
+                let (key, value) = arguments(2)?;
+                config::Action::Set { key, value, add }
+            } else {
+                let (key, _) = arguments(1)?;
+                config::Action::Get { key, all: false }
+            };
+            let location = match file {
+                Some(file) => Some(config::Location::File(file)),
+                None if system => Some(config::Location::Scope(config::Scope::System)),
+                None if global => Some(config::Location::Scope(config::Scope::Global)),
+                None if local => Some(config::Location::Scope(config::Scope::Local)),
+                None => None,
+            };
+            let value_type = match value_type.as_deref() {
+                Some("bool") => Some(config::Type::Bool),
+                Some("int") => Some(config::Type::Int),
+                _ if bool => Some(config::Type::Bool),
+                _ if int => Some(config::Type::Int),
+                _ => None,
+            };
+            repo.config(action, location, value_type, show_origin, show_scope)
+        }
+        Commands::CheckIgnore {
+            verbose,
+            quiet,
+            non_matching,
+            no_index,
+            paths,
+        } => {
+            if quiet && verbose {
+                return Err(Error::fatal("cannot have both --quiet and --verbose"));
+            }
+            if non_matching && !verbose {
+                return Err(Error::fatal("--non-matching is only valid with --verbose"));
+            }
+            if paths.is_empty() {
+                return Err(Error::fatal("no path specified"));
+            }
+            repo.check_ignore(paths, verbose, quiet, non_matching, no_index)
+        }
+        Commands::Checkout {
+            new_branch,
+            detach,
+            force,
+            quiet,
+            target,
+        } => repo.checkout(
+            target,
+            checkout::Options {
+                new_branch,
+                detach,
+                force,
+                quiet,
+                switch: false,
+            },
+        ),
+        Commands::Switch {
+            create,
+            detach,
+            force,
+            quiet,
+            target,
+        } => {
+            if target.is_none() && create.is_none() && !detach {
+                return Err(Error::fatal("missing branch or commit argument"));
+            }
+            repo.checkout(
+                target,
+                checkout::Options {
+                    new_branch: create,
+                    detach,
+                    force,
+                    quiet,
+                    switch: true,
+                },
+            )
+        }
+        Commands::MergeBase {
+            all,
+            octopus,
+            commits,
+        } => {
+            if !octopus && commits.len() < 2 {
+                return Err(Error::Usage(String::from(
+                    "merge-base needs at least two commits",
+                )));
+            }
+            repo.merge_base(commits, all, octopus)
+        }
+        Commands::Merge {
+            message,
+            no_ff,
+            ff_only,
+            no_commit,
+            abort,
+            commit,
+        } => {
+            if abort {
+                return repo.merge_abort();
+            }
+            let Some(commit) = commit else {
+                return Err(Error::fatal("No remote for the current branch."));
+            };
+            let fast_forward = match (no_ff, ff_only) {
+                (true, _) => merge::FastForward::Never,
+                (_, true) => merge::FastForward::Only,
+                _ => merge::FastForward::Allow,
+            };
+            repo.merge(&commit, message, fast_forward, !no_commit)
+        }
+        Commands::PackObjects {
+            base_name,
+            window,
+            depth,
+            ..
+        } => repo.pack_objects(base_name, window, depth),
+        Commands::Repack {
+            all,
+            all_loosen,
+            delete,
+            window,
+            depth,
+            ..
+        } => repo.repack(all || all_loosen, all_loosen, delete, window, depth),
+        Commands::Prune {
+            dry_run,
+            verbose,
+            expire,
+        } => {
+            let expire = match expire {
+                Some(date) => signature::parse_expiry_date(&date)
+                    .ok_or_else(|| Error::fatal(format!("malformed expiration date '{}'", date)))?,
+                None => i64::MAX,
+            };
+            repo.prune(expire, dry_run, verbose)
+        }
+        Commands::PackRefs { all, no_prune } => repo.pack_refs(all, !no_prune),
+        Commands::Gc {
+            auto,
+            aggressive,
+            prune,
+            no_prune,
+            quiet,
+        } => {
+            let prune = match no_prune {
+                true => Some(String::from("never")),
+                false => prune,
+            };
+            repo.gc(auto, aggressive, prune, quiet)
+        }
+        Commands::Fsck {
+            unreachable,
+            no_dangling,
+            strict,
+        } => repo.fsck(fsck::Options {
+            unreachable,
+            dangling: !no_dangling,
+            strict,
+        }),
     }
 }
@@ -0,0 +1,1138 @@
+use std::collections::HashMap;
+
+/// How a diff pairs up lines. All of them follow git's xdiff, so that
+/// the same files give the same hunks.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Algorithm {
+    /// Myers' algorithm in linear space, giving up on a minimal diff when
+    /// the files differ a lot.
+    #[default]
+    Myers,
+    /// Myers' algorithm without the heuristics, always finding a minimal
+    /// diff.
+    Minimal,
+    /// Recursively matches up the lines that occur once in both files,
+    /// falling back to Myers where there are none.
+    Patience,
+    /// Like patience, but matches up the least frequent lines instead of
+    /// only unique ones.
+    Histogram,
+}
+
+impl Algorithm {
+    /// Parses a `--diff-algorithm` value.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "myers" | "default" => Some(Self::Myers),
+            "minimal" => Some(Self::Minimal),
+            "patience" => Some(Self::Patience),
+            "histogram" => Some(Self::Histogram),
+            _ => None,
+        }
+    }
+}
+
+/// Which lines of each file a diff marks as changed: deleted from the old
+/// file or added to the new one.
+#[derive(Debug, PartialEq)]
+pub struct LineDiff {
+    pub old: Vec<bool>,
+    pub new: Vec<bool>,
+}
+
+/// A run of changed lines: `old_len` lines of the old file starting at
+/// `old_start` replaced by `new_len` lines of the new file starting at
+/// `new_start`, counting from 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edit {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+impl LineDiff {
+    /// The runs of changed lines, in file order.
+    pub fn edits(&self) -> Vec<Edit> {
+        let (mut i, mut j) = (0, 0);
+        let mut edits = vec![];
+        while i < self.old.len() || j < self.new.len() {
+            let old_changed = self.old.get(i) == Some(&true);
+            let new_changed = self.new.get(j) == Some(&true);
+            if !old_changed && !new_changed {
+                i += 1;
+                j += 1;
+                continue;
+            }
+            let (old_start, new_start) = (i, j);
+            while self.old.get(i) == Some(&true) {
+                i += 1;
+            }
+            while self.new.get(j) == Some(&true) {
+                j += 1;
+            }
+            edits.push(Edit {
+                old_start,
+                old_len: i - old_start,
+                new_start,
+                new_len: j - new_start,
+            });
+        }
+        edits
+    }
+}
+
+/// Splits content into lines, each keeping its `\n`.
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Diffs two files given as lines. Changes that could be placed at several
+/// positions are moved where git puts them: next to a change in the other
+/// file if possible, otherwise where indentation suggests a block starts.
+pub fn diff(old: &[&[u8]], new: &[&[u8]], algorithm: Algorithm) -> LineDiff {
+    diff_with_heuristic(old, new, algorithm, true)
+}
+
+/// Like [`diff`], but without `indent_heuristic` a change that could be
+/// placed at several positions and is not next to a change in the other
+/// file is put as late as possible, as content merges do.
+pub fn diff_with_heuristic(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    algorithm: Algorithm,
+    indent_heuristic: bool,
+) -> LineDiff {
+    // Lines are compared through numbers standing for their content.
+    let mut ids = HashMap::new();
+    let mut intern = |lines: &[&[u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|line| {
+                let next = ids.len();
+                *ids.entry(line.to_vec()).or_insert(next)
+            })
+            .collect()
+    };
+    let a = intern(old);
+    let b = intern(new);
+
+    let mut diff = LineDiff {
+        old: vec![false; a.len()],
+        new: vec![false; b.len()],
+    };
+    match algorithm {
+        Algorithm::Myers => myers(&a, &b, false, &mut diff.old, &mut diff.new),
+        Algorithm::Minimal => myers(&a, &b, true, &mut diff.old, &mut diff.new),
+        Algorithm::Patience => patience(&a, &b, &mut diff.old, &mut diff.new),
+        Algorithm::Histogram => {
+            let (start, a_end, b_end) = trim_ends(&a, &b);
+            let mut histogram = Histogram {
+                a: &a,
+                b: &b,
+                a_changed: &mut diff.old,
+                b_changed: &mut diff.new,
+            };
+            histogram.diff(start, a_end, start, b_end);
+        }
+    }
+    compact(&a, old, &mut diff.old, &diff.new, indent_heuristic);
+    compact(&b, new, &mut diff.new, &diff.old, indent_heuristic);
+    diff
+}
+
+/// Returns the length of the common prefix and where the common suffix
+/// starts in each file, without overlapping the prefix.
+fn trim_ends(a: &[usize], b: &[usize]) -> (usize, usize, usize) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    (prefix, a.len() - suffix, b.len() - suffix)
+}
+
+/// An integer square root approximation, as xdiff uses to size its limits.
+fn bogo_sqrt(mut n: usize) -> usize {
+    let mut i = 1;
+    while n > 0 {
+        i <<= 1;
+        n >>= 2;
+    }
+    i
+}
+
+/// Sizes of the edit cost and the snakes at which Myers' algorithm starts
+/// taking shortcuts, and how much better a shortcut must look.
+const MAX_COST_MIN: usize = 256;
+const SNAKE_COUNT: isize = 20;
+const HEURISTIC_MIN_COST: isize = 256;
+const HEURISTIC_FACTOR: isize = 4;
+/// Lines with more matches than this in the other file may be left out.
+const MAX_EQUAL_LIMIT: usize = 1024;
+/// How far to look around a line when deciding to leave it out.
+const SIMILAR_SCAN_WINDOW: usize = 100;
+const KEEP_DISCARDED_RUN: usize = 4;
+
+/// Diffs with Myers' algorithm after the preparation xdiff does: the
+/// common ends are skipped, and lines without a match in the other file, or
+/// with many matches amid such lines, are marked changed up front.
+fn myers(a: &[usize], b: &[usize], minimal: bool, a_changed: &mut [bool], b_changed: &mut [bool]) {
+    let (start, a_end, b_end) = trim_ends(a, b);
+    let count = |lines: &[usize]| {
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        for &line in lines {
+            *counts.entry(line).or_default() += 1;
+        }
+        counts
+    };
+    let (a_counts, b_counts) = (count(a), count(b));
+    let (a_kept, a_index) = discard_lines(a, start..a_end, &b_counts, a_changed);
+    let (b_kept, b_index) = discard_lines(b, start..b_end, &a_counts, b_changed);
+
+    let diagonals = a_kept.len() + b_kept.len() + 3;
+    let mut myers = Myers {
+        a: &a_kept,
+        b: &b_kept,
+        a_index: &a_index,
+        b_index: &b_index,
+        a_changed,
+        b_changed,
+        forward: vec![0; diagonals],
+        backward: vec![0; diagonals],
+        offset: b_kept.len() as isize + 1,
+        max_cost: bogo_sqrt(diagonals).max(MAX_COST_MIN) as isize,
+    };
+    myers.compare(0, a_kept.len() as isize, 0, b_kept.len() as isize, minimal);
+}
+
+/// Picks the lines of `range` that the diff proper looks at, marking the
+/// others changed: lines that the other file lacks, and lines with many
+/// matches that sit in a run of such lines. Returns the kept lines and
+/// their positions.
+fn discard_lines(
+    lines: &[usize],
+    range: std::ops::Range<usize>,
+    other_counts: &HashMap<usize, usize>,
+    changed: &mut [bool],
+) -> (Vec<usize>, Vec<usize>) {
+    let limit = bogo_sqrt(lines.len()).min(MAX_EQUAL_LIMIT);
+    // 0: no match, 1: keep, 2: many matches.
+    let actions: Vec<u8> = lines
+        .iter()
+        .map(|line| match other_counts.get(line).copied().unwrap_or(0) {
+            0 => 0,
+            n if n >= limit => 2,
+            _ => 1,
+        })
+        .collect();
+
+    let (mut kept, mut index) = (vec![], vec![]);
+    for i in range.clone() {
+        let keep = actions[i] == 1
+            || (actions[i] == 2 && !is_discardable(&actions, i, range.start, range.end - 1));
+        if keep {
+            kept.push(lines[i]);
+            index.push(i);
+        } else {
+            changed[i] = true;
+        }
+    }
+    (kept, index)
+}
+
+/// Whether a line with many matches is surrounded by enough lines without
+/// any to be left out, like xdiff's `xdl_clean_mmatch`.
+fn is_discardable(actions: &[u8], i: usize, start: usize, end: usize) -> bool {
+    let start = start.max(i.saturating_sub(SIMILAR_SCAN_WINDOW));
+    let end = end.min(i + SIMILAR_SCAN_WINDOW);
+    let scan = |positions: &mut dyn Iterator<Item = usize>| {
+        let (mut unmatched, mut multiple) = (0, 1);
+        for j in positions {
+            match actions[j] {
+                0 => unmatched += 1,
+                2 => multiple += 1,
+                _ => break,
+            }
+        }
+        (unmatched, multiple)
+    };
+    let (before, before_multiple) = scan(&mut (start..i).rev());
+    if before == 0 {
+        return false;
+    }
+    let (after, after_multiple) = scan(&mut (i + 1..=end));
+    if after == 0 {
+        return false;
+    }
+    let unmatched = before + after;
+    let multiple = before_multiple + after_multiple;
+    multiple * KEEP_DISCARDED_RUN < multiple + unmatched
+}
+
+/// Where to split a range in two, and whether each half must be diffed
+/// minimally.
+struct Split {
+    i1: isize,
+    i2: isize,
+    min_low: bool,
+    min_high: bool,
+}
+
+/// The divide and conquer form of Myers' algorithm: each range is split at
+/// the middle of an optimal path, found by searching from both ends.
+struct Myers<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    a_index: &'a [usize],
+    b_index: &'a [usize],
+    a_changed: &'a mut [bool],
+    b_changed: &'a mut [bool],
+    /// The furthest reaching paths per diagonal, from the start and from
+    /// the end, indexed by diagonal plus `offset`.
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+}
+
+impl Myers<'_> {
+    fn compare(
+        &mut self,
+        mut off1: isize,
+        mut lim1: isize,
+        mut off2: isize,
+        mut lim2: isize,
+        minimal: bool,
+    ) {
+        while off1 < lim1 && off2 < lim2 && self.a[off1 as usize] == self.b[off2 as usize] {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && self.a[lim1 as usize - 1] == self.b[lim2 as usize - 1] {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+
+        if off1 == lim1 {
+            for i in off2..lim2 {
+                self.b_changed[self.b_index[i as usize]] = true;
+            }
+        } else if off2 == lim2 {
+            for i in off1..lim1 {
+                self.a_changed[self.a_index[i as usize]] = true;
+            }
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, minimal);
+            self.compare(off1, split.i1, off2, split.i2, split.min_low);
+            self.compare(split.i1, lim1, split.i2, lim2, split.min_high);
+        }
+    }
+
+    fn fwd(&mut self, d: isize) -> &mut isize {
+        &mut self.forward[(d + self.offset) as usize]
+    }
+
+    fn bwd(&mut self, d: isize) -> &mut isize {
+        &mut self.backward[(d + self.offset) as usize]
+    }
+
+    fn matches(&self, i1: isize, i2: isize) -> bool {
+        self.a[i1 as usize] == self.b[i2 as usize]
+    }
+
+    /// Finds the middle snake of the range like xdiff's `xdl_split`. Unless
+    /// `minimal`, an expensive search settles for a good looking diagonal
+    /// or the furthest reaching path.
+    fn split(
+        &mut self,
+        off1: isize,
+        lim1: isize,
+        off2: isize,
+        lim2: isize,
+        minimal: bool,
+    ) -> Split {
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+        *self.fwd(fmid) = off1;
+        *self.bwd(bmid) = lim1;
+
+        let mut cost = 1;
+        loop {
+            let mut got_snake = false;
+
+            if fmin > dmin {
+                fmin -= 1;
+                *self.fwd(fmin - 1) = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                *self.fwd(fmax + 1) = -1;
+            } else {
+                fmax -= 1;
+            }
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut i1 = if *self.fwd(d - 1) >= *self.fwd(d + 1) {
+                    *self.fwd(d - 1) + 1
+                } else {
+                    *self.fwd(d + 1)
+                };
+                let prev = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && self.matches(i1, i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                *self.fwd(d) = i1;
+                if odd && bmin <= d && d <= bmax && *self.bwd(d) <= i1 {
+                    return Split {
+                        i1,
+                        i2,
+                        min_low: true,
+                        min_high: true,
+                    };
+                }
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                *self.bwd(bmin - 1) = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                *self.bwd(bmax + 1) = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut i1 = if *self.bwd(d - 1) < *self.bwd(d + 1) {
+                    *self.bwd(d - 1)
+                } else {
+                    *self.bwd(d + 1) - 1
+                };
+                let prev = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && self.matches(i1 - 1, i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev - i1 > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                *self.bwd(d) = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= *self.fwd(d) {
+                    return Split {
+                        i1,
+                        i2,
+                        min_low: true,
+                        min_high: true,
+                    };
+                }
+            }
+
+            if minimal {
+                cost += 1;
+                continue;
+            }
+
+            // Past some cost, a long enough snake far along a diagonal
+            // close to the middle one is taken as the split.
+            if got_snake && cost > HEURISTIC_MIN_COST {
+                let mut best: Option<(isize, isize, isize)> = None;
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let dd = (d - fmid).abs();
+                    let i1 = *self.fwd(d);
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+                    if v > HEURISTIC_FACTOR * cost
+                        && best.map_or(true, |(best, ..)| v > best)
+                        && off1 + SNAKE_COUNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_COUNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_COUNT).all(|k| self.matches(i1 - k, i2 - k))
+                    {
+                        best = Some((v, i1, i2));
+                    }
+                }
+                if let Some((_, i1, i2)) = best {
+                    return Split {
+                        i1,
+                        i2,
+                        min_low: true,
+                        min_high: false,
+                    };
+                }
+
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let dd = (d - bmid).abs();
+                    let i1 = *self.bwd(d);
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+                    if v > HEURISTIC_FACTOR * cost
+                        && best.map_or(true, |(best, ..)| v > best)
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_COUNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_COUNT
+                        && (0..SNAKE_COUNT).all(|k| self.matches(i1 + k, i2 + k))
+                    {
+                        best = Some((v, i1, i2));
+                    }
+                }
+                if let Some((_, i1, i2)) = best {
+                    return Split {
+                        i1,
+                        i2,
+                        min_low: false,
+                        min_high: true,
+                    };
+                }
+            }
+
+            // Enough is enough: split at the furthest reaching path.
+            if cost >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let mut i1 = (*self.fwd(d)).min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                }
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let mut i1 = off1.max(*self.bwd(d));
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                }
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Split {
+                        i1: fbest1,
+                        i2: fbest - fbest1,
+                        min_low: true,
+                        min_high: false,
+                    }
+                } else {
+                    Split {
+                        i1: bbest1,
+                        i2: bbest - bbest1,
+                        min_low: false,
+                        min_high: true,
+                    }
+                };
+            }
+            cost += 1;
+        }
+    }
+}
+
+/// Patience diff: the lines that occur exactly once in both files are
+/// matched up along their longest common subsequence, and the gaps between
+/// them are diffed the same way.
+fn patience(a: &[usize], b: &[usize], a_changed: &mut [bool], b_changed: &mut [bool]) {
+    if a.is_empty() || b.is_empty() {
+        a_changed.fill(true);
+        b_changed.fill(true);
+        return;
+    }
+
+    // The lines of `a` in order of first occurrence, with their position
+    // in `b` if they are unique in both.
+    struct Entry {
+        line1: usize,
+        line2: Option<usize>,
+        unique: bool,
+    }
+    let mut entries: Vec<Entry> = vec![];
+    let mut by_line: HashMap<usize, usize> = HashMap::new();
+    for (i, &line) in a.iter().enumerate() {
+        match by_line.get(&line) {
+            Some(&e) => entries[e].unique = false,
+            None => {
+                by_line.insert(line, entries.len());
+                entries.push(Entry {
+                    line1: i,
+                    line2: None,
+                    unique: true,
+                });
+            }
+        }
+    }
+    let mut has_matches = false;
+    for (j, &line) in b.iter().enumerate() {
+        if let Some(&e) = by_line.get(&line) {
+            has_matches = true;
+            let entry = &mut entries[e];
+            match entry.line2 {
+                Some(_) => entry.unique = false,
+                None => entry.line2 = Some(j),
+            }
+        }
+    }
+    if !has_matches {
+        a_changed.fill(true);
+        b_changed.fill(true);
+        return;
+    }
+
+    // Patience sorting: `sequence[i]` ends the best increasing run of
+    // length `i + 1` found so far.
+    let candidates: Vec<(usize, usize)> = entries
+        .iter()
+        .filter(|e| e.unique)
+        .filter_map(|e| e.line2.map(|line2| (e.line1, line2)))
+        .collect();
+    let mut sequence: Vec<usize> = vec![];
+    let mut previous = vec![None; candidates.len()];
+    for (c, &(_, line2)) in candidates.iter().enumerate() {
+        let i = sequence.partition_point(|&s| candidates[s].1 <= line2);
+        previous[c] = i.checked_sub(1).map(|i| sequence[i]);
+        if i == sequence.len() {
+            sequence.push(c);
+        } else {
+            sequence[i] = c;
+        }
+    }
+    let Some(&last) = sequence.last() else {
+        return myers(a, b, false, a_changed, b_changed);
+    };
+    let mut common = vec![];
+    let mut entry = Some(last);
+    while let Some(c) = entry {
+        common.push(candidates[c]);
+        entry = previous[c];
+    }
+    common.reverse();
+
+    let (mut line1, mut line2) = (0, 0);
+    let mut next = 0;
+    loop {
+        let (next1, next2) = match common.get(next) {
+            Some(&(mut next1, mut next2)) => {
+                while next1 > line1 && next2 > line2 && a[next1 - 1] == b[next2 - 1] {
+                    next1 -= 1;
+                    next2 -= 1;
+                }
+                (next1, next2)
+            }
+            None => (a.len(), b.len()),
+        };
+        while line1 < next1 && line2 < next2 && a[line1] == b[line2] {
+            line1 += 1;
+            line2 += 1;
+        }
+        if next1 > line1 || next2 > line2 {
+            patience(
+                &a[line1..next1],
+                &b[line2..next2],
+                &mut a_changed[line1..next1],
+                &mut b_changed[line2..next2],
+            );
+        }
+        if next == common.len() {
+            return;
+        }
+        while next + 1 < common.len()
+            && common[next + 1] == (common[next].0 + 1, common[next].1 + 1)
+        {
+            next += 1;
+        }
+        (line1, line2) = (common[next].0 + 1, common[next].1 + 1);
+        next += 1;
+    }
+}
+
+/// Lines occurring more often than this are not used to match up the
+/// files; if there is nothing else, histogram diff falls back to Myers.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// A common region of the two files, with inclusive ends.
+#[derive(Clone, Copy)]
+struct Region {
+    begin1: usize,
+    end1: usize,
+    begin2: usize,
+    end2: usize,
+}
+
+enum Lcs {
+    Found(Region),
+    /// The files have nothing in common.
+    None,
+    /// The files only share lines that occur too often.
+    Fallback,
+}
+
+/// Histogram diff, like xdiff's `xhistogram.c`: the files are split around
+/// the longest common region containing the least frequent lines of `a`,
+/// and the parts before and after it are diffed the same way.
+struct Histogram<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    a_changed: &'a mut [bool],
+    b_changed: &'a mut [bool],
+}
+
+impl Histogram<'_> {
+    fn diff(&mut self, mut start1: usize, end1: usize, mut start2: usize, end2: usize) {
+        loop {
+            if start1 == end1 || start2 == end2 {
+                self.a_changed[start1..end1].fill(true);
+                self.b_changed[start2..end2].fill(true);
+                return;
+            }
+            match self.find_lcs(start1, end1, start2, end2) {
+                Lcs::Fallback => {
+                    return myers(
+                        &self.a[start1..end1],
+                        &self.b[start2..end2],
+                        false,
+                        &mut self.a_changed[start1..end1],
+                        &mut self.b_changed[start2..end2],
+                    );
+                }
+                Lcs::None => {
+                    self.a_changed[start1..end1].fill(true);
+                    self.b_changed[start2..end2].fill(true);
+                    return;
+                }
+                Lcs::Found(lcs) => {
+                    self.diff(start1, lcs.begin1, start2, lcs.begin2);
+                    start1 = lcs.end1 + 1;
+                    start2 = lcs.end2 + 1;
+                }
+            }
+        }
+    }
+
+    fn find_lcs(&self, start1: usize, end1: usize, start2: usize, end2: usize) -> Lcs {
+        // For each line of `a`, the next occurrence of the same line, and
+        // for each distinct line its first occurrence and count.
+        let mut next_occurrence = vec![None; end1 - start1];
+        let mut records: HashMap<usize, (usize, usize)> = HashMap::new();
+        for ptr in (start1..end1).rev() {
+            match records.get_mut(&self.a[ptr]) {
+                Some((first, count)) => {
+                    next_occurrence[ptr - start1] = Some(*first);
+                    *first = ptr;
+                    *count += 1;
+                }
+                None => {
+                    records.insert(self.a[ptr], (ptr, 1));
+                }
+            }
+        }
+        let count_of = |ptr: usize| records[&self.a[ptr]].1;
+
+        let mut lcs: Option<Region> = None;
+        let mut min_count = MAX_CHAIN_LENGTH + 1;
+        let mut has_common = false;
+        let mut b_ptr = start2;
+        while b_ptr < end2 {
+            let mut b_next = b_ptr + 1;
+            if let Some(&(first, count)) = records.get(&self.b[b_ptr]) {
+                has_common = true;
+                if count <= min_count {
+                    let mut as_ = first;
+                    loop {
+                        let mut np = next_occurrence[as_ - start1];
+                        let (mut bs, mut ae, mut be) = (b_ptr, as_, b_ptr);
+                        let mut rc = count;
+                        while start1 < as_ && start2 < bs && self.a[as_ - 1] == self.b[bs - 1] {
+                            as_ -= 1;
+                            bs -= 1;
+                            if rc > 1 {
+                                rc = rc.min(count_of(as_));
+                            }
+                        }
+                        while ae + 1 < end1 && be + 1 < end2 && self.a[ae + 1] == self.b[be + 1] {
+                            ae += 1;
+                            be += 1;
+                            if rc > 1 {
+                                rc = rc.min(count_of(ae));
+                            }
+                        }
+
+                        if b_next <= be {
+                            b_next = be + 1;
+                        }
+                        let lcs_len = lcs.map_or(0, |lcs| lcs.end1 - lcs.begin1);
+                        if lcs_len < ae - as_ || rc < min_count {
+                            lcs = Some(Region {
+                                begin1: as_,
+                                end1: ae,
+                                begin2: bs,
+                                end2: be,
+                            });
+                            min_count = rc;
+                        }
+
+                        while let Some(ptr) = np.filter(|&ptr| ptr <= ae) {
+                            np = next_occurrence[ptr - start1];
+                        }
+                        match np {
+                            Some(ptr) => as_ = ptr,
+                            None => break,
+                        }
+                    }
+                }
+            }
+            b_ptr = b_next;
+        }
+
+        match lcs {
+            _ if has_common && min_count > MAX_CHAIN_LENGTH => Lcs::Fallback,
+            Some(lcs) => Lcs::Found(lcs),
+            None => Lcs::None,
+        }
+    }
+}
+
+/// The maximum indentation and number of blank lines measured around a
+/// split, and the weights git's indent heuristic gives to what it finds.
+const MAX_INDENT: isize = 200;
+const MAX_BLANKS: isize = 20;
+const START_OF_FILE_PENALTY: isize = 1;
+const END_OF_FILE_PENALTY: isize = 21;
+const TOTAL_BLANK_WEIGHT: isize = -30;
+const POST_BLANK_WEIGHT: isize = 6;
+const RELATIVE_INDENT_PENALTY: isize = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: isize = 10;
+const RELATIVE_OUTDENT_PENALTY: isize = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: isize = 17;
+const RELATIVE_DEDENT_PENALTY: isize = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: isize = 17;
+const INDENT_WEIGHT: isize = 60;
+const INDENT_HEURISTIC_MAX_SLIDING: isize = 100;
+
+/// A run of changed lines in one file, `start..end`, possibly empty.
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Self {
+        let end = changed.iter().take_while(|&&c| c).count();
+        Self { start: 0, end }
+    }
+
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while changed.get(self.end) == Some(&true) {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+        true
+    }
+
+    /// Moves the group one line down if the line after it equals its first
+    /// line, merging it with a group it runs into.
+    fn slide_down(&mut self, lines: &[usize], changed: &mut [bool]) -> bool {
+        if self.end < lines.len() && lines[self.start] == lines[self.end] {
+            changed[self.start] = false;
+            changed[self.end] = true;
+            self.start += 1;
+            self.end += 1;
+            while changed.get(self.end) == Some(&true) {
+                self.end += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    fn slide_up(&mut self, lines: &[usize], changed: &mut [bool]) -> bool {
+        if self.start > 0 && lines[self.start - 1] == lines[self.end - 1] {
+            self.start -= 1;
+            self.end -= 1;
+            changed[self.start] = true;
+            changed[self.end] = false;
+            while self.start > 0 && changed[self.start - 1] {
+                self.start -= 1;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+/// Slides each group of changed lines of one file to its final place, like
+/// xdiff's `xdl_change_compact`, keeping track of the matching group in
+/// the other file.
+fn compact(
+    lines: &[usize],
+    text: &[&[u8]],
+    changed: &mut [bool],
+    other: &[bool],
+    indent_heuristic: bool,
+) {
+    let mut g = Group::first(changed);
+    let mut go = Group::first(other);
+    loop {
+        if g.end != g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let size = g.end - g.start;
+                end_matching_other = None;
+                while g.slide_up(lines, changed) {
+                    go.previous(other);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = Some(g.end);
+                }
+                while g.slide_down(lines, changed) {
+                    go.next(other);
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                }
+                if size == g.end - g.start {
+                    break;
+                }
+            }
+
+            if g.end == earliest_end {
+                // The group cannot move.
+            } else if end_matching_other.is_some() {
+                while go.end == go.start {
+                    g.slide_up(lines, changed);
+                    go.previous(other);
+                }
+            } else if indent_heuristic {
+                let size = (g.end - g.start) as isize;
+                let end = g.end as isize;
+                let mut shift = (earliest_end as isize)
+                    .max(end - size - 1)
+                    .max(end - INDENT_HEURISTIC_MAX_SLIDING);
+                let mut best: Option<(isize, (isize, isize))> = None;
+                while shift <= end {
+                    let mut score = (0, 0);
+                    score_split(&measure_split(text, shift), &mut score);
+                    score_split(&measure_split(text, shift - size), &mut score);
+                    if best.map_or(true, |(_, best)| compare_scores(score, best) <= 0) {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+                let best_shift = best.unwrap().0;
+                while g.end as isize > best_shift {
+                    g.slide_up(lines, changed);
+                    go.previous(other);
+                }
+            }
+        }
+        if !g.next(changed) {
+            break;
+        }
+        go.next(other);
+    }
+}
+
+/// The indentation of a line, with tabs to multiples of 8, or -1 if it is
+/// blank.
+fn indent(line: &[u8]) -> isize {
+    let mut indent = 0;
+    for &c in line {
+        if !c.is_ascii_whitespace() && c != 0x0b {
+            return indent;
+        }
+        match c {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            _ => {}
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+/// What surrounds the split before line `split`.
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: isize,
+    pre_blank: isize,
+    pre_indent: isize,
+    post_blank: isize,
+    post_indent: isize,
+}
+
+fn measure_split(text: &[&[u8]], split: isize) -> SplitMeasurement {
+    let len = text.len() as isize;
+    let end_of_file = split >= len;
+    let line_indent = if end_of_file {
+        -1
+    } else {
+        indent(text[split as usize])
+    };
+    let (mut pre_blank, mut pre_indent) = (0, -1);
+    for i in (0..split).rev() {
+        pre_indent = indent(text[i as usize]);
+        if pre_indent != -1 {
+            break;
+        }
+        pre_blank += 1;
+        if pre_blank == MAX_BLANKS {
+            pre_indent = 0;
+            break;
+        }
+    }
+    let (mut post_blank, mut post_indent) = (0, -1);
+    for i in split + 1..len {
+        post_indent = indent(text[i as usize]);
+        if post_indent != -1 {
+            break;
+        }
+        post_blank += 1;
+        if post_blank == MAX_BLANKS {
+            post_indent = 0;
+            break;
+        }
+    }
+    SplitMeasurement {
+        end_of_file,
+        indent: line_indent,
+        pre_blank,
+        pre_indent,
+        post_blank,
+        post_indent,
+    }
+}
+
+/// Adds the badness of a split to a score of effective indent and penalty.
+fn score_split(m: &SplitMeasurement, score: &mut (isize, isize)) {
+    if m.pre_indent == -1 && m.pre_blank == 0 {
+        score.1 += START_OF_FILE_PENALTY;
+    }
+    if m.end_of_file {
+        score.1 += END_OF_FILE_PENALTY;
+    }
+    let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+    let total_blank = m.pre_blank + post_blank;
+    score.1 += TOTAL_BLANK_WEIGHT * total_blank;
+    score.1 += POST_BLANK_WEIGHT * post_blank;
+    let indent = if m.indent != -1 {
+        m.indent
+    } else {
+        m.post_indent
+    };
+    let any_blanks = total_blank != 0;
+    score.0 += indent;
+    if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+        // No adjustment needed.
+    } else if indent > m.pre_indent {
+        score.1 += match any_blanks {
+            true => RELATIVE_INDENT_WITH_BLANK_PENALTY,
+            false => RELATIVE_INDENT_PENALTY,
+        };
+    } else if m.post_indent != -1 && m.post_indent > indent {
+        score.1 += match any_blanks {
+            true => RELATIVE_OUTDENT_WITH_BLANK_PENALTY,
+            false => RELATIVE_OUTDENT_PENALTY,
+        };
+    } else {
+        score.1 += match any_blanks {
+            true => RELATIVE_DEDENT_WITH_BLANK_PENALTY,
+            false => RELATIVE_DEDENT_PENALTY,
+        };
+    }
+}
+
+/// Negative if score `a` is better than `b`.
+fn compare_scores(a: (isize, isize), b: (isize, isize)) -> isize {
+    INDENT_WEIGHT * (a.0.cmp(&b.0) as isize) + (a.1 - b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, split_lines, Algorithm, Edit};
+
+    fn edits(old: &str, new: &str, algorithm: Algorithm) -> Vec<(usize, usize, usize, usize)> {
+        let old = split_lines(old.as_bytes());
+        let new = split_lines(new.as_bytes());
+        diff(&old, &new, algorithm)
+            .edits()
+            .into_iter()
+            .map(|e: Edit| (e.old_start, e.old_len, e.new_start, e.new_len))
+            .collect()
+    }
+
+    #[test]
+    fn test_algorithms() {
+        // The edits git makes for these files.
+        let (old, new) = ("a\nb\nc\na\nb\nb\na\n", "c\nb\na\nb\na\nc\n");
+        let myers = [(0, 2, 0, 0), (3, 1, 1, 0), (5, 0, 2, 1), (7, 0, 5, 1)];
+        for algorithm in [Algorithm::Myers, Algorithm::Minimal, Algorithm::Patience] {
+            assert_eq!(edits(old, new, algorithm), myers);
+        }
+        assert_eq!(
+            edits(old, new, Algorithm::Histogram),
+            [(0, 2, 0, 0), (3, 2, 1, 0), (7, 0, 3, 3)]
+        );
+
+        let (old, new) = ("x\nsame\ny\nsame\nz\n", "y\nsame\nx\nsame\nz\n");
+        for algorithm in [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram] {
+            assert_eq!(edits(old, new, algorithm), [(0, 2, 0, 0), (4, 0, 2, 2)]);
+        }
+    }
+
+    #[test]
+    fn test_indent_heuristic() {
+        // The added function is shown as a whole, not starting at the
+        // closing brace of the one before it.
+        let old = "fn a() {\n    x\n}\n\nfn c() {\n    z\n}\n";
+        let new = "fn a() {\n    x\n}\n\nfn b() {\n    y\n}\n\nfn c() {\n    z\n}\n";
+        assert_eq!(edits(old, new, Algorithm::Myers), [(4, 0, 4, 4)]);
+    }
+
+    #[test]
+    fn test_parse_algorithm() {
+        assert_eq!(Algorithm::parse("default"), Some(Algorithm::Myers));
+        assert_eq!(Algorithm::parse("histogram"), Some(Algorithm::Histogram));
+        assert_eq!(Algorithm::parse("fast"), None);
+    }
+}
@@ -0,0 +1,466 @@
+use crate::line_diff::{self, Algorithm, Edit};
+
+/// The size of conflict markers in a merge that is not nested in another.
+pub const DEFAULT_MARKER_SIZE: usize = 7;
+
+/// How a conflict is written out, from `merge.conflictStyle`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Style {
+    /// Both sides' versions.
+    #[default]
+    Merge,
+    /// Both sides' versions with the common ancestor's between them.
+    Diff3,
+    /// Like `Diff3`, with the lines both sides start and end a conflict
+    /// with moved out of it.
+    Zdiff3,
+}
+
+impl Style {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "merge" => Some(Self::Merge),
+            "diff3" => Some(Self::Diff3),
+            "zdiff3" => Some(Self::Zdiff3),
+            _ => None,
+        }
+    }
+}
+
+/// The labels and markers of a content merge.
+pub struct Options<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+    pub style: Style,
+    pub marker_size: usize,
+}
+
+/// The merged content, with conflict markers around each of `conflicts`.
+pub struct Merged {
+    pub content: Vec<u8>,
+    pub conflicts: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Conflict,
+    Ours,
+    Theirs,
+    /// A conflict whose sides turned out to be the same.
+    Same,
+}
+
+/// A region where the sides changed the base: lines `i1..i1 + chg1` of
+/// ours and `i2..i2 + chg2` of theirs stand for `i0..i0 + chg0` of the
+/// base. Like xdiff's, they are signed since the arithmetic that lines
+/// them up steps outside the files on the way.
+#[derive(Debug, Clone, Copy)]
+struct Hunk {
+    mode: Mode,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+}
+
+fn edits(old: &[&[u8]], new: &[&[u8]]) -> Vec<Edit> {
+    line_diff::diff_with_heuristic(old, new, Algorithm::Histogram, false).edits()
+}
+
+/// Adds a hunk, or grows the last one if the two touch on either side, in
+/// which case they only stay conflict-free if both are from the same side.
+fn append(hunks: &mut Vec<Hunk>, hunk: Hunk) {
+    if let Some(last) = hunks.last_mut() {
+        if hunk.i1 <= last.i1 + last.chg1 || hunk.i2 <= last.i2 + last.chg2 {
+            if hunk.mode != last.mode {
+                last.mode = Mode::Conflict;
+            }
+            last.chg0 = hunk.i0 + hunk.chg0 - last.i0;
+            last.chg1 = hunk.i1 + hunk.chg1 - last.i1;
+            last.chg2 = hunk.i2 + hunk.chg2 - last.i2;
+            return;
+        }
+    }
+    hunks.push(hunk);
+}
+
+/// Three-way merges `ours` and `theirs` against `base` line by line, like
+/// xdiff's `xdl_merge` at git's default level: changes on one side are
+/// taken, and overlapping ones conflict unless they are the same. The
+/// lines both sides of a conflict agree on are moved out of it, and
+/// conflicts with at most three lines between them become one.
+pub fn merge(base: &[u8], ours: &[u8], theirs: &[u8], options: &Options) -> Merged {
+    let base_lines = line_diff::split_lines(base);
+    let our_lines = line_diff::split_lines(ours);
+    let their_lines = line_diff::split_lines(theirs);
+    let ours_edits = edits(&base_lines, &our_lines);
+    let theirs_edits = edits(&base_lines, &their_lines);
+    if ours_edits.is_empty() {
+        return Merged {
+            content: theirs.to_vec(),
+            conflicts: 0,
+        };
+    }
+    if theirs_edits.is_empty() {
+        return Merged {
+            content: ours.to_vec(),
+            conflicts: 0,
+        };
+    }
+
+    let edit = |edit: &Edit| {
+        (
+            edit.old_start as isize,
+            edit.old_len as isize,
+            edit.new_start as isize,
+            edit.new_len as isize,
+        )
+    };
+    let mut hunks = vec![];
+    let (mut x1, mut x2) = (0, 0);
+    while x1 < ours_edits.len() && x2 < theirs_edits.len() {
+        let (a0, a_len, a1, a1_len) = edit(&ours_edits[x1]);
+        let (b0, b_len, b2, b2_len) = edit(&theirs_edits[x2]);
+        if a0 + a_len < b0 {
+            let i2 = b2 - b0 + a0;
+            append(
+                &mut hunks,
+                hunk(Mode::Ours, a0, a_len, a1, a1_len, i2, a_len),
+            );
+            x1 += 1;
+            continue;
+        }
+        if b0 + b_len < a0 {
+            let i1 = a1 - a0 + b0;
+            append(
+                &mut hunks,
+                hunk(Mode::Theirs, b0, b_len, i1, b_len, b2, b2_len),
+            );
+            x2 += 1;
+            continue;
+        }
+        let same = a0 == b0
+            && a_len == b_len
+            && a1_len == b2_len
+            && our_lines[a1 as usize..(a1 + a1_len) as usize]
+                == their_lines[b2 as usize..(b2 + b2_len) as usize];
+        if !same {
+            let off = a0 - b0;
+            let ffo = off + a_len - b_len;
+            let (mut i0, mut i1, mut i2) = (a0, a1, b2);
+            if off > 0 {
+                i0 -= off;
+                i1 -= off;
+            } else {
+                i2 += off;
+            }
+            let mut chg0 = a0 + a_len - i0;
+            let mut chg1 = a1 + a1_len - i1;
+            let mut chg2 = b2 + b2_len - i2;
+            if ffo < 0 {
+                chg0 -= ffo;
+                chg1 -= ffo;
+            } else {
+                chg2 += ffo;
+            }
+            append(
+                &mut hunks,
+                hunk(Mode::Conflict, i0, chg0, i1, chg1, i2, chg2),
+            );
+        }
+        let (end1, end2) = (a0 + a_len, b0 + b_len);
+        if end1 >= end2 {
+            x2 += 1;
+        }
+        if end2 >= end1 {
+            x1 += 1;
+        }
+    }
+    let base_len = base_lines.len() as isize;
+    for e in &ours_edits[x1..] {
+        let (a0, a_len, a1, a1_len) = edit(e);
+        let i2 = a0 + their_lines.len() as isize - base_len;
+        append(
+            &mut hunks,
+            hunk(Mode::Ours, a0, a_len, a1, a1_len, i2, a_len),
+        );
+    }
+    for e in &theirs_edits[x2..] {
+        let (b0, b_len, b2, b2_len) = edit(e);
+        let i1 = b0 + our_lines.len() as isize - base_len;
+        append(
+            &mut hunks,
+            hunk(Mode::Theirs, b0, b_len, i1, b_len, b2, b2_len),
+        );
+    }
+
+    // Showing the base next to a conflict only makes sense if the
+    // conflict still covers everything the sides changed.
+    match options.style {
+        Style::Merge => {
+            hunks = refine_conflicts(hunks, &our_lines, &their_lines);
+            hunks = join_conflicts(hunks);
+        }
+        Style::Diff3 => {}
+        Style::Zdiff3 => trim_conflicts(&mut hunks, &our_lines, &their_lines),
+    }
+
+    let mut merged = Merged {
+        content: vec![],
+        conflicts: 0,
+    };
+    let out = &mut merged.content;
+    let lines = |lines: &[&[u8]], start: isize, len: isize| -> Vec<u8> {
+        lines[start as usize..(start + len) as usize].concat()
+    };
+    let mut i = 0;
+    for hunk in &hunks {
+        match hunk.mode {
+            Mode::Same => continue,
+            Mode::Ours | Mode::Theirs => {
+                out.extend(lines(&our_lines, i, hunk.i1 - i));
+                match hunk.mode {
+                    Mode::Ours => out.extend(lines(&our_lines, hunk.i1, hunk.chg1)),
+                    _ => out.extend(lines(&their_lines, hunk.i2, hunk.chg2)),
+                }
+            }
+            Mode::Conflict => {
+                merged.conflicts += 1;
+                out.extend(lines(&our_lines, i, hunk.i1 - i));
+                let mut side = |marker: u8, name: &str, content: Vec<u8>| {
+                    if marker != b'<' {
+                        out.extend(content);
+                    }
+                    out.extend(vec![marker; options.marker_size]);
+                    if !name.is_empty() {
+                        out.push(b' ');
+                        out.extend(name.as_bytes());
+                    }
+                    out.push(b'\n');
+                };
+                side(b'<', options.ours, vec![]);
+                let mut pending = with_newline(lines(&our_lines, hunk.i1, hunk.chg1));
+                if options.style != Style::Merge {
+                    side(b'|', options.base, pending);
+                    pending = with_newline(lines(&base_lines, hunk.i0, hunk.chg0));
+                }
+                side(b'=', "", pending);
+                side(
+                    b'>',
+                    options.theirs,
+                    with_newline(lines(&their_lines, hunk.i2, hunk.chg2)),
+                );
+            }
+        }
+        i = hunk.i1 + hunk.chg1;
+    }
+    out.extend(lines(&our_lines, i, our_lines.len() as isize - i));
+    merged
+}
+
+fn hunk(
+    mode: Mode,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+) -> Hunk {
+    Hunk {
+        mode,
+        i0,
+        chg0,
+        i1,
+        chg1,
+        i2,
+        chg2,
+    }
+}
+
+/// Ends content put inside conflict markers with a newline, so that the
+/// next marker starts a line.
+fn with_newline(mut content: Vec<u8>) -> Vec<u8> {
+    if content.last().is_some_and(|&c| c != b'\n') {
+        content.push(b'\n');
+    }
+    content
+}
+
+/// Narrows each conflict down to the parts where the sides differ, which
+/// may split it in several. A conflict whose sides are the same goes away.
+fn refine_conflicts(hunks: Vec<Hunk>, ours: &[&[u8]], theirs: &[&[u8]]) -> Vec<Hunk> {
+    let mut refined = vec![];
+    for hunk in hunks {
+        if hunk.mode != Mode::Conflict || hunk.chg1 == 0 || hunk.chg2 == 0 {
+            refined.push(hunk);
+            continue;
+        }
+        let our_part = &ours[hunk.i1 as usize..(hunk.i1 + hunk.chg1) as usize];
+        let their_part = &theirs[hunk.i2 as usize..(hunk.i2 + hunk.chg2) as usize];
+        let edits = edits(our_part, their_part);
+        if edits.is_empty() {
+            refined.push(Hunk {
+                mode: Mode::Same,
+                ..hunk
+            });
+            continue;
+        }
+        for edit in edits {
+            refined.push(Hunk {
+                i1: hunk.i1 + edit.old_start as isize,
+                chg1: edit.old_len as isize,
+                i2: hunk.i2 + edit.new_start as isize,
+                chg2: edit.new_len as isize,
+                ..hunk
+            });
+        }
+    }
+    refined
+}
+
+/// Moves the lines both sides of a conflict start and end with out of it,
+/// keeping the base it shows whole, like xdiff's zealous diff3.
+fn trim_conflicts(hunks: &mut [Hunk], ours: &[&[u8]], theirs: &[&[u8]]) {
+    for hunk in hunks.iter_mut().filter(|hunk| hunk.mode == Mode::Conflict) {
+        while hunk.chg1 > 0 && hunk.chg2 > 0 && ours[hunk.i1 as usize] == theirs[hunk.i2 as usize] {
+            hunk.i1 += 1;
+            hunk.chg1 -= 1;
+            hunk.i2 += 1;
+            hunk.chg2 -= 1;
+        }
+        while hunk.chg1 > 0
+            && hunk.chg2 > 0
+            && ours[(hunk.i1 + hunk.chg1 - 1) as usize]
+                == theirs[(hunk.i2 + hunk.chg2 - 1) as usize]
+        {
+            hunk.chg1 -= 1;
+            hunk.chg2 -= 1;
+        }
+    }
+}
+
+/// Joins conflicts that only have up to three lines between them, which
+/// reads better than several small ones.
+fn join_conflicts(hunks: Vec<Hunk>) -> Vec<Hunk> {
+    let mut joined: Vec<Hunk> = vec![];
+    for hunk in hunks {
+        if let Some(last) = joined.last_mut() {
+            let between = hunk.i1 - (last.i1 + last.chg1);
+            if last.mode == Mode::Conflict && hunk.mode == Mode::Conflict && between <= 3 {
+                last.chg0 = hunk.i0 + hunk.chg0 - last.i0;
+                last.chg1 = hunk.i1 + hunk.chg1 - last.i1;
+                last.chg2 = hunk.i2 + hunk.chg2 - last.i2;
+                continue;
+            }
+        }
+        joined.push(hunk);
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        line_merge::{merge, Options, Style, DEFAULT_MARKER_SIZE},
+        reference_impl, test_utils,
+    };
+
+    #[test]
+    fn test_merge() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let base = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let cases = [
+            // Changes far apart.
+            ("A\nb\nc\nd\ne\nf\ng\nh\ni\n", "a\nb\nc\nd\ne\nf\ng\nh\nI\n"),
+            // The same change on both sides.
+            ("a\nb\nX\nd\ne\nf\ng\nh\ni\n", "a\nb\nX\nd\ne\nf\ng\nh\ni\n"),
+            // Overlapping changes with a common part.
+            ("a\nB\nC\nD\ne\nf\ng\nh\ni\n", "a\nb\nC\nd\ne\nf\ng\nh\ni\n"),
+            ("a\nX\nc\nY\ne\nf\ng\nh\ni\n", "a\nZ\nc\nW\ne\nf\ng\nh\ni\n"),
+            // Conflicts close enough to be joined, and ones that are not.
+            ("a\nX\nc\nd\nY\nf\ng\nh\ni\n", "a\nZ\nc\nd\nW\nf\ng\nh\ni\n"),
+            ("X\nb\nc\nd\ne\nY\ng\nh\ni\n", "Z\nb\nc\nd\ne\nW\ng\nh\ni\n"),
+            // Deletions and additions at the ends.
+            ("b\nc\nd\ne\nf\ng\nh\ni\nj\n", "0\na\nb\nc\nd\ne\nf\ng\nh\n"),
+            (
+                "a\nb\nc\nd\ne\nf\ng\nh\ni\nmine",
+                "a\nb\nc\nd\ne\nf\ng\nh\ni\ntheirs",
+            ),
+            ("", "a\nb\nc\nd\nE\nf\ng\nh\ni\n"),
+        ];
+        for (ours, theirs) in cases {
+            for style in [Style::Merge, Style::Diff3] {
+                let options = Options {
+                    ours: "ours",
+                    base: "base",
+                    theirs: "theirs",
+                    style,
+                    marker_size: DEFAULT_MARKER_SIZE,
+                };
+                let want = reference_impl::git_merge_file(
+                    &repository,
+                    base.as_bytes(),
+                    ours.as_bytes(),
+                    theirs.as_bytes(),
+                    style == Style::Diff3,
+                );
+                let got = merge(
+                    base.as_bytes(),
+                    ours.as_bytes(),
+                    theirs.as_bytes(),
+                    &options,
+                );
+                assert_eq!(
+                    (String::from_utf8(got.content).unwrap(), got.conflicts > 0),
+                    want,
+                    "ours {:?} theirs {:?} {:?}",
+                    ours,
+                    theirs,
+                    style
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_zdiff3() {
+        assert_eq!(Style::parse("zdiff3"), Some(Style::Zdiff3));
+        let base = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        // Expected output recorded from `git merge-file -p --zdiff3`, which
+        // git2 does not expose.
+        let cases = [
+            (
+                "a\nX\nY\nZ\ne\nf\ng\nh\ni\n",
+                "a\nX\nW\nZ\ne\nf\ng\nh\ni\n",
+                "a\nX\n<<<<<<< ours\nY\n||||||| base\nb\nc\nd\n=======\nW\n>>>>>>> theirs\nZ\ne\nf\ng\nh\ni\n",
+            ),
+            (
+                "a\nX\nY\ne\nf\ng\nh\ni\n",
+                "a\nX\nY\nZ\ne\nf\ng\nh\ni\n",
+                "a\nX\nY\n<<<<<<< ours\n||||||| base\nb\nc\nd\n=======\nZ\n>>>>>>> theirs\ne\nf\ng\nh\ni\n",
+            ),
+        ];
+        let options = Options {
+            ours: "ours",
+            base: "base",
+            theirs: "theirs",
+            style: Style::Zdiff3,
+            marker_size: DEFAULT_MARKER_SIZE,
+        };
+        for (ours, theirs, want) in cases {
+            let got = merge(
+                base.as_bytes(),
+                ours.as_bytes(),
+                theirs.as_bytes(),
+                &options,
+            );
+            assert_eq!(String::from_utf8(got.content).unwrap(), want);
+            assert_eq!(got.conflicts, 1);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+
+use crate::error::{Error, Result};
+
+/// A `<file>.lock` held while a file is rewritten; committing renames it
+/// over the file, dropping it without committing releases it.
+pub struct Lock {
+    path: PathBuf,
+    lock_path: PathBuf,
+    committed: bool,
+}
+
+impl Lock {
+    pub fn acquire(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| Error::io("could not create directory", parent, e))?;
+        }
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::LockExists(lock_path))
+            }
+            Err(e) => return Err(Error::io("Unable to create", lock_path, e)),
+        }
+        Ok(Self {
+            path,
+            lock_path,
+            committed: false,
+        })
+    }
+
+    pub fn commit(mut self, content: impl AsRef<[u8]>) -> Result<()> {
+        let error = |e| Error::io("could not write", &self.lock_path, e);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.lock_path)
+            .map_err(error)?;
+        file.write_all(content.as_ref()).map_err(error)?;
+        file.sync_all().map_err(error)?;
+        fs::rename(&self.lock_path, &self.path)
+            .map_err(|e| Error::io("could not rename into", &self.path, e))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
@@ -0,0 +1,709 @@
+use std::path::Path;
+
+use crate::{commit::Commit, error::Result, hash::Hash, object, patch, revwalk::Walked};
+
+/// What `log` shows and how.
+pub struct Options {
+    pub revisions: Vec<String>,
+    pub paths: Vec<String>,
+    pub format: Format,
+    pub max_count: Option<usize>,
+    pub graph: bool,
+    pub first_parent: bool,
+    pub author: Option<String>,
+    pub grep: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    /// How each commit's changes are shown, if they are.
+    pub diff: Option<patch::Options>,
+}
+
+impl Options {
+    /// Whether a commit passes the `--author`, `--grep`, `--since` and
+    /// `--until` filters. Patterns are matched as plain text.
+    pub fn matches(&self, commit: &Commit) -> bool {
+        let author = commit.author.identity();
+        let date = commit.committer.timestamp;
+        self.author
+            .as_ref()
+            .map_or(true, |p| author.contains(p.as_str()))
+            && self.grep.as_ref().map_or(true, |p| {
+                String::from_utf8_lossy(&commit.message).contains(p.as_str())
+            })
+            && self.since.map_or(true, |since| date >= since)
+            && self.until.map_or(true, |until| date <= until)
+    }
+}
+
+pub enum Format {
+    /// git's default `medium` format.
+    Medium,
+    /// `--oneline`, as the format string it stands for.
+    Oneline(String),
+    /// A `--format` string with `%` placeholders.
+    Custom(String),
+}
+
+impl Format {
+    /// Parses a `--format`/`--pretty` value: `medium`, `oneline`, or a
+    /// format string, optionally prefixed by `format:` or `tformat:`.
+    pub fn parse(format: &str) -> Self {
+        match format {
+            "medium" => Self::Medium,
+            "oneline" => Self::Oneline(String::from("%H %s")),
+            format => {
+                let format = format
+                    .strip_prefix("format:")
+                    .or_else(|| format.strip_prefix("tformat:"))
+                    .unwrap_or(format);
+                Self::Custom(format.to_string())
+            }
+        }
+    }
+
+    /// Whether entries are separated by a blank line.
+    pub fn is_multiline(&self) -> bool {
+        matches!(self, Self::Medium)
+    }
+
+    /// Whether a blank line separates an entry from the changes shown
+    /// after it.
+    pub fn separates_diff(&self) -> bool {
+        !matches!(self, Self::Oneline(_))
+    }
+
+    /// Formats a commit's entry, without a trailing newline.
+    pub fn format(&self, root: impl AsRef<Path>, walked: &Walked) -> Result<String> {
+        let commit = &walked.commit;
+        let message = String::from_utf8_lossy(&commit.message);
+        let (Self::Oneline(format) | Self::Custom(format)) = self else {
+            let mut lines = vec![format!("commit {}", walked.hash)];
+            if commit.parents.len() > 1 {
+                let parents = commit
+                    .parents
+                    .iter()
+                    .map(|parent| object::abbreviate(&root, parent, 7))
+                    .collect::<Result<Vec<_>>>()?;
+                lines.push(format!("Merge: {}", parents.join(" ")));
+            }
+            lines.push(format!("Author: {}", commit.author.identity()));
+            lines.push(format!("Date:   {}", commit.author.format_date()));
+            lines.push(String::new());
+            for line in message.trim_end_matches('\n').lines() {
+                lines.push(format!("    {}", line));
+            }
+            return Ok(lines.join("\n"));
+        };
+
+        let abbreviate = |hash: &Hash| object::abbreviate(&root, hash, 7);
+        let (subject, body) = split_message(&message);
+        let mut output = String::new();
+        let mut rest = format.as_str();
+        while let Some(pos) = rest.find('%') {
+            output.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            let signature = match rest.get(..1) {
+                Some("a") => Some(&commit.author),
+                Some("c") => Some(&commit.committer),
+                _ => None,
+            };
+            let field = signature.and_then(|signature| match rest.get(1..2) {
+                Some("n") => Some(String::from_utf8_lossy(&signature.name).into_owned()),
+                Some("e") => Some(String::from_utf8_lossy(&signature.email).into_owned()),
+                Some("d") => Some(signature.format_date()),
+                _ => None,
+            });
+            let (expansion, len) = match (field, rest.get(..1).unwrap_or_default()) {
+                (Some(field), _) => (field, 2),
+                (None, "H") => (walked.hash.to_string(), 1),
+                (None, "h") => (abbreviate(&walked.hash)?, 1),
+                (None, "T") => (commit.tree.to_string(), 1),
+                (None, "t") => (abbreviate(&commit.tree)?, 1),
+                (None, "P") => (join(&commit.parents, |p| Ok(p.to_string()))?, 1),
+                (None, "p") => (join(&commit.parents, abbreviate)?, 1),
+                (None, "s") => (subject.clone(), 1),
+                (None, "b") => (body.clone(), 1),
+                (None, "n") => (String::from("\n"), 1),
+                (None, "%") => (String::from("%"), 1),
+                // Unknown placeholders are printed as they are.
+                (None, _) => (String::from("%"), 0),
+            };
+            output.push_str(&expansion);
+            rest = &rest[len..];
+        }
+        output.push_str(rest);
+        Ok(output)
+    }
+}
+
+fn join(hashes: &[Hash], format: impl Fn(&Hash) -> Result<String>) -> Result<String> {
+    Ok(hashes
+        .iter()
+        .map(format)
+        .collect::<Result<Vec<_>>>()?
+        .join(" "))
+}
+
+/// Splits a message into its subject, the first paragraph joined into one
+/// line, and its body, the rest after the blank lines that follow.
+pub fn split_message(message: &str) -> (String, String) {
+    let mut lines = message.lines().skip_while(|line| line.trim().is_empty());
+    let subject: Vec<_> = lines
+        .by_ref()
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect();
+    let body: Vec<_> = lines.skip_while(|line| line.trim().is_empty()).collect();
+    let body = match body.is_empty() {
+        true => String::new(),
+        false => format!("{}\n", body.join("\n")),
+    };
+    (subject.join(" "), body)
+}
+
+/// What the next line of a commit's graph shows.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum State {
+    /// The columns carry on unchanged; the commit is done.
+    #[default]
+    Padding,
+    /// Commits were left out before this one.
+    Skip,
+    /// Rows that widen the graph around a merge of three or more parents.
+    PreCommit,
+    /// The row with the commit itself.
+    Commit,
+    /// The row where a merge branches out to its parents.
+    PostMerge,
+    /// Rows that move lines left until every one is in its column.
+    Collapsing,
+}
+
+/// Draws the ASCII history graph of `log --graph` one commit at a time,
+/// following git's `graph.c`: each column holds the commit expected next in
+/// it, and `mapping` tells, for every character of the row being drawn,
+/// which of the new columns the line there leads to.
+#[derive(Default)]
+pub struct Graph {
+    commit: Option<Hash>,
+    parents: Vec<Hash>,
+    columns: Vec<Hash>,
+    new_columns: Vec<Hash>,
+    mapping: Vec<Option<usize>>,
+    old_mapping: Vec<Option<usize>>,
+    mapping_size: usize,
+    width: usize,
+    expansion_row: usize,
+    state: State,
+    prev_state: State,
+    commit_index: usize,
+    prev_commit_index: usize,
+    /// Whether a merge's first parent is drawn right below it (0) or one
+    /// column to its right (1).
+    merge_layout: isize,
+    /// How many columns a merge adds to the right of it, or -1 when its
+    /// last parent joins the column right next to it.
+    edges_added: isize,
+    prev_edges_added: isize,
+}
+
+impl Graph {
+    /// Moves on to the next commit shown, whose columns lead to `parents`.
+    pub fn update(&mut self, hash: Hash, parents: &[Hash]) {
+        self.commit = Some(hash);
+        self.parents = parents.to_vec();
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    /// Moves past a commit that is not shown, so that its column continues
+    /// with its parents.
+    pub fn skip(&mut self, hash: Hash, parents: &[Hash]) {
+        self.update(hash, parents);
+        while self.state != State::Padding {
+            self.next_line();
+        }
+    }
+
+    /// The width of the graph in front of the current commit's lines.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The line printed between two entries, before the entry of the
+    /// commit last passed to [`Graph::update`].
+    pub fn separator(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+        let mut line = String::new();
+        for column in &self.columns {
+            line.push('|');
+            match Some(*column) == self.commit && self.parents.len() > 2 {
+                true => line.push_str(&" ".repeat((self.parents.len() - 2) * 2)),
+                false => line.push(' '),
+            }
+        }
+        self.prev_state = State::Padding;
+        self.pad(line)
+    }
+
+    /// Prefixes the lines of the current commit's entry with the graph,
+    /// adding lines that show the columns branching or merging if the entry
+    /// is too short to hold them.
+    pub fn render(&mut self, text: &str) -> Vec<String> {
+        let mut lines = vec![];
+        let mut commit_line = loop {
+            let (line, is_commit) = self.next_line();
+            if is_commit {
+                break Some(line);
+            }
+            lines.push(line);
+        };
+        for text_line in text.split('\n') {
+            let prefix = commit_line.take().unwrap_or_else(|| self.next_line().0);
+            lines.push(format!("{}{}", prefix, text_line));
+        }
+        while self.state != State::Padding {
+            lines.push(self.next_line().0);
+        }
+        lines
+    }
+
+    /// The graph in front of the lines printed after a commit's entry,
+    /// once it is done.
+    pub fn padding(&mut self) -> String {
+        self.next_line().0
+    }
+
+    /// Replaces the current commit's column by its parents, merging columns
+    /// that expect the same commit, and maps where each line leads.
+    fn update_columns(&mut self) {
+        let hash = self.commit.unwrap();
+        self.columns = std::mem::take(&mut self.new_columns);
+        let max_new_columns = self.columns.len() + self.parents.len();
+        self.mapping = vec![None; 2 * max_new_columns];
+        if self.old_mapping.len() < self.mapping.len() {
+            self.old_mapping.resize(self.mapping.len(), None);
+        }
+        self.mapping_size = self.mapping.len();
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        let mut seen = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => *column,
+                None if seen => break,
+                None => hash,
+            };
+            if column == hash {
+                seen = true;
+                self.commit_index = i;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    self.insert_into_new_columns(parent, Some(i));
+                }
+                // The commit takes up room even without parents.
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(column, None);
+            }
+        }
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1].is_none() {
+            self.mapping_size -= 1;
+        }
+    }
+
+    /// Adds `hash` to the new columns, unless a column expects it already,
+    /// and maps the line leading there from the old column `idx`, the
+    /// current commit's, or from the next position.
+    fn insert_into_new_columns(&mut self, hash: Hash, idx: Option<usize>) {
+        let i = match self.new_columns.iter().position(|&column| column == hash) {
+            Some(i) => i,
+            None => {
+                self.new_columns.push(hash);
+                self.new_columns.len() - 1
+            }
+        };
+        let mapping_idx = match idx {
+            // The first parent of a merge: its layout depends on whether
+            // that parent has a column to the left already.
+            Some(idx) if self.parents.len() > 1 && self.merge_layout == -1 => {
+                let dist = idx as isize - i as isize;
+                let shift = if dist > 1 { 2 * dist - 3 } else { 1 };
+                self.merge_layout = if dist > 0 { 0 } else { 1 };
+                self.edges_added = self.parents.len() as isize + self.merge_layout - 2;
+                let mapping_idx = self.width as isize + (self.merge_layout - 1) * shift;
+                self.width += 2 * self.merge_layout as usize;
+                mapping_idx as usize
+            }
+            // A merge's line that joins the column right next to it.
+            _ if self.edges_added > 0
+                && self.width >= 2
+                && self.mapping[self.width - 2] == Some(i) =>
+            {
+                self.edges_added = -1;
+                self.width - 2
+            }
+            _ => {
+                self.width += 2;
+                self.width - 2
+            }
+        };
+        self.mapping[mapping_idx] = Some(i);
+    }
+
+    fn num_dashed_parents(&self) -> isize {
+        self.parents.len() as isize + self.merge_layout - 3
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && (self.commit_index as isize) < self.columns.len() as isize - 1
+            && (self.expansion_row as isize) < self.num_dashed_parents() * 2
+    }
+
+    /// Whether every line is in its column, or one to the right of it,
+    /// where the `/` just drawn puts it right on the next row.
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, target)| target.map_or(true, |target| target == i / 2))
+    }
+
+    fn update_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    fn pad(&self, line: String) -> String {
+        format!("{:<width$}", line, width = self.width)
+    }
+
+    /// Draws the next line of the graph, and whether it is the commit's.
+    fn next_line(&mut self) -> (String, bool) {
+        let mut line = String::new();
+        let state = self.state;
+        match state {
+            State::Padding => {
+                for _ in &self.new_columns {
+                    line.push_str("| ");
+                }
+            }
+            State::Skip => {
+                line.push_str("...");
+                let state = match self.needs_pre_commit_line() {
+                    true => State::PreCommit,
+                    false => State::Commit,
+                };
+                self.update_state(state);
+            }
+            State::PreCommit => self.pre_commit_line(&mut line),
+            State::Commit => self.commit_line(&mut line),
+            State::PostMerge => self.post_merge_line(&mut line),
+            State::Collapsing => self.collapsing_line(&mut line),
+        }
+        (self.pad(line), state == State::Commit)
+    }
+
+    /// Widens the graph by a column to the right of a merge of three or
+    /// more parents, two rows per parent past the second.
+    fn pre_commit_line(&mut self, line: &mut String) {
+        let mut seen = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if Some(*column) == self.commit {
+                seen = true;
+                line.push('|');
+                line.push_str(&" ".repeat(self.expansion_row));
+            } else if seen && self.expansion_row == 0 {
+                // Lines drawn as `\` after the last merge stay so.
+                match self.prev_state == State::PostMerge && self.prev_commit_index < i {
+                    true => line.push('\\'),
+                    false => line.push('|'),
+                }
+            } else if seen {
+                line.push('\\');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(State::Commit);
+        }
+    }
+
+    fn commit_line(&mut self, line: &mut String) {
+        let hash = self.commit.unwrap();
+        let mut seen = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => *column,
+                None if seen => break,
+                None => hash,
+            };
+            if column == hash {
+                seen = true;
+                line.push('*');
+                // The dashes of an octopus merge.
+                let dashed = self.num_dashed_parents();
+                for j in 0..dashed.max(0) {
+                    line.push('-');
+                    line.push(if j == dashed - 1 { '.' } else { '-' });
+                }
+            } else if seen && self.edges_added > 1 {
+                line.push('\\');
+            } else if seen && self.edges_added == 1 {
+                // A line drawn as `\` after the last merge stays so.
+                match self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    true => line.push('\\'),
+                    false => line.push('|'),
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping.get(2 * i + 1) == Some(&Some(i))
+                && self.mapping[2 * i].map_or(true, |target| target < i)
+            {
+                line.push('/');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+        let state = if self.parents.len() > 1 {
+            State::PostMerge
+        } else if self.is_mapping_correct() {
+            State::Padding
+        } else {
+            State::Collapsing
+        };
+        self.update_state(state);
+    }
+
+    /// Branches a merge out to the columns of its parents.
+    fn post_merge_line(&mut self, line: &mut String) {
+        let hash = self.commit.unwrap();
+        let mut seen = false;
+        let mut after_first_parent = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => *column,
+                None if seen => break,
+                None => hash,
+            };
+            if column == hash {
+                seen = true;
+                let mut idx = self.merge_layout as usize;
+                for j in 0..self.parents.len() {
+                    line.push(['/', '|', '\\'][idx]);
+                    if idx < 2 {
+                        idx += 1;
+                    } else if self.edges_added > 0 || j < self.parents.len() - 1 {
+                        line.push(' ');
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen {
+                line.push(if self.edges_added > 0 { '\\' } else { '|' });
+                line.push(' ');
+            } else {
+                line.push('|');
+                if self.merge_layout != 0 || i + 1 != self.commit_index {
+                    line.push(if after_first_parent { '_' } else { ' ' });
+                }
+            }
+            if column == self.parents[0] {
+                after_first_parent = true;
+            }
+        }
+        let state = match self.is_mapping_correct() {
+            true => State::Padding,
+            false => State::Collapsing,
+        };
+        self.update_state(state);
+    }
+
+    /// Moves every line that is not in its column one step left, crossing
+    /// or joining the lines in its way. One line at a time may move further
+    /// along a horizontal `_` edge.
+    fn collapsing_line(&mut self, line: &mut String) {
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        let size = self.mapping_size;
+        self.mapping[..size].fill(None);
+
+        let mut horizontal_edge = None;
+        let mut horizontal_edge_target = None;
+        for i in 0..size {
+            let Some(target) = self.old_mapping[i] else {
+                continue;
+            };
+            if target * 2 == i {
+                self.mapping[i] = Some(target);
+                continue;
+            }
+            let edge = match self.mapping[i - 1] {
+                // Nothing to the left: move one position left.
+                None => {
+                    self.mapping[i - 1] = Some(target);
+                    i
+                }
+                // Join the line to the left, which leads to the same
+                // column.
+                Some(left) if left == target => continue,
+                // Cross the line to the left.
+                Some(_) => {
+                    self.mapping[i - 2] = Some(target);
+                    i - 1
+                }
+            };
+            if horizontal_edge.is_none() {
+                horizontal_edge = Some(edge);
+                horizontal_edge_target = Some(target);
+                let mut j = target * 2 + 3;
+                while j + 2 < i {
+                    self.mapping[j] = Some(target);
+                    j += 2;
+                }
+            }
+        }
+        self.old_mapping[..size].copy_from_slice(&self.mapping[..size]);
+        if self.mapping[size - 1].is_none() {
+            self.mapping_size -= 1;
+        }
+
+        let mut used_horizontal = false;
+        for i in 0..self.mapping_size {
+            match self.mapping[i] {
+                None => line.push(' '),
+                Some(target) if target * 2 == i => line.push('|'),
+                Some(target)
+                    if Some(target) == horizontal_edge_target && Some(i + 1) != horizontal_edge =>
+                {
+                    // Only the first segment continues on the next row.
+                    if i != target * 2 + 3 {
+                        self.mapping[i] = None;
+                    }
+                    used_horizontal = true;
+                    line.push('_');
+                }
+                Some(_) => {
+                    if used_horizontal && horizontal_edge.is_some_and(|edge| i < edge) {
+                        self.mapping[i] = None;
+                    }
+                    line.push('/');
+                }
+            }
+        }
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::Hash;
+
+    use super::{split_message, Graph};
+
+    #[test]
+    fn test_split_message() {
+        assert_eq!(
+            split_message("sub line\ncontinued\n\nbody para\n\nsecond\n"),
+            (
+                String::from("sub line continued"),
+                String::from("body para\n\nsecond\n")
+            )
+        );
+        assert_eq!(
+            split_message("subject\n"),
+            (String::from("subject"), String::new())
+        );
+    }
+
+    #[test]
+    fn test_graph() {
+        let [merge, three, two, one] = [1, 2, 3, 4].map(|byte| Hash::new([byte; 20]));
+        let mut graph = Graph::default();
+        let mut lines = vec![];
+        for (hash, parents, text) in [
+            (merge, vec![two, three], "merge"),
+            (three, vec![one], "three"),
+            (two, vec![one], "two"),
+            (one, vec![], "one"),
+        ] {
+            graph.update(hash, &parents);
+            lines.extend(graph.render(text));
+        }
+        assert_eq!(
+            lines,
+            [
+                "*   merge",
+                "|\\  ",
+                "| * three",
+                "* | two",
+                "|/  ",
+                "* one"
+            ]
+        );
+
+        let mut graph = Graph::default();
+        graph.update(merge, &[two, three]);
+        assert_eq!(graph.width(), 4);
+        let lines = graph.render("commit\nMerge: 3 2\nAuthor");
+        assert_eq!(lines, ["*   commit", "|\\  Merge: 3 2", "| | Author"]);
+        graph.update(three, &[one]);
+        assert_eq!(graph.separator(), "| | ");
+        let mut graph = Graph::default();
+        graph.update(merge, &[two, three]);
+        assert_eq!(graph.separator(), "    ");
+    }
+
+    #[test]
+    fn test_graph_criss_cross() {
+        let [f, c2, m2, b2, m1, c1, b1, a] =
+            [1, 2, 3, 4, 5, 6, 7, 8].map(|byte| Hash::new([byte; 20]));
+        let mut graph = Graph::default();
+        let mut lines = vec![];
+        for (hash, parents, text) in [
+            (f, vec![b2, c2], "F"),
+            (c2, vec![m2], "C2"),
+            (m2, vec![c1, b1], "M2"),
+            (b2, vec![m1], "B2"),
+            (m1, vec![b1, c1], "M1"),
+            (c1, vec![a], "C1"),
+            (b1, vec![a], "B1"),
+            (a, vec![], "A"),
+        ] {
+            graph.update(hash, &parents);
+            lines.extend(graph.render(text));
+        }
+        // What `git log --graph --format=%s` draws: the lines of the merges
+        // join the columns their parents have already.
+        assert_eq!(
+            lines,
+            [
+                "*   F", "|\\  ", "| * C2", "| *   M2", "| |\\  ", "* | | B2", "* | | M1",
+                "|\\| | ", "| |/  ", "|/|   ", "| * C1", "* | B1", "|/  ", "* A",
+            ]
+        );
+    }
+}
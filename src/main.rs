@@ -1,5 +1,7 @@
+use std::process::ExitCode;
+
 use codecrafters_git::run;
 
-fn main() {
-    run();
+fn main() -> ExitCode {
+    run()
 }
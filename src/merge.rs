@@ -0,0 +1,477 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use crate::{
+    blob::Blob,
+    error::Result,
+    file_mode::FileMode,
+    hash::Hash,
+    index::IndexEntry,
+    line_merge::{self, Style},
+    merge_base,
+    object::{self, Object},
+    patch, revision, tree,
+    tree_diff::{self, DiffEntry, Status},
+};
+
+/// Whether `merge` may just move the branch forward when it can.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FastForward {
+    Allow,
+    /// Always creates a merge commit.
+    Never,
+    /// Refuses to merge unless it can fast-forward.
+    Only,
+}
+
+/// What a merge calls its sides in messages and conflict markers, and how
+/// it writes conflicts.
+pub struct Options<'a> {
+    pub ours: &'a str,
+    pub theirs: &'a str,
+    pub style: Style,
+}
+
+/// A file's mode and blob.
+type Version = (u32, Hash);
+
+/// A path left unmerged, with the versions the index records for it as
+/// stages 1, 2 and 3: the merge base's, ours and theirs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub path: String,
+    pub stages: [Option<Version>; 3],
+}
+
+/// The result of a merge: the merged tree, in which conflicting files
+/// hold conflict markers, with the paths left unmerged and the messages
+/// describing what happened to them.
+pub struct Merged {
+    pub tree: Hash,
+    pub conflicts: Vec<Conflict>,
+    pub messages: Vec<String>,
+}
+
+/// A commit to merge, or a merge of merge bases made up on the way, which
+/// stands for the real commits it was made from.
+struct Virtual {
+    commits: Vec<Hash>,
+    tree: Option<Hash>,
+}
+
+impl Virtual {
+    fn commit(root: &Path, hash: Hash) -> Result<Self> {
+        Ok(Self {
+            commits: vec![hash],
+            tree: Some(revision::peel(root, hash, "tree")?.0),
+        })
+    }
+}
+
+/// Merges commit `theirs` into commit `ours` like git's `ort` strategy.
+/// When they have several merge bases, as in criss-cross histories, the
+/// bases are first merged into one, oldest first, and conflicts in that
+/// merge are left in the tree it serves as the base for.
+pub fn merge_commits(
+    root: impl AsRef<Path>,
+    ours: Hash,
+    theirs: Hash,
+    options: &Options,
+) -> Result<Merged> {
+    let root = root.as_ref();
+    let ours = Virtual::commit(root, ours)?;
+    let theirs = Virtual::commit(root, theirs)?;
+    merge_recursive(root, &ours, &theirs, options, 0)
+}
+
+fn merge_recursive(
+    root: &Path,
+    ours: &Virtual,
+    theirs: &Virtual,
+    options: &Options,
+    depth: usize,
+) -> Result<Merged> {
+    let mut bases = merge_base::merge_bases(root, &ours.commits, &theirs.commits)?;
+    bases.reverse();
+    let ancestor = match bases.as_slice() {
+        [] => String::from("empty tree"),
+        [base] => object::abbreviate(root, base, 7)?,
+        _ => String::from("merged common ancestors"),
+    };
+    let mut base = match bases.first() {
+        Some(&first) => Virtual::commit(root, first)?,
+        None => Virtual {
+            commits: vec![],
+            tree: None,
+        },
+    };
+    for &next in bases.iter().skip(1) {
+        let next = Virtual::commit(root, next)?;
+        let inner = Options {
+            ours: "Temporary merge branch 1",
+            theirs: "Temporary merge branch 2",
+            style: options.style,
+        };
+        let merged = merge_recursive(root, &base, &next, &inner, depth + 1)?;
+        base.commits.extend(next.commits);
+        base.tree = Some(merged.tree);
+    }
+
+    let trees = [base.tree, ours.tree, theirs.tree];
+    let labels = [ancestor.as_str(), options.ours, options.theirs];
+    merge_trees(root, trees, labels, options.style, depth)
+}
+
+/// A path of the merged tree and what each side has there. With renames,
+/// a side's version may come from another path, which `names` records.
+struct Slot {
+    stages: [Option<Version>; 3],
+    names: [String; 3],
+    /// A conflict found while pairing up renames, and the version the
+    /// merged tree gets.
+    conflict: Option<Option<Version>>,
+}
+
+impl Slot {
+    fn new(path: &str) -> Self {
+        Self {
+            stages: [None; 3],
+            names: [path.to_string(), path.to_string(), path.to_string()],
+            conflict: None,
+        }
+    }
+}
+
+/// Three-way merges trees `[base, ours, theirs]` called by `labels`.
+/// `depth` counts how deep in merging merge bases this is; such inner
+/// merges say nothing, take the base's version where they cannot merge,
+/// and use longer conflict markers.
+fn merge_trees(
+    root: &Path,
+    trees: [Option<Hash>; 3],
+    labels: [&str; 3],
+    style: Style,
+    depth: usize,
+) -> Result<Merged> {
+    let flat = [
+        tree_diff::flatten_tree(root, trees[0])?,
+        tree_diff::flatten_tree(root, trees[1])?,
+        tree_diff::flatten_tree(root, trees[2])?,
+    ];
+    let maps: Vec<BTreeMap<String, Version>> = flat
+        .iter()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+                .collect()
+        })
+        .collect();
+    let mut slots: BTreeMap<String, Slot> = BTreeMap::new();
+    for (side, map) in maps.iter().enumerate() {
+        for (path, version) in map {
+            let slot = slots.entry(path.clone()).or_insert_with(|| Slot::new(path));
+            slot.stages[side] = Some(*version);
+        }
+    }
+
+    let mut messages: Vec<(String, String)> = vec![];
+    let renames = [
+        renames(root, &flat[0], &flat[1])?,
+        renames(root, &flat[0], &flat[2])?,
+    ];
+    for side in [1, 2] {
+        let other = 3 - side;
+        for (src, dst) in &renames[side - 1] {
+            let base = maps[0][src];
+            match renames[other - 1].get(src) {
+                // Both renamed it the same way, which only needs doing once.
+                Some(other_dst) if other_dst == dst => {
+                    if side == 1 {
+                        let slot = slots.get_mut(dst).unwrap();
+                        slot.stages[0] = Some(base);
+                        slot.names[0] = src.clone();
+                        slots.remove(src);
+                    }
+                }
+                Some(other_dst) => {
+                    if side == 1 {
+                        messages.push((
+                            src.clone(),
+                            format!(
+                                "CONFLICT (rename/rename): {} renamed to {} in {} and to {} in {}.",
+                                src, dst, labels[1], other_dst, labels[2]
+                            ),
+                        ));
+                        for (side, dst) in [(1, dst), (2, other_dst)] {
+                            let slot = slots.get_mut(dst).unwrap();
+                            slot.stages[0] = Some(base);
+                            slot.names[0] = src.clone();
+                            slot.conflict = Some(slot.stages[side]);
+                        }
+                        slots.remove(src);
+                    }
+                }
+                None if maps[other].contains_key(dst) => {}
+                None => {
+                    let kept = maps[other].get(src).copied();
+                    let slot = slots.get_mut(dst).unwrap();
+                    slot.stages[0] = Some(base);
+                    slot.names[0] = src.clone();
+                    match kept {
+                        Some(version) => {
+                            slot.stages[other] = Some(version);
+                            slot.names[other] = src.clone();
+                        }
+                        None if depth == 0 => {
+                            messages.push((
+                                dst.clone(),
+                                format!(
+                                    "CONFLICT (rename/delete): {} renamed to {} in {}, but deleted in {}.",
+                                    src, dst, labels[side], labels[other]
+                                ),
+                            ));
+                            slot.conflict = Some(slot.stages[side]);
+                        }
+                        None => slot.conflict = Some(None),
+                    }
+                    slots.remove(src);
+                }
+            }
+        }
+    }
+
+    let mut results: BTreeMap<String, Version> = BTreeMap::new();
+    let mut conflicts: BTreeMap<String, [Option<Version>; 3]> = BTreeMap::new();
+    let mut merger = Merger {
+        root,
+        labels,
+        style,
+        depth,
+        messages,
+    };
+    // Paths below a directory come first, so that a file knows whether
+    // a directory in its place survives the merge, in which case it is
+    // moved out of the way.
+    for (path, slot) in slots.iter().rev() {
+        let mut path = path.clone();
+        let has_file = slot.stages[1].is_some() || slot.stages[2].is_some();
+        let in_the_way = has_file && has_below(&results, &path);
+        if in_the_way {
+            let side = if has_below(&maps[1], &path) { 2 } else { 1 };
+            let label = labels[side].replace('/', "_");
+            let mut moved = format!("{}~{}", path, label);
+            let mut n = 0;
+            while slots.contains_key(&moved) || results.contains_key(&moved) {
+                moved = format!("{}~{}_{}", path, label, n);
+                n += 1;
+            }
+            merger.say(
+                &moved,
+                format!(
+                    "CONFLICT (file/directory): directory in the way of {} from {}; \
+                     moving it to {} instead.",
+                    path, labels[side], moved
+                ),
+            );
+            path = moved;
+        }
+        let (result, clean) = match slot.conflict {
+            Some(result) => (result, false),
+            None => merger.merge_path(&path, slot)?,
+        };
+        if let Some(result) = result {
+            results.insert(path.clone(), result);
+        }
+        if !clean || (in_the_way && result.is_some()) {
+            conflicts.insert(path, slot.stages);
+        }
+    }
+    let mut messages = merger.messages;
+
+    let entries: Vec<IndexEntry> = results
+        .into_iter()
+        .map(|(path, (mode, hash))| IndexEntry::new(path, hash, mode))
+        .collect();
+    let tree = tree::write_entries(root, &entries)?;
+    messages.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(Merged {
+        tree,
+        conflicts: conflicts
+            .into_iter()
+            .map(|(path, stages)| Conflict { path, stages })
+            .collect(),
+        messages: match depth {
+            0 => messages.into_iter().map(|(_, message)| message).collect(),
+            _ => vec![],
+        },
+    })
+}
+
+/// Whether `paths` has any below directory `path`.
+fn has_below<T>(paths: &BTreeMap<String, T>, path: &str) -> bool {
+    let dir = format!("{}/", path);
+    paths
+        .range(dir.clone()..)
+        .next()
+        .is_some_and(|(next, _)| next.starts_with(&dir))
+}
+
+/// The files `new` renamed from `base`, by their old path.
+fn renames(root: &Path, base: &[DiffEntry], new: &[DiffEntry]) -> Result<HashMap<String, String>> {
+    let changes = tree_diff::diff_entries(base.to_vec(), new.to_vec());
+    let changes =
+        tree_diff::detect_renames(changes, tree_diff::DEFAULT_MIN_SCORE, false, |entry| {
+            Ok(object::read_raw(root, &entry.hash.to_string())?.1)
+        })?;
+    Ok(changes
+        .into_iter()
+        .filter(|change| matches!(change.status, Status::Renamed(_)))
+        .filter_map(|change| Some((change.old?.path, change.new?.path)))
+        .collect())
+}
+
+struct Merger<'a> {
+    root: &'a Path,
+    labels: [&'a str; 3],
+    style: Style,
+    depth: usize,
+    messages: Vec<(String, String)>,
+}
+
+impl Merger<'_> {
+    fn say(&mut self, path: &str, message: String) {
+        self.messages.push((path.to_string(), message));
+    }
+
+    /// Merges what the sides have at `path`, returning the merged version
+    /// and whether it merged cleanly.
+    fn merge_path(&mut self, path: &str, slot: &Slot) -> Result<(Option<Version>, bool)> {
+        let [base, ours, theirs] = slot.stages;
+        if ours == theirs || base == theirs {
+            return Ok((ours, true));
+        }
+        if base == ours {
+            return Ok((theirs, true));
+        }
+        let labels = self.labels;
+        match (ours, theirs) {
+            (Some(ours), Some(theirs)) => {
+                let is_file = |mode| FileMode::type_of(mode) == Some(FileMode::Blob);
+                let both_files = is_file(ours.0) && is_file(theirs.0);
+                if !both_files {
+                    // Symbolic links, submodules and files that changed
+                    // type cannot be merged.
+                    self.say(
+                        path,
+                        format!("CONFLICT (content): Merge conflict in {}", path),
+                    );
+                    return Ok((if self.depth > 0 { base } else { Some(ours) }, false));
+                }
+                let (result, clean) = self.merge_files(path, slot, ours, theirs)?;
+                if !clean {
+                    let reason = if base.is_some() { "content" } else { "add/add" };
+                    self.say(
+                        path,
+                        format!("CONFLICT ({}): Merge conflict in {}", reason, path),
+                    );
+                }
+                Ok((Some(result), clean))
+            }
+            (modified, _) => {
+                if self.depth > 0 {
+                    return Ok((base, false));
+                }
+                let (deleted_in, modified_in) = match modified {
+                    Some(_) => (labels[2], labels[1]),
+                    None => (labels[1], labels[2]),
+                };
+                let modified = modified.or(theirs);
+                self.say(
+                    path,
+                    format!(
+                        "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  \
+                         Version {} of {} left in tree.",
+                        path, deleted_in, modified_in, modified_in, path
+                    ),
+                );
+                Ok((modified, false))
+            }
+        }
+    }
+
+    /// Merges two versions of a regular file, their modes and contents.
+    fn merge_files(
+        &mut self,
+        path: &str,
+        slot: &Slot,
+        ours: Version,
+        theirs: Version,
+    ) -> Result<(Version, bool)> {
+        let base = slot.stages[0];
+        let base_mode = base.map_or(0, |(mode, _)| mode);
+        let (mode, mut clean) = if ours.0 == theirs.0 || ours.0 == base_mode {
+            (theirs.0, true)
+        } else {
+            (ours.0, theirs.0 == base_mode)
+        };
+        let base_hash = base.map(|(_, hash)| hash);
+        if ours.1 == theirs.1 || Some(ours.1) == base_hash {
+            return Ok(((mode, theirs.1), clean));
+        }
+        if Some(theirs.1) == base_hash {
+            return Ok(((mode, ours.1), clean));
+        }
+
+        let read = |hash: Option<Hash>| -> Result<Vec<u8>> {
+            match hash {
+                Some(hash) => Ok(object::read_raw(self.root, &hash.to_string())?.1),
+                None => Ok(vec![]),
+            }
+        };
+        let base_content = read(base_hash)?;
+        let our_content = read(Some(ours.1))?;
+        let their_content = read(Some(theirs.1))?;
+        let binary = [&base_content, &our_content, &their_content]
+            .iter()
+            .any(|content| patch::is_binary(content));
+        let hash = if binary {
+            if self.depth > 0 {
+                base_hash.unwrap_or(ours.1)
+            } else {
+                self.say(
+                    path,
+                    format!(
+                        "warning: Cannot merge binary files: {} ({} vs. {})",
+                        path, self.labels[1], self.labels[2]
+                    ),
+                );
+                clean = false;
+                ours.1
+            }
+        } else {
+            let [base_label, our_label, their_label] = match slot.names.iter().all(|n| n == path) {
+                true => self.labels.map(str::to_string),
+                false => [0, 1, 2].map(|i| format!("{}:{}", self.labels[i], slot.names[i])),
+            };
+            let merged = line_merge::merge(
+                &base_content,
+                &our_content,
+                &their_content,
+                &line_merge::Options {
+                    ours: &our_label,
+                    base: &base_label,
+                    theirs: &their_label,
+                    style: self.style,
+                    marker_size: line_merge::DEFAULT_MARKER_SIZE + 2 * self.depth,
+                },
+            );
+            clean &= merged.conflicts == 0;
+            Object::Blob(Blob::new(merged.content)).write(self.root)?
+        };
+        self.say(path, format!("Auto-merging {}", path));
+        Ok(((mode, hash), clean))
+    }
+}
@@ -0,0 +1,154 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::{error::Result, hash::Hash, revwalk::RevWalk};
+
+/// Returns the commits reachable from `starts`, themselves included, in
+/// the order a walk by date reaches them.
+fn ancestors(root: &Path, starts: &[Hash]) -> Result<Vec<Hash>> {
+    let mut walk = RevWalk::new(root);
+    for start in starts {
+        walk.push(*start)?;
+    }
+    let mut commits = vec![];
+    while let Some(walked) = walk.next()? {
+        commits.push(walked.hash);
+    }
+    Ok(commits)
+}
+
+/// Returns the best common ancestors of `ones` and `twos`: the commits
+/// reachable from both that no other such commit can reach, youngest
+/// first. Like git, several commits on a side stand for a merge of them,
+/// so `merge-base A B C` finds the bases of `A` and a merge of `B` and `C`.
+pub fn merge_bases(root: impl AsRef<Path>, ones: &[Hash], twos: &[Hash]) -> Result<Vec<Hash>> {
+    let root = root.as_ref();
+    let from_twos: HashSet<Hash> = ancestors(root, twos)?.into_iter().collect();
+    let common: Vec<Hash> = ancestors(root, ones)?
+        .into_iter()
+        .filter(|hash| from_twos.contains(hash))
+        .collect();
+
+    // A common ancestor reachable from another one is not among the best.
+    let mut walk = RevWalk::new(root);
+    for hash in &common {
+        walk.push(*hash)?;
+    }
+    let mut redundant = HashSet::new();
+    while let Some(walked) = walk.next()? {
+        redundant.extend(walked.parents);
+    }
+    let mut bases: Vec<Hash> = common
+        .into_iter()
+        .filter(|hash| !redundant.contains(hash))
+        .collect();
+    bases.dedup();
+    Ok(bases)
+}
+
+/// Returns the common ancestors of all of `commits` that are needed for an
+/// octopus merge of them: the bases of the first two, then the bases of
+/// each of those with the third, and so on, leaving out the ones another
+/// reaches.
+pub fn octopus_bases(root: impl AsRef<Path>, commits: &[Hash]) -> Result<Vec<Hash>> {
+    let root = root.as_ref();
+    let Some((first, rest)) = commits.split_first() else {
+        return Ok(vec![]);
+    };
+    let mut bases = vec![*first];
+    for commit in rest {
+        let mut next = vec![];
+        for base in &bases {
+            next.extend(merge_bases(root, &[*commit], &[*base])?);
+        }
+        bases = next;
+    }
+    reduce(root, bases)
+}
+
+/// Drops the duplicates among `commits` and the ones another of them can
+/// reach.
+pub fn reduce(root: impl AsRef<Path>, commits: Vec<Hash>) -> Result<Vec<Hash>> {
+    let root = root.as_ref();
+    let mut unique = vec![];
+    for commit in commits {
+        if !unique.contains(&commit) {
+            unique.push(commit);
+        }
+    }
+    let mut kept = vec![];
+    for (i, commit) in unique.iter().enumerate() {
+        let others: Vec<Hash> = unique
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, other)| *other)
+            .collect();
+        if !is_ancestor(root, *commit, &others)? {
+            kept.push(*commit);
+        }
+    }
+    Ok(kept)
+}
+
+/// Whether `commit` is reachable from any of `descendants`.
+pub fn is_ancestor(root: impl AsRef<Path>, commit: Hash, descendants: &[Hash]) -> Result<bool> {
+    Ok(ancestors(root.as_ref(), descendants)?.contains(&commit))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        hash::Hash,
+        merge_base::{is_ancestor, merge_bases, octopus_bases},
+        reference_impl, test_utils,
+    };
+
+    #[test]
+    fn test_merge_bases() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let commit = |message: &str, parents: &[&str]| {
+            reference_impl::git_commit(&repository, message, parents)
+        };
+
+        // A criss-cross history, where `left` and `right` both merge `a`
+        // and `b`, has two best common ancestors.
+        let root_commit = commit("root", &[]);
+        let a = commit("a", &[&root_commit]);
+        let b = commit("b", &[&root_commit]);
+        let left = commit("left", &[&a, &b]);
+        let right = commit("right", &[&b, &a]);
+        let side = commit("side", &[&a]);
+        let lone = commit("lone", &[]);
+
+        for (one, two) in [
+            (&left, &right),
+            (&a, &b),
+            (&left, &a),
+            (&side, &right),
+            (&side, &b),
+        ] {
+            let mut expected = reference_impl::git_merge_bases(&repository, one, two);
+            let mut actual: Vec<String> =
+                merge_bases(&root, &[one.parse().unwrap()], &[two.parse().unwrap()])
+                    .unwrap()
+                    .iter()
+                    .map(Hash::to_string)
+                    .collect();
+            expected.sort();
+            actual.sort();
+            assert_eq!(actual, expected, "{} {}", one, two);
+        }
+        let hash = |hex: &String| -> Hash { hex.parse().unwrap() };
+        assert!(merge_bases(&root, &[hash(&lone)], &[hash(&left)])
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            octopus_bases(&root, &[hash(&side), hash(&left), hash(&b)]).unwrap(),
+            vec![hash(&root_commit)]
+        );
+        assert!(is_ancestor(&root, hash(&a), &[hash(&right)]).unwrap());
+        assert!(!is_ancestor(&root, hash(&side), &[hash(&left), hash(&right)]).unwrap());
+    }
+}
@@ -1,7 +1,14 @@
 use std::{path::Path, str::from_utf8};
 
 use crate::{
-    blob::Blob, bytes_reader::BytesReader, codec, commit::Commit, hash::Hash, input_output,
+    blob::Blob,
+    bytes_reader::BytesReader,
+    codec,
+    commit::Commit,
+    error::{Error, Result},
+    hash::Hash,
+    input_output, pack,
+    tag::Tag,
     tree_node::TreeNode,
 };
 
@@ -15,14 +22,18 @@ impl<'a> Header<'a> {
         Self { kind, size }
     }
 
-    pub fn parse(reader: &mut BytesReader<'a>) -> Self {
-        let kind = reader.read_until(b' ');
-        let kind = from_utf8(kind).unwrap();
-        reader.skip();
-        let size = reader.read_until(0);
-        let size = from_utf8(size).unwrap().parse::<usize>().unwrap();
-        reader.skip();
-        Self::new(kind, size)
+    pub fn parse(reader: &mut BytesReader<'a>) -> Result<Self> {
+        let bad_header = || Error::corrupt("bad object header");
+        let kind = reader.read_until(b' ').map_err(|_| bad_header())?;
+        let kind = from_utf8(kind).map_err(|_| bad_header())?;
+        reader.skip()?;
+        let size = reader.read_until(0).map_err(|_| bad_header())?;
+        let size = from_utf8(size)
+            .ok()
+            .and_then(|size| size.parse::<usize>().ok())
+            .ok_or_else(bad_header)?;
+        reader.skip()?;
+        Ok(Self::new(kind, size))
     }
 
     pub fn encode(&self) -> Vec<u8> {
@@ -30,35 +41,102 @@ impl<'a> Header<'a> {
     }
 }
 
+/// Returns the type and content of an object, looking at loose objects first
+/// and falling back to packs.
+pub fn read_raw(root: impl AsRef<Path>, hash: &str) -> Result<(&'static str, Vec<u8>)> {
+    if input_output::obj_exists(&root, hash) {
+        let compressed = input_output::read_obj(root, hash)?;
+        let bytes = codec::decompress(&compressed)
+            .map_err(|e| Error::corrupt(format!("loose object {} is corrupt: {}", hash, e)))?;
+        let mut reader = BytesReader::new(&bytes);
+        let header = Header::parse(&mut reader)?;
+        if reader.len() != header.size {
+            return Err(Error::corrupt(format!("object {} has a bad size", hash)));
+        }
+        let kind = parse_kind(header.kind)?;
+        return Ok((kind, reader.read_all().to_vec()));
+    }
+
+    let Ok(parsed) = hash.parse() else {
+        return Err(Error::InvalidObjectName(hash.to_string()));
+    };
+    pack::read_obj(root.as_ref(), &parsed)?
+        .ok_or_else(|| Error::InvalidObjectName(hash.to_string()))
+}
+
+/// Returns the objects, loose or packed, whose hash starts with the hex
+/// digits `prefix`.
+pub fn find_by_prefix(root: impl AsRef<Path>, prefix: &str) -> Result<Vec<Hash>> {
+    let prefix = prefix.to_ascii_lowercase();
+    let mut hashes = input_output::find_objs(&root, &prefix)?
+        .iter()
+        .map(|hash| hash.parse())
+        .collect::<Result<Vec<Hash>>>()?;
+    for pack in pack::packs(root.as_ref())? {
+        hashes.extend(pack.index.find_prefix(&prefix));
+    }
+    hashes.sort();
+    hashes.dedup();
+    Ok(hashes)
+}
+
+/// Returns the shortest prefix of `hash`, at least `min_len` digits long,
+/// that names no other object.
+pub fn abbreviate(root: impl AsRef<Path>, hash: &Hash, min_len: usize) -> Result<String> {
+    let hex = hash.to_string();
+    let mut len = min_len.clamp(4, hex.len());
+    // Every longer prefix only matches a subset of these.
+    let candidates = find_by_prefix(&root, &hex[..len])?;
+    while len < hex.len()
+        && candidates
+            .iter()
+            .any(|other| other != hash && other.to_string().starts_with(&hex[..len]))
+    {
+        len += 1;
+    }
+    Ok(hex[..len].to_string())
+}
+
+fn parse_kind(kind: &str) -> Result<&'static str> {
+    match kind {
+        "blob" => Ok("blob"),
+        "tree" => Ok("tree"),
+        "commit" => Ok("commit"),
+        "tag" => Ok("tag"),
+        kind => Err(Error::corrupt(format!("invalid object type \"{}\"", kind))),
+    }
+}
+
+/// Writes a loose object of the given type and returns its hash.
+pub fn write_raw(root: impl AsRef<Path>, kind: &str, content: &[u8]) -> Result<Hash> {
+    let mut bytes = Header::new(kind, content.len()).encode();
+    bytes.extend_from_slice(content);
+    let hash = Hash::hash(&bytes);
+    input_output::write_obj(root, &hash.to_string(), &codec::compress(&bytes))?;
+    Ok(hash)
+}
+
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Object {
     Blob(Blob),
     TreeNode(TreeNode),
     Commit(Commit),
+    Tag(Tag),
 }
 
 impl Object {
-    pub fn get_type(&self) -> &'static str {
-        match self {
-            Self::Blob(_) => "file",
-            Self::TreeNode(_) => "tree",
-            Self::Commit(_) => "commit",
-        }
-    }
-
-    pub fn read(root: impl AsRef<Path>, hash: &str) -> Self {
-        let compressed = input_output::read_obj(root, hash);
-        let bytes = codec::decompress(&compressed);
-        let mut reader = BytesReader::new(&bytes);
-        let header = Header::parse(&mut reader);
-        assert_eq!(reader.len(), header.size);
+    pub fn read(root: impl AsRef<Path>, hash: &str) -> Result<Self> {
+        let (kind, content) = read_raw(root, hash)?;
+        let mut reader = BytesReader::new(&content);
 
-        match header.kind {
+        let object = match kind {
             "blob" => Self::Blob(Blob::parse(&mut reader)),
-            "tree" => Self::TreeNode(TreeNode::parse(&mut reader)),
-            "commit" => Self::Commit(Commit::parse(&mut reader)),
-            kind => panic!("unknown object type: {}", kind),
-        }
+            "tree" => Self::TreeNode(TreeNode::parse(&mut reader)?),
+            "commit" => Self::Commit(Commit::parse(&mut reader)?),
+            _ => Self::Tag(Tag::parse(&mut reader)?),
+        };
+        Ok(object)
     }
 
     pub fn hash(&self) -> Hash {
@@ -66,22 +144,24 @@ impl Object {
             Self::Blob(blob) => blob.serialize(),
             Self::TreeNode(tree) => tree.serialize(),
             Self::Commit(commit) => commit.serialize(),
+            Self::Tag(tag) => tag.serialize(),
         };
         Hash::hash(&serialized)
     }
 
-    pub fn write(&self, root: impl AsRef<Path>) -> Hash {
+    pub fn write(&self, root: impl AsRef<Path>) -> Result<Hash> {
         let (hash, encoded) = match self {
             Self::Blob(blob) => blob.encode(),
             Self::TreeNode(tree_node) => tree_node.encode(),
             Self::Commit(commit) => commit.encode(),
+            Self::Tag(tag) => tag.encode(),
         };
-        input_output::write_obj(root, &hash.to_string(), &encoded);
-        hash
+        input_output::write_obj(root, &hash.to_string(), &encoded)?;
+        Ok(hash)
     }
 
     #[cfg(test)]
-    pub fn as_blob(self) -> Blob {
+    pub fn into_blob(self) -> Blob {
         match self {
             Self::Blob(blob) => blob,
             _ => panic!("not blob"),
@@ -89,12 +169,28 @@ impl Object {
     }
 
     #[cfg(test)]
-    pub fn as_tree(self) -> TreeNode {
+    pub fn into_tree(self) -> TreeNode {
         match self {
             Self::TreeNode(tree) => tree,
             _ => panic!("not tree"),
         }
     }
+
+    #[cfg(test)]
+    pub fn into_commit(self) -> Commit {
+        match self {
+            Self::Commit(commit) => commit,
+            _ => panic!("not commit"),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn into_tag(self) -> Tag {
+        match self {
+            Self::Tag(tag) => tag,
+            _ => panic!("not tag"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -114,7 +210,7 @@ mod tests {
         fs::write(root.join(filename), contents).unwrap();
         let hash = reference_impl::git_add_path(&repository, filename);
 
-        let blob = Object::read(root, &hash).as_blob();
+        let blob = Object::read(root, &hash).unwrap().into_blob();
         assert_eq!(blob.content, contents.as_bytes());
     }
 
@@ -128,16 +224,16 @@ mod tests {
         let repository = reference_impl::create_repository(&root);
         fs::write(root.join(filename), contents).unwrap();
         let hash_want = reference_impl::git_add_path(&repository, filename);
-        let encoded_want = input_output::read_obj(&root, &hash_want);
+        let encoded_want = input_output::read_obj(&root, &hash_want).unwrap();
 
         // got
         let root = test_utils::create_test_dir();
         let repo = Repo::new(&root);
-        repo.init();
+        repo.init().unwrap();
         let blob = Blob::new(String::from(contents).bytes().collect());
         let obj = Object::Blob(blob);
-        let hash_got = obj.write(&root);
-        let encoded_got = input_output::read_obj(&root, &hash_got.to_string());
+        let hash_got = obj.write(&root).unwrap();
+        let encoded_got = input_output::read_obj(&root, &hash_got.to_string()).unwrap();
 
         assert_eq!(hash_got.to_string(), hash_want);
         assert_eq!(encoded_got, encoded_want);
@@ -149,14 +245,14 @@ mod tests {
         let repository = reference_impl::create_repository(&root);
 
         let contents = "";
-        input_output::write(root.join("file1"), contents);
-        input_output::write(root.join("dir1/file_in_dir_1"), contents);
-        input_output::write(root.join("dir1/file_in_dir_2"), contents);
-        input_output::write(root.join("dir2/file_in_dir_3"), contents);
+        input_output::write(root.join("file1"), contents).unwrap();
+        input_output::write(root.join("dir1/file_in_dir_1"), contents).unwrap();
+        input_output::write(root.join("dir1/file_in_dir_2"), contents).unwrap();
+        input_output::write(root.join("dir2/file_in_dir_3"), contents).unwrap();
         reference_impl::git_add_all(&repository);
         let hash = reference_impl::git_write_tree(&repository);
 
-        let tree_node = Object::read(root, &hash).as_tree();
+        let tree_node = Object::read(root, &hash).unwrap().into_tree();
         let wants = ["dir1", "dir2", "file1"];
         for (got, want) in tree_node.into_iter().zip(wants) {
             assert_eq!(got.name, want)
@@ -0,0 +1,470 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{
+    codec, delta,
+    error::{Error, Result},
+    hash::Hash,
+    object,
+    pack_index::PackIndex,
+};
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+pub const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// The longest delta chain read before a pack is taken to be corrupt. git
+/// never writes chains deeper than this, and it stops delta cycles.
+const MAX_DELTA_DEPTH: usize = 4095;
+
+/// The packs opened so far, by pack directory.
+static PACKS: Mutex<BTreeMap<PathBuf, Vec<Arc<Pack>>>> = Mutex::new(BTreeMap::new());
+
+pub fn kind_name(kind: u8) -> Result<&'static str> {
+    match kind {
+        OBJ_COMMIT => Ok("commit"),
+        OBJ_TREE => Ok("tree"),
+        OBJ_BLOB => Ok("blob"),
+        OBJ_TAG => Ok("tag"),
+        kind => Err(Error::corrupt(format!("unknown pack object type {}", kind))),
+    }
+}
+
+pub fn kind_code(kind: &str) -> Result<u8> {
+    match kind {
+        "commit" => Ok(OBJ_COMMIT),
+        "tree" => Ok(OBJ_TREE),
+        "blob" => Ok(OBJ_BLOB),
+        "tag" => Ok(OBJ_TAG),
+        kind => Err(Error::corrupt(format!("invalid object type \"{}\"", kind))),
+    }
+}
+
+fn read_byte(reader: &mut impl Read) -> Result<u8> {
+    let mut byte = [0];
+    reader
+        .read_exact(&mut byte)
+        .map_err(|_| Error::corrupt("pack entry is truncated"))?;
+    Ok(byte[0])
+}
+
+/// Reads the type and inflated size that start every pack entry.
+pub fn read_entry_header(reader: &mut impl Read) -> Result<(u8, usize)> {
+    let mut byte = read_byte(reader)?;
+    let kind = (byte >> 4) & 0x7;
+    let mut size = (byte & 0xf) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        if shift >= usize::BITS {
+            return Err(Error::corrupt("bad pack entry header"));
+        }
+        byte = read_byte(reader)?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+    Ok((kind, size))
+}
+
+/// Writes the header of a pack entry, the counterpart of
+/// `read_entry_header`.
+pub fn write_entry_header(pack: &mut Vec<u8>, kind: u8, mut size: usize) {
+    let mut byte = (kind << 4) | (size & 0xf) as u8;
+    size >>= 4;
+    while size != 0 {
+        pack.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    pack.push(byte);
+}
+
+/// Writes the distance from an OFS_DELTA entry back to its base, the
+/// counterpart of `read_base_distance`.
+pub fn write_base_distance(pack: &mut Vec<u8>, mut distance: u64) {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance != 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    pack.extend_from_slice(&bytes);
+}
+
+/// Reads the distance from an OFS_DELTA entry back to its base.
+pub fn read_base_distance(reader: &mut impl Read) -> Result<u64> {
+    let mut byte = read_byte(reader)?;
+    let mut distance = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        if distance >> (u64::BITS - 7) != 0 {
+            return Err(Error::corrupt("delta base offset overflow"));
+        }
+        byte = read_byte(reader)?;
+        distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+    }
+    Ok(distance)
+}
+
+pub fn get_pack_dir(root: impl AsRef<Path>) -> PathBuf {
+    root.as_ref().join(".git").join("objects").join("pack")
+}
+
+pub struct Pack {
+    path: PathBuf,
+    pub index: PackIndex,
+}
+
+impl Pack {
+    pub fn open(idx_path: impl AsRef<Path>) -> Result<Self> {
+        let idx_path = idx_path.as_ref();
+        Ok(Self {
+            path: idx_path.with_extension("pack"),
+            index: PackIndex::read(idx_path)?,
+        })
+    }
+
+    /// The path of the `.pack` file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the entry at `offset`, resolving delta chains. REF_DELTA bases
+    /// that are not in this pack are looked up in the whole object store.
+    pub fn read_at(&self, root: &Path, offset: u64) -> Result<(&'static str, Vec<u8>)> {
+        self.read_chain(root, offset, 0)
+    }
+
+    /// Reads the entry at `offset`, reached through `depth` deltas so far.
+    fn read_chain(
+        &self,
+        root: &Path,
+        mut offset: u64,
+        depth: usize,
+    ) -> Result<(&'static str, Vec<u8>)> {
+        let file =
+            File::open(&self.path).map_err(|e| Error::io("could not open", &self.path, e))?;
+        let mut file = BufReader::new(file);
+        let mut deltas = vec![];
+
+        let (kind, mut content) = loop {
+            if depth + deltas.len() > MAX_DELTA_DEPTH {
+                return Err(Error::corrupt(format!(
+                    "delta chain too deep in {}",
+                    self.path.display()
+                )));
+            }
+            file.seek(SeekFrom::Start(offset))?;
+            let (kind, size) = read_entry_header(&mut file)?;
+            match kind {
+                OBJ_OFS_DELTA => {
+                    let distance = read_base_distance(&mut file)?;
+                    deltas.push(codec::decompress_from(&mut file)?);
+                    offset = offset
+                        .checked_sub(distance)
+                        .filter(|_| distance != 0)
+                        .ok_or_else(|| Error::corrupt("delta base offset out of bounds"))?;
+                }
+                OBJ_REF_DELTA => {
+                    let mut base_hash = [0; 20];
+                    file.read_exact(&mut base_hash)
+                        .map_err(|_| Error::corrupt("pack entry is truncated"))?;
+                    deltas.push(codec::decompress_from(&mut file)?);
+                    let base_hash = Hash::new(base_hash);
+                    match self.index.find(&base_hash)? {
+                        Some(base_offset) => offset = base_offset,
+                        None => break read_base(root, &base_hash, depth + deltas.len())?,
+                    }
+                }
+                kind => {
+                    let content = codec::decompress_from(&mut file)?;
+                    if content.len() != size {
+                        return Err(Error::corrupt(format!(
+                            "inflated size mismatch in {}",
+                            self.path.display()
+                        )));
+                    }
+                    break (kind_name(kind)?, content);
+                }
+            }
+        };
+
+        for delta in deltas.iter().rev() {
+            content = delta::apply(&content, delta)?;
+        }
+        Ok((kind, content))
+    }
+}
+
+/// Returns the packs of the repository. Each is opened once and reused
+/// until it leaves the pack directory.
+pub fn packs(root: &Path) -> Result<Vec<Arc<Pack>>> {
+    let dir = get_pack_dir(root);
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(vec![]);
+    };
+    let mut cache = PACKS.lock().unwrap_or_else(PoisonError::into_inner);
+    let opened = cache.remove(&dir).unwrap_or_default();
+    let mut packs = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").exists()
+        {
+            let pack_path = path.with_extension("pack");
+            match opened.iter().find(|pack| pack.path == pack_path) {
+                Some(pack) => packs.push(Arc::clone(pack)),
+                None => packs.push(Arc::new(Pack::open(path)?)),
+            }
+        }
+    }
+    cache.insert(dir, packs.clone());
+    Ok(packs)
+}
+
+/// Looks `hash` up in every pack of the repository.
+pub fn read_obj(root: &Path, hash: &Hash) -> Result<Option<(&'static str, Vec<u8>)>> {
+    for pack in packs(root)? {
+        if let Some(offset) = pack.index.find(hash)? {
+            return pack.read_at(root, offset).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the base of a REF_DELTA that is not in the delta's own pack,
+/// `depth` deltas down the chain.
+fn read_base(root: &Path, hash: &Hash, depth: usize) -> Result<(&'static str, Vec<u8>)> {
+    for pack in packs(root)? {
+        if let Some(offset) = pack.index.find(hash)? {
+            return pack.read_chain(root, offset, depth);
+        }
+    }
+    object::read_raw(root, &hash.to_string())
+}
+
+enum DeltaBase {
+    Offset(u64),
+    Hash(Hash),
+}
+
+/// Parses a complete packfile, as received from a remote, and writes each
+/// of its objects to the object store as a loose object.
+pub fn unpack(root: &Path, bytes: &[u8]) -> Result<()> {
+    if bytes.len() < 32 || &bytes[..4] != b"PACK" {
+        return Err(Error::corrupt("protocol error: bad pack header"));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 20);
+    if Hash::hash(body).bytes() != checksum {
+        return Err(Error::corrupt("pack is corrupted (SHA1 mismatch)"));
+    }
+    let count = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
+    let truncated = || Error::corrupt("pack has junk at the end or is truncated");
+
+    let mut resolved: HashMap<u64, (&'static str, Vec<u8>)> = HashMap::new();
+    let mut offsets: HashMap<Hash, u64> = HashMap::new();
+    let mut external: HashMap<Hash, (&'static str, Vec<u8>)> = HashMap::new();
+    let mut pending = vec![];
+
+    let mut data = &body[12..];
+    for _ in 0..count {
+        let offset = (body.len() - data.len()) as u64;
+        let (kind, _) = read_entry_header(&mut data)?;
+        let base = match kind {
+            OBJ_OFS_DELTA => {
+                let distance = read_base_distance(&mut data)?;
+                let base = offset
+                    .checked_sub(distance)
+                    .ok_or_else(|| Error::corrupt("delta base offset out of bounds"))?;
+                Some(DeltaBase::Offset(base))
+            }
+            OBJ_REF_DELTA => {
+                if data.len() < 20 {
+                    return Err(truncated());
+                }
+                let (hash, rest) = data.split_at(20);
+                data = rest;
+                Some(DeltaBase::Hash(Hash::new(hash.try_into().unwrap())))
+            }
+            _ => None,
+        };
+        let (content, len) = codec::decompress_prefix(data)?;
+        data = &data[len..];
+
+        match base {
+            Some(base) => pending.push((offset, base, content)),
+            None => {
+                let kind = kind_name(kind)?;
+                offsets.insert(object::write_raw(root, kind, &content)?, offset);
+                resolved.insert(offset, (kind, content));
+            }
+        }
+    }
+
+    // Deltas may refer to bases that come later in the pack, so resolve them
+    // in rounds until nothing is left.
+    while !pending.is_empty() {
+        let before = pending.len();
+        let mut unresolved = vec![];
+        for (offset, base, delta) in pending {
+            let base_object = match &base {
+                DeltaBase::Offset(base) => resolved.get(base),
+                DeltaBase::Hash(hash) => match offsets.get(hash) {
+                    Some(base) => resolved.get(base),
+                    None => external.get(hash),
+                },
+            };
+            let Some((kind, base_content)) = base_object else {
+                unresolved.push((offset, base, delta));
+                continue;
+            };
+            let kind = *kind;
+            let content = delta::apply(base_content, &delta)?;
+            offsets.insert(object::write_raw(root, kind, &content)?, offset);
+            resolved.insert(offset, (kind, content));
+        }
+        pending = unresolved;
+
+        if pending.len() == before {
+            // Thin packs omit bases the receiver is known to have.
+            let missing: Vec<_> = pending
+                .iter()
+                .filter_map(|(_, base, _)| match base {
+                    DeltaBase::Hash(hash) if !external.contains_key(hash) => Some(*hash),
+                    _ => None,
+                })
+                .collect();
+            if missing.is_empty() {
+                return Err(Error::corrupt("pack has unresolved deltas"));
+            }
+            for hash in missing {
+                external.insert(hash, object::read_raw(root, &hash.to_string())?);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use crate::{
+        codec, delta,
+        error::Error,
+        hash::Hash,
+        input_output,
+        object::Object,
+        pack_index::{Entry, PackIndex},
+        reference_impl,
+        repo::Repo,
+        test_utils,
+    };
+
+    use super::{get_pack_dir, packs, read_base_distance, read_entry_header, write_entry_header};
+
+    #[test]
+    fn test_read_packed_objects() {
+        let root = test_utils::create_test_dir();
+        let origin = root.join("origin");
+        let repository = reference_impl::create_repository(&origin);
+
+        // Two large, similar blobs so that the second is stored as a delta.
+        let lines: Vec<_> = (0..200).map(|i| format!("line {}\n", i)).collect();
+        input_output::write(origin.join("file1"), lines.concat()).unwrap();
+        input_output::write(origin.join("dir/file2"), lines[1..].concat() + "end\n").unwrap();
+        reference_impl::git_add_all(&repository);
+        let commit = reference_impl::git_commit(&repository, "packed", &[]);
+
+        let packed = root.join("packed");
+        Repo::new(&packed).init().unwrap();
+        reference_impl::git_pack_commit(&repository, &commit, &packed);
+
+        let want = Object::read(&origin, &commit).unwrap().into_commit();
+        let got = Object::read(&packed, &commit).unwrap().into_commit();
+        assert_eq!(got.serialize(), want.serialize());
+
+        let tree = Object::read(&packed, &got.tree.to_string())
+            .unwrap()
+            .into_tree();
+        let names: Vec<_> = tree.into_iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["dir", "file1"]);
+
+        let dir = tree.into_iter().next().unwrap().hash.to_string();
+        let dir = Object::read(&packed, &dir).unwrap().into_tree();
+        let file2 = dir.into_iter().next().unwrap().hash.to_string();
+        let blob = Object::read(&packed, &file2).unwrap().into_blob();
+        assert_eq!(blob.content, (lines[1..].concat() + "end\n").as_bytes());
+    }
+
+    #[test]
+    fn test_packs_are_cached() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        input_output::write(root.join("file"), "content\n").unwrap();
+        reference_impl::git_add_all(&repository);
+        let commit = reference_impl::git_commit(&repository, "packed", &[]);
+
+        let packed = root.join("packed");
+        Repo::new(&packed).init().unwrap();
+        assert!(packs(&packed).unwrap().is_empty());
+        reference_impl::git_pack_commit(&repository, &commit, &packed);
+        let first = packs(&packed).unwrap();
+        assert_eq!(first.len(), 1);
+        assert!(Arc::ptr_eq(&first[0], &packs(&packed).unwrap()[0]));
+
+        for entry in fs::read_dir(get_pack_dir(&packed)).unwrap() {
+            fs::remove_file(entry.unwrap().path()).unwrap();
+        }
+        assert!(packs(&packed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_corrupt_headers() {
+        assert!(read_entry_header(&mut &[0xff; 16][..]).is_err());
+        assert!(read_base_distance(&mut &[0xff; 16][..]).is_err());
+    }
+
+    #[test]
+    fn test_read_delta_cycle() {
+        let root = test_utils::create_test_dir();
+        Repo::new(&root).init().unwrap();
+
+        // Two REF_DELTAs, each based on the other.
+        let (first, second) = (Hash::hash(b"first"), Hash::hash(b"second"));
+        let delta = codec::compress(&delta::create(b"base\n", b"target\n", usize::MAX).unwrap());
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x02".to_vec();
+        let mut entries = vec![];
+        for (hash, base) in [(first, second), (second, first)] {
+            entries.push(Entry {
+                hash,
+                crc32: 0,
+                offset: pack.len() as u64,
+            });
+            write_entry_header(&mut pack, super::OBJ_REF_DELTA, delta.len());
+            pack.extend_from_slice(&base.bytes());
+            pack.extend_from_slice(&delta);
+        }
+        let checksum = Hash::hash(&pack);
+        pack.extend_from_slice(&checksum.bytes());
+        let path = get_pack_dir(&root).join(format!("pack-{}", checksum));
+        fs::create_dir_all(get_pack_dir(&root)).unwrap();
+        fs::write(path.with_extension("pack"), &pack).unwrap();
+        let index = PackIndex::build(entries, checksum);
+        fs::write(path.with_extension("idx"), index.bytes()).unwrap();
+
+        let error = Object::read(&root, &first.to_string()).unwrap_err();
+        assert!(
+            matches!(&error, Error::Corrupt(message) if message.contains("delta chain too deep")),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::{cmp::Ordering, fs, path::Path};
+
+use crate::{
+    error::{Error, Result},
+    hash::Hash,
+};
+
+const MAGIC: &[u8] = b"\xfftOc";
+const FANOUT_LEN: usize = 256 * 4;
+
+/// What a `.idx` records about one object of its pack.
+pub struct Entry {
+    pub hash: Hash,
+    pub crc32: u32,
+    pub offset: u64,
+}
+
+/// A version 2 `.idx` file: a fan-out table over the first hash byte, the
+/// sorted object names, their CRC32s and their offsets into the `.pack`.
+pub struct PackIndex {
+    bytes: Vec<u8>,
+    count: usize,
+}
+
+impl PackIndex {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| Error::io("could not read", path, e))?;
+        Self::parse(bytes)
+            .map_err(|e| Error::corrupt(format!("index file {} is corrupt: {}", path.display(), e)))
+    }
+
+    pub fn parse(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() < 8 + FANOUT_LEN || &bytes[..4] != MAGIC || read_u32(&bytes, 4) != 2 {
+            return Err(Error::corrupt("unsupported pack index version"));
+        }
+        let count = read_u32(&bytes, 8 + 255 * 4) as usize;
+        // Names, CRC32s and offsets, followed by the two checksums.
+        if bytes.len() < 8 + FANOUT_LEN + count * 28 + 40 {
+            return Err(Error::corrupt("pack index is truncated"));
+        }
+        Ok(Self { bytes, count })
+    }
+
+    /// Builds the index of the pack with checksum `pack_checksum` that holds
+    /// `entries`. Offsets that do not fit in 31 bits go to the table of
+    /// large offsets.
+    pub fn build(mut entries: Vec<Entry>, pack_checksum: Hash) -> Self {
+        entries.sort_by_key(|entry| entry.hash);
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&2u32.to_be_bytes());
+        let mut counts = [0u32; 256];
+        for entry in &entries {
+            counts[entry.hash.bytes()[0] as usize] += 1;
+        }
+        let mut total = 0;
+        for count in counts {
+            total += count;
+            bytes.extend_from_slice(&total.to_be_bytes());
+        }
+        for entry in &entries {
+            bytes.extend_from_slice(&entry.hash.bytes());
+        }
+        for entry in &entries {
+            bytes.extend_from_slice(&entry.crc32.to_be_bytes());
+        }
+        let mut large_offsets = vec![];
+        for entry in &entries {
+            let offset = match u32::try_from(entry.offset) {
+                Ok(offset) if offset & 0x8000_0000 == 0 => offset,
+                _ => {
+                    large_offsets.push(entry.offset);
+                    0x8000_0000 | (large_offsets.len() - 1) as u32
+                }
+            };
+            bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+        for offset in large_offsets {
+            bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+        bytes.extend_from_slice(&pack_checksum.bytes());
+        bytes.extend_from_slice(&Hash::hash(&bytes).bytes());
+        Self {
+            bytes,
+            count: entries.len(),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn fanout(&self, byte: u8) -> usize {
+        read_u32(&self.bytes, 8 + byte as usize * 4) as usize
+    }
+
+    fn name(&self, i: usize) -> &[u8] {
+        let start = 8 + FANOUT_LEN + i * 20;
+        &self.bytes[start..start + 20]
+    }
+
+    pub fn offset(&self, i: usize) -> Result<u64> {
+        let offsets = 8 + FANOUT_LEN + self.count * 24;
+        let offset = read_u32(&self.bytes, offsets + i * 4);
+        if offset & 0x8000_0000 == 0 {
+            return Ok(offset as u64);
+        }
+        let large_offsets = offsets + self.count * 4;
+        let pos = large_offsets + (offset & 0x7fff_ffff) as usize * 8;
+        let large = self
+            .bytes
+            .get(pos..pos + 8)
+            .ok_or_else(|| Error::corrupt("pack index offset out of range"))?;
+        Ok(u64::from_be_bytes(large.try_into().unwrap()))
+    }
+
+    /// The CRC32 of the `i`th object's entry in the pack, as stored.
+    pub fn crc32(&self, i: usize) -> u32 {
+        read_u32(&self.bytes, 8 + FANOUT_LEN + self.count * 20 + i * 4)
+    }
+
+    /// Returns the names of all objects of the pack, sorted.
+    pub fn hashes(&self) -> Vec<Hash> {
+        (0..self.count)
+            .map(|i| Hash::new(self.name(i).try_into().unwrap()))
+            .collect()
+    }
+
+    pub fn find(&self, hash: &Hash) -> Result<Option<u64>> {
+        let hash = hash.bytes();
+        let mut lo = if hash[0] == 0 {
+            0
+        } else {
+            self.fanout(hash[0] - 1)
+        };
+        let mut hi = self.fanout(hash[0]);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.name(mid).cmp(&hash) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return self.offset(mid).map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the names starting with the hex digits `prefix`, which must
+    /// be at least two digits long.
+    pub fn find_prefix(&self, prefix: &str) -> Vec<Hash> {
+        let Ok(first) = u8::from_str_radix(&prefix[..2], 16) else {
+            return vec![];
+        };
+        let lo = if first == 0 {
+            0
+        } else {
+            self.fanout(first - 1)
+        };
+        (lo..self.fanout(first))
+            .map(|i| Hash::new(self.name(i).try_into().unwrap()))
+            .filter(|hash| hash.to_string().starts_with(prefix))
+            .collect()
+    }
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
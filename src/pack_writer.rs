@@ -0,0 +1,238 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashSet, VecDeque},
+    path::Path,
+};
+
+use flate2::Crc;
+
+use crate::{
+    codec, delta,
+    error::{Error, Result},
+    hash::Hash,
+    object, pack,
+    pack_index::{self, PackIndex},
+};
+
+pub const DEFAULT_WINDOW: usize = 10;
+pub const DEFAULT_DEPTH: usize = 50;
+
+/// An object on its way into a pack, with the delta chosen for it.
+struct Entry {
+    hash: Hash,
+    kind: &'static str,
+    content: Vec<u8>,
+    name_hash: u32,
+    base: Option<(usize, Vec<u8>)>,
+    depth: usize,
+}
+
+/// A pack and its index, ready to be written out.
+pub struct Written {
+    pub pack: Vec<u8>,
+    pub index: PackIndex,
+    pub checksum: Hash,
+}
+
+/// Hashes the path an object was found at so that the last characters
+/// count the most, like git does, which brings files of the same name and
+/// of the same kind next to each other.
+fn name_hash(name: &str) -> u32 {
+    name.bytes()
+        .filter(|c| !c.is_ascii_whitespace())
+        .fold(0, |hash: u32, c| (hash >> 2).wrapping_add((c as u32) << 24))
+}
+
+/// Packs `objects`, each given with the path it was found at (which may be
+/// empty). Each object is compared with the `window` objects before it when
+/// sorted by type, name and size, and stored as an OFS_DELTA against the
+/// one that gives the smallest delta, as long as no chain of deltas gets
+/// longer than `depth`.
+pub fn write(
+    root: impl AsRef<Path>,
+    objects: &[(Hash, String)],
+    window: usize,
+    depth: usize,
+) -> Result<Written> {
+    let root = root.as_ref();
+    let mut seen = HashSet::new();
+    let mut entries = vec![];
+    for (hash, name) in objects {
+        if !seen.insert(*hash) {
+            continue;
+        }
+        let (kind, content) = match object::read_raw(root, &hash.to_string()) {
+            Err(Error::InvalidObjectName(_)) => {
+                return Err(Error::fatal(format!("unable to read {}", hash)))
+            }
+            result => result?,
+        };
+        entries.push(Entry {
+            hash: *hash,
+            kind,
+            content,
+            name_hash: name_hash(name),
+            base: None,
+            depth: 0,
+        });
+    }
+    find_deltas(&mut entries, window, depth);
+
+    let mut pack = b"PACK".to_vec();
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    let mut offsets = vec![None; entries.len()];
+    let mut index = vec![];
+    for i in 0..entries.len() {
+        write_entry(&mut pack, &entries, i, &mut offsets, &mut index)?;
+    }
+    let checksum = Hash::hash(&pack);
+    pack.extend_from_slice(&checksum.bytes());
+    Ok(Written {
+        pack,
+        index: PackIndex::build(index, checksum),
+        checksum,
+    })
+}
+
+fn find_deltas(entries: &mut [Entry], window: usize, depth: usize) {
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&i| {
+        let entry = &entries[i];
+        (entry.kind, entry.name_hash, Reverse(entry.content.len()))
+    });
+
+    let mut recent: VecDeque<usize> = VecDeque::new();
+    for i in order {
+        let target = &entries[i];
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for &j in recent.iter().rev() {
+            let base = &entries[j];
+            if base.kind != target.kind || base.depth >= depth {
+                continue;
+            }
+            // A delta has to beat half the object to be worth it, and then
+            // the best delta so far.
+            let max_size = match &best {
+                Some((_, delta)) => delta.len() - 1,
+                None => (target.content.len() / 2).saturating_sub(20),
+            };
+            let (base_size, target_size) = (base.content.len(), target.content.len());
+            if max_size == 0
+                || base_size < target_size / 32
+                || base_size.abs_diff(target_size) >= max_size
+            {
+                continue;
+            }
+            if let Some(delta) = delta::create(&base.content, &target.content, max_size) {
+                best = Some((j, delta));
+            }
+        }
+        if let Some((j, delta)) = best {
+            entries[i].depth = entries[j].depth + 1;
+            entries[i].base = Some((j, delta));
+        }
+        recent.push_back(i);
+        if recent.len() > window {
+            recent.pop_front();
+        }
+    }
+}
+
+/// Appends entry `i` to `pack`, after its delta base if that is not in
+/// yet.
+fn write_entry(
+    pack: &mut Vec<u8>,
+    entries: &[Entry],
+    i: usize,
+    offsets: &mut [Option<u64>],
+    index: &mut Vec<pack_index::Entry>,
+) -> Result<()> {
+    if offsets[i].is_some() {
+        return Ok(());
+    }
+    let entry = &entries[i];
+    let base = match &entry.base {
+        Some((base, delta)) => {
+            write_entry(pack, entries, *base, offsets, index)?;
+            offsets[*base].map(|offset| (offset, delta))
+        }
+        None => None,
+    };
+
+    let offset = pack.len() as u64;
+    let data = match base {
+        Some((base_offset, delta)) => {
+            pack::write_entry_header(pack, pack::OBJ_OFS_DELTA, delta.len());
+            pack::write_base_distance(pack, offset - base_offset);
+            delta
+        }
+        None => {
+            pack::write_entry_header(pack, pack::kind_code(entry.kind)?, entry.content.len());
+            &entry.content
+        }
+    };
+    pack.extend_from_slice(&codec::compress(data));
+
+    let mut crc = Crc::new();
+    crc.update(&pack[offset as usize..]);
+    offsets[i] = Some(offset);
+    index.push(pack_index::Entry {
+        hash: entry.hash,
+        crc32: crc.sum(),
+        offset,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Repository;
+
+    use crate::{
+        hash::Hash, input_output, pack, pack_writer, reference_impl, repo::Repo, test_utils,
+    };
+
+    #[test]
+    fn test_write() {
+        let root = test_utils::create_test_dir();
+        let origin = root.join("origin");
+        let repository = reference_impl::create_repository(&origin);
+
+        // Similar files, which make good deltas of one another.
+        let lines: Vec<_> = (0..300).map(|i| format!("line {}\n", i)).collect();
+        input_output::write(origin.join("file"), lines.concat()).unwrap();
+        input_output::write(origin.join("dir/file"), lines[5..].concat()).unwrap();
+        input_output::write(origin.join("other"), lines[..150].concat() + "end\n").unwrap();
+        input_output::write(origin.join("small"), "small\n").unwrap();
+        reference_impl::git_add_all(&repository);
+        let commit = reference_impl::git_commit(&repository, "packed", &[]);
+        let objects: Vec<(Hash, String)> = reference_impl::git_list_objects(&repository, &commit)
+            .into_iter()
+            .map(|(hash, name)| (hash.parse().unwrap(), name))
+            .collect();
+
+        let written = pack_writer::write(&origin, &objects, 10, 50).unwrap();
+        let undeltified = pack_writer::write(&origin, &objects, 0, 50).unwrap();
+        assert!(written.pack.len() < undeltified.pack.len());
+
+        let packed = root.join("packed");
+        Repo::new(&packed).init().unwrap();
+        let name = format!("pack-{}", written.checksum);
+        let pack_dir = pack::get_pack_dir(&packed);
+        input_output::write(pack_dir.join(format!("{}.pack", name)), &written.pack).unwrap();
+        input_output::write(
+            pack_dir.join(format!("{}.idx", name)),
+            written.index.bytes(),
+        )
+        .unwrap();
+        let packed = Repository::open(&packed).unwrap();
+        for (hash, _) in &objects {
+            let hash = hash.to_string();
+            assert_eq!(
+                reference_impl::git_read_object(&packed, &hash),
+                reference_impl::git_read_object(&repository, &hash)
+            );
+        }
+    }
+}
@@ -0,0 +1,706 @@
+use std::path::Path;
+
+use crate::{
+    error::Result,
+    hash::Hash,
+    line_diff::{self, Algorithm, Edit},
+    object,
+    tree_diff::{Change, DiffEntry, Status},
+};
+
+/// How much of a file git looks at to tell whether it is binary.
+const BINARY_CHECK_LEN: usize = 8000;
+/// The longest function name shown in a hunk header.
+const FUNC_NAME_MAX: usize = 80;
+/// The width of `--stat` output when not writing to a terminal.
+const STAT_WIDTH: usize = 80;
+
+/// What `diff`, `show` and `log -p` show for each changed file, and how.
+#[derive(Clone)]
+pub struct Options {
+    pub output: Output,
+    /// The number of unchanged lines shown around changes.
+    pub context: usize,
+    pub algorithm: Algorithm,
+    /// The similarity renames need, if detecting them.
+    pub renames: Option<u32>,
+    /// The width of what is printed before every line, like the graph of
+    /// `log --graph`, which `--stat` leaves room for.
+    pub prefix_width: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Output {
+    Patch,
+    Stat,
+    Numstat,
+    NameOnly,
+    NameStatus,
+}
+
+/// A changed file along with its content on both sides, empty for a
+/// missing side.
+pub struct FilePair<'a> {
+    pub change: &'a Change,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+impl FilePair<'_> {
+    fn is_binary(&self) -> bool {
+        is_binary(&self.old) || is_binary(&self.new)
+    }
+
+    /// The name `--stat` and `--numstat` show, with renames written as
+    /// `dir/{old => new}`.
+    fn display_name(&self) -> String {
+        match (&self.change.old, &self.change.new) {
+            (Some(old), Some(new)) if old.path != new.path => rename_name(&old.path, &new.path),
+            _ => self.change.path().to_string(),
+        }
+    }
+
+    /// Counts the lines added and deleted, or for binary files the sizes.
+    fn counts(&self, algorithm: Algorithm) -> (usize, usize) {
+        if self.is_binary() {
+            return (self.new.len(), self.old.len());
+        }
+        let old = line_diff::split_lines(&self.old);
+        let new = line_diff::split_lines(&self.new);
+        let diff = line_diff::diff(&old, &new, algorithm);
+        let count = |changed: &[bool]| changed.iter().filter(|&&c| c).count();
+        (count(&diff.new), count(&diff.old))
+    }
+}
+
+/// Whether content looks binary: git's check for a NUL byte near the start.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(BINARY_CHECK_LEN).any(|&b| b == 0)
+}
+
+/// Formats the changes in the output `options` ask for.
+pub fn format(root: impl AsRef<Path>, pairs: &[FilePair], options: &Options) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    match options.output {
+        Output::Patch => {
+            for pair in pairs {
+                format_patch(&root, pair, options, &mut out)?;
+            }
+        }
+        Output::Stat => {
+            for line in format_stat(pairs, options) {
+                out.extend(line.into_bytes());
+                out.push(b'\n');
+            }
+        }
+        Output::Numstat => {
+            for pair in pairs {
+                let name = pair.display_name();
+                let line = match pair.is_binary() {
+                    true => format!("-\t-\t{}\n", name),
+                    false => {
+                        let (added, deleted) = pair.counts(options.algorithm);
+                        format!("{}\t{}\t{}\n", added, deleted, name)
+                    }
+                };
+                out.extend(line.into_bytes());
+            }
+        }
+        Output::NameOnly | Output::NameStatus => {
+            for pair in pairs {
+                let line = match options.output {
+                    Output::NameOnly => pair.change.path().to_string(),
+                    _ => pair.change.format_name_status(),
+                };
+                out.extend(line.into_bytes());
+                out.push(b'\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Formats the `--summary` lines for the changes that create, delete,
+/// rename or copy files or change their mode.
+pub fn format_summary(changes: &[Change]) -> Vec<u8> {
+    let mut out = String::new();
+    for change in changes {
+        match (&change.status, &change.old, &change.new) {
+            (Status::Added, _, Some(new)) => {
+                out.push_str(&format!(" create mode {:06o} {}\n", new.mode, new.path))
+            }
+            (Status::Deleted, Some(old), _) => {
+                out.push_str(&format!(" delete mode {:06o} {}\n", old.mode, old.path))
+            }
+            (Status::Renamed(score) | Status::Copied(score), Some(old), Some(new)) => {
+                let kind = match change.status {
+                    Status::Renamed(_) => "rename",
+                    _ => "copy",
+                };
+                let name = rename_name(&old.path, &new.path);
+                out.push_str(&format!(" {} {} ({}%)\n", kind, name, score));
+                if old.mode != new.mode {
+                    out.push_str(&format!(
+                        " mode change {:06o} => {:06o}\n",
+                        old.mode, new.mode
+                    ));
+                }
+            }
+            (_, Some(old), Some(new)) if old.mode != new.mode => out.push_str(&format!(
+                " mode change {:06o} => {:06o} {}\n",
+                old.mode, new.mode, new.path
+            )),
+            _ => {}
+        }
+    }
+    out.into_bytes()
+}
+
+/// Formats a file's `diff --git` header and hunks. A change between a file
+/// and a symlink is shown as a deletion followed by a creation.
+fn format_patch(
+    root: impl AsRef<Path>,
+    pair: &FilePair,
+    options: &Options,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    let (old, new) = (pair.change.old.as_ref(), pair.change.new.as_ref());
+    if pair.change.status == Status::TypeChanged {
+        format_file(pair, old, None, index_line(&root, old, None)?, options, out);
+        format_file(pair, None, new, index_line(&root, None, new)?, options, out);
+        return Ok(());
+    }
+    format_file(pair, old, new, index_line(&root, old, new)?, options, out);
+    Ok(())
+}
+
+/// The `index` line naming both sides' blobs, with the mode if it stays
+/// the same, or `None` if the content does not change.
+fn index_line(
+    root: impl AsRef<Path>,
+    old: Option<&DiffEntry>,
+    new: Option<&DiffEntry>,
+) -> Result<Option<String>> {
+    let side = |entry: Option<&DiffEntry>| entry.map_or((Hash::zero(), 0), |e| (e.hash, e.mode));
+    let ((old_hash, old_mode), (new_hash, new_mode)) = (side(old), side(new));
+    if old_hash == new_hash {
+        return Ok(None);
+    }
+    let mut line = format!(
+        "index {}..{}",
+        object::abbreviate(&root, &old_hash, 7)?,
+        object::abbreviate(&root, &new_hash, 7)?
+    );
+    if old_mode == new_mode {
+        line.push_str(&format!(" {:06o}", old_mode));
+    }
+    Ok(Some(line))
+}
+
+fn format_file(
+    pair: &FilePair,
+    old: Option<&DiffEntry>,
+    new: Option<&DiffEntry>,
+    index: Option<String>,
+    options: &Options,
+    out: &mut Vec<u8>,
+) {
+    let old_path = old.or(new).map(|e| e.path.as_str()).unwrap();
+    let new_path = new.or(old).map(|e| e.path.as_str()).unwrap();
+    let mut header = vec![format!("diff --git a/{} b/{}", old_path, new_path)];
+    match (old, new) {
+        (None, Some(new)) => header.push(format!("new file mode {:06o}", new.mode)),
+        (Some(old), None) => header.push(format!("deleted file mode {:06o}", old.mode)),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header.push(format!("old mode {:06o}", old.mode));
+            header.push(format!("new mode {:06o}", new.mode));
+        }
+        _ => {}
+    }
+    match pair.change.status {
+        Status::Renamed(score) => {
+            header.push(format!("similarity index {}%", score));
+            header.push(format!("rename from {}", old_path));
+            header.push(format!("rename to {}", new_path));
+        }
+        Status::Copied(score) => {
+            header.push(format!("similarity index {}%", score));
+            header.push(format!("copy from {}", old_path));
+            header.push(format!("copy to {}", new_path));
+        }
+        _ => {}
+    }
+    if let Some(index) = index {
+        header.push(index);
+    }
+    for line in header {
+        out.extend(line.into_bytes());
+        out.push(b'\n');
+    }
+
+    let old_content: &[u8] = if old.is_some() { &pair.old } else { &[] };
+    let new_content: &[u8] = if new.is_some() { &pair.new } else { &[] };
+    let old_name = old.map_or(String::from("/dev/null"), |_| format!("a/{}", old_path));
+    let new_name = new.map_or(String::from("/dev/null"), |_| format!("b/{}", new_path));
+    if old_content == new_content {
+        return;
+    }
+    if is_binary(old_content) || is_binary(new_content) {
+        let line = format!("Binary files {} and {} differ\n", old_name, new_name);
+        out.extend(line.into_bytes());
+        return;
+    }
+    let hunks = format_hunks(old_content, new_content, options);
+    if !hunks.is_empty() {
+        out.extend(format!("--- {}\n+++ {}\n", old_name, new_name).into_bytes());
+        out.extend(hunks);
+    }
+}
+
+/// Formats the hunks of a unified diff, like xdiff: changes closer than
+/// twice the context are shown together, and each hunk header names the
+/// nearest line above the hunk that starts a function.
+pub fn format_hunks(old: &[u8], new: &[u8], options: &Options) -> Vec<u8> {
+    let a = line_diff::split_lines(old);
+    let b = line_diff::split_lines(new);
+    let edits = line_diff::diff(&a, &b, options.algorithm).edits();
+    let context = options.context;
+
+    let mut out = vec![];
+    let mut func_name: Vec<u8> = vec![];
+    let mut func_searched_to: isize = -1;
+    let mut i = 0;
+    while i < edits.len() {
+        let mut j = i;
+        while j + 1 < edits.len() && edits[j + 1].old_start - old_end(&edits[j]) <= 2 * context {
+            j += 1;
+        }
+        let (first, last) = (edits[i], edits[j]);
+        let s1 = first.old_start.saturating_sub(context);
+        let s2 = first.new_start.saturating_sub(context);
+        let post = context
+            .min(a.len() - old_end(&last))
+            .min(b.len() - (last.new_start + last.new_len));
+        let e1 = old_end(&last) + post;
+        let e2 = last.new_start + last.new_len + post;
+
+        // A hunk without a function line of its own repeats the previous
+        // hunk's.
+        let mut l = s1 as isize - 1;
+        while l > func_searched_to {
+            if let Some(name) = function_name(a[l as usize]) {
+                func_name = name;
+                break;
+            }
+            l -= 1;
+        }
+        func_searched_to = s1 as isize - 1;
+
+        let range = |start: usize, count: usize| match count {
+            0 => format!("{},0", start),
+            1 => format!("{}", start + 1),
+            _ => format!("{},{}", start + 1, count),
+        };
+        out.extend(format!("@@ -{} +{} @@", range(s1, e1 - s1), range(s2, e2 - s2)).into_bytes());
+        if !func_name.is_empty() {
+            out.push(b' ');
+            out.extend(&func_name);
+        }
+        out.push(b'\n');
+
+        let mut line2 = s2;
+        for edit in &edits[i..=j] {
+            for line in &b[line2..edit.new_start] {
+                push_line(&mut out, b' ', line);
+            }
+            for line in &a[edit.old_start..old_end(edit)] {
+                push_line(&mut out, b'-', line);
+            }
+            for line in &b[edit.new_start..edit.new_start + edit.new_len] {
+                push_line(&mut out, b'+', line);
+            }
+            line2 = edit.new_start + edit.new_len;
+        }
+        for line in &b[line2..e2] {
+            push_line(&mut out, b' ', line);
+        }
+        i = j + 1;
+    }
+    out
+}
+
+fn old_end(edit: &Edit) -> usize {
+    edit.old_start + edit.old_len
+}
+
+fn push_line(out: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    out.push(prefix);
+    out.extend(line);
+    if !line.ends_with(b"\n") {
+        out.extend(b"\n\\ No newline at end of file\n");
+    }
+}
+
+/// git's default function line: one starting with a letter, `_` or `$`,
+/// cut to 80 bytes and without trailing whitespace.
+fn function_name(line: &[u8]) -> Option<Vec<u8>> {
+    let first = *line.first()?;
+    if !first.is_ascii_alphabetic() && first != b'_' && first != b'$' {
+        return None;
+    }
+    let mut name = &line[..line.len().min(FUNC_NAME_MAX)];
+    while let Some((last, rest)) = name.split_last() {
+        if !last.is_ascii_whitespace() && *last != 0x0b {
+            break;
+        }
+        name = rest;
+    }
+    Some(name.to_vec())
+}
+
+/// Writes a rename as `old => new`, putting the common leading directories
+/// and trailing path components outside of braces.
+fn rename_name(old: &str, new: &str) -> String {
+    let (a, b) = (old.as_bytes(), new.as_bytes());
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // The suffix may run back into the prefix by its slash.
+    let mut suffix = 0;
+    let min = prefix.saturating_sub(1);
+    let (mut i, mut j) = (a.len(), b.len());
+    let at = |s: &[u8], i: usize| s.get(i).copied().unwrap_or(0);
+    while i >= min && j >= min && at(a, i) == at(b, j) {
+        if at(a, i) == b'/' {
+            suffix = a.len() - i;
+        }
+        if i == 0 || j == 0 {
+            break;
+        }
+        i -= 1;
+        j -= 1;
+    }
+
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let (a_mid, b_mid) = (&old[prefix..prefix + a_mid], &new[prefix..prefix + b_mid]);
+    if prefix + suffix == 0 {
+        return format!("{} => {}", a_mid, b_mid);
+    }
+    format!(
+        "{}{{{} => {}}}{}",
+        &old[..prefix],
+        a_mid,
+        b_mid,
+        &old[old.len() - suffix..]
+    )
+}
+
+/// Formats `--stat`: a line per file with its number of changed lines and
+/// a bar of `+` and `-` scaled to fit, then a summary.
+fn format_stat(pairs: &[FilePair], options: &Options) -> Vec<String> {
+    struct FileStat {
+        name: String,
+        added: usize,
+        deleted: usize,
+        binary: bool,
+    }
+    let stats: Vec<_> = pairs
+        .iter()
+        .map(|pair| {
+            let (added, deleted) = pair.counts(options.algorithm);
+            FileStat {
+                name: pair.display_name(),
+                added,
+                deleted,
+                binary: pair.is_binary(),
+            }
+        })
+        .collect();
+
+    let width_of = |n: usize| n.to_string().len();
+    let max_len = stats
+        .iter()
+        .map(|s| s.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max_change = stats
+        .iter()
+        .filter(|s| !s.binary)
+        .map(|s| s.added + s.deleted)
+        .max()
+        .unwrap_or(0);
+    let mut number_width = 0;
+    let mut bin_width = 0;
+    for stat in stats.iter().filter(|s| s.binary) {
+        // "Bin XXX -> YYY bytes"
+        bin_width = bin_width.max(14 + width_of(stat.added) + width_of(stat.deleted));
+        number_width = 3;
+    }
+    number_width = number_width.max(width_of(max_change));
+
+    let width = STAT_WIDTH
+        .saturating_sub(options.prefix_width)
+        .max(16 + 6 + number_width);
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width > (width * 3 / 8).saturating_sub(number_width + 6) {
+            graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    let scale = |n: usize| match n {
+        0 => 0,
+        n => 1 + n * (graph_width - 1) / max_change,
+    };
+    let mut lines = vec![];
+    for stat in &stats {
+        let mut name = stat.name.clone();
+        let mut prefix = "";
+        let len = name.chars().count();
+        if name_width < len {
+            // Keep the end of the name, starting at a directory if possible.
+            prefix = "...";
+            let keep = name_width.saturating_sub(3);
+            let mut rest: String = name.chars().skip(len - keep.min(len)).collect();
+            if let Some(slash) = rest.find('/') {
+                rest = rest[slash..].to_string();
+            }
+            name = rest;
+        }
+        let padding = name_width
+            .saturating_sub(prefix.len())
+            .saturating_sub(name.chars().count());
+        let name = format!(" {}{}{}", prefix, name, " ".repeat(padding));
+        if stat.binary {
+            match stat.added + stat.deleted {
+                0 => lines.push(format!("{} | {:>number_width$}", name, "Bin")),
+                _ => lines.push(format!(
+                    "{} | {:>number_width$} {} -> {} bytes",
+                    name, "Bin", stat.deleted, stat.added
+                )),
+            }
+            continue;
+        }
+
+        let (mut add, mut del) = (stat.added, stat.deleted);
+        if graph_width <= max_change {
+            let mut total = scale(add + del);
+            if total < 2 && add > 0 && del > 0 {
+                total = 2;
+            }
+            if add < del {
+                add = scale(add);
+                del = total - add;
+            } else {
+                del = scale(del);
+                add = total - del;
+            }
+        }
+        let total = stat.added + stat.deleted;
+        lines.push(format!(
+            "{} | {:>number_width$}{}{}{}",
+            name,
+            total,
+            if total > 0 { " " } else { "" },
+            "+".repeat(add),
+            "-".repeat(del)
+        ));
+    }
+
+    let text: Vec<_> = stats.iter().filter(|s| !s.binary).collect();
+    let insertions: usize = text.iter().map(|s| s.added).sum();
+    let deletions: usize = text.iter().map(|s| s.deleted).sum();
+    lines.push(summary(stats.len(), insertions, deletions));
+    lines
+}
+
+/// The last line of `--stat`.
+fn summary(files: usize, insertions: usize, deletions: usize) -> String {
+    if files == 0 {
+        return String::from(" 0 files changed");
+    }
+    let plural = |n: usize, one: &str, many: &str| match n {
+        1 => format!("{} {}", n, one),
+        _ => format!("{} {}", n, many),
+    };
+    let mut line = format!(" {} changed", plural(files, "file", "files"));
+    if insertions > 0 || deletions == 0 {
+        line.push_str(&format!(
+            ", {}",
+            plural(insertions, "insertion(+)", "insertions(+)")
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        line.push_str(&format!(
+            ", {}",
+            plural(deletions, "deletion(-)", "deletions(-)")
+        ));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        hash::Hash,
+        line_diff::Algorithm,
+        reference_impl,
+        tree_diff::{Change, DiffEntry},
+    };
+
+    use super::{format, format_hunks, rename_name, FilePair, Options, Output};
+
+    fn options(output: Output, context: usize, algorithm: Algorithm) -> Options {
+        Options {
+            output,
+            context,
+            algorithm,
+            renames: None,
+            prefix_width: 0,
+        }
+    }
+
+    /// Generates files of short lines from a few distinct ones, so that
+    /// they share many lines with each other.
+    fn random_file(seed: &mut u64, lines: usize) -> Vec<u8> {
+        let mut content = vec![];
+        for _ in 0..lines {
+            *seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let line = match (*seed >> 33) % 8 {
+                0 => String::from("}"),
+                1 => String::new(),
+                n => format!("line {}", n),
+            };
+            content.extend(line.into_bytes());
+            content.push(b'\n');
+        }
+        content
+    }
+
+    #[test]
+    fn test_format_hunks() {
+        let mut seed = 1;
+        for round in 0..100 {
+            let old = random_file(&mut seed, 40);
+            let mut new = random_file(&mut seed, 40);
+            if round % 10 == 0 {
+                new.pop();
+            }
+            for context in [0, 1, 3] {
+                for (algorithm, patience) in
+                    [(Algorithm::Myers, false), (Algorithm::Patience, true)]
+                {
+                    let hunks =
+                        format_hunks(&old, &new, &options(Output::Patch, context, algorithm));
+                    // libgit2 picks function names differently.
+                    let hunks: String = String::from_utf8(hunks)
+                        .unwrap()
+                        .lines()
+                        .map(|line| match line.rfind(" @@") {
+                            Some(end) if line.starts_with("@@") => {
+                                format!("{}\n", &line[..end + 3])
+                            }
+                            _ => format!("{}\n", line),
+                        })
+                        .collect();
+                    let want = reference_impl::git_diff_hunks(&old, &new, context as u32, patience);
+                    assert_eq!(hunks, want);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_function_name() {
+        let old = b"fn main() {\n    a\n    b\n    c\n    d\n}\n";
+        let new = b"fn main() {\n    a\n    b\n    c\n    e\n}\n";
+        let hunks = format_hunks(old, new, &options(Output::Patch, 1, Algorithm::Myers));
+        assert_eq!(
+            String::from_utf8(hunks).unwrap(),
+            "@@ -4,3 +4,3 @@ fn main() {\n     c\n-    d\n+    e\n }\n"
+        );
+    }
+
+    #[test]
+    fn test_stat() {
+        let entry = |path: &str| DiffEntry {
+            path: path.to_string(),
+            mode: 0o100644,
+            hash: Hash::zero(),
+        };
+        let modified = Change::between(Some(entry("a.txt")), Some(entry("a.txt")));
+        let added = Change::between(None, Some(entry("dir/new")));
+        let pairs = [
+            FilePair {
+                change: &modified,
+                old: b"1\n2\n3\n".to_vec(),
+                new: b"1\n3\n4\n5\n".to_vec(),
+            },
+            FilePair {
+                change: &added,
+                old: vec![],
+                new: b"\0".to_vec(),
+            },
+        ];
+        let stat = format("", &pairs, &options(Output::Stat, 3, Algorithm::Myers)).unwrap();
+        assert_eq!(
+            String::from_utf8(stat).unwrap(),
+            " a.txt   |   3 ++-\n dir/new | Bin 0 -> 1 bytes\n 2 files changed, 2 insertions(+), 1 deletion(-)\n"
+        );
+        let numstat = format("", &pairs, &options(Output::Numstat, 3, Algorithm::Myers)).unwrap();
+        assert_eq!(
+            String::from_utf8(numstat).unwrap(),
+            "2\t1\ta.txt\n-\t-\tdir/new\n"
+        );
+
+        // git fits the bar into 80 columns, less the graph in front of it.
+        let lines: String = (0..300).map(|i| format!("{}\n", i)).collect();
+        let pairs = [FilePair {
+            change: &modified,
+            old: vec![],
+            new: lines.into_bytes(),
+        }];
+        for (prefix_width, width) in [(0, 79), (4, 75)] {
+            let options = Options {
+                prefix_width,
+                ..options(Output::Stat, 3, Algorithm::Myers)
+            };
+            let stat = String::from_utf8(format("", &pairs, &options).unwrap()).unwrap();
+            assert_eq!(stat.lines().next().unwrap().len(), width);
+        }
+    }
+
+    #[test]
+    fn test_rename_name() {
+        assert_eq!(rename_name("a.txt", "b.txt"), "a.txt => b.txt");
+        assert_eq!(
+            rename_name("dir/a.txt", "dir/b.txt"),
+            "dir/{a.txt => b.txt}"
+        );
+        assert_eq!(rename_name("a/x/file", "b/x/file"), "{a => b}/x/file");
+        assert_eq!(
+            rename_name("dir/file", "dir/sub/file"),
+            "dir/{ => sub}/file"
+        );
+    }
+}
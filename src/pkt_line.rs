@@ -0,0 +1,61 @@
+use crate::{
+    bytes_reader::BytesReader,
+    error::{Error, Result},
+};
+
+pub const FLUSH: &[u8] = b"0000";
+
+pub enum PktLine<'a> {
+    Flush,
+    Data(&'a [u8]),
+}
+
+pub fn encode(data: impl AsRef<[u8]>) -> Vec<u8> {
+    let data = data.as_ref();
+    let mut line = format!("{:04x}", data.len() + 4).into_bytes();
+    line.extend_from_slice(data);
+    line
+}
+
+pub fn parse<'a>(reader: &mut BytesReader<'a>) -> Result<PktLine<'a>> {
+    let len = reader.read_n(4)?;
+    let len = std::str::from_utf8(len)
+        .ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .ok_or_else(|| {
+            Error::corrupt(format!(
+                "protocol error: bad line length character: {}",
+                String::from_utf8_lossy(len)
+            ))
+        })?;
+    match len {
+        0 => Ok(PktLine::Flush),
+        1..=3 => Err(Error::corrupt(format!(
+            "protocol error: bad line length {}",
+            len
+        ))),
+        len => Ok(PktLine::Data(reader.read_n(len - 4)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytes_reader::BytesReader;
+
+    use super::{encode, parse, PktLine, FLUSH};
+
+    #[test]
+    fn test_round_trip() {
+        let mut bytes = encode("want abc\n");
+        assert_eq!(bytes, b"000dwant abc\n");
+        bytes.extend_from_slice(FLUSH);
+
+        let mut reader = BytesReader::new(&bytes);
+        assert!(matches!(
+            parse(&mut reader).unwrap(),
+            PktLine::Data(b"want abc\n")
+        ));
+        assert!(matches!(parse(&mut reader).unwrap(), PktLine::Flush));
+        assert!(reader.is_at_end());
+    }
+}
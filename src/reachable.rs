@@ -0,0 +1,70 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::{error::Result, file_mode::FileMode, hash::Hash, index::Index, object::Object, refs};
+
+/// Lists the objects reachable from the refs, HEAD, the reflogs and the
+/// index, each with the path it was first found at: commits and tags
+/// first, then trees and blobs. Blobs are not read, so one that is missing
+/// goes unnoticed.
+pub fn objects(root: impl AsRef<Path>) -> Result<Vec<(Hash, String)>> {
+    let root = root.as_ref();
+    // Objects still to visit, with their path and whether they are blobs.
+    let mut pending: Vec<(Hash, String, bool)> = vec![];
+    for entry in Index::read(root)?.entries() {
+        if entry.mode != FileMode::Gitlink.bits() {
+            pending.push((entry.hash, entry.path.clone(), true));
+        }
+    }
+    for name in refs::list_reflogs(root)? {
+        for hash in refs::read_reflog(root, &name)? {
+            pending.push((hash, String::new(), false));
+        }
+    }
+    for (_, hash) in refs::list(root, "refs/")? {
+        pending.push((hash, String::new(), false));
+    }
+    if let Some(head) = refs::resolve(root, "HEAD")? {
+        pending.push((head, String::new(), false));
+    }
+
+    let mut seen = HashSet::new();
+    let mut commits = vec![];
+    let mut contents = vec![];
+    while let Some((hash, name, is_blob)) = pending.pop() {
+        if hash.is_zero() || !seen.insert(hash) {
+            continue;
+        }
+        if is_blob {
+            contents.push((hash, name));
+            continue;
+        }
+        match Object::read(root, &hash.to_string())? {
+            Object::Commit(commit) => {
+                pending.push((commit.tree, String::new(), false));
+                pending.extend(commit.parents.iter().map(|p| (*p, String::new(), false)));
+                commits.push((hash, name));
+            }
+            Object::Tag(tag) => {
+                pending.push((tag.object, name.clone(), false));
+                commits.push((hash, name));
+            }
+            Object::TreeNode(tree) => {
+                for entry in &tree {
+                    // Submodule commits live in another repository.
+                    if entry.mode == FileMode::Gitlink {
+                        continue;
+                    }
+                    let path = match name.is_empty() {
+                        true => entry.name.clone(),
+                        false => format!("{}/{}", name, entry.name),
+                    };
+                    pending.push((entry.hash, path, entry.mode != FileMode::Tree));
+                }
+                contents.push((hash, name));
+            }
+            Object::Blob(_) => contents.push((hash, name)),
+        }
+    }
+    commits.append(&mut contents);
+    Ok(commits)
+}
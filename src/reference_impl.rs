@@ -1,6 +1,16 @@
-use std::path::Path;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    thread,
+};
 
-use git2::{IndexAddOption, Repository};
+use git2::{
+    build::CheckoutBuilder, Buf, Delta, DiffFile, DiffFindOptions, DiffOptions, IndexAddOption,
+    ObjectType, Patch, Repository, Signature, Sort, Status, StatusOptions, Time, TreeWalkMode,
+    TreeWalkResult,
+};
 
 pub fn create_repository(root: impl AsRef<Path>) -> Repository {
     Repository::init(&root).unwrap()
@@ -25,6 +35,15 @@ pub fn git_write_tree(repo: &Repository) -> String {
     oid.to_string()
 }
 
+/// Writes a tree of `(name, hash, mode)` entries given in any order.
+pub fn git_build_tree(repo: &Repository, entries: &[(&str, &str, i32)]) -> String {
+    let mut builder = repo.treebuilder(None).unwrap();
+    for (name, hash, mode) in entries {
+        builder.insert(name, hash.parse().unwrap(), *mode).unwrap();
+    }
+    builder.write().unwrap().to_string()
+}
+
 pub fn read_commit(root: impl AsRef<Path>, hash: &str) -> (String, Vec<String>, Option<String>) {
     let repo = Repository::open(root).unwrap();
     let oid = repo.revparse_single(hash).unwrap().id();
@@ -38,3 +57,408 @@ pub fn read_commit(root: impl AsRef<Path>, hash: &str) -> (String, Vec<String>,
     let message = commit.message();
     (tree, parents, message.map(|m| m.to_string()))
 }
+
+fn git_commit_buffer(repo: &Repository, message: &str, parents: &[&str]) -> String {
+    let author = Signature::new(
+        "Author Name",
+        "author@example.com",
+        &Time::new(1700000000, 90),
+    )
+    .unwrap();
+    let committer = Signature::new(
+        "Committer Name",
+        "committer@example.com",
+        &Time::new(1700003600, -480),
+    )
+    .unwrap();
+    let tree = repo
+        .find_tree(git_write_tree(repo).parse().unwrap())
+        .unwrap();
+    let parents: Vec<_> = parents
+        .iter()
+        .map(|p| repo.find_commit(p.parse().unwrap()).unwrap())
+        .collect();
+    let parents: Vec<_> = parents.iter().collect();
+    let buf = repo
+        .commit_create_buffer(&author, &committer, message, &tree, &parents)
+        .unwrap();
+    buf.as_str().unwrap().to_string()
+}
+
+pub fn git_commit(repo: &Repository, message: &str, parents: &[&str]) -> String {
+    let buf = git_commit_buffer(repo, message, parents);
+    let oid = repo
+        .odb()
+        .unwrap()
+        .write(ObjectType::Commit, buf.as_bytes());
+    oid.unwrap().to_string()
+}
+
+pub fn git_commit_signed(repo: &Repository, message: &str) -> String {
+    let buf = git_commit_buffer(repo, message, &[]);
+    let signature =
+        "-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n=abcd\n-----END PGP SIGNATURE-----";
+    let oid = repo.commit_signed(&buf, signature, None).unwrap();
+    oid.to_string()
+}
+
+pub fn git_tag(repo: &Repository, name: &str, target: &str, message: &str) -> String {
+    let tagger = Signature::new(
+        "Tagger Name",
+        "tagger@example.com",
+        &Time::new(1700007200, 60),
+    )
+    .unwrap();
+    let target = repo.find_object(target.parse().unwrap(), None).unwrap();
+    let oid = repo.tag(name, &target, &tagger, message, false).unwrap();
+    oid.to_string()
+}
+
+pub fn git_write_object(repo: &Repository, kind: &str, content: impl AsRef<[u8]>) -> String {
+    let kind = ObjectType::from_str(kind).unwrap();
+    let oid = repo.odb().unwrap().write(kind, content.as_ref());
+    oid.unwrap().to_string()
+}
+
+pub fn git_pack_commit(repo: &Repository, hash: &str, target_root: impl AsRef<Path>) {
+    let mut builder = repo.packbuilder().unwrap();
+    builder.insert_commit(hash.parse().unwrap()).unwrap();
+    let mut buf = Buf::new();
+    builder.write_buf(&mut buf).unwrap();
+
+    fs::create_dir_all(target_root.as_ref().join(".git/objects/pack")).unwrap();
+    let target = Repository::open(target_root).unwrap();
+    let odb = target.odb().unwrap();
+    let mut writer = odb.packwriter().unwrap();
+    writer.write_all(&buf).unwrap();
+    writer.commit().unwrap();
+}
+
+/// Lists the objects reachable from `commit` with the paths they were
+/// found at, like `rev-list --objects`.
+pub fn git_list_objects(repo: &Repository, commit: &str) -> Vec<(String, String)> {
+    let commit = repo.find_commit(commit.parse().unwrap()).unwrap();
+    let tree = commit.tree().unwrap();
+    let mut objects = vec![
+        (commit.id().to_string(), String::new()),
+        (tree.id().to_string(), String::new()),
+    ];
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        let path = format!("{}{}", dir, entry.name().unwrap());
+        objects.push((entry.id().to_string(), path));
+        TreeWalkResult::Ok
+    })
+    .unwrap();
+    objects
+}
+
+pub fn git_read_object(repo: &Repository, hash: &str) -> (String, Vec<u8>) {
+    let odb = repo.odb().unwrap();
+    let object = odb.read(hash.parse().unwrap()).unwrap();
+    (object.kind().str().to_string(), object.data().to_vec())
+}
+
+pub fn git_update_ref(repo: &Repository, name: &str, hash: &str) {
+    repo.reference(name, hash.parse().unwrap(), true, "update")
+        .unwrap();
+}
+
+fn pkt_line(line: &[u8]) -> Vec<u8> {
+    let mut pkt = format!("{:04x}", line.len() + 4).into_bytes();
+    pkt.extend_from_slice(line);
+    pkt
+}
+
+fn advertise_refs(repo: &Repository) -> Vec<u8> {
+    let mut body = pkt_line(b"# service=git-upload-pack\n");
+    body.extend_from_slice(b"0000");
+    let head = repo.head().unwrap();
+    let capabilities = format!(
+        "side-band-64k ofs-delta symref=HEAD:{}",
+        head.name().unwrap()
+    );
+    let line = format!("{} HEAD\0{}\n", head.target().unwrap(), capabilities);
+    body.extend(pkt_line(line.as_bytes()));
+    for reference in repo.references().unwrap() {
+        let reference = reference.unwrap();
+        let line = format!(
+            "{} {}\n",
+            reference.target().unwrap(),
+            reference.name().unwrap()
+        );
+        body.extend(pkt_line(line.as_bytes()));
+    }
+    body.extend_from_slice(b"0000");
+    body
+}
+
+fn upload_pack(repo: &Repository, request: &[u8]) -> Vec<u8> {
+    let request = String::from_utf8_lossy(request);
+    let mut builder = repo.packbuilder().unwrap();
+    for (pos, _) in request.match_indices("want ") {
+        let hash = &request[pos + 5..pos + 45];
+        builder
+            .insert_recursive(hash.parse().unwrap(), None)
+            .unwrap();
+    }
+    let mut pack = Buf::new();
+    builder.write_buf(&mut pack).unwrap();
+
+    let mut body = pkt_line(b"NAK\n");
+    for chunk in pack.chunks(65515) {
+        body.extend(pkt_line(&[&[1], chunk].concat()));
+    }
+    body.extend_from_slice(b"0000");
+    body
+}
+
+fn read_http_request(stream: &TcpStream) -> (String, Vec<u8>) {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let path = request_line.split(' ').nth(1).unwrap().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+    (path, body)
+}
+
+/// Serves the repository at `root` over a minimal smart HTTP endpoint and
+/// returns its URL.
+pub fn serve_repository(root: impl AsRef<Path>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/fixture.git", listener.local_addr().unwrap());
+    let root = root.as_ref().to_path_buf();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let (path, request) = read_http_request(&stream);
+            let repo = Repository::open(&root).unwrap();
+            let (content_type, body) = if path.ends_with("/info/refs?service=git-upload-pack") {
+                ("advertisement", advertise_refs(&repo))
+            } else {
+                ("result", upload_pack(&repo, &request))
+            };
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/x-git-upload-pack-{}\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n",
+                content_type,
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+    url
+}
+
+pub fn git_index_hash(repo: &Repository, path: &str) -> String {
+    let index = repo.index().unwrap();
+    index.get_path(Path::new(path), 0).unwrap().id.to_string()
+}
+
+pub fn git_index_paths(repo: &Repository) -> Vec<String> {
+    let mut index = repo.index().unwrap();
+    index.read(true).unwrap();
+    index
+        .iter()
+        .map(|entry| String::from_utf8(entry.path).unwrap())
+        .collect()
+}
+
+pub fn git_set_index_version(repo: &Repository, version: u32) {
+    let mut index = repo.index().unwrap();
+    index.set_version(version).unwrap();
+    index.write().unwrap();
+}
+
+pub fn git_resolve_ref(repo: &Repository, name: &str) -> String {
+    let reference = repo.find_reference(name).unwrap();
+    reference.resolve().unwrap().target().unwrap().to_string()
+}
+
+pub fn git_reflog_len(repo: &Repository, name: &str) -> usize {
+    repo.reflog(name).unwrap().len()
+}
+
+pub fn git_rev_parse(repo: &Repository, rev: &str) -> String {
+    repo.revparse_single(rev).unwrap().id().to_string()
+}
+
+pub fn git_rev_list(repo: &Repository, start: &str) -> Vec<String> {
+    let mut walk = repo.revwalk().unwrap();
+    walk.set_sorting(Sort::TIME).unwrap();
+    walk.push(start.parse().unwrap()).unwrap();
+    walk.map(|oid| oid.unwrap().to_string()).collect()
+}
+
+pub fn git_merge_bases(repo: &Repository, one: &str, two: &str) -> Vec<String> {
+    let bases = repo
+        .merge_bases(one.parse().unwrap(), two.parse().unwrap())
+        .unwrap();
+    bases.iter().map(|oid| oid.to_string()).collect()
+}
+
+/// Merges two commits, returning the merged tree, or `None` if there
+/// were conflicts.
+pub fn git_merge_commits(repo: &Repository, ours: &str, theirs: &str) -> Option<String> {
+    let ours = repo.find_commit(ours.parse().unwrap()).unwrap();
+    let theirs = repo.find_commit(theirs.parse().unwrap()).unwrap();
+    let mut index = repo.merge_commits(&ours, &theirs, None).unwrap();
+    if index.has_conflicts() {
+        return None;
+    }
+    Some(index.write_tree_to(repo).unwrap().to_string())
+}
+
+/// Diffs two trees like `git diff-tree -r --name-status`, optionally with
+/// `-M`.
+pub fn git_diff_tree(repo: &Repository, old: &str, new: &str, renames: bool) -> Vec<String> {
+    let old = repo.find_tree(old.parse().unwrap()).unwrap();
+    let new = repo.find_tree(new.parse().unwrap()).unwrap();
+    let mut diff = repo
+        .diff_tree_to_tree(Some(&old), Some(&new), None)
+        .unwrap();
+    if renames {
+        diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+            .unwrap();
+    }
+    diff.deltas()
+        .map(|delta| {
+            let path = |file: DiffFile| file.path().unwrap().to_str().unwrap().to_string();
+            match delta.status() {
+                Delta::Renamed => {
+                    format!("R\t{}\t{}", path(delta.old_file()), path(delta.new_file()))
+                }
+                Delta::Added => format!("A\t{}", path(delta.new_file())),
+                Delta::Deleted => format!("D\t{}", path(delta.old_file())),
+                Delta::Typechange => format!("T\t{}", path(delta.new_file())),
+                _ => format!("M\t{}", path(delta.new_file())),
+            }
+        })
+        .collect()
+}
+
+/// The hunks of a unified diff between two buffers, with the function names
+/// left out of the hunk headers.
+pub fn git_diff_hunks(old: &[u8], new: &[u8], context: u32, patience: bool) -> String {
+    let mut options = DiffOptions::new();
+    options
+        .context_lines(context)
+        .patience(patience)
+        .indent_heuristic(true);
+    let patch = Patch::from_buffers(old, None, new, None, Some(&mut options)).unwrap();
+    let mut hunks = String::new();
+    for i in 0..patch.num_hunks() {
+        let (hunk, lines) = patch.hunk(i).unwrap();
+        let header = String::from_utf8_lossy(hunk.header());
+        let end = header.rfind("@@").unwrap();
+        hunks.push_str(&header[..end + 2]);
+        hunks.push('\n');
+        for j in 0..lines {
+            let line = patch.line_in_hunk(i, j).unwrap();
+            match line.origin() {
+                ' ' | '+' | '-' => hunks.push(line.origin()),
+                _ => {}
+            }
+            hunks.push_str(&String::from_utf8_lossy(line.content()));
+        }
+    }
+    hunks
+}
+
+pub fn git_is_ignored(repo: &Repository, path: &str) -> bool {
+    repo.is_path_ignored(path).unwrap()
+}
+
+/// The short format of `status`, without quoting paths. Like git, untracked
+/// files come last.
+pub fn git_status_short(repo: &Repository) -> String {
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).renames_head_to_index(true);
+    let statuses = repo.statuses(Some(&mut options)).unwrap();
+    let mut lines = String::new();
+    let mut untracked = String::new();
+    for entry in statuses.iter() {
+        let status = entry.status();
+        if status.contains(Status::WT_NEW) {
+            untracked.push_str(&format!("?? {}\n", entry.path().unwrap()));
+            continue;
+        }
+        let staged = [
+            (Status::INDEX_NEW, 'A'),
+            (Status::INDEX_MODIFIED, 'M'),
+            (Status::INDEX_DELETED, 'D'),
+            (Status::INDEX_RENAMED, 'R'),
+            (Status::INDEX_TYPECHANGE, 'T'),
+        ];
+        let unstaged = [
+            (Status::WT_MODIFIED, 'M'),
+            (Status::WT_DELETED, 'D'),
+            (Status::WT_TYPECHANGE, 'T'),
+        ];
+        let code = |flags: &[(Status, char)]| {
+            flags
+                .iter()
+                .find(|(flag, _)| status.contains(*flag))
+                .map_or(' ', |(_, code)| *code)
+        };
+        let path = match entry.head_to_index() {
+            Some(delta) if delta.status() == Delta::Renamed => {
+                let path = |file: DiffFile| file.path().unwrap().to_str().unwrap().to_string();
+                format!("{} -> {}", path(delta.old_file()), path(delta.new_file()))
+            }
+            _ => entry.path().unwrap().to_string(),
+        };
+        lines.push_str(&format!("{}{} {}\n", code(&staged), code(&unstaged), path));
+    }
+    lines + &untracked
+}
+
+/// Merges three versions of a file like `git merge-file`, returning the
+/// result, conflict markers included, and whether there were conflicts.
+pub fn git_merge_file(
+    repo: &Repository,
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    diff3: bool,
+) -> (String, bool) {
+    let tree = |content: &[u8]| {
+        let blob = repo.blob(content).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("file", blob, 0o100644).unwrap();
+        repo.find_tree(builder.write().unwrap()).unwrap()
+    };
+    let mut index = repo
+        .merge_trees(&tree(base), &tree(ours), &tree(theirs), None)
+        .unwrap();
+    let conflicts = index.has_conflicts();
+    let mut checkout = CheckoutBuilder::new();
+    checkout
+        .force()
+        .allow_conflicts(true)
+        .conflict_style_merge(!diff3)
+        .conflict_style_diff3(diff3)
+        .ancestor_label("base")
+        .our_label("ours")
+        .their_label("theirs");
+    repo.checkout_index(Some(&mut index), Some(&mut checkout))
+        .unwrap();
+    let content = fs::read(repo.workdir().unwrap().join("file")).unwrap();
+    (String::from_utf8(content).unwrap(), conflicts)
+}
@@ -0,0 +1,539 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    hash::Hash,
+    input_output,
+    lockfile::Lock,
+    signature::Signature,
+};
+
+const MAX_SYMREF_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ref {
+    Direct(Hash),
+    Symbolic(String),
+}
+
+impl Ref {
+    fn parse(content: &str) -> Result<Self> {
+        let content = content.trim_end();
+        match content.strip_prefix("ref: ") {
+            Some(target) => Ok(Self::Symbolic(target.to_string())),
+            None => Ok(Self::Direct(content.parse()?)),
+        }
+    }
+}
+
+fn get_git_dir(root: impl AsRef<Path>) -> PathBuf {
+    root.as_ref().join(".git")
+}
+
+fn get_ref_path(root: impl AsRef<Path>, name: &str) -> PathBuf {
+    get_git_dir(root).join(name)
+}
+
+/// Checks the rules of `git check-ref-format` that matter for file-backed
+/// refs.
+pub fn is_valid_name(name: &str) -> bool {
+    if name == "HEAD" {
+        return true;
+    }
+    !name.is_empty()
+        && name.starts_with("refs/")
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+        && name
+            .split('/')
+            .all(|part| !part.starts_with('.') && !part.ends_with(".lock"))
+}
+
+/// The refs stored in `.git/packed-refs`, with the peeled value of annotated
+/// tags if known.
+#[derive(Debug, Default)]
+pub struct PackedRefs {
+    refs: BTreeMap<String, (Hash, Option<Hash>)>,
+}
+
+impl PackedRefs {
+    fn get_path(root: impl AsRef<Path>) -> PathBuf {
+        get_git_dir(root).join("packed-refs")
+    }
+
+    pub fn read(root: impl AsRef<Path>) -> Result<Self> {
+        let path = Self::get_path(root);
+        match fs::read_to_string(&path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::io("could not read", path, e)),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let corrupt =
+            |line: &str| Error::corrupt(format!("unexpected line in packed-refs: {}", line));
+        let mut refs = BTreeMap::new();
+        let mut last: Option<String> = None;
+        for line in content.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            if let Some(peeled) = line.strip_prefix('^') {
+                let entry: &mut (Hash, Option<Hash>) = last
+                    .as_ref()
+                    .and_then(|name| refs.get_mut(name))
+                    .ok_or_else(|| corrupt(line))?;
+                entry.1 = Some(peeled.parse().map_err(|_| corrupt(line))?);
+                continue;
+            }
+            let (hash, name) = line.split_once(' ').ok_or_else(|| corrupt(line))?;
+            let hash = hash.parse().map_err(|_| corrupt(line))?;
+            refs.insert(name.to_string(), (hash, None));
+            last = Some(name.to_string());
+        }
+        Ok(Self { refs })
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut content = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+        for (name, (hash, peeled)) in &self.refs {
+            content.push_str(&format!("{} {}\n", hash, name));
+            if let Some(peeled) = peeled {
+                content.push_str(&format!("^{}\n", peeled));
+            }
+        }
+        content
+    }
+
+    pub fn get(&self, name: &str) -> Option<Hash> {
+        self.refs.get(name).map(|(hash, _)| *hash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Hash)> {
+        self.refs.iter().map(|(name, (hash, _))| (name, hash))
+    }
+
+    pub fn insert(&mut self, name: String, hash: Hash, peeled: Option<Hash>) {
+        self.refs.insert(name, (hash, peeled));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.refs.remove(name).is_some()
+    }
+
+    pub fn write(&self, root: impl AsRef<Path>) -> Result<()> {
+        let lock = Lock::acquire(Self::get_path(&root))?;
+        lock.commit(self.serialize())
+    }
+}
+
+/// Moves loose refs into `packed-refs`: all of them with `all`, otherwise
+/// only tags and the refs that were packed already. `peel` gives what an
+/// annotated tag ultimately points at, and `None` for other objects. With
+/// `prune`, the loose files are deleted afterwards, along with the
+/// directories this leaves empty below `refs/heads`, `refs/tags` and the
+/// like.
+pub fn pack(
+    root: impl AsRef<Path>,
+    all: bool,
+    prune: bool,
+    peel: impl Fn(Hash) -> Result<Option<Hash>>,
+) -> Result<()> {
+    let root = root.as_ref();
+    let lock = Lock::acquire(PackedRefs::get_path(root))?;
+    let mut packed = PackedRefs::read(root)?;
+    let mut loose = vec![];
+    for name in list_loose(root, "refs")? {
+        if !all && !name.starts_with("refs/tags/") && packed.get(&name).is_none() {
+            continue;
+        }
+        // Symbolic refs stay loose.
+        let Some(Ref::Direct(hash)) = read_ref(root, &name)? else {
+            continue;
+        };
+        packed.insert(name.clone(), hash, peel(hash)?);
+        loose.push(name);
+    }
+    lock.commit(packed.serialize())?;
+
+    if prune {
+        let refs_dir = get_ref_path(root, "refs");
+        for name in loose {
+            let path = get_ref_path(root, &name);
+            let _ = fs::remove_file(&path);
+            let mut dir = path.parent();
+            while let Some(parent) = dir {
+                let depth = parent
+                    .strip_prefix(&refs_dir)
+                    .map_or(0, |d| d.components().count());
+                if depth < 2 || fs::remove_dir(parent).is_err() {
+                    break;
+                }
+                dir = parent.parent();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a single ref without following symbolic refs, preferring the loose
+/// file over `packed-refs`.
+pub fn read_ref(root: impl AsRef<Path>, name: &str) -> Result<Option<Ref>> {
+    let path = get_ref_path(&root, name);
+    if path.is_file() {
+        let content =
+            fs::read_to_string(&path).map_err(|e| Error::io("could not read", &path, e))?;
+        let reference = Ref::parse(&content)
+            .map_err(|_| Error::fatal(format!("bad ref for {}: {}", name, content.trim_end())))?;
+        return Ok(Some(reference));
+    }
+    Ok(PackedRefs::read(root)?.get(name).map(Ref::Direct))
+}
+
+/// Follows symbolic refs starting at `name` and returns the name of the last
+/// ref in the chain along with its value, if that ref exists.
+pub fn resolve_name(root: impl AsRef<Path>, name: &str) -> Result<(String, Option<Hash>)> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read_ref(&root, &name)? {
+            Some(Ref::Symbolic(target)) => name = target,
+            Some(Ref::Direct(hash)) => return Ok((name, Some(hash))),
+            None => return Ok((name, None)),
+        }
+    }
+    Err(Error::fatal(format!("symbolic ref loop at {}", name)))
+}
+
+pub fn resolve(root: impl AsRef<Path>, name: &str) -> Result<Option<Hash>> {
+    Ok(resolve_name(root, name)?.1)
+}
+
+/// Shortens a full ref name for display: `refs/heads/main` becomes `main`.
+pub fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Lists all refs below `prefix` with their values, loose refs shadowing
+/// packed ones, sorted by name.
+pub fn list(root: impl AsRef<Path>, prefix: &str) -> Result<Vec<(String, Hash)>> {
+    let mut refs: BTreeMap<String, Hash> = PackedRefs::read(&root)?
+        .iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .map(|(name, hash)| (name.clone(), *hash))
+        .collect();
+    for name in list_loose(root.as_ref(), "refs")? {
+        if !name.starts_with(prefix) {
+            continue;
+        }
+        if let Some(hash) = resolve(&root, &name)? {
+            refs.insert(name, hash);
+        }
+    }
+    Ok(refs.into_iter().collect())
+}
+
+fn list_loose(root: &Path, dir: &str) -> Result<Vec<String>> {
+    let Ok(entries) = fs::read_dir(get_ref_path(root, dir)) else {
+        return Ok(vec![]);
+    };
+    let mut names = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        let name = format!("{}/{}", dir, input_output::basename(&path));
+        if path.is_dir() {
+            names.append(&mut list_loose(root, &name)?);
+        } else if !name.ends_with(".lock") {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+fn check_old_value(name: &str, current: Option<Hash>, old: Option<Hash>) -> Result<()> {
+    let Some(old) = old else {
+        return Ok(());
+    };
+    let matches = if old.is_zero() {
+        current.is_none()
+    } else {
+        current == Some(old)
+    };
+    match (matches, current) {
+        (true, _) => Ok(()),
+        (false, Some(current)) => Err(Error::fatal(format!(
+            "cannot lock ref '{}': is at {} but expected {}",
+            name, current, old
+        ))),
+        (false, None) => Err(Error::fatal(format!(
+            "cannot lock ref '{}': unable to resolve reference '{}'",
+            name, name
+        ))),
+    }
+}
+
+/// Points `name`, or the ref it symbolically refers to, at `new`. With
+/// `old`, the update only happens if the ref currently has that value, the
+/// zero hash meaning that it must not exist yet.
+pub fn update(root: impl AsRef<Path>, name: &str, new: &Hash, old: Option<Hash>) -> Result<()> {
+    let (name, _) = resolve_name(&root, name)?;
+    if !is_valid_name(&name) {
+        return Err(Error::fatal(format!("invalid ref name '{}'", name)));
+    }
+    let lock = Lock::acquire(get_ref_path(&root, &name))?;
+    check_old_value(&name, resolve(&root, &name)?, old)?;
+    lock.commit(format!("{}\n", new))
+}
+
+/// Deletes `name` from both the loose refs and `packed-refs`.
+pub fn delete(root: impl AsRef<Path>, name: &str, old: Option<Hash>) -> Result<()> {
+    let path = get_ref_path(&root, name);
+    let lock = Lock::acquire(path.clone())?;
+    let current = match read_ref(&root, name)? {
+        Some(Ref::Direct(hash)) => Some(hash),
+        Some(Ref::Symbolic(_)) => None,
+        None => {
+            return Err(Error::failed(format!(
+                "unable to delete '{}': ref does not exist",
+                name
+            )))
+        }
+    };
+    if old.is_some() {
+        check_old_value(name, current, old)?;
+    }
+
+    let mut packed = PackedRefs::read(&root)?;
+    if packed.remove(name) {
+        packed.write(&root)?;
+    }
+    let _ = fs::remove_file(&path);
+    drop(lock);
+    Ok(())
+}
+
+fn get_reflog_path(root: impl AsRef<Path>, name: &str) -> PathBuf {
+    get_git_dir(root).join("logs").join(name)
+}
+
+/// Reads the values `name` was given according to its reflog, oldest
+/// first. A missing reflog is empty.
+pub fn read_reflog(root: impl AsRef<Path>, name: &str) -> Result<Vec<Hash>> {
+    let path = get_reflog_path(root, name);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(Error::io("could not read", path, e)),
+    };
+    content
+        .lines()
+        .map(|line| {
+            line.split(' ')
+                .nth(1)
+                .and_then(|new| new.parse().ok())
+                .ok_or_else(|| Error::corrupt(format!("bad reflog entry for {}: {}", name, line)))
+        })
+        .collect()
+}
+
+/// Lists the refs that have a reflog, HEAD included.
+pub fn list_reflogs(root: impl AsRef<Path>) -> Result<Vec<String>> {
+    let mut names = vec![];
+    if get_reflog_path(&root, "HEAD").is_file() {
+        names.push(String::from("HEAD"));
+    }
+    let mut dirs = vec![String::from("refs")];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(get_reflog_path(&root, &dir)) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let name = format!("{}/{}", dir, input_output::basename(&path));
+            match path.is_dir() {
+                true => dirs.push(name),
+                false => names.push(name),
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Appends an entry to the reflog of `name`, recording who moved it from
+/// `old` (the zero hash for a new ref) to `new` and why.
+pub fn append_reflog(
+    root: impl AsRef<Path>,
+    name: &str,
+    old: &Hash,
+    new: &Hash,
+    committer: &Signature,
+    message: &str,
+) -> Result<()> {
+    let path = get_reflog_path(root, name);
+    let error = |e| Error::io("unable to append to", &path, e);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(error)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(error)?;
+    let message = message.lines().next().unwrap_or_default();
+    writeln!(file, "{} {} {}\t{}", old, new, committer, message).map_err(error)
+}
+
+pub fn write_symbolic(root: impl AsRef<Path>, name: &str, target: &str) -> Result<()> {
+    if !is_valid_name(target) {
+        return Err(Error::fatal(format!("invalid ref name '{}'", target)));
+    }
+    let lock = Lock::acquire(get_ref_path(root, name))?;
+    lock.commit(format!("ref: {}\n", target))
+}
+
+/// Points `name` itself at `hash` even if it is a symbolic ref, the way
+/// HEAD is detached.
+pub fn write_direct(root: impl AsRef<Path>, name: &str, hash: &Hash) -> Result<()> {
+    let lock = Lock::acquire(get_ref_path(root, name))?;
+    lock.commit(format!("{}\n", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{hash::Hash, reference_impl, repo::Repo, test_utils};
+
+    use super::{is_valid_name, list, read_ref, resolve, resolve_name, Ref};
+
+    fn hash(byte: u8) -> Hash {
+        Hash::new([byte; 20])
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = test_utils::create_test_dir();
+        Repo::new(&root).init().unwrap();
+        fs::write(
+            root.join(".git/packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n\
+                 {} refs/heads/main\n{} refs/tags/v1\n^{}\n",
+                hash(1),
+                hash(2),
+                hash(3)
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolve_name(&root, "HEAD").unwrap(),
+            (String::from("refs/heads/main"), Some(hash(1)))
+        );
+        super::update(&root, "HEAD", &hash(4), Some(hash(1))).unwrap();
+        assert_eq!(resolve(&root, "refs/heads/main").unwrap(), Some(hash(4)));
+        assert_eq!(
+            read_ref(&root, "HEAD").unwrap(),
+            Some(Ref::Symbolic(String::from("refs/heads/main")))
+        );
+        assert_eq!(
+            list(&root, "refs/").unwrap(),
+            [
+                (String::from("refs/heads/main"), hash(4)),
+                (String::from("refs/tags/v1"), hash(2))
+            ]
+        );
+
+        super::delete(&root, "refs/tags/v1", None).unwrap();
+        assert_eq!(resolve(&root, "refs/tags/v1").unwrap(), None);
+        assert!(!fs::read_to_string(root.join(".git/packed-refs"))
+            .unwrap()
+            .contains("v1"));
+    }
+
+    #[test]
+    fn test_update_old_value_mismatch() {
+        let root = test_utils::create_test_dir();
+        Repo::new(&root).init().unwrap();
+        super::update(&root, "refs/heads/main", &hash(1), Some(Hash::zero())).unwrap();
+        let error = super::update(&root, "refs/heads/main", &hash(2), Some(hash(3))).unwrap_err();
+        assert!(error.to_string().contains("is at"));
+        assert_eq!(error.exit_code(), 128);
+        assert!(!root.join(".git/refs/heads/main.lock").exists());
+    }
+
+    #[test]
+    fn test_read_by_git() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        super::update(&root, "refs/heads/topic", &hash(5), None).unwrap();
+        super::write_symbolic(&root, "HEAD", "refs/heads/topic").unwrap();
+        assert_eq!(
+            reference_impl::git_resolve_ref(&repository, "HEAD"),
+            hash(5).to_string()
+        );
+    }
+
+    #[test]
+    fn test_pack() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        super::update(&root, "refs/heads/main", &hash(1), None).unwrap();
+        super::update(&root, "refs/heads/feature/x", &hash(1), None).unwrap();
+        super::update(&root, "refs/tags/v1", &hash(2), None).unwrap();
+        super::write_symbolic(&root, "refs/heads/alias", "refs/heads/main").unwrap();
+        let peel = |hash: Hash| Ok((hash == self::hash(2)).then(|| self::hash(3)));
+
+        super::pack(&root, false, true, peel).unwrap();
+        assert!(!root.join(".git/refs/tags/v1").exists());
+        assert!(root.join(".git/refs/heads/main").exists());
+
+        super::pack(&root, true, true, peel).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join(".git/packed-refs")).unwrap(),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n\
+                 {} refs/heads/feature/x\n{} refs/heads/main\n{} refs/tags/v1\n^{}\n",
+                hash(1),
+                hash(1),
+                hash(2),
+                hash(3)
+            )
+        );
+        assert!(!root.join(".git/refs/heads/feature").exists());
+        assert!(root.join(".git/refs/heads").is_dir());
+        assert_eq!(
+            read_ref(&root, "refs/heads/alias").unwrap(),
+            Some(Ref::Symbolic(String::from("refs/heads/main")))
+        );
+        assert_eq!(
+            reference_impl::git_resolve_ref(&repository, "refs/heads/feature/x"),
+            hash(1).to_string()
+        );
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("refs/heads/main"));
+        assert!(is_valid_name("refs/heads/feature/x-1"));
+        assert!(!is_valid_name("refs/heads/a..b"));
+        assert!(!is_valid_name("refs/heads/a.lock"));
+        assert!(!is_valid_name("refs/heads/a b"));
+        assert!(!is_valid_name("refs/heads/.hidden"));
+        assert!(!is_valid_name("main"));
+    }
+}
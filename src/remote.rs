@@ -0,0 +1,216 @@
+use std::{fmt::Display, io::Read, str::from_utf8};
+
+use crate::{
+    bytes_reader::BytesReader,
+    error::{Error, Result},
+    hash::Hash,
+    pkt_line::{self, PktLine},
+};
+
+const UPLOAD_PACK: &str = "git-upload-pack";
+
+/// The refs and capabilities a server announces in response to
+/// `info/refs?service=git-upload-pack`.
+pub struct Advertisement {
+    pub refs: Vec<(String, Hash)>,
+    pub capabilities: Vec<String>,
+}
+
+impl Advertisement {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = BytesReader::new(bytes);
+        let mut refs = vec![];
+        let mut capabilities = vec![];
+        while !reader.is_at_end() {
+            let PktLine::Data(line) = pkt_line::parse(&mut reader)? else {
+                continue;
+            };
+            if line.starts_with(b"# service=") {
+                continue;
+            }
+            let invalid = || {
+                Error::fatal(format!(
+                    "protocol error: unexpected '{}'",
+                    String::from_utf8_lossy(line).trim_end()
+                ))
+            };
+            let line = from_utf8(line)
+                .map_err(|_| invalid())?
+                .trim_end_matches('\n');
+            let line = match line.split_once('\0') {
+                Some((line, caps)) => {
+                    capabilities = caps.split(' ').map(String::from).collect();
+                    line
+                }
+                None => line,
+            };
+            let (hash, name) = line.split_once(' ').ok_or_else(invalid)?;
+            // An empty repository advertises its capabilities on a dummy ref.
+            if name != "capabilities^{}" {
+                refs.push((name.to_string(), hash.parse().map_err(|_| invalid())?));
+            }
+        }
+        Ok(Self { refs, capabilities })
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Returns the ref HEAD points to, as announced by the `symref`
+    /// capability or guessed from the branch with HEAD's hash.
+    pub fn head_target(&self) -> Option<String> {
+        let symref = self
+            .capabilities
+            .iter()
+            .find_map(|c| c.strip_prefix("symref=HEAD:"));
+        if let Some(target) = symref {
+            return Some(target.to_string());
+        }
+        let (_, head) = self.refs.iter().find(|(name, _)| name == "HEAD")?;
+        self.refs
+            .iter()
+            .filter(|(name, _)| name.starts_with("refs/heads/"))
+            .find(|(_, hash)| hash == head)
+            .map(|(name, _)| name.clone())
+    }
+}
+
+fn strip_url(url: &str) -> &str {
+    url.trim_end_matches('/')
+}
+
+/// Derives the directory `clone` creates from the repository URL, like
+/// `https://host/user/project.git` -> `project`.
+pub fn default_directory(url: &str) -> String {
+    let name = strip_url(url).rsplit('/').next().unwrap();
+    name.trim_end_matches(".git").to_string()
+}
+
+/// Reports a failed request for the repository at `url` the way git does,
+/// with the repository URL once rather than the URL of the request.
+fn remote_error(url: &str, error: impl Display) -> Error {
+    Error::Remote {
+        url: format!("{}/", strip_url(url)),
+        message: error.to_string(),
+    }
+}
+
+/// Describes a failed request without the request URL, which ureq puts in
+/// front of its messages.
+fn request_error(url: &str, error: ureq::Error) -> Error {
+    let message = match error {
+        ureq::Error::Status(code, _) => format!("The requested URL returned error: {}", code),
+        ureq::Error::Transport(transport) => {
+            let mut message = transport.kind().to_string();
+            if let Some(detail) = transport.message() {
+                message = format!("{}: {}", message, detail);
+            }
+            if let Some(source) = std::error::Error::source(&transport) {
+                message = format!("{}: {}", message, source);
+            }
+            message
+        }
+    };
+    remote_error(url, message)
+}
+
+pub fn discover_refs(url: &str) -> Result<Advertisement> {
+    let request = format!("{}/info/refs?service={}", strip_url(url), UPLOAD_PACK);
+    let mut body = vec![];
+    ureq::get(&request)
+        .call()
+        .map_err(|e| request_error(url, e))?
+        .into_reader()
+        .read_to_end(&mut body)
+        .map_err(|e| remote_error(url, e))?;
+    Advertisement::parse(&body)
+}
+
+fn build_request(advertisement: &Advertisement, wants: &[Hash], haves: &[Hash]) -> Vec<u8> {
+    let capabilities: Vec<_> = ["side-band-64k", "ofs-delta"]
+        .into_iter()
+        .filter(|c| advertisement.has_capability(c))
+        .chain(["agent=codecrafters-git"])
+        .collect();
+
+    let mut request = vec![];
+    for (i, want) in wants.iter().enumerate() {
+        let line = if i == 0 {
+            format!("want {} {}\n", want, capabilities.join(" "))
+        } else {
+            format!("want {}\n", want)
+        };
+        request.extend(pkt_line::encode(line));
+    }
+    request.extend_from_slice(pkt_line::FLUSH);
+    for have in haves {
+        request.extend(pkt_line::encode(format!("have {}\n", have)));
+    }
+    request.extend(pkt_line::encode("done\n"));
+    request
+}
+
+/// Extracts the packfile from an upload-pack response, demultiplexing the
+/// side-band channels if they were negotiated.
+fn read_pack(advertisement: &Advertisement, response: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = BytesReader::new(response);
+    // Acknowledgements for our haves, terminated by NAK or the final ACK.
+    loop {
+        let PktLine::Data(line) = pkt_line::parse(&mut reader)? else {
+            continue;
+        };
+        if !line.ends_with(b"continue\n") {
+            break;
+        }
+    }
+
+    if !advertisement.has_capability("side-band-64k") {
+        return Ok(reader.read_all().to_vec());
+    }
+
+    let mut pack = vec![];
+    while !reader.is_at_end() {
+        let PktLine::Data(line) = pkt_line::parse(&mut reader)? else {
+            break;
+        };
+        match line.first() {
+            Some(1) => pack.extend_from_slice(&line[1..]),
+            Some(2) => eprint!("{}", String::from_utf8_lossy(&line[1..])),
+            Some(3) => {
+                let message = String::from_utf8_lossy(&line[1..]);
+                return Err(Error::fatal(format!(
+                    "remote error: {}",
+                    message.trim_end()
+                )));
+            }
+            band => {
+                return Err(Error::fatal(format!(
+                    "protocol error: bad band #{}",
+                    band.copied().unwrap_or_default()
+                )))
+            }
+        }
+    }
+    Ok(pack)
+}
+
+pub fn fetch_pack(
+    url: &str,
+    advertisement: &Advertisement,
+    wants: &[Hash],
+    haves: &[Hash],
+) -> Result<Vec<u8>> {
+    let endpoint = format!("{}/{}", strip_url(url), UPLOAD_PACK);
+    let request = build_request(advertisement, wants, haves);
+    let mut response = vec![];
+    ureq::post(&endpoint)
+        .set("Content-Type", "application/x-git-upload-pack-request")
+        .set("Accept", "application/x-git-upload-pack-result")
+        .send_bytes(&request)
+        .map_err(|e| request_error(url, e))?
+        .into_reader()
+        .read_to_end(&mut response)
+        .map_err(|e| remote_error(url, e))?;
+    read_pack(advertisement, &response)
+}
@@ -1,9 +1,43 @@
 use std::{
-    env, fs,
+    collections::{BTreeMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{self, Write},
     path::{Path, PathBuf},
+    time::UNIX_EPOCH,
 };
 
-use crate::{blob::Blob, commit::Commit, input_output, object::Object, tree};
+use crate::{
+    blob::Blob,
+    checkout,
+    commit::Commit,
+    config::{self, Action, Config, ConfigFile, Location, Scope},
+    error::{Error, Result},
+    file_mode::FileMode,
+    fsck,
+    hash::Hash,
+    ignore::Ignore,
+    index::{self, Index},
+    input_output,
+    line_merge::Style,
+    log,
+    merge::{self, FastForward},
+    merge_base,
+    object::{self, Object},
+    pack, pack_writer,
+    patch::{self, FilePair},
+    reachable,
+    refs::{self, PackedRefs, Ref},
+    remote, revision,
+    revwalk::{RevWalk, Walked},
+    signature::{self, Signature},
+    status,
+    tag::{self, Tag},
+    tree,
+    tree_diff::{self, Change, DiffEntry},
+    wildmatch::wildmatch,
+    worktree,
+};
 
 pub struct Repo {
     root: PathBuf,
@@ -14,71 +48,3197 @@ impl Repo {
         Self { root: root.into() }
     }
 
-    pub fn new_current_dir() -> Self {
-        Self::new(env::current_dir().unwrap())
+    pub fn new_current_dir() -> Result<Self> {
+        Ok(Self::new(env::current_dir()?))
     }
 
     pub fn get_root(&self) -> &Path {
         &self.root
     }
 
-    pub fn init(&self) {
-        fs::create_dir_all(self.get_root().join(".git/objects")).unwrap();
-        fs::create_dir_all(self.get_root().join(".git/refs")).unwrap();
-        input_output::write(self.get_root().join(".git/HEAD"), "ref: refs/heads/main\n");
+    pub fn init(&self) -> Result<()> {
+        for dir in [".git/objects", ".git/refs"] {
+            let path = self.get_root().join(dir);
+            fs::create_dir_all(&path).map_err(|e| Error::io("cannot mkdir", path, e))?;
+        }
+        refs::write_symbolic(self.get_root(), "HEAD", "refs/heads/main")?;
+
+        let mut config = ConfigFile::read(self.get_root().join(".git/config"))?;
+        config.set("core.repositoryformatversion", "0")?;
+        config.set("core.filemode", "true")?;
+        config.set("core.bare", "false")?;
+        config.set("core.logallrefupdates", "true")?;
+        config.write()
     }
 
-    pub fn cat_file(&self, hash: &str) {
-        let obj = Object::read(self.get_root(), hash);
+    pub fn cat_file(&self, rev: &str) -> Result<()> {
+        let hash = revision::resolve(self.get_root(), rev)?.to_string();
+        let obj = Object::read(self.get_root(), &hash)?;
         match obj {
             Object::Blob(blob) => print!("{}", blob),
-            Object::TreeNode(_) => self.ls_tree(false, hash),
-            Object::Commit(_) => unimplemented!(),
+            Object::TreeNode(_) => self.ls_tree(false, &hash)?,
+            Object::Commit(commit) => write_stdout(&commit.payload())?,
+            Object::Tag(tag) => write_stdout(&tag.payload())?,
         };
+        Ok(())
     }
 
-    pub fn hash_object(&self, path: &str) {
-        let content = fs::read(self.root.join(path)).unwrap();
+    pub fn hash_object(&self, path: &str) -> Result<()> {
+        let content =
+            fs::read(self.root.join(path)).map_err(|e| Error::io("could not open", path, e))?;
         let obj = Object::Blob(Blob::new(content));
-        let hash = obj.write(self.get_root());
+        let hash = obj.write(self.get_root())?;
         print!("{}", hash);
+        Ok(())
     }
 
-    pub fn ls_tree(&self, name_only: bool, tree_ish: &str) {
-        let Object::TreeNode(tree) = Object::read(self.get_root(), tree_ish) else {
-            panic!("fatal: not a tree object")
+    pub fn ls_tree(&self, name_only: bool, tree_ish: &str) -> Result<()> {
+        let hash = revision::resolve(self.get_root(), tree_ish)?;
+        let (hash, _) = revision::peel(self.get_root(), hash, "tree")?;
+        let Object::TreeNode(tree) = Object::read(self.get_root(), &hash.to_string())? else {
+            return Err(Error::fatal("not a tree object"));
         };
 
         if name_only {
             tree.into_iter()
                 .map(|entry| &entry.name)
                 .for_each(|name| println!("{}", name));
-            return;
+            return Ok(());
         }
 
         for entry in &tree {
-            let obj = Object::read(self.get_root(), &entry.hash.to_string());
             println!(
                 "{:0>6} {} {}\t{}",
                 entry.mode,
-                obj.get_type(),
+                entry.mode.object_type(),
                 entry.hash,
                 entry.name
             )
         }
+        Ok(())
+    }
+
+    pub fn add(&self, pathspecs: Vec<String>, all: bool, update: bool, force: bool) -> Result<()> {
+        let pathspecs = if pathspecs.is_empty() {
+            if !all && !update {
+                println!("Nothing specified, nothing added.");
+                return Ok(());
+            }
+            vec![String::new()]
+        } else {
+            pathspecs
+                .iter()
+                .map(|p| worktree::normalize_pathspec(p))
+                .collect()
+        };
+
+        let mut index = Index::read(self.get_root())?;
+        let ignore = match force {
+            true => None,
+            false => Some(Ignore::load(self.get_root())?),
+        };
+        let mut ignored = vec![];
+        for pathspec in pathspecs {
+            let files = worktree::list_files(self.get_root(), &pathspec, &index, ignore.as_ref())?;
+            let tracked = index.matching(&pathspec);
+            if files.is_empty() && tracked.is_empty() {
+                let full_path = worktree::get_path(self.get_root(), &pathspec);
+                let is_dir = fs::symlink_metadata(full_path).map(|m| m.is_dir());
+                let excluded = is_dir.is_ok_and(|is_dir| {
+                    ignore
+                        .as_ref()
+                        .is_some_and(|ignore| ignore.is_excluded(&pathspec, is_dir))
+                });
+                if excluded {
+                    ignored.push(pathspec);
+                    continue;
+                }
+                return Err(Error::fatal(format!(
+                    "pathspec '{}' did not match any files",
+                    pathspec
+                )));
+            }
+
+            for path in &files {
+                if update && index.get(path).is_none() {
+                    continue;
+                }
+                index.add(worktree::stage_file(self.get_root(), path)?);
+            }
+            // `files` is in path order, not string order, so it cannot be
+            // searched by halves.
+            let files: HashSet<&str> = files.iter().map(String::as_str).collect();
+            for path in tracked {
+                if !files.contains(path.as_str()) {
+                    index.remove(&path);
+                }
+            }
+        }
+        index.write(self.get_root())?;
+
+        if ignored.is_empty() {
+            return Ok(());
+        }
+        eprintln!("The following paths are ignored by one of your .gitignore files:");
+        for path in ignored {
+            eprintln!("{}", path);
+        }
+        eprintln!("hint: Use -f if you really want to add them.");
+        eprintln!("hint: Turn this message off by running");
+        eprintln!("hint: \"git config advice.addIgnoredFile false\"");
+        Err(Error::Exit(1))
+    }
+
+    pub fn rm(
+        &self,
+        pathspecs: Vec<String>,
+        cached: bool,
+        recursive: bool,
+        force: bool,
+    ) -> Result<()> {
+        let mut index = Index::read(self.get_root())?;
+        let mut paths = vec![];
+        for pathspec in pathspecs {
+            let pathspec = worktree::normalize_pathspec(&pathspec);
+            let tracked = index.matching(&pathspec);
+            if tracked.is_empty() {
+                return Err(Error::fatal(format!(
+                    "pathspec '{}' did not match any files",
+                    pathspec
+                )));
+            }
+            if !recursive && tracked.iter().any(|path| *path != pathspec) {
+                return Err(Error::fatal(format!(
+                    "not removing '{}' recursively without -r",
+                    pathspec
+                )));
+            }
+            paths.extend(tracked);
+        }
+
+        if !force && !cached {
+            let mut modified = vec![];
+            for path in &paths {
+                let full_path = worktree::get_path(self.get_root(), path);
+                if fs::symlink_metadata(full_path).is_err() {
+                    continue;
+                }
+                let (_, content) = worktree::read_content(self.get_root(), path)?;
+                let entry = index.get(path).unwrap();
+                if Object::Blob(Blob::new(content)).hash() != entry.hash {
+                    modified.push(format!("    {}", path));
+                }
+            }
+            if !modified.is_empty() {
+                return Err(Error::failed(format!(
+                    "the following files have local modifications:\n{}\n\
+                     (use --cached to keep the file, or -f to force removal)",
+                    modified.join("\n")
+                )));
+            }
+        }
+
+        for path in paths {
+            index.remove(&path);
+            if !cached {
+                worktree::remove_file(self.get_root(), &path);
+            }
+            println!("rm '{}'", path);
+        }
+        index.write(self.get_root())
+    }
+
+    pub fn write_tree(&self) -> Result<()> {
+        let hash = tree::write_tree(self.get_root())?;
+        print!("{}", hash);
+        Ok(())
+    }
+
+    fn expect_kind(&self, rev: &str, kind: &str) -> Result<Hash> {
+        let hash = revision::resolve(self.get_root(), rev)?;
+        let (got, _) = object::read_raw(self.get_root(), &hash.to_string())?;
+        if got != kind {
+            return Err(Error::fatal(format!(
+                "{} is not a valid '{}' object",
+                hash, kind
+            )));
+        }
+        Ok(hash)
     }
 
-    pub fn write_tree(&self) {
-        let hash = tree::write_tree(self.get_root());
-        if let Some(hash) = hash {
-            print!("{}", hash)
+    /// Builds a commit message the way `commit-tree` does: `-m` values become
+    /// paragraphs, `-F` files (`-` for stdin) are taken verbatim, and without
+    /// either the message is read from stdin.
+    fn read_message(messages: Vec<String>, files: Vec<String>) -> Result<String> {
+        let mut message = String::new();
+        for paragraph in messages {
+            if !message.is_empty() {
+                message.push('\n');
+            }
+            message.push_str(&paragraph);
+            if !message.ends_with('\n') {
+                message.push('\n');
+            }
         }
+        for file in &files {
+            if !message.is_empty() {
+                message.push('\n');
+            }
+            let content = if file == "-" {
+                io::read_to_string(io::stdin())?
+            } else {
+                fs::read_to_string(file)
+                    .map_err(|e| Error::io("could not read log file", file, e))?
+            };
+            message.push_str(&content);
+        }
+        if message.is_empty() && files.is_empty() {
+            message = io::read_to_string(io::stdin())?;
+        }
+        Ok(message)
     }
 
-    pub fn commit_tree(&self, tree: String, parent: String, message: String) {
-        let commit = Commit::new_current_time(tree.into(), vec![parent.into()], message);
+    pub fn commit_tree(
+        &self,
+        tree: String,
+        parents: Vec<String>,
+        messages: Vec<String>,
+        files: Vec<String>,
+    ) -> Result<()> {
+        let tree = self.expect_kind(&tree, "tree")?;
+        let mut parent_hashes: Vec<Hash> = vec![];
+        for parent in parents {
+            let parent = self.expect_kind(&parent, "commit")?;
+            if parent_hashes.contains(&parent) {
+                eprintln!("error: duplicate parent {} ignored", parent);
+                continue;
+            }
+            parent_hashes.push(parent);
+        }
+        let message = Self::read_message(messages, files)?;
+
+        let commit = Commit::new(
+            tree,
+            parent_hashes,
+            Signature::author(self.get_root())?,
+            Signature::committer(self.get_root())?,
+            message,
+        );
         let obj = Object::Commit(commit);
-        let hash = obj.write(self.get_root());
-        println!("{}", hash)
+        let hash = obj.write(self.get_root())?;
+        println!("{}", hash);
+        Ok(())
+    }
+
+    pub fn ls_files(&self, stage: bool) -> Result<()> {
+        let index = Index::read(self.get_root())?;
+        for entry in index.entries() {
+            if stage {
+                println!(
+                    "{:06o} {} {}\t{}",
+                    entry.mode, entry.hash, entry.stage, entry.path
+                );
+            } else {
+                println!("{}", entry.path);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn commit(&self, messages: Vec<String>, allow_empty: bool) -> Result<()> {
+        let index = Index::read(self.get_root())?;
+        let mut unmerged: Vec<&str> = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage != 0)
+            .map(|entry| entry.path.as_str())
+            .collect();
+        unmerged.dedup();
+        if !unmerged.is_empty() {
+            for path in unmerged {
+                println!("U\t{}", path);
+            }
+            return Err(unresolved_conflict("Committing"));
+        }
+
+        let tree = tree::write_tree(self.get_root())?;
+        let (branch, parent) = refs::resolve_name(self.get_root(), "HEAD")?;
+        let merge_head = self.merge_head()?;
+        if let (Some(parent), None) = (parent, merge_head) {
+            let Object::Commit(parent) = Object::read(self.get_root(), &parent.to_string())? else {
+                return Err(Error::fatal("HEAD is not a commit"));
+            };
+            if parent.tree == tree && !allow_empty {
+                println!("nothing to commit, working tree clean");
+                return Err(Error::Exit(1));
+            }
+        }
+
+        // A merge is concluded with the message it prepared, the way the
+        // editor would show it.
+        let message = match merge_head {
+            Some(_) if messages.is_empty() => {
+                let path = self.get_root().join(".git/MERGE_MSG");
+                let prepared =
+                    fs::read_to_string(&path).map_err(|e| Error::io("could not read", path, e))?;
+                let lines: Vec<&str> = prepared
+                    .lines()
+                    .filter(|line| !line.starts_with('#'))
+                    .collect();
+                strip_space(&lines.join("\n"))
+            }
+            _ => Self::read_message(messages, vec![])?,
+        };
+        if message.trim().is_empty() {
+            eprintln!("Aborting commit due to empty commit message.");
+            return Err(Error::Exit(1));
+        }
+        let summary = message.lines().next().unwrap_or_default().to_string();
+        let commit = Commit::new(
+            tree,
+            parent.into_iter().chain(merge_head).collect(),
+            Signature::author(self.get_root())?,
+            Signature::committer(self.get_root())?,
+            message,
+        );
+        let committer = commit.committer.clone();
+        let hash = Object::Commit(commit).write(self.get_root())?;
+
+        let old = parent.unwrap_or(Hash::zero());
+        refs::update(self.get_root(), &branch, &hash, Some(old))?;
+        let reflog_message = match (parent, merge_head) {
+            (_, Some(_)) => format!("commit (merge): {}", summary),
+            (Some(_), None) => format!("commit: {}", summary),
+            (None, None) => format!("commit (initial): {}", summary),
+        };
+        // A detached HEAD is its own branch and is logged once.
+        let mut names = vec!["HEAD"];
+        if branch != "HEAD" {
+            names.push(&branch);
+        }
+        for name in names {
+            refs::append_reflog(
+                self.get_root(),
+                name,
+                &old,
+                &hash,
+                &committer,
+                &reflog_message,
+            )?;
+        }
+
+        let short = &hash.to_string()[..7];
+        let label = branch
+            .strip_prefix("refs/heads/")
+            .unwrap_or("detached HEAD");
+        let root_commit = if parent.is_none() {
+            " (root-commit)"
+        } else {
+            ""
+        };
+        self.remove_merge_state();
+        println!("[{}{} {}] {}", label, root_commit, short, summary);
+        Ok(())
+    }
+
+    pub fn update_ref(&self, delete: bool, name: &str, values: Vec<String>) -> Result<()> {
+        let values = values
+            .iter()
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| Error::fatal(format!("{}: not a valid SHA1", value)))
+            })
+            .collect::<Result<Vec<Hash>>>()?;
+        let mut values = values.into_iter();
+        if delete {
+            return refs::delete(self.get_root(), name, values.next());
+        }
+        let new = values.next().ok_or_else(|| {
+            Error::Usage(String::from("wrong number of arguments, should be 2 or 3"))
+        })?;
+        refs::update(self.get_root(), name, &new, values.next())
+    }
+
+    pub fn symbolic_ref(&self, name: &str, target: Option<String>) -> Result<()> {
+        match target {
+            Some(target) => refs::write_symbolic(self.get_root(), name, &target),
+            None => match refs::read_ref(self.get_root(), name)? {
+                Some(Ref::Symbolic(target)) => {
+                    println!("{}", target);
+                    Ok(())
+                }
+                _ => Err(Error::fatal(format!("ref {} is not a symbolic ref", name))),
+            },
+        }
+    }
+
+    pub fn show_ref(&self, head: bool, patterns: Vec<String>) -> Result<()> {
+        let mut all = vec![];
+        if head {
+            if let Some(hash) = refs::resolve(self.get_root(), "HEAD")? {
+                all.push((String::from("HEAD"), hash));
+            }
+        }
+        all.extend(refs::list(self.get_root(), "refs/")?);
+
+        for (name, hash) in all {
+            let matches = patterns.is_empty()
+                || patterns
+                    .iter()
+                    .any(|p| name == *p || name.ends_with(&format!("/{}", p)));
+            if matches {
+                println!("{} {}", hash, name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the object each revision names, abbreviated to at least
+    /// `short` digits if given. With `verify`, exactly one revision must be
+    /// given and resolve; `quiet` then turns the failure into exit code 1.
+    /// With `abbrev_ref`, revisions that are refs print as short ref names.
+    pub fn rev_parse(
+        &self,
+        revs: Vec<String>,
+        verify: bool,
+        quiet: bool,
+        short: Option<usize>,
+        abbrev_ref: bool,
+    ) -> Result<()> {
+        let print = |hash: Hash| -> Result<()> {
+            match short {
+                Some(len) => println!("{}", object::abbreviate(self.get_root(), &hash, len)?),
+                None => println!("{}", hash),
+            }
+            Ok(())
+        };
+
+        if verify || short.is_some() {
+            let resolved = match revs.as_slice() {
+                [rev] => revision::resolve(self.get_root(), rev),
+                _ => Err(Error::InvalidObjectName(revs.join(" "))),
+            };
+            return match resolved {
+                Ok(hash) => print(hash),
+                Err(Error::InvalidObjectName(_)) if quiet => Err(Error::Exit(1)),
+                Err(Error::InvalidObjectName(_)) => Err(Error::fatal("Needed a single revision")),
+                Err(e) => Err(e),
+            };
+        }
+
+        for rev in &revs {
+            let hash = revision::resolve(self.get_root(), rev).map_err(|e| match e {
+                Error::InvalidObjectName(rev) => Error::UnknownRevision(rev),
+                e => e,
+            })?;
+            if !abbrev_ref {
+                print(hash)?;
+                continue;
+            }
+            let name = if rev == "@" { "HEAD" } else { rev };
+            if let Some(refname) = revision::expand_ref(self.get_root(), name)? {
+                let (refname, _) = refs::resolve_name(self.get_root(), &refname)?;
+                println!("{}", refs::shorten(&refname));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn log(&self, mut options: log::Options) -> Result<()> {
+        // Arguments that are not revisions but exist in the worktree are
+        // paths, as if given after `--`.
+        let mut starts = vec![];
+        for rev in &options.revisions {
+            match revision::resolve(self.get_root(), rev) {
+                Ok(hash) => starts.push(hash),
+                Err(Error::InvalidObjectName(_)) if self.get_root().join(rev).exists() => {
+                    options.paths.push(rev.clone())
+                }
+                Err(Error::InvalidObjectName(rev)) => return Err(Error::UnknownRevision(rev)),
+                Err(e) => return Err(e),
+            }
+        }
+        if starts.is_empty() {
+            let (branch, head) = refs::resolve_name(self.get_root(), "HEAD")?;
+            let Some(head) = head else {
+                return Err(Error::fatal(format!(
+                    "your current branch '{}' does not have any commits yet",
+                    refs::shorten(&branch)
+                )));
+            };
+            starts.push(head);
+        }
+
+        let mut walk = RevWalk::new(self.get_root())
+            .first_parent(options.first_parent)
+            .paths(options.paths.clone())
+            .topo_order(options.graph);
+        for start in starts {
+            walk.push(start)?;
+        }
+
+        let mut graph = options.graph.then(log::Graph::default);
+        let mut shown = 0;
+        while let Some(walked) = walk.next()? {
+            if options.max_count.is_some_and(|max| shown >= max) {
+                break;
+            }
+            if walked.treesame || !options.matches(&walked.commit) {
+                if let Some(graph) = &mut graph {
+                    graph.skip(walked.hash, &walked.parents);
+                }
+                continue;
+            }
+
+            if let Some(graph) = &mut graph {
+                graph.update(walked.hash, &walked.parents);
+            }
+            if shown > 0 && options.format.is_multiline() {
+                match &mut graph {
+                    Some(graph) => println!("{}", graph.separator()),
+                    None => println!(),
+                }
+            }
+            let text = options.format.format(self.get_root(), &walked)?;
+            let diff = match &options.diff {
+                Some(diff) => {
+                    let diff = patch::Options {
+                        prefix_width: graph.as_ref().map_or(0, log::Graph::width),
+                        ..diff.clone()
+                    };
+                    let first_parent = options.first_parent;
+                    self.log_changes(&walked.commit, &options.paths, first_parent, &diff)?
+                }
+                None => vec![],
+            };
+            let separator = match options.format.separates_diff() {
+                true => "\n",
+                false => "",
+            };
+            match &mut graph {
+                Some(graph) => {
+                    for line in graph.render(&text) {
+                        println!("{}", line);
+                    }
+                    // The graph is done with the commit before its changes.
+                    if !diff.is_empty() {
+                        let diff = format!("{}{}", separator, String::from_utf8_lossy(&diff));
+                        for line in diff.trim_end_matches('\n').split('\n') {
+                            println!("{}{}", graph.padding(), line);
+                        }
+                    }
+                }
+                None => {
+                    println!("{}", text);
+                    if !diff.is_empty() {
+                        print!("{}", separator);
+                        write_stdout(&diff)?;
+                    }
+                }
+            }
+            shown += 1;
+        }
+        Ok(())
+    }
+
+    pub fn diff_tree(&self, options: tree_diff::Options) -> Result<()> {
+        let root = self.get_root();
+        let resolve = |rev: &str| -> Result<Hash> {
+            let hash = revision::resolve(root, rev)?;
+            Ok(revision::peel(root, hash, "tree")?.0)
+        };
+        // With a single commit, its changes against its parent are shown
+        // after its name. Merges are not shown, like git without `-m`.
+        let (header, old, new) = match options.trees.as_slice() {
+            [old, new] => (None, Some(resolve(old)?), Some(resolve(new)?)),
+            [rev] => {
+                let hash = revision::resolve(root, rev)?;
+                let (hash, kind) = revision::peel(root, hash, "commit")?;
+                let Object::Commit(commit) = Object::read(root, &hash.to_string())? else {
+                    return Err(Error::fatal(format!(
+                        "object {} is a {}, not a commit",
+                        hash, kind
+                    )));
+                };
+                let old = match commit.parents.as_slice() {
+                    [] if options.root => None,
+                    [parent] => Some(resolve(&parent.to_string())?),
+                    _ => return Ok(()),
+                };
+                (Some(hash), old, Some(commit.tree))
+            }
+            _ => {
+                return Err(Error::Usage(String::from(
+                    "usage: git diff-tree <tree-ish> [<tree-ish>]",
+                )))
+            }
+        };
+
+        let mut changes = tree_diff::diff_trees(root, old, new, options.recursive)?;
+        if let Some(min_score) = options.copies.or(options.renames) {
+            let copies = options.copies.is_some();
+            changes = tree_diff::detect_renames(changes, min_score, copies, |entry| {
+                Ok(object::read_raw(root, &entry.hash.to_string())?.1)
+            })?;
+        }
+        if changes.is_empty() {
+            return Ok(());
+        }
+        if let Some(header) = header {
+            println!("{}", header);
+        }
+        for change in &changes {
+            println!("{}", options.output.format(change));
+        }
+        Ok(())
+    }
+
+    pub fn diff(
+        &self,
+        revisions: Vec<String>,
+        cached: bool,
+        mut paths: Vec<String>,
+        options: patch::Options,
+    ) -> Result<()> {
+        let root = self.get_root();
+        // `A..B` compares the same trees as `A B`.
+        let revisions = match revisions.as_slice() {
+            [range] if range.contains("..") => {
+                let (old, new) = range.split_once("..").unwrap();
+                let side = |rev: &str| match rev {
+                    "" => String::from("HEAD"),
+                    rev => rev.to_string(),
+                };
+                vec![side(old), side(new)]
+            }
+            _ => revisions,
+        };
+        // Arguments that are not revisions but exist in the worktree are
+        // paths, as if given after `--`.
+        let mut trees = vec![];
+        for rev in &revisions {
+            match revision::resolve(root, rev) {
+                Ok(hash) => trees.push(revision::peel(root, hash, "tree")?.0),
+                Err(Error::InvalidObjectName(_)) if root.join(rev).exists() => {
+                    paths.push(rev.clone())
+                }
+                Err(Error::InvalidObjectName(rev)) => return Err(Error::UnknownRevision(rev)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let index = Index::read(root)?;
+        let mut contents = worktree::Contents::new();
+        let mut working = || -> Result<Vec<DiffEntry>> {
+            let (entries, read) = worktree::tracked_entries(root, &index)?;
+            contents = read;
+            Ok(entries)
+        };
+        let changes = match (trees.as_slice(), cached) {
+            ([], false) => tree_diff::diff_entries(staged_entries(&index), working()?),
+            ([], true) => {
+                let head = refs::resolve_name(root, "HEAD")?.1;
+                let head = match head {
+                    Some(head) => Some(revision::peel(root, head, "tree")?.0),
+                    None => None,
+                };
+                tree_diff::diff_entries(
+                    tree_diff::flatten_tree(root, head)?,
+                    staged_entries(&index),
+                )
+            }
+            ([tree], false) => {
+                tree_diff::diff_entries(tree_diff::flatten_tree(root, Some(*tree))?, working()?)
+            }
+            ([tree], true) => tree_diff::diff_entries(
+                tree_diff::flatten_tree(root, Some(*tree))?,
+                staged_entries(&index),
+            ),
+            ([old, new], false) => tree_diff::diff_trees(root, Some(*old), Some(*new), true)?,
+            _ => {
+                return Err(Error::Usage(String::from(
+                    "usage: git diff [<options>] [<commit>] [--] [<path>...]",
+                )))
+            }
+        };
+        let paths: Vec<_> = paths
+            .iter()
+            .map(|path| worktree::normalize_pathspec(path))
+            .collect();
+        let changes = filter_changes(changes, &paths);
+        write_stdout(&self.format_changes(changes, &contents, &options)?)
+    }
+
+    pub fn status(&self, options: status::Options) -> Result<()> {
+        let report = self.status_report(options.untracked)?;
+        print!("{}", status::format(self.get_root(), &report, &options)?);
+        Ok(())
+    }
+
+    /// Compares HEAD with the index and the index with the working tree,
+    /// and lists the untracked files.
+    fn status_report(&self, untracked: status::Untracked) -> Result<status::Report> {
+        let root = self.get_root();
+        let (head_ref, head) = refs::resolve_name(root, "HEAD")?;
+        let name = head_ref.strip_prefix("refs/heads/").map(str::to_string);
+        let upstream = match (&name, head) {
+            (Some(name), Some(head)) => self.upstream(name, head)?,
+            _ => None,
+        };
+        let head_tree = match head {
+            Some(head) => Some(revision::peel(root, head, "tree")?.0),
+            None => None,
+        };
+
+        let index = Index::read(root)?;
+        let mut unmerged: BTreeMap<&str, [Option<(u32, Hash)>; 3]> = BTreeMap::new();
+        for entry in index.entries().iter().filter(|e| e.stage != 0) {
+            let stages = unmerged.entry(&entry.path).or_default();
+            stages[entry.stage as usize - 1] = Some((entry.mode, entry.hash));
+        }
+        let mut head_entries = tree_diff::flatten_tree(root, head_tree)?;
+        head_entries.retain(|entry| !unmerged.contains_key(entry.path.as_str()));
+        let staged = tree_diff::diff_entries(head_entries, staged_entries(&index));
+        let staged =
+            tree_diff::detect_renames(staged, tree_diff::DEFAULT_MIN_SCORE, false, |entry| {
+                Ok(object::read_raw(root, &entry.hash.to_string())?.1)
+            })?;
+        let (worktree_entries, _) = worktree::tracked_entries(root, &index)?;
+        let unstaged = tree_diff::diff_entries(staged_entries(&index), worktree_entries);
+
+        let mut changes: BTreeMap<String, (Option<Change>, Option<Change>)> = BTreeMap::new();
+        for change in staged {
+            let path = change.path().to_string();
+            changes.entry(path).or_default().0 = Some(change);
+        }
+        for change in unstaged {
+            let path = change.path().to_string();
+            changes.entry(path).or_default().1 = Some(change);
+        }
+        let mut entries: Vec<_> = changes
+            .into_values()
+            .map(|(staged, unstaged)| status::Entry::Changed { staged, unstaged })
+            .collect();
+        for (path, stages) in unmerged {
+            let worktree_mode =
+                input_output::get_mode(worktree::get_path(root, path)).map_or(0, FileMode::bits);
+            entries.push(status::Entry::Unmerged {
+                path: path.to_string(),
+                stages,
+                worktree_mode,
+            });
+        }
+        entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+        let untracked = match untracked {
+            status::Untracked::No => vec![],
+            untracked => {
+                let ignore = Ignore::load(root)?;
+                let all = untracked == status::Untracked::All;
+                worktree::untracked_files(root, &index, &ignore, all)?
+            }
+        };
+        Ok(status::Report {
+            branch: status::Branch {
+                name,
+                head,
+                upstream,
+            },
+            entries,
+            untracked,
+            merging: root.join(".git/MERGE_HEAD").exists(),
+        })
+    }
+
+    /// The branch `branch` is set to track with `branch.<name>.remote` and
+    /// `branch.<name>.merge`, and how far apart it is from `head`.
+    fn upstream(&self, branch: &str, head: Hash) -> Result<Option<status::Upstream>> {
+        let root = self.get_root();
+        let config = Config::read(root)?;
+        let remote = config.get(&format!("branch.{}.remote", branch));
+        let merge = config.get(&format!("branch.{}.merge", branch));
+        let (Some(remote), Some(merge)) = (remote, merge) else {
+            return Ok(None);
+        };
+        // Remote branches are assumed to be fetched with the default
+        // refspec, `.` standing for the local repository.
+        let refname = match (remote, merge.strip_prefix("refs/heads/")) {
+            (".", _) => merge.to_string(),
+            (remote, Some(merged)) => format!("refs/remotes/{}/{}", remote, merged),
+            (_, None) => return Ok(None),
+        };
+        let counts = match refs::resolve(root, &refname)? {
+            Some(upstream) => Some(self.count_ahead_behind(head, upstream)?),
+            None => None,
+        };
+        Ok(Some(status::Upstream {
+            name: refs::shorten(&refname).to_string(),
+            counts,
+        }))
+    }
+
+    /// Counts the commits only reachable from `a`, and only from `b`.
+    fn count_ahead_behind(&self, a: Hash, b: Hash) -> Result<(usize, usize)> {
+        let reachable = |start: Hash| -> Result<HashSet<Hash>> {
+            let mut walk = RevWalk::new(self.get_root());
+            walk.push(start)?;
+            let mut commits = HashSet::new();
+            while let Some(walked) = walk.next()? {
+                commits.insert(walked.hash);
+            }
+            Ok(commits)
+        };
+        let (from_a, from_b) = (reachable(a)?, reachable(b)?);
+        Ok((
+            from_a.difference(&from_b).count(),
+            from_b.difference(&from_a).count(),
+        ))
+    }
+
+    /// Shows objects: a commit with its changes, a tag followed by what it
+    /// points at, the names in a tree, or the content of a blob. Merges are
+    /// shown without changes.
+    pub fn show(&self, objects: Vec<String>, options: patch::Options) -> Result<()> {
+        let root = self.get_root();
+        let objects = match objects.is_empty() {
+            true => vec![String::from("HEAD")],
+            false => objects,
+        };
+        let mut shown_one = false;
+        for name in objects {
+            let mut hash = match revision::resolve(root, &name) {
+                Err(Error::InvalidObjectName(rev)) => return Err(Error::UnknownRevision(rev)),
+                result => result?,
+            };
+            loop {
+                match Object::read(root, &hash.to_string())? {
+                    Object::Blob(blob) => write_stdout(&blob.content)?,
+                    Object::TreeNode(tree) => {
+                        if shown_one {
+                            println!();
+                        }
+                        println!("tree {}\n", name);
+                        for entry in &tree {
+                            match entry.mode == FileMode::Tree {
+                                true => println!("{}/", entry.name),
+                                false => println!("{}", entry.name),
+                            }
+                        }
+                        shown_one = true;
+                    }
+                    Object::Commit(commit) => {
+                        if shown_one {
+                            println!();
+                        }
+                        let walked = Walked {
+                            hash,
+                            parents: commit.parents.clone(),
+                            commit,
+                            treesame: false,
+                        };
+                        println!("{}", log::Format::Medium.format(root, &walked)?);
+                        let diff = self.show_changes(&walked.commit, &options)?;
+                        if !diff.is_empty() {
+                            println!();
+                            write_stdout(&diff)?;
+                        }
+                        shown_one = true;
+                    }
+                    Object::Tag(tag) => {
+                        if shown_one {
+                            println!();
+                        }
+                        println!("tag {}", tag.name);
+                        if let Some(tagger) = &tag.tagger {
+                            println!("Tagger: {}", tagger.identity());
+                            println!("Date:   {}", tagger.format_date());
+                        }
+                        println!();
+                        print!("{}", String::from_utf8_lossy(&tag.message));
+                        shown_one = true;
+                        hash = tag.object;
+                        continue;
+                    }
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The changes a commit makes to its first parent below `paths`, or
+    /// none for a merge, like git without `-m`.
+    fn commit_changes(&self, commit: &Commit, paths: &[String]) -> Result<Vec<Change>> {
+        let root = self.get_root();
+        let parent_tree = match commit.parents.as_slice() {
+            [] => None,
+            [parent] => Some(revision::peel(root, *parent, "tree")?.0),
+            _ => return Ok(vec![]),
+        };
+        let changes = tree_diff::diff_trees(root, parent_tree, Some(commit.tree), true)?;
+        Ok(filter_changes(changes, paths))
+    }
+
+    /// The changes `log` prints under a commit. Like git, nothing is printed
+    /// for a merge or a commit that changes nothing, not even an empty stat,
+    /// except that `--first-parent` shows a merge against its first parent.
+    fn log_changes(
+        &self,
+        commit: &Commit,
+        paths: &[String],
+        first_parent: bool,
+        options: &patch::Options,
+    ) -> Result<Vec<u8>> {
+        let changes = match commit.parents.as_slice() {
+            [parent, _, ..] if first_parent => {
+                let root = self.get_root();
+                let parent_tree = revision::peel(root, *parent, "tree")?.0;
+                let changes =
+                    tree_diff::diff_trees(root, Some(parent_tree), Some(commit.tree), true)?;
+                filter_changes(changes, paths)
+            }
+            _ => self.commit_changes(commit, paths)?,
+        };
+        if changes.is_empty() {
+            return Ok(vec![]);
+        }
+        self.format_changes(changes, &worktree::Contents::new(), options)
+    }
+
+    /// The changes `show` prints for a commit. A merge is shown against its
+    /// first parent with `--stat` and `--numstat`, while `--name-only` and
+    /// `--name-status` list the paths that differ from every parent, like
+    /// git's combined diff. Its patch is left out.
+    fn show_changes(&self, commit: &Commit, options: &patch::Options) -> Result<Vec<u8>> {
+        let root = self.get_root();
+        let contents = worktree::Contents::new();
+        if commit.parents.len() < 2 {
+            let changes = self.commit_changes(commit, &[])?;
+            return self.format_changes(changes, &contents, options);
+        }
+
+        let mut per_parent = vec![];
+        for parent in &commit.parents {
+            let parent_tree = revision::peel(root, *parent, "tree")?.0;
+            per_parent.push(tree_diff::diff_trees(
+                root,
+                Some(parent_tree),
+                Some(commit.tree),
+                true,
+            )?);
+        }
+        match options.output {
+            patch::Output::Stat | patch::Output::Numstat => {
+                let changes = per_parent.swap_remove(0);
+                self.format_changes(changes, &contents, options)
+            }
+            patch::Output::NameOnly | patch::Output::NameStatus => {
+                let mut out = vec![];
+                for change in &per_parent[0] {
+                    let codes: Option<String> = per_parent
+                        .iter()
+                        .map(|changes| {
+                            let other = changes.iter().find(|other| other.path() == change.path());
+                            other.map(|other| other.status.code())
+                        })
+                        .collect();
+                    let Some(codes) = codes else {
+                        continue;
+                    };
+                    if options.output == patch::Output::NameStatus {
+                        out.extend_from_slice(codes.as_bytes());
+                        out.push(b'\t');
+                    }
+                    out.extend_from_slice(change.path().as_bytes());
+                    out.push(b'\n');
+                }
+                Ok(out)
+            }
+            patch::Output::Patch => Ok(vec![]),
+        }
+    }
+
+    /// Reads one side of a change, from the working tree files read while
+    /// comparing or from the object database. A submodule is shown as the
+    /// commit it is at.
+    fn read_entry(&self, entry: &DiffEntry, contents: &worktree::Contents) -> Result<Vec<u8>> {
+        if entry.mode == FileMode::Gitlink.bits() {
+            return Ok(format!("Subproject commit {}\n", entry.hash).into_bytes());
+        }
+        match contents.get(&entry.hash) {
+            Some(content) => Ok(content.clone()),
+            None => Ok(object::read_raw(self.get_root(), &entry.hash.to_string())?.1),
+        }
+    }
+
+    /// Detects renames if `options` ask for it and formats the changes.
+    fn format_changes(
+        &self,
+        mut changes: Vec<Change>,
+        contents: &worktree::Contents,
+        options: &patch::Options,
+    ) -> Result<Vec<u8>> {
+        if let Some(min_score) = options.renames {
+            changes = tree_diff::detect_renames(changes, min_score, false, |entry| {
+                self.read_entry(entry, contents)
+            })?;
+        }
+        let needs_content = matches!(
+            options.output,
+            patch::Output::Patch | patch::Output::Stat | patch::Output::Numstat
+        );
+        let read = |entry: &Option<DiffEntry>| match entry {
+            Some(entry) if needs_content => self.read_entry(entry, contents),
+            _ => Ok(vec![]),
+        };
+        let pairs = changes
+            .iter()
+            .map(|change| {
+                Ok(FilePair {
+                    change,
+                    old: read(&change.old)?,
+                    new: read(&change.new)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        patch::format(self.get_root(), &pairs, options)
+    }
+
+    pub fn tag(&self, action: tag::Action) -> Result<()> {
+        match action {
+            tag::Action::List { patterns } => {
+                for (refname, _) in refs::list(self.get_root(), "refs/tags/")? {
+                    let name = &refname["refs/tags/".len()..];
+                    let matches = patterns.is_empty()
+                        || patterns.iter().any(|p| wildmatch(p, name, false, false));
+                    if matches {
+                        println!("{}", name);
+                    }
+                }
+                Ok(())
+            }
+            tag::Action::Create {
+                name,
+                target,
+                annotate,
+                messages,
+                file,
+                force,
+            } => {
+                let refname = format!("refs/tags/{}", name);
+                if !refs::is_valid_name(&refname) {
+                    return Err(Error::fatal(format!("'{}' is not a valid tag name.", name)));
+                }
+                let target = target.as_deref().unwrap_or("HEAD");
+                let target = revision::resolve(self.get_root(), target).map_err(|e| match e {
+                    Error::InvalidObjectName(_) => {
+                        Error::fatal(format!("Failed to resolve '{}' as a valid ref.", target))
+                    }
+                    e => e,
+                })?;
+                let previous = refs::resolve(self.get_root(), &refname)?;
+                if previous.is_some() && !force {
+                    return Err(Error::fatal(format!("tag '{}' already exists", name)));
+                }
+
+                let hash = if annotate || !messages.is_empty() || file.is_some() {
+                    if messages.is_empty() && file.is_none() {
+                        return Err(Error::fatal("no tag message?"));
+                    }
+                    let message = Self::read_message(messages, file.into_iter().collect())?;
+                    let (kind, _) = object::read_raw(self.get_root(), &target.to_string())?;
+                    let tag = Tag::new(
+                        target,
+                        kind.to_string(),
+                        name.clone(),
+                        Signature::committer(self.get_root())?,
+                        strip_space(&message),
+                    );
+                    Object::Tag(tag).write(self.get_root())?
+                } else {
+                    target
+                };
+                refs::update(self.get_root(), &refname, &hash, None)?;
+
+                if let Some(previous) = previous.filter(|previous| *previous != hash) {
+                    println!(
+                        "Updated tag '{}' (was {})",
+                        name,
+                        &previous.to_string()[..7]
+                    );
+                }
+                Ok(())
+            }
+            tag::Action::Delete { names } => {
+                let mut failed = false;
+                for name in names {
+                    let refname = format!("refs/tags/{}", name);
+                    match refs::resolve(self.get_root(), &refname)? {
+                        Some(hash) => {
+                            refs::delete(self.get_root(), &refname, Some(hash))?;
+                            println!("Deleted tag '{}' (was {})", name, &hash.to_string()[..7]);
+                        }
+                        None => {
+                            eprintln!("error: tag '{}' not found.", name);
+                            failed = true;
+                        }
+                    }
+                }
+                match failed {
+                    true => Err(Error::Exit(1)),
+                    false => Ok(()),
+                }
+            }
+        }
+    }
+
+    pub fn config(
+        &self,
+        action: Action,
+        location: Option<Location>,
+        value_type: Option<config::Type>,
+        show_origin: bool,
+        show_scope: bool,
+    ) -> Result<()> {
+        let read = || match &location {
+            None => Config::read(self.get_root()),
+            Some(Location::Scope(scope)) => Config::read_scope(self.get_root(), *scope),
+            Some(Location::File(path)) => Config::read_file(path),
+        };
+        let path = || match &location {
+            None => config::scope_path(self.get_root(), Scope::Local),
+            Some(Location::Scope(scope)) => config::scope_path(self.get_root(), *scope),
+            Some(Location::File(path)) => Ok(path.clone()),
+        };
+        let print = |entry: &config::Entry, line: String| {
+            let mut prefix = String::new();
+            if show_scope {
+                prefix.push_str(&format!("{}\t", entry.scope));
+            }
+            if show_origin {
+                prefix.push_str(&format!("{}\t", entry.origin));
+            }
+            println!("{}{}", prefix, line);
+        };
+        let format = |entry: &config::Entry| match value_type {
+            Some(value_type) => value_type.format(entry),
+            None => Ok(entry.as_str().to_string()),
+        };
+
+        match action {
+            Action::List => {
+                for entry in read()?.entries() {
+                    let line = match (&entry.value, value_type) {
+                        (None, None) => entry.key.clone(),
+                        _ => format!("{}={}", entry.key, format(entry)?),
+                    };
+                    print(entry, line);
+                }
+            }
+            Action::Get { key, all } => {
+                let config = read()?;
+                let entries: Vec<_> = config.get_all(&key).collect();
+                let Some(last) = entries.last() else {
+                    return Err(Error::Exit(1));
+                };
+                let entries = if all { &entries[..] } else { &[*last][..] };
+                for entry in entries {
+                    print(entry, format(entry)?);
+                }
+            }
+            Action::Set { key, value, add } => {
+                let value = match value_type {
+                    Some(value_type) => value_type.normalize(&value)?,
+                    None => value,
+                };
+                let mut file = ConfigFile::read(path()?)?;
+                if add {
+                    file.add(&key, &value)?;
+                } else {
+                    file.set(&key, &value)?;
+                }
+                file.write()?;
+            }
+            Action::Unset { key, all } => {
+                let mut file = ConfigFile::read(path()?)?;
+                if !file.unset(&key, all)? {
+                    return Err(Error::Exit(5));
+                }
+                file.write()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the paths that are ignored, along with the pattern that
+    /// decides about each with `verbose`. Tracked paths are not checked
+    /// unless `no_index` is set.
+    pub fn check_ignore(
+        &self,
+        paths: Vec<String>,
+        verbose: bool,
+        quiet: bool,
+        non_matching: bool,
+        no_index: bool,
+    ) -> Result<()> {
+        let root = self.get_root();
+        let ignore = Ignore::load(root)?;
+        let index = match no_index {
+            true => Index::new(),
+            false => Index::read(root)?,
+        };
+        let mut matched = 0;
+        for path in paths {
+            let pathspec = worktree::normalize_pathspec(&path);
+            let pattern = match index.matching(&pathspec).is_empty() {
+                true => {
+                    let full_path = worktree::get_path(root, &pathspec);
+                    let is_dir = fs::symlink_metadata(full_path).is_ok_and(|m| m.is_dir());
+                    ignore.check(&pathspec, is_dir)
+                }
+                false => None,
+            };
+            let pattern = pattern.filter(|pattern| verbose || !pattern.negated);
+            if pattern.is_some() {
+                matched += 1;
+            }
+            if quiet || (pattern.is_none() && !non_matching) {
+                continue;
+            }
+            let path = status::quote_path(&path, false);
+            match (verbose, pattern) {
+                (false, _) => println!("{}", path),
+                (true, None) => println!("::\t{}", path),
+                (true, Some(pattern)) => println!(
+                    "{}:{}:{}{}\t{}",
+                    pattern.source,
+                    pattern.line,
+                    if pattern.negated { "!" } else { "" },
+                    pattern.text,
+                    path
+                ),
+            }
+        }
+        match matched {
+            0 => Err(Error::Exit(1)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Switches to a branch, possibly a new one, or detaches HEAD at a
+    /// commit, bringing the index and working tree along while keeping
+    /// the local changes that don't get in the way.
+    pub fn checkout(&self, target: Option<String>, options: checkout::Options) -> Result<()> {
+        let root = self.get_root();
+        let (head_ref, head) = refs::resolve_name(root, "HEAD")?;
+        let old_branch = head_ref.strip_prefix("refs/heads/").map(str::to_string);
+        let mut index = Index::read(root)?;
+        if !options.force && index.entries().iter().any(|entry| entry.stage != 0) {
+            let mut unmerged: Vec<&str> = index
+                .entries()
+                .iter()
+                .filter(|entry| entry.stage != 0)
+                .map(|entry| entry.path.as_str())
+                .collect();
+            unmerged.dedup();
+            for path in unmerged {
+                eprintln!("{}: needs merge", path);
+            }
+            return Err(Error::failed(
+                "you need to resolve your current index first",
+            ));
+        }
+
+        // Where HEAD goes: the branch, if any, the commit, the name the
+        // target was given by, and the start point of a new branch.
+        let (branch, commit, name, start) = match (options.new_branch, target) {
+            (Some(new_branch), start) => {
+                let refname = format!("refs/heads/{}", new_branch);
+                if !refs::is_valid_name(&refname) {
+                    return Err(Error::fatal(format!(
+                        "'{}' is not a valid branch name",
+                        new_branch
+                    )));
+                }
+                if refs::read_ref(root, &refname)?.is_some() {
+                    return Err(Error::fatal(format!(
+                        "a branch named '{}' already exists",
+                        new_branch
+                    )));
+                }
+                let start = start.unwrap_or_else(|| String::from("HEAD"));
+                let commit = match (start.as_str(), head) {
+                    ("HEAD", None) => None,
+                    _ => Some(self.resolve_commit(&start).map_err(|_| {
+                        Error::fatal(format!(
+                            "'{}' is not a commit and a branch '{}' cannot be created from it",
+                            start, new_branch
+                        ))
+                    })?),
+                };
+                (Some(new_branch.clone()), commit, new_branch, Some(start))
+            }
+            (None, target)
+                if !options.detach && target.as_deref().map_or(true, |t| t == "HEAD") =>
+            {
+                // Nothing to switch to, only local changes to show.
+                let tree = self.tree_of(head)?;
+                checkout::switch_trees(
+                    root,
+                    &mut index,
+                    tree,
+                    tree,
+                    options.force,
+                    checkout::Operation::Checkout,
+                )?;
+                index.write(root)?;
+                if !options.quiet {
+                    if !options.force {
+                        self.show_local_changes(&index, tree)?;
+                    }
+                    self.report_tracking(old_branch.as_deref(), head)?;
+                }
+                return Ok(());
+            }
+            (None, target) => {
+                let target = target.unwrap_or_else(|| String::from("HEAD"));
+                let local = format!("refs/heads/{}", target);
+                if !options.detach && refs::read_ref(root, &local)?.is_some() {
+                    let commit = refs::resolve(root, &local)?;
+                    (Some(target.clone()), commit, target, None)
+                } else if let Ok(commit) = self.resolve_commit(&target) {
+                    if options.switch && !options.detach {
+                        let kind = match revision::expand_ref(root, &target)? {
+                            Some(refname) if refname.starts_with("refs/tags/") => "tag",
+                            Some(refname) if refname.starts_with("refs/remotes/") => {
+                                "remote branch"
+                            }
+                            _ => "commit",
+                        };
+                        eprintln!("fatal: a branch is expected, got {} '{}'", kind, target);
+                        eprintln!(
+                            "hint: If you want to detach HEAD at the commit, \
+                             try again with the --detach option."
+                        );
+                        return Err(Error::Exit(128));
+                    }
+                    (None, Some(commit), target, None)
+                } else if let Some(remote) = self.remote_branch(&target)? {
+                    let commit = refs::resolve(root, &remote)?;
+                    (Some(target.clone()), commit, target, Some(remote))
+                } else if options.switch {
+                    return Err(Error::fatal(format!("invalid reference: {}", target)));
+                } else {
+                    return Err(Error::failed(format!(
+                        "pathspec '{}' did not match any file(s) known to git",
+                        target
+                    )));
+                }
+            }
+        };
+
+        let old_tree = self.tree_of(head)?;
+        let new_tree = self.tree_of(commit)?;
+        checkout::switch_trees(
+            root,
+            &mut index,
+            old_tree,
+            new_tree,
+            options.force,
+            checkout::Operation::Checkout,
+        )?;
+        index.write(root)?;
+        if !options.quiet && !options.force {
+            self.show_local_changes(&index, new_tree)?;
+        }
+        if let (None, Some(head), false) = (&old_branch, head, options.quiet) {
+            if commit != Some(head) {
+                self.orphaned_commit_warning(head, commit)?;
+            }
+        }
+
+        let committer = Signature::committer(root)?;
+        if let (Some(branch), Some(start)) = (&branch, &start) {
+            let refname = format!("refs/heads/{}", branch);
+            if let Some(commit) = commit {
+                refs::update(root, &refname, &commit, Some(Hash::zero()))?;
+                let message = format!("branch: Created from {}", start);
+                refs::append_reflog(root, &refname, &Hash::zero(), &commit, &committer, &message)?;
+            }
+            let remote = revision::expand_ref(root, start)?
+                .and_then(|refname| refname.strip_prefix("refs/remotes/").map(str::to_string));
+            if let Some((remote, merged)) = remote.as_deref().and_then(|r| r.split_once('/')) {
+                let mut config = ConfigFile::read(root.join(".git/config"))?;
+                config.set(&format!("branch.{}.remote", branch), remote)?;
+                config.set(
+                    &format!("branch.{}.merge", branch),
+                    &format!("refs/heads/{}", merged),
+                )?;
+                config.write()?;
+                if !options.quiet {
+                    println!(
+                        "branch '{}' set up to track '{}/{}'.",
+                        branch, remote, merged
+                    );
+                }
+            }
+        }
+
+        match (&branch, commit) {
+            (Some(branch), _) => {
+                refs::write_symbolic(root, "HEAD", &format!("refs/heads/{}", branch))?
+            }
+            (None, Some(commit)) => refs::write_direct(root, "HEAD", &commit)?,
+            (None, None) => unreachable!("a detached HEAD always has a commit"),
+        }
+        // Like git, a detached HEAD that stays put gets no reflog entry.
+        let moved = branch.is_some() || old_branch.is_some() || commit != head;
+        if let (Some(commit), true) = (commit, moved) {
+            let from = match (&old_branch, head) {
+                (Some(branch), _) => branch.clone(),
+                (None, head) => head.unwrap_or(Hash::zero()).to_string(),
+            };
+            let message = format!("checkout: moving from {} to {}", from, name);
+            let old = head.unwrap_or(Hash::zero());
+            refs::append_reflog(root, "HEAD", &old, &commit, &committer, &message)?;
+        }
+        self.remove_merge_state();
+
+        if options.quiet {
+            return Ok(());
+        }
+        match (&branch, commit) {
+            (Some(branch), _) if old_branch.as_ref() == Some(branch) => {
+                eprintln!("Already on '{}'", branch)
+            }
+            (Some(branch), _) if start.is_some() => {
+                eprintln!("Switched to a new branch '{}'", branch)
+            }
+            (Some(branch), _) => eprintln!("Switched to branch '{}'", branch),
+            (None, Some(commit)) => {
+                let advice = Config::read(root)?
+                    .get("advice.detachedHead")
+                    .map_or(true, |value| config::parse_bool(value) != Some(false));
+                if old_branch.is_some() && !options.detach && advice {
+                    eprintln!("{}", detached_head_advice(&name));
+                }
+                eprintln!("HEAD is now at {}", self.describe_commit(commit)?);
+            }
+            (None, None) => {}
+        }
+        // Like git, a branch only just created is not compared with its
+        // upstream.
+        if start.is_none() {
+            self.report_tracking(branch.as_deref(), commit)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a revision to the commit it names.
+    fn resolve_commit(&self, rev: &str) -> Result<Hash> {
+        let hash = revision::resolve(self.get_root(), rev)?;
+        Ok(revision::peel(self.get_root(), hash, "commit")?.0)
+    }
+
+    fn tree_of(&self, commit: Option<Hash>) -> Result<Option<Hash>> {
+        match commit {
+            Some(commit) => Ok(Some(revision::peel(self.get_root(), commit, "tree")?.0)),
+            None => Ok(None),
+        }
+    }
+
+    /// The remote-tracking branch `checkout <branch>` creates a missing
+    /// branch from, if exactly one remote has a branch of that name.
+    fn remote_branch(&self, name: &str) -> Result<Option<String>> {
+        let mut found = refs::list(self.get_root(), "refs/remotes/")?
+            .into_iter()
+            .map(|(refname, _)| refname)
+            .filter(|refname| {
+                refname["refs/remotes/".len()..]
+                    .split_once('/')
+                    .is_some_and(|(_, branch)| branch == name)
+            });
+        Ok(match (found.next(), found.next()) {
+            (Some(refname), None) => Some(refname),
+            _ => None,
+        })
+    }
+
+    /// Prints how the working tree differs from `tree`, one `M\tpath`
+    /// line per file, after switching to it.
+    fn show_local_changes(&self, index: &Index, tree: Option<Hash>) -> Result<()> {
+        let root = self.get_root();
+        let (worktree_entries, _) = worktree::tracked_entries(root, index)?;
+        let changes =
+            tree_diff::diff_entries(tree_diff::flatten_tree(root, tree)?, worktree_entries);
+        for change in changes {
+            println!("{}\t{}", change.status.code(), change.path());
+        }
+        Ok(())
+    }
+
+    /// Prints how `branch` compares with its upstream, if it has one.
+    fn report_tracking(&self, branch: Option<&str>, head: Option<Hash>) -> Result<()> {
+        let (Some(branch), Some(head)) = (branch, head) else {
+            return Ok(());
+        };
+        if let Some(upstream) = self.upstream(branch, head)? {
+            for line in status::tracking_lines(&upstream) {
+                println!("{}", line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Warns about the commits only the detached HEAD at `old` reaches,
+    /// which leaving it for `new` loses track of, or says where HEAD was.
+    fn orphaned_commit_warning(&self, old: Hash, new: Option<Hash>) -> Result<()> {
+        let root = self.get_root();
+        let mut kept = RevWalk::new(root);
+        for (_, hash) in refs::list(root, "refs/")? {
+            if revision::peel(root, hash, "commit").is_ok() {
+                kept.push(hash)?;
+            }
+        }
+        if let Some(new) = new {
+            kept.push(new)?;
+        }
+        let mut reachable = HashSet::new();
+        while let Some(walked) = kept.next()? {
+            reachable.insert(walked.hash);
+        }
+        if reachable.contains(&old) {
+            eprintln!("Previous HEAD position was {}", self.describe_commit(old)?);
+            return Ok(());
+        }
+
+        let mut walk = RevWalk::new(root);
+        walk.push(old)?;
+        let mut lost = vec![];
+        while let Some(walked) = walk.next()? {
+            if !reachable.contains(&walked.hash) {
+                lost.push(walked.hash);
+            }
+        }
+        const ORPHAN_CUTOFF: usize = 4;
+        let mut described = String::new();
+        let shown = match lost.len() {
+            n if n == ORPHAN_CUTOFF + 1 => n,
+            n => n.min(ORPHAN_CUTOFF),
+        };
+        for hash in &lost[..shown] {
+            described.push_str(&format!("  {}\n", self.describe_commit(*hash)?));
+        }
+        if lost.len() > shown {
+            described.push_str(&format!(" ... and {} more.\n", lost.len() - shown));
+        }
+        let (commits, them) = match lost.len() {
+            1 => ("commit", "it"),
+            _ => ("commits", "them"),
+        };
+        eprintln!(
+            "Warning: you are leaving {} {} behind, not connected to\n\
+             any of your branches:\n\n{}\n\
+             If you want to keep {} by creating a new branch, this may be a good time\n\
+             to do so with:\n\n git branch <new-branch-name> {}\n",
+            lost.len(),
+            commits,
+            described,
+            them,
+            object::abbreviate(root, &old, 7)?
+        );
+        Ok(())
+    }
+
+    /// The abbreviated hash and subject of a commit, as in `HEAD is now at
+    /// <commit>`.
+    fn describe_commit(&self, hash: Hash) -> Result<String> {
+        let Object::Commit(commit) = Object::read(self.get_root(), &hash.to_string())? else {
+            return Err(Error::fatal(format!("{} is not a commit", hash)));
+        };
+        let (subject, _) = log::split_message(&String::from_utf8_lossy(&commit.message));
+        Ok(format!(
+            "{} {}",
+            object::abbreviate(self.get_root(), &hash, 7)?,
+            subject
+        ))
+    }
+
+    pub fn merge_base(&self, commits: Vec<String>, all: bool, octopus: bool) -> Result<()> {
+        let root = self.get_root();
+        let commits = commits
+            .iter()
+            .map(|rev| self.resolve_commit(rev))
+            .collect::<Result<Vec<_>>>()?;
+        let bases = match octopus {
+            true => merge_base::octopus_bases(root, &commits)?,
+            false => merge_base::merge_bases(root, &commits[..1], &commits[1..])?,
+        };
+        if bases.is_empty() {
+            return Err(Error::Exit(1));
+        }
+        let shown = if all { bases.len() } else { 1 };
+        for base in &bases[..shown] {
+            println!("{}", base);
+        }
+        Ok(())
+    }
+
+    pub fn merge(
+        &self,
+        rev: &str,
+        messages: Vec<String>,
+        fast_forward: FastForward,
+        commit: bool,
+    ) -> Result<()> {
+        let root = self.get_root();
+        let mut index = Index::read(root)?;
+        if index.entries().iter().any(|entry| entry.stage != 0) {
+            return Err(unresolved_conflict("Merging"));
+        }
+        if self.merge_head()?.is_some() {
+            eprintln!("fatal: You have not concluded your merge (MERGE_HEAD exists).");
+            eprintln!("Please, commit your changes before you merge.");
+            return Err(Error::Exit(128));
+        }
+        let Ok(theirs) = self.resolve_commit(rev) else {
+            eprintln!("merge: {} - not something we can merge", rev);
+            return Err(Error::Exit(1));
+        };
+        let (head_ref, head) = refs::resolve_name(root, "HEAD")?;
+        let Some(head) = head else {
+            // Merging into an unborn branch only has to check it out.
+            let tree = self.tree_of(Some(theirs))?;
+            checkout::switch_trees(
+                root,
+                &mut index,
+                None,
+                tree,
+                false,
+                checkout::Operation::Merge,
+            )?;
+            index.write(root)?;
+            return refs::update(root, "HEAD", &theirs, Some(Hash::zero()));
+        };
+        let branch = head_ref.strip_prefix("refs/heads/");
+
+        let bases = merge_base::merge_bases(root, &[head], &[theirs])?;
+        if bases.contains(&theirs) {
+            println!("Already up to date.");
+            return Ok(());
+        }
+        let can_fast_forward = bases == [head];
+        if fast_forward == FastForward::Only && !can_fast_forward {
+            return Err(Error::fatal("Not possible to fast-forward, aborting."));
+        }
+        let committer = Signature::committer(root)?;
+        let head_tree = self.tree_of(Some(head))?;
+        let their_tree = self.tree_of(Some(theirs))?;
+        if can_fast_forward && fast_forward != FastForward::Never {
+            println!(
+                "Updating {}..{}",
+                object::abbreviate(root, &head, 7)?,
+                object::abbreviate(root, &theirs, 7)?
+            );
+            refs::write_direct(root, "ORIG_HEAD", &head)?;
+            let operation = checkout::Operation::Merge;
+            checkout::switch_trees(root, &mut index, head_tree, their_tree, false, operation)?;
+            index.write(root)?;
+            println!("Fast-forward");
+            self.update_head(
+                &head_ref,
+                head,
+                theirs,
+                &format!("merge {}: Fast-forward", rev),
+            )?;
+            return self.print_diffstat(head_tree, their_tree);
+        }
+        if bases.is_empty() {
+            return Err(Error::fatal("refusing to merge unrelated histories"));
+        }
+        refs::write_direct(root, "ORIG_HEAD", &head)?;
+
+        let staged = tree_diff::diff_entries(
+            tree_diff::flatten_tree(root, head_tree)?,
+            staged_entries(&index),
+        );
+        if !staged.is_empty() {
+            let paths: Vec<&str> = staged.iter().map(Change::path).collect();
+            eprintln!(
+                "error: Your local changes to the following files would be overwritten by merge:\n  {}",
+                paths.join(" ")
+            );
+            eprintln!("Merge with strategy ort failed.");
+            return Err(Error::Exit(2));
+        }
+        let style = match Config::read(root)?.get("merge.conflictStyle") {
+            Some(name) => Style::parse(name).ok_or_else(|| {
+                Error::fatal(format!(
+                    "unknown style '{}' given for 'merge.conflictstyle'",
+                    name
+                ))
+            })?,
+            None => Style::default(),
+        };
+        let options = merge::Options {
+            ours: "HEAD",
+            theirs: rev,
+            style,
+        };
+        let merged = merge::merge_commits(root, head, theirs, &options)?;
+        let operation = checkout::Operation::Merge;
+        let switched = checkout::switch_trees(
+            root,
+            &mut index,
+            head_tree,
+            Some(merged.tree),
+            false,
+            operation,
+        );
+        if let Err(error) = switched {
+            if let Some(report) = error.report() {
+                eprintln!("{}", report);
+            }
+            eprintln!("Merge with strategy ort failed.");
+            return Err(Error::Exit(2));
+        }
+        for conflict in &merged.conflicts {
+            index.remove(&conflict.path);
+            for (i, stage) in conflict.stages.iter().enumerate() {
+                if let Some((mode, hash)) = stage {
+                    let mut entry = index::IndexEntry::new(conflict.path.clone(), *hash, *mode);
+                    entry.stage = i as u8 + 1;
+                    index.add(entry);
+                }
+            }
+        }
+        index.write(root)?;
+        refs::write_direct(root, "AUTO_MERGE", &merged.tree)?;
+        for message in &merged.messages {
+            println!("{}", message);
+        }
+
+        let mut message = match messages.is_empty() {
+            true => self.merge_message(rev, branch)?,
+            false => Self::read_message(messages, vec![])?,
+        };
+        if !merged.conflicts.is_empty() || !commit {
+            let git_dir = root.join(".git");
+            let write = |name: &str, content: &str| {
+                fs::write(git_dir.join(name), content)
+                    .map_err(|e| Error::io("could not write", git_dir.join(name), e))
+            };
+            write("MERGE_HEAD", &format!("{}\n", theirs))?;
+            let mode = match fast_forward {
+                FastForward::Never => "no-ff",
+                _ => "",
+            };
+            write("MERGE_MODE", mode)?;
+            if !merged.conflicts.is_empty() {
+                message.push_str("\n# Conflicts:\n");
+                for conflict in &merged.conflicts {
+                    message.push_str(&format!("#\t{}\n", conflict.path));
+                }
+            }
+            write("MERGE_MSG", &message)?;
+            if merged.conflicts.is_empty() {
+                eprintln!("Automatic merge went well; stopped before committing as requested");
+                return Ok(());
+            }
+            println!("Automatic merge failed; fix conflicts and then commit the result.");
+            return Err(Error::Exit(1));
+        }
+
+        let commit = Commit::new(
+            merged.tree,
+            vec![head, theirs],
+            Signature::author(root)?,
+            committer,
+            message,
+        );
+        let hash = Object::Commit(commit).write(root)?;
+        let strategy = "Merge made by the 'ort' strategy.";
+        self.update_head(
+            &head_ref,
+            head,
+            hash,
+            &format!("merge {}: {}", rev, strategy),
+        )?;
+        self.remove_merge_state();
+        println!("{}", strategy);
+        self.print_diffstat(head_tree, Some(merged.tree))
+    }
+
+    /// Packs the objects listed on stdin, one per line and optionally
+    /// followed by the path they were found at, into `<base_name>-<hash>.pack`
+    /// and its `.idx`, printing the hash, or writes the pack to stdout when
+    /// there is no base name.
+    pub fn pack_objects(
+        &self,
+        base_name: Option<String>,
+        window: usize,
+        depth: usize,
+    ) -> Result<()> {
+        let mut objects = vec![];
+        for line in io::stdin().lines() {
+            let line = line?;
+            let (hex, name) = line.split_once(' ').unwrap_or((&line, ""));
+            let hash = hex.parse().map_err(|_| {
+                Error::fatal(format!("expected object ID, got garbage:\n {}\n", line))
+            })?;
+            objects.push((hash, name.to_string()));
+        }
+        let written = pack_writer::write(self.get_root(), &objects, window, depth)?;
+        let Some(base_name) = base_name else {
+            io::stdout().write_all(&written.pack)?;
+            return Ok(());
+        };
+        let path = format!("{}-{}", base_name, written.checksum);
+        input_output::write(format!("{}.pack", path), &written.pack)?;
+        input_output::write(format!("{}.idx", path), written.index.bytes())?;
+        println!("{}", written.checksum);
+        Ok(())
+    }
+
+    /// Packs the reachable objects: with `all`, all of them into a single
+    /// new pack, otherwise only those that are still loose. With `delete`,
+    /// packs the new one makes redundant are deleted, unless they have a
+    /// `.keep` file, and so are loose objects that are packed now. With
+    /// `loosen` as well, unreachable objects in the deleted packs become
+    /// loose objects as old as their pack, so that `prune` decides when
+    /// they go.
+    pub fn repack(
+        &self,
+        all: bool,
+        loosen: bool,
+        delete: bool,
+        window: usize,
+        depth: usize,
+    ) -> Result<()> {
+        let root = self.get_root();
+        let reachable = reachable::objects(root)?;
+        let old_packs = pack::packs(root)?;
+        let mut objects = vec![];
+        for (hash, name) in &reachable {
+            let mut packed = false;
+            for pack in &old_packs {
+                packed = packed || pack.index.find(hash)?.is_some();
+            }
+            if all || !packed {
+                objects.push((*hash, name.clone()));
+            }
+        }
+
+        let mut new_pack = None;
+        if objects.is_empty() {
+            println!("Nothing new to pack.");
+        } else {
+            let written = pack_writer::write(root, &objects, window, depth)?;
+            let path = pack::get_pack_dir(root).join(format!("pack-{}.pack", written.checksum));
+            input_output::write(&path, &written.pack)?;
+            input_output::write(path.with_extension("idx"), written.index.bytes())?;
+            new_pack = Some(path);
+        }
+        if !delete {
+            return Ok(());
+        }
+
+        if all {
+            let reachable: HashSet<Hash> = reachable.into_iter().map(|(hash, _)| hash).collect();
+            for pack in &old_packs {
+                let path = pack.path();
+                if new_pack.as_deref() == Some(path) || path.with_extension("keep").exists() {
+                    continue;
+                }
+                if loosen {
+                    let modified = fs::metadata(path)
+                        .and_then(|metadata| metadata.modified())
+                        .map_err(|e| Error::io("could not stat", path, e))?;
+                    for hash in pack.index.hashes() {
+                        let hex = hash.to_string();
+                        if reachable.contains(&hash) || input_output::obj_exists(root, &hex) {
+                            continue;
+                        }
+                        let offset = pack.index.find(&hash)?.unwrap_or_default();
+                        let (kind, content) = pack.read_at(root, offset)?;
+                        object::write_raw(root, kind, &content)?;
+                        let loose = input_output::get_obj_path(root, &hex);
+                        File::options()
+                            .write(true)
+                            .open(&loose)
+                            .and_then(|file| file.set_modified(modified))
+                            .map_err(|e| Error::io("could not touch", &loose, e))?;
+                    }
+                }
+                for path in [path.to_path_buf(), path.with_extension("idx")] {
+                    fs::remove_file(&path).map_err(|e| Error::io("could not remove", &path, e))?;
+                }
+            }
+        }
+        self.prune_packed(false)
+    }
+
+    /// Deletes the unreachable loose objects last modified at or before
+    /// `expire`, printing each with its type when `dry_run` or `verbose`,
+    /// and then the loose objects that are also packed.
+    pub fn prune(&self, expire: i64, dry_run: bool, verbose: bool) -> Result<()> {
+        let root = self.get_root();
+        let reachable: HashSet<Hash> = reachable::objects(root)?
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+        for hex in input_output::list_objs(root)? {
+            if reachable.contains(&hex.parse()?) {
+                continue;
+            }
+            let path = input_output::get_obj_path(root, &hex);
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|e| Error::io("could not stat", &path, e))?;
+            let modified = modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs() as i64);
+            if modified > expire {
+                continue;
+            }
+            if dry_run || verbose {
+                println!("{} {}", hex, object::read_raw(root, &hex)?.0);
+            }
+            if !dry_run {
+                fs::remove_file(&path).map_err(|e| Error::io("could not remove", &path, e))?;
+            }
+        }
+        self.prune_packed(dry_run)
+    }
+
+    /// Deletes the loose objects that are also in a pack, and the fan-out
+    /// directories this empties. With `dry_run`, only says what it would
+    /// delete.
+    fn prune_packed(&self, dry_run: bool) -> Result<()> {
+        let root = self.get_root();
+        let packs = pack::packs(root)?;
+        for hex in input_output::list_objs(root)? {
+            let hash = hex.parse()?;
+            let mut packed = false;
+            for pack in &packs {
+                packed = packed || pack.index.find(&hash)?.is_some();
+            }
+            if !packed {
+                continue;
+            }
+            let path = input_output::get_obj_path(root, &hex);
+            match dry_run {
+                true => println!("rm -f {}", path.display()),
+                false => {
+                    fs::remove_file(&path).map_err(|e| Error::io("could not remove", &path, e))?;
+                    if let Some(dir) = path.parent() {
+                        let _ = fs::remove_dir(dir);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn pack_refs(&self, all: bool, prune: bool) -> Result<()> {
+        let root = self.get_root();
+        refs::pack(root, all, prune, |hash| {
+            match object::read_raw(root, &hash.to_string())?.0 {
+                "tag" => Ok(Some(revision::peel(root, hash, "")?.0)),
+                _ => Ok(None),
+            }
+        })
+    }
+
+    /// Packs refs and objects and prunes unreachable loose objects that
+    /// expired at `prune`, by default `gc.pruneExpire` or two weeks ago.
+    /// With `auto`, does nothing unless there are more loose objects than
+    /// `gc.auto` or more packs than `gc.autoPackLimit`, and only packs the
+    /// loose objects unless it is the packs.
+    pub fn gc(
+        &self,
+        auto: bool,
+        aggressive: bool,
+        prune: Option<String>,
+        quiet: bool,
+    ) -> Result<()> {
+        let root = self.get_root();
+        let config = Config::read(root)?;
+        let int = |key: &str, default: i64| {
+            config
+                .get_entry(key)
+                .map_or(Ok(default), |entry| entry.as_int())
+        };
+        let prune = prune
+            .as_deref()
+            .or(config.get("gc.pruneExpire"))
+            .unwrap_or("2.weeks.ago");
+        let expire = signature::parse_expiry_date(prune)
+            .ok_or_else(|| Error::fatal(format!("malformed expiration date '{}'", prune)))?;
+        let loose_limit = int("gc.auto", 6700)?;
+        let too_many_loose = || -> Result<bool> {
+            // Like git, estimate from the objects in one fan-out directory.
+            let in_17 = input_output::find_objs(root, "17")?.len() as i64;
+            Ok(loose_limit > 0 && in_17 > (loose_limit + 255) / 256)
+        };
+
+        let mut incremental = false;
+        if auto {
+            let pack_limit = int("gc.autoPackLimit", 50)?;
+            let packs = pack::packs(root)?
+                .iter()
+                .filter(|pack| !pack.path().with_extension("keep").exists())
+                .count() as i64;
+            let too_many_packs = pack_limit > 0 && packs > pack_limit;
+            if loose_limit <= 0 || (!too_many_packs && !too_many_loose()?) {
+                return Ok(());
+            }
+            if !quiet {
+                eprintln!("Auto packing the repository for optimum performance.");
+                eprintln!("See \"git help gc\" for manual housekeeping.");
+            }
+            incremental = !too_many_packs;
+        }
+
+        self.pack_refs(true, true)?;
+        let (window, depth) = match aggressive {
+            true => (
+                int("gc.aggressiveWindow", 250)? as usize,
+                int("gc.aggressiveDepth", 50)? as usize,
+            ),
+            false => (pack_writer::DEFAULT_WINDOW, pack_writer::DEFAULT_DEPTH),
+        };
+        self.repack(!incremental, !incremental, true, window, depth)?;
+        self.prune(expire, false, false)?;
+        if auto && too_many_loose()? {
+            eprintln!(
+                "warning: There are too many unreachable loose objects; run 'git prune' to remove them."
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks the objects and their connectivity, exiting with git's bits
+    /// for the kinds of errors found.
+    pub fn fsck(&self, options: fsck::Options) -> Result<()> {
+        let report = fsck::check(self.get_root(), &options)?;
+        for line in &report.err {
+            eprintln!("{}", line);
+        }
+        for line in &report.out {
+            println!("{}", line);
+        }
+        if let Some(error) = report.fatal {
+            return Err(error);
+        }
+        match report.errors {
+            0 => Ok(()),
+            errors => Err(Error::Exit(errors)),
+        }
+    }
+
+    /// Gives up on the merge in progress, putting back the files it changed
+    /// while keeping local changes to the others, like `reset --merge`.
+    pub fn merge_abort(&self) -> Result<()> {
+        let root = self.get_root();
+        if self.merge_head()?.is_none() {
+            return Err(Error::fatal(
+                "There is no merge to abort (MERGE_HEAD missing).",
+            ));
+        }
+        let (_, head) = refs::resolve_name(root, "HEAD")?;
+        let head_tree = self.tree_of(head)?;
+        if let Some(head_tree) = head_tree {
+            tree::verify_tree(root, &head_tree)?;
+        }
+        let head_entries: BTreeMap<String, DiffEntry> = tree_diff::flatten_tree(root, head_tree)?
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        let mut index = Index::read(root)?;
+
+        // Conflicting files go back to HEAD's version whatever they hold.
+        let mut unmerged: Vec<String> = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage != 0)
+            .map(|entry| entry.path.clone())
+            .collect();
+        unmerged.dedup();
+        for path in unmerged {
+            index.remove(&path);
+            match head_entries.get(&path) {
+                Some(entry) => index.add(worktree::checkout_file(
+                    root, &path, entry.mode, entry.hash,
+                )?),
+                None => worktree::remove_file(root, &path),
+            }
+        }
+        let staged: Vec<index::IndexEntry> = index
+            .entries()
+            .iter()
+            .filter(|entry| !entry.intent_to_add)
+            .cloned()
+            .collect();
+        let index_tree = tree::write_entries(root, &staged)?;
+        let operation = checkout::Operation::Merge;
+        checkout::switch_trees(
+            root,
+            &mut index,
+            Some(index_tree),
+            head_tree,
+            false,
+            operation,
+        )?;
+        index.write(root)?;
+        self.remove_merge_state();
+        Ok(())
+    }
+
+    /// The commit being merged, if a merge is in progress.
+    fn merge_head(&self) -> Result<Option<Hash>> {
+        let path = self.get_root().join(".git/MERGE_HEAD");
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                let line = content.lines().next().unwrap_or_default();
+                let hash = line
+                    .parse()
+                    .map_err(|_| Error::corrupt(format!("could not parse {}", path.display())))?;
+                Ok(Some(hash))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::io("could not read", path, e)),
+        }
+    }
+
+    /// Forgets about a merge in progress.
+    fn remove_merge_state(&self) {
+        for file in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE", "AUTO_MERGE"] {
+            let _ = fs::remove_file(self.get_root().join(".git").join(file));
+        }
+    }
+
+    /// The default message of a merge of `rev` into `branch`, naming what
+    /// kind of ref `rev` is. Merges into the usual main branches leave out
+    /// where they went.
+    fn merge_message(&self, rev: &str, branch: Option<&str>) -> Result<String> {
+        let merged = match revision::expand_ref(self.get_root(), rev)? {
+            Some(refname) if refname.starts_with("refs/heads/") => {
+                format!("branch '{}'", &refname["refs/heads/".len()..])
+            }
+            Some(refname) if refname.starts_with("refs/remotes/") => {
+                format!(
+                    "remote-tracking branch '{}'",
+                    &refname["refs/remotes/".len()..]
+                )
+            }
+            Some(refname) if refname.starts_with("refs/tags/") => {
+                format!("tag '{}'", &refname["refs/tags/".len()..])
+            }
+            _ => format!("commit '{}'", rev),
+        };
+        Ok(match branch {
+            Some(branch) if branch != "main" && branch != "master" => {
+                format!("Merge {} into {}\n", merged, branch)
+            }
+            _ => format!("Merge {}\n", merged),
+        })
+    }
+
+    /// Moves HEAD, or the branch it is on, from `old` to `new`, noting why
+    /// in the reflogs.
+    fn update_head(&self, head_ref: &str, old: Hash, new: Hash, message: &str) -> Result<()> {
+        let root = self.get_root();
+        refs::update(root, "HEAD", &new, Some(old))?;
+        let committer = Signature::committer(root)?;
+        let mut names = vec!["HEAD"];
+        if head_ref != "HEAD" {
+            names.push(head_ref);
+        }
+        for name in names {
+            refs::append_reflog(root, name, &old, &new, &committer, message)?;
+        }
+        Ok(())
+    }
+
+    /// Prints the `--stat --summary` of what a merge changed.
+    fn print_diffstat(&self, old: Option<Hash>, new: Option<Hash>) -> Result<()> {
+        let root = self.get_root();
+        let changes = tree_diff::diff_trees(root, old, new, true)?;
+        let changes =
+            tree_diff::detect_renames(changes, tree_diff::DEFAULT_MIN_SCORE, false, |entry| {
+                self.read_entry(entry, &worktree::Contents::new())
+            })?;
+        let options = patch::Options {
+            output: patch::Output::Stat,
+            context: 3,
+            algorithm: Default::default(),
+            renames: None,
+            prefix_width: 0,
+        };
+        let stat = self.format_changes(changes.clone(), &worktree::Contents::new(), &options)?;
+        write_stdout(&stat)?;
+        write_stdout(&patch::format_summary(&changes))
+    }
+
+    /// Clones the repository at `url` into `directory`, which must not
+    /// exist or be empty. A failed clone removes what it created.
+    pub fn clone_remote(&self, url: &str, directory: &str) -> Result<()> {
+        let path = self.get_root().join(directory);
+        let existed = path.exists();
+        if existed && fs::read_dir(&path).map_or(true, |mut dir| dir.next().is_some()) {
+            return Err(Error::fatal(format!(
+                "destination path '{}' already exists and is not an empty directory.",
+                directory
+            )));
+        }
+        let result = Repo::new(&path).fetch_clone(url);
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&path);
+            if existed {
+                let _ = fs::create_dir(&path);
+            }
+        }
+        result
+    }
+
+    fn fetch_clone(&self, url: &str) -> Result<()> {
+        self.init()?;
+        let advertisement = remote::discover_refs(url)?;
+
+        let mut wants: Vec<Hash> = advertisement
+            .refs
+            .iter()
+            .filter(|(name, _)| !name.ends_with("^{}"))
+            .map(|(_, hash)| *hash)
+            .collect();
+        wants.sort();
+        wants.dedup();
+        if wants.is_empty() {
+            eprintln!("warning: You appear to have cloned an empty repository.");
+            return Ok(());
+        }
+
+        let pack = remote::fetch_pack(url, &advertisement, &wants, &[])?;
+        pack::unpack(self.get_root(), &pack)?;
+
+        // Like git, keep what we got from the remote in packed-refs and only
+        // create the checked out branch as a loose ref.
+        let mut packed = PackedRefs::default();
+        for (name, hash) in &advertisement.refs {
+            if let Some(branch) = name.strip_prefix("refs/heads/") {
+                let name = format!("refs/remotes/origin/{}", branch);
+                packed.insert(name, *hash, None);
+            } else if let Some(tag) = name.strip_suffix("^{}") {
+                if let Some(target) = packed.get(tag) {
+                    packed.insert(tag.to_string(), target, Some(*hash));
+                }
+            } else if name.starts_with("refs/tags/") {
+                packed.insert(name.clone(), *hash, None);
+            }
+        }
+        packed.write(self.get_root())?;
+
+        let head = advertisement
+            .head_target()
+            .unwrap_or_else(|| String::from("refs/heads/main"));
+        let branch = head
+            .strip_prefix("refs/heads/")
+            .ok_or_else(|| Error::fatal(format!("remote HEAD points to {}", head)))?;
+        refs::write_symbolic(self.get_root(), "HEAD", &head)?;
+        let mut config = ConfigFile::read(self.get_root().join(".git/config"))?;
+        config.set("remote.origin.url", url)?;
+        config.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+        config.set(&format!("branch.{}.remote", branch), "origin")?;
+        config.set(&format!("branch.{}.merge", branch), &head)?;
+        config.write()?;
+
+        let Some((_, hash)) = advertisement.refs.iter().find(|(name, _)| *name == head) else {
+            return Ok(());
+        };
+        refs::update(self.get_root(), &head, hash, Some(Hash::zero()))?;
+        refs::write_symbolic(
+            self.get_root(),
+            "refs/remotes/origin/HEAD",
+            &format!("refs/remotes/origin/{}", branch),
+        )?;
+        let Object::Commit(commit) = Object::read(self.get_root(), &hash.to_string())? else {
+            return Err(Error::fatal(format!("{} is not a commit", head)));
+        };
+        tree::checkout_tree(self.get_root(), &commit.tree)?.write(self.get_root())
+    }
+}
+
+/// What git says when HEAD gets detached, on how to keep or undo it.
+fn detached_head_advice(name: &str) -> String {
+    format!(
+        "Note: switching to '{}'.\n\n\
+         You are in 'detached HEAD' state. You can look around, make experimental\n\
+         changes and commit them, and you can discard any commits you make in this\n\
+         state without impacting any branches by switching back to a branch.\n\n\
+         If you want to create a new branch to retain commits you create, you may\n\
+         do so (now or later) by using -c with the switch command. Example:\n\n  \
+         git switch -c <new-branch-name>\n\n\
+         Or undo this operation with:\n\n  \
+         git switch -\n\n\
+         Turn off this advice by setting config variable advice.detachedHead to false\n",
+        name
+    )
+}
+
+/// Reports that `doing` something is not possible with conflicts in the
+/// index.
+fn unresolved_conflict(doing: &str) -> Error {
+    eprintln!(
+        "error: {} is not possible because you have unmerged files.",
+        doing
+    );
+    eprintln!("hint: Fix them up in the work tree, and then use 'git add/rm <file>'");
+    eprintln!("hint: as appropriate to mark resolution and make a commit.");
+    Error::fatal("Exiting because of an unresolved conflict.")
+}
+
+/// Cleans up a message like git's `stripspace`: trailing whitespace is
+/// removed from every line, runs of blank lines are collapsed and blank
+/// lines at the start and end are dropped.
+fn strip_space(message: &str) -> String {
+    let mut stripped = String::new();
+    let mut blank = false;
+    for line in message.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank = !stripped.is_empty();
+            continue;
+        }
+        if blank {
+            stripped.push('\n');
+            blank = false;
+        }
+        stripped.push_str(line);
+        stripped.push('\n');
+    }
+    stripped
+}
+
+/// Keeps the changes to files below one of `paths` on either side, or all
+/// of them without paths.
+fn filter_changes(changes: Vec<Change>, paths: &[String]) -> Vec<Change> {
+    let matches = |entry: &Option<DiffEntry>| {
+        entry.as_ref().is_some_and(|entry| {
+            paths
+                .iter()
+                .any(|path| index::matches_pathspec(&entry.path, path))
+        })
+    };
+    changes
+        .into_iter()
+        .filter(|change| paths.is_empty() || matches(&change.old) || matches(&change.new))
+        .collect()
+}
+
+/// Returns the files of the index in path order, leaving out unmerged ones
+/// and the ones added with `--intent-to-add`, which have no content yet.
+fn staged_entries(index: &Index) -> Vec<DiffEntry> {
+    index
+        .entries()
+        .iter()
+        .filter(|e| e.stage == 0 && !e.intent_to_add)
+        .map(|e| DiffEntry {
+            path: e.path.clone(),
+            mode: e.mode,
+            hash: e.hash,
+        })
+        .collect()
+}
+
+fn write_stdout(bytes: &[u8]) -> Result<()> {
+    io::stdout().write_all(bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, net::TcpListener, os::unix::fs::PermissionsExt};
+
+    use crate::{
+        checkout,
+        commit::Commit,
+        error::Error,
+        file_mode::FileMode,
+        hash::Hash,
+        index::Index,
+        input_output,
+        merge::FastForward,
+        object::{self, Object},
+        pack, patch, reference_impl, refs,
+        repo::Repo,
+        signature::Signature,
+        status, tag, test_utils, tree,
+    };
+
+    #[test]
+    fn test_clone() {
+        let root = test_utils::create_test_dir();
+        let origin = root.join("origin");
+        let repository = reference_impl::create_repository(&origin);
+
+        input_output::write(origin.join("README"), "hello\n").unwrap();
+        input_output::write_with_mode(
+            origin.join("bin/run.sh"),
+            "#!/bin/sh\n",
+            FileMode::Executable,
+        )
+        .unwrap();
+        reference_impl::git_add_all(&repository);
+        let first = reference_impl::git_commit(&repository, "first", &[]);
+        input_output::write(origin.join("README"), "hello again\n").unwrap();
+        reference_impl::git_add_all(&repository);
+        let second = reference_impl::git_commit(&repository, "second", &[&first]);
+        reference_impl::git_update_ref(&repository, "refs/heads/main", &second);
+        reference_impl::git_update_ref(&repository, "refs/heads/old", &first);
+        reference_impl::git_update_ref(&repository, "refs/tags/v1", &first);
+        repository.set_head("refs/heads/main").unwrap();
+
+        let url = reference_impl::serve_repository(&origin);
+        let clone = root.join("clone");
+        Repo::new(&root).clone_remote(&url, "clone").unwrap();
+
+        let git_dir = clone.join(".git");
+        let read = |path: &str| fs::read_to_string(git_dir.join(path)).unwrap();
+        assert_eq!(read("HEAD"), "ref: refs/heads/main\n");
+        assert_eq!(read("refs/heads/main"), format!("{}\n", second));
+        let resolve = |name: &str| refs::resolve(&clone, name).unwrap().unwrap().to_string();
+        assert_eq!(resolve("refs/remotes/origin/old"), first);
+        assert_eq!(resolve("refs/remotes/origin/HEAD"), second);
+        assert_eq!(resolve("refs/tags/v1"), first);
+
+        let commit = Object::read(&clone, &second).unwrap().into_commit();
+        assert_eq!(commit.parents[0].to_string(), first);
+        assert_eq!(
+            fs::read_to_string(clone.join("README")).unwrap(),
+            "hello again\n"
+        );
+        let mode = fs::metadata(clone.join("bin/run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_ne!(mode & 0o111, 0);
+        assert_eq!(Index::read(&clone).unwrap().entries().len(), 2);
+
+        let error = Repo::new(&root)
+            .clone_remote(&url, "clone")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "destination path 'clone' already exists and is not an empty directory."
+        );
+        assert_eq!(read("HEAD"), "ref: refs/heads/main\n");
+    }
+
+    #[test]
+    fn test_clone_failed() {
+        let root = test_utils::create_test_dir();
+        // Nothing listens on the port once the listener is dropped.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/missing.git", listener.local_addr().unwrap());
+        drop(listener);
+        let error = Repo::new(&root)
+            .clone_remote(&url, "clone")
+            .unwrap_err()
+            .to_string();
+        let prefix = format!("unable to access '{}/': ", url);
+        assert!(error.starts_with(&prefix));
+        assert!(!error[prefix.len()..].contains(&url));
+        assert!(!root.join("clone").exists());
+
+        fs::create_dir(root.join("empty")).unwrap();
+        Repo::new(&root).clone_remote(&url, "empty").unwrap_err();
+        assert_eq!(fs::read_dir(root.join("empty")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_add() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+
+        input_output::write(root.join("file1"), "one").unwrap();
+        input_output::write(root.join("dir/file2"), "two").unwrap();
+        input_output::write(root.join("dir/file3"), "three").unwrap();
+        repo.add(vec![String::from("dir")], false, false, false)
+            .unwrap();
+        assert_eq!(
+            Index::read(&root).unwrap().matching(""),
+            ["dir/file2", "dir/file3"]
+        );
+
+        // -u only touches tracked files, picking up modifications and deletions.
+        input_output::write(root.join("dir/file2"), "changed").unwrap();
+        fs::remove_file(root.join("dir/file3")).unwrap();
+        repo.add(vec![], false, true, false).unwrap();
+        let index = Index::read(&root).unwrap();
+        assert_eq!(index.matching(""), ["dir/file2"]);
+        let hash = index.get("dir/file2").unwrap().hash;
+        assert_eq!(
+            Object::read(&root, &hash.to_string())
+                .unwrap()
+                .into_blob()
+                .content,
+            b"changed"
+        );
+
+        repo.add(vec![], true, false, false).unwrap();
+        assert_eq!(
+            Index::read(&root).unwrap().matching(""),
+            ["dir/file2", "file1"]
+        );
+    }
+
+    #[test]
+    fn test_add_keeps_sorted_paths() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+
+        // "a-b" sorts before "a/x" as a string but after it as a path.
+        input_output::write(root.join("a-b"), "one").unwrap();
+        input_output::write(root.join("a/x"), "two").unwrap();
+        for _ in 0..2 {
+            repo.add(vec![String::from(".")], false, false, false)
+                .unwrap();
+            assert_eq!(Index::read(&root).unwrap().matching(""), ["a-b", "a/x"]);
+        }
+    }
+
+    #[test]
+    fn test_add_ignored() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+
+        input_output::write(root.join("target/kept"), "kept").unwrap();
+        repo.add(vec![String::from("target")], false, false, false)
+            .unwrap();
+        input_output::write(root.join(".gitignore"), "target/\n*.o\n").unwrap();
+        input_output::write(root.join("target/new"), "new").unwrap();
+        input_output::write(root.join("main.o"), "object").unwrap();
+        input_output::write(root.join("main.c"), "source").unwrap();
+
+        // Tracked files stay tracked even in an ignored directory.
+        repo.add(vec![], true, false, false).unwrap();
+        assert_eq!(
+            Index::read(&root).unwrap().matching(""),
+            [".gitignore", "main.c", "target/kept"]
+        );
+
+        let error = repo
+            .add(vec![String::from("main.o")], false, false, false)
+            .unwrap_err();
+        assert!(matches!(error, Error::Exit(1)));
+        repo.add(vec![String::from("main.o")], false, false, true)
+            .unwrap();
+        assert!(Index::read(&root).unwrap().get("main.o").is_some());
+    }
+
+    #[test]
+    fn test_commit() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let repo = Repo::new(&root);
+
+        input_output::write(root.join("file1"), "one").unwrap();
+        repo.add(vec![String::from("file1")], false, false, false)
+            .unwrap();
+        repo.commit(vec![String::from("first")], false).unwrap();
+        let first = refs::resolve(&root, "refs/heads/master").unwrap().unwrap();
+        assert!(Object::read(&root, &first.to_string())
+            .unwrap()
+            .into_commit()
+            .parents
+            .is_empty());
+
+        // Nothing staged: HEAD does not move.
+        let error = repo.commit(vec![String::from("empty")], false).unwrap_err();
+        assert!(matches!(error, Error::Exit(1)));
+        assert_eq!(refs::resolve(&root, "HEAD").unwrap(), Some(first));
+
+        input_output::write(root.join("file1"), "two").unwrap();
+        repo.add(vec![String::from("file1")], false, false, false)
+            .unwrap();
+        repo.commit(vec![String::from("second"), String::from("body")], false)
+            .unwrap();
+        let (_, parents, message) = reference_impl::read_commit(&root, "HEAD");
+        assert_eq!(parents, [first.to_string()]);
+        assert_eq!(message.unwrap(), "second\n\nbody\n");
+
+        let reflog = fs::read_to_string(root.join(".git/logs/HEAD")).unwrap();
+        let messages: Vec<_> = reflog
+            .lines()
+            .map(|line| line.split_once('\t').unwrap().1)
+            .collect();
+        assert_eq!(messages, ["commit (initial): first", "commit: second"]);
+        assert_eq!(reference_impl::git_reflog_len(&repository, "HEAD"), 2);
+
+        // Detached, the commit is logged once, and only for HEAD.
+        let second = refs::resolve(&root, "HEAD").unwrap().unwrap();
+        refs::write_direct(&root, "HEAD", &second).unwrap();
+        input_output::write(root.join("file1"), "three").unwrap();
+        repo.add(vec![String::from("file1")], false, false, false)
+            .unwrap();
+        repo.commit(vec![String::from("third")], false).unwrap();
+        assert_ne!(refs::resolve(&root, "HEAD").unwrap(), Some(second));
+        assert_eq!(
+            refs::resolve(&root, "refs/heads/master").unwrap(),
+            Some(second)
+        );
+        assert_eq!(reference_impl::git_reflog_len(&repository, "HEAD"), 3);
+        assert_eq!(
+            reference_impl::git_reflog_len(&repository, "refs/heads/master"),
+            2
+        );
+    }
+
+    #[test]
+    fn test_commit_tree() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+
+        input_output::write(root.join("file1"), "one").unwrap();
+        repo.add(vec![String::from("file1")], false, false, false)
+            .unwrap();
+        let tree = tree::write_tree(&root).unwrap();
+        let commit = |parents: Vec<Hash>, message: &str| {
+            let commit = Commit::new(
+                tree,
+                parents,
+                Signature::author(&root).unwrap(),
+                Signature::committer(&root).unwrap(),
+                message.to_string(),
+            );
+            Object::Commit(commit).write(&root).unwrap()
+        };
+        let first = commit(vec![], "first");
+        let second = commit(vec![], "second");
+        let merge = commit(vec![first, second], "merge");
+
+        let (_, parents, _) = reference_impl::read_commit(&root, &merge.to_string());
+        assert_eq!(parents, [first.to_string(), second.to_string()]);
+
+        let message = Repo::read_message(
+            vec![String::from("subject"), String::from("body\n")],
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(message, "subject\n\nbody\n");
+    }
+
+    #[test]
+    fn test_commit_tree_parent_not_commit() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+
+        input_output::write(root.join("file1"), "one").unwrap();
+        repo.add(vec![String::from("file1")], false, false, false)
+            .unwrap();
+        let tree = tree::write_tree(&root).unwrap().to_string();
+        let error = repo
+            .commit_tree(
+                tree.clone(),
+                vec![tree.clone()],
+                vec![String::from("msg")],
+                vec![],
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.report().unwrap(),
+            format!("fatal: {} is not a valid 'commit' object", tree)
+        );
+        assert_eq!(error.exit_code(), 128);
+    }
+
+    #[test]
+    fn test_show_merge() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let repo = Repo::new(&root);
+
+        let commit = |files: &[(&str, &str)], parents: Vec<Hash>| {
+            let blobs: Vec<_> = files
+                .iter()
+                .map(|(_, content)| reference_impl::git_write_object(&repository, "blob", content))
+                .collect();
+            let entries: Vec<_> = files
+                .iter()
+                .zip(&blobs)
+                .map(|((name, _), blob)| (*name, blob.as_str(), 0o100644))
+                .collect();
+            let tree = reference_impl::git_build_tree(&repository, &entries);
+            let signature =
+                Signature::new(String::from("A"), String::from("a@x"), 0, "+0000".into());
+            Commit::new(
+                tree.parse().unwrap(),
+                parents,
+                signature.clone(),
+                signature,
+                String::from("message"),
+            )
+        };
+        let write = |commit: Commit| Object::Commit(commit).write(&root).unwrap();
+        let base = write(commit(&[("a", "1\n2\n3\n4\n5\n6\n"), ("b", "x\n")], vec![]));
+        let main = write(commit(
+            &[("a", "M\n2\n3\n4\n5\n6\n"), ("b", "x\n"), ("m", "m\n")],
+            vec![base],
+        ));
+        let side = write(commit(
+            &[("a", "1\n2\n3\n4\n5\nS\n"), ("s", "s\n")],
+            vec![base],
+        ));
+        let merge = commit(
+            &[("a", "M\n2\n3\n4\n5\nS\n"), ("m", "m\n"), ("s", "s\n")],
+            vec![main, side],
+        );
+
+        // What git shows for the same merge.
+        for (output, want) in [
+            (
+                patch::Output::Stat,
+                " a | 2 +-\n b | 1 -\n s | 1 +\n 3 files changed, 2 insertions(+), 2 deletions(-)\n",
+            ),
+            (patch::Output::Numstat, "1\t1\ta\n0\t1\tb\n1\t0\ts\n"),
+            (patch::Output::NameOnly, "a\n"),
+            (patch::Output::NameStatus, "MM\ta\n"),
+            (patch::Output::Patch, ""),
+        ] {
+            let options = patch::Options {
+                output,
+                context: 3,
+                algorithm: Default::default(),
+                renames: Some(50),
+                prefix_width: 0,
+            };
+            let shown = repo.show_changes(&merge, &options).unwrap();
+            assert_eq!(String::from_utf8(shown).unwrap(), want);
+        }
+
+        // `log` prints nothing under the merge, nor under an empty commit.
+        let empty = commit(&[("a", "1\n2\n3\n4\n5\n6\n"), ("b", "x\n")], vec![base]);
+        for commit in [&merge, &empty] {
+            for output in [patch::Output::Stat, patch::Output::Numstat] {
+                let options = patch::Options {
+                    output,
+                    context: 3,
+                    algorithm: Default::default(),
+                    renames: Some(50),
+                    prefix_width: 0,
+                };
+                assert!(repo
+                    .log_changes(commit, &[], false, &options)
+                    .unwrap()
+                    .is_empty());
+            }
+        }
+
+        // With `--first-parent`, against the first parent like `show`.
+        let options = patch::Options {
+            output: patch::Output::NameStatus,
+            context: 3,
+            algorithm: Default::default(),
+            renames: Some(50),
+            prefix_width: 0,
+        };
+        let logged = repo.log_changes(&merge, &[], true, &options).unwrap();
+        assert_eq!(String::from_utf8(logged).unwrap(), "M\ta\nD\tb\nA\ts\n");
+    }
+
+    #[test]
+    fn test_tag() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let repo = Repo::new(&root);
+
+        input_output::write(root.join("file1"), "one").unwrap();
+        repo.add(vec![String::from("file1")], false, false, false)
+            .unwrap();
+        repo.commit(vec![String::from("first")], false).unwrap();
+        let head = refs::resolve(&root, "HEAD").unwrap().unwrap();
+
+        let create = |name: &str, messages: Vec<String>, force: bool| {
+            repo.tag(tag::Action::Create {
+                name: name.to_string(),
+                target: None,
+                annotate: false,
+                messages,
+                file: None,
+                force,
+            })
+        };
+        create("light", vec![], false).unwrap();
+        create(
+            "v1.0",
+            vec![String::from("release  "), String::from("")],
+            false,
+        )
+        .unwrap();
+        let error = create("light", vec![], false).unwrap_err();
+        assert_eq!(error.to_string(), "tag 'light' already exists");
+        create("light", vec![String::from("now annotated")], true).unwrap();
+
+        let light = refs::resolve(&root, "refs/tags/light").unwrap().unwrap();
+        let tag = repository
+            .find_tag(light.to_string().parse().unwrap())
+            .unwrap();
+        assert_eq!(tag.message(), Some("now annotated\n"));
+        let v1 = refs::resolve(&root, "refs/tags/v1.0").unwrap().unwrap();
+        let tag = Object::read(&root, &v1.to_string()).unwrap().into_tag();
+        assert_eq!(tag.object, head);
+        assert_eq!(tag.kind, "commit");
+        assert_eq!(tag.message, b"release\n");
+
+        repo.tag(tag::Action::Delete {
+            names: vec![String::from("light")],
+        })
+        .unwrap();
+        assert_eq!(refs::resolve(&root, "refs/tags/light").unwrap(), None);
+        let error = repo
+            .tag(tag::Action::Delete {
+                names: vec![String::from("light")],
+            })
+            .unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_rm() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+
+        input_output::write(root.join("file1"), "one").unwrap();
+        input_output::write(root.join("dir/file2"), "two").unwrap();
+        input_output::write(root.join("dir/file3"), "three").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+
+        repo.rm(vec![String::from("file1")], true, false, false)
+            .unwrap();
+        assert!(root.join("file1").exists());
+        repo.rm(vec![String::from("dir")], false, true, false)
+            .unwrap();
+        assert!(!root.join("dir").exists());
+        assert!(Index::read(&root).unwrap().entries().is_empty());
+    }
+
+    #[test]
+    fn test_status() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let repo = Repo::new(&root);
+        let files = [
+            ("modified", "one\n"),
+            ("deleted", "gone\n"),
+            ("moved", "a longer file\nthat gets renamed\n"),
+            ("dir/kept", "kept\n"),
+        ];
+        for (path, content) in files {
+            input_output::write(root.join(path), content).unwrap();
+        }
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("first")], false).unwrap();
+
+        input_output::write(root.join("modified"), "two\n").unwrap();
+        fs::remove_file(root.join("deleted")).unwrap();
+        fs::rename(root.join("moved"), root.join("renamed")).unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        input_output::write(root.join("modified"), "three\n").unwrap();
+        fs::remove_file(root.join("dir/kept")).unwrap();
+        input_output::write(root.join("new"), "new\n").unwrap();
+        input_output::write(root.join("untracked/dir/file"), "").unwrap();
+        input_output::write(root.join("ignored.o"), "").unwrap();
+        input_output::write(root.join(".gitignore"), "*.o\n").unwrap();
+
+        let options = status::Options {
+            format: status::Format::Short,
+            branch: false,
+            untracked: status::Untracked::Normal,
+        };
+        let report = repo.status_report(options.untracked).unwrap();
+        let short = status::format(&root, &report, &options).unwrap();
+        assert_eq!(
+            short,
+            "D  deleted\n D dir/kept\nMM modified\nR  moved -> renamed\n\
+             ?? .gitignore\n?? new\n?? untracked/\n"
+        );
+        assert_eq!(short, reference_impl::git_status_short(&repository));
+    }
+
+    #[test]
+    fn test_checkout() {
+        let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
+        let options = |new_branch: Option<&str>, detach: bool| checkout::Options {
+            new_branch: new_branch.map(str::to_string),
+            detach,
+            force: false,
+            quiet: true,
+            switch: false,
+        };
+
+        input_output::write(root.join("file"), "one\n").unwrap();
+        input_output::write(root.join("gone"), "gone\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("first")], false).unwrap();
+        let first = refs::resolve(&root, "HEAD").unwrap().unwrap();
+
+        repo.checkout(None, options(Some("dev"), false)).unwrap();
+        input_output::write(root.join("file"), "two\n").unwrap();
+        input_output::write_with_mode(root.join("script"), "#!/bin/sh\n", FileMode::Executable)
+            .unwrap();
+        input_output::write_with_mode(root.join("link"), "file", FileMode::Symlink).unwrap();
+        fs::remove_file(root.join("gone")).unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("second")], false).unwrap();
+        let second = refs::resolve(&root, "HEAD").unwrap().unwrap();
+
+        repo.checkout(Some(String::from("main")), options(None, false))
+            .unwrap();
+        assert_eq!(fs::read(root.join("file")).unwrap(), b"one\n");
+        assert!(root.join("gone").exists());
+        assert!(!root.join("script").exists() && !root.join("link").exists());
+
+        // Nothing is touched if a local change or an untracked file would
+        // be lost.
+        input_output::write(root.join("gone"), "changed\n").unwrap();
+        let error = repo
+            .checkout(Some(String::from("dev")), options(None, false))
+            .unwrap_err();
+        assert!(matches!(error, Error::Failed(_)));
+        input_output::write(root.join("gone"), "gone\n").unwrap();
+        input_output::write(root.join("script"), "untracked\n").unwrap();
+        let error = repo
+            .checkout(Some(String::from("dev")), options(None, false))
+            .unwrap_err();
+        assert!(matches!(error, Error::Failed(_)));
+        fs::remove_file(root.join("script")).unwrap();
+
+        repo.checkout(Some(String::from("dev")), options(None, false))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join(".git/HEAD")).unwrap(),
+            "ref: refs/heads/dev\n"
+        );
+        assert_eq!(fs::read(root.join("file")).unwrap(), b"two\n");
+        assert!(!root.join("gone").exists());
+        let mode = fs::metadata(root.join("script"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o111, 0o111);
+        assert_eq!(
+            fs::read_link(root.join("link")).unwrap().to_str(),
+            Some("file")
+        );
+        let Object::Commit(commit) = Object::read(&root, &second.to_string()).unwrap() else {
+            panic!("not a commit");
+        };
+        assert_eq!(tree::write_tree(&root).unwrap(), commit.tree);
+
+        repo.checkout(Some(String::from("main")), options(None, true))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join(".git/HEAD")).unwrap(),
+            format!("{}\n", first)
+        );
+        assert_eq!(
+            refs::resolve(&root, "refs/heads/main").unwrap(),
+            Some(first)
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let repo = Repo::new(&root);
+        let options = |new_branch: Option<&str>| checkout::Options {
+            new_branch: new_branch.map(str::to_string),
+            detach: false,
+            force: false,
+            quiet: true,
+            switch: false,
+        };
+        let head = || refs::resolve(&root, "HEAD").unwrap().unwrap();
+
+        input_output::write(root.join("file"), "a\nb\nc\nd\ne\nf\ng\n").unwrap();
+        input_output::write(root.join("other"), "other\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("first")], false).unwrap();
+        let first = head();
+
+        repo.checkout(None, options(Some("dev"))).unwrap();
+        input_output::write(root.join("file"), "a\nb\nc\nd\ne\nf\nG\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("dev")], false).unwrap();
+        let dev = head();
+
+        // Nothing to merge, then a fast-forward.
+        repo.checkout(Some(String::from("master")), options(None))
+            .unwrap();
+        repo.merge("master", vec![], FastForward::Allow, true)
+            .unwrap();
+        assert_eq!(head(), first);
+        repo.merge("dev", vec![], FastForward::Allow, true).unwrap();
+        assert_eq!(head(), dev);
+
+        // Diverged histories that merge cleanly, like git merges them.
+        repo.checkout(None, options(Some("topic"))).unwrap();
+        input_output::write(root.join("other"), "changed\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("topic")], false).unwrap();
+        let topic = head();
+        repo.checkout(Some(String::from("master")), options(None))
+            .unwrap();
+        input_output::write(root.join("file"), "A\nb\nc\nd\ne\nf\nG\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("master")], false).unwrap();
+        let main = head();
+        let expected =
+            reference_impl::git_merge_commits(&repository, &main.to_string(), &topic.to_string());
+        repo.merge("topic", vec![], FastForward::Allow, true)
+            .unwrap();
+        let (_, parents, message) = reference_impl::read_commit(&root, &head().to_string());
+        assert_eq!(parents, vec![main.to_string(), topic.to_string()]);
+        assert_eq!(message.as_deref(), Some("Merge branch 'topic'\n"));
+        assert_eq!(Some(tree::write_tree(&root).unwrap().to_string()), expected);
+        assert_eq!(fs::read(root.join("other")).unwrap(), b"changed\n");
+
+        // A conflict stops the merge with the stages in the index and
+        // markers in the file, until the result is committed.
+        let merged = head();
+        repo.checkout(None, options(Some("clash"))).unwrap();
+        input_output::write(root.join("file"), "A\nb\nc\nd\ne\nf\ntheirs\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("clash")], false).unwrap();
+        let clash = head();
+        repo.checkout(Some(String::from("master")), options(None))
+            .unwrap();
+        input_output::write(root.join("file"), "A\nb\nc\nd\ne\nf\nours\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("ours")], false).unwrap();
+        let ours = head();
+        assert!(reference_impl::git_merge_commits(
+            &repository,
+            &ours.to_string(),
+            &clash.to_string()
+        )
+        .is_none());
+        let error = repo
+            .merge("clash", vec![], FastForward::Allow, true)
+            .unwrap_err();
+        assert!(matches!(error, Error::Exit(1)));
+        let stages: Vec<u8> = Index::read(&root)
+            .unwrap()
+            .entries()
+            .iter()
+            .filter(|entry| entry.path == "file")
+            .map(|entry| entry.stage)
+            .collect();
+        assert_eq!(stages, vec![1, 2, 3]);
+        assert_eq!(
+            fs::read_to_string(root.join("file")).unwrap(),
+            "A\nb\nc\nd\ne\nf\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> clash\n"
+        );
+        assert!(repo.commit(vec![], false).is_err());
+
+        // Aborting puts everything back.
+        repo.merge_abort().unwrap();
+        assert!(!root.join(".git/MERGE_HEAD").exists());
+        assert_eq!(head(), ours);
+        assert_eq!(
+            fs::read(root.join("file")).unwrap(),
+            b"A\nb\nc\nd\ne\nf\nours\n"
+        );
+
+        repo.merge("clash", vec![], FastForward::Allow, true)
+            .unwrap_err();
+        input_output::write(root.join("file"), "A\nb\nc\nd\ne\nf\nboth\n").unwrap();
+        repo.add(vec![String::from("file")], false, false, false)
+            .unwrap();
+        repo.commit(vec![], false).unwrap();
+        let (_, parents, message) = reference_impl::read_commit(&root, &head().to_string());
+        assert_eq!(parents, vec![ours.to_string(), clash.to_string()]);
+        assert_eq!(message.as_deref(), Some("Merge branch 'clash'\n"));
+        assert!(!root.join(".git/MERGE_HEAD").exists());
+        assert_ne!(merged, head());
+    }
+
+    #[test]
+    fn test_gc() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let repo = Repo::new(&root);
+        let loose = || input_output::list_objs(&root).unwrap();
+        let options = |new_branch: Option<&str>| checkout::Options {
+            new_branch: new_branch.map(str::to_string),
+            detach: false,
+            force: false,
+            quiet: true,
+            switch: false,
+        };
+
+        input_output::write(root.join("file"), "one\n").unwrap();
+        input_output::write(root.join("dir/file"), "two\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("first")], false).unwrap();
+        let head = refs::resolve(&root, "HEAD").unwrap().unwrap().to_string();
+        let dangling = object::write_raw(&root, "blob", b"dangling\n").unwrap();
+
+        // Everything reachable moves into one pack.
+        repo.repack(true, false, true, 10, 50).unwrap();
+        assert_eq!(loose(), vec![dangling.to_string()]);
+        let packs = pack::packs(&root).unwrap();
+        assert_eq!(packs.len(), 1);
+        for (hash, _) in reference_impl::git_list_objects(&repository, &head) {
+            let (kind, content) = object::read_raw(&root, &hash).unwrap();
+            assert_eq!(
+                reference_impl::git_read_object(&repository, &hash),
+                (kind.to_string(), content)
+            );
+        }
+
+        // Unreachable objects are only pruned once they expire.
+        repo.prune(0, false, false).unwrap();
+        assert_eq!(loose(), vec![dangling.to_string()]);
+        repo.prune(i64::MAX, false, false).unwrap();
+        assert!(loose().is_empty());
+
+        // A packed object that is no longer reachable becomes loose again
+        // when its pack goes.
+        repo.checkout(None, options(Some("topic"))).unwrap();
+        input_output::write(root.join("file"), "three\n").unwrap();
+        repo.add(vec![], true, false, false).unwrap();
+        repo.commit(vec![String::from("second")], false).unwrap();
+        repo.repack(true, false, true, 10, 50).unwrap();
+        repo.checkout(Some(String::from("master")), options(None))
+            .unwrap();
+        refs::delete(&root, "refs/heads/topic", None).unwrap();
+        fs::remove_dir_all(root.join(".git/logs")).unwrap();
+        repo.gc(false, false, Some(String::from("never")), true)
+            .unwrap();
+        // The commit, its tree and the new blob.
+        assert_eq!(loose().len(), 3);
+        assert_eq!(pack::packs(&root).unwrap().len(), 1);
+        assert!(fs::read_to_string(root.join(".git/packed-refs"))
+            .unwrap()
+            .contains("refs/heads/master"));
+        repo.gc(false, false, Some(String::from("now")), true)
+            .unwrap();
+        assert!(loose().is_empty());
+        assert_eq!(
+            reference_impl::git_resolve_ref(&repository, "refs/heads/master"),
+            head
+        );
     }
 }
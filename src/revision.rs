@@ -0,0 +1,474 @@
+use std::path::Path;
+
+use crate::{
+    bytes_reader::BytesReader,
+    commit::Commit,
+    error::{Error, Result},
+    hash::Hash,
+    index::Index,
+    object::{self, Object},
+    refs,
+    revwalk::RevWalk,
+    tag::Tag,
+};
+
+/// Hex prefixes shorter than this are never taken as object names.
+const MIN_ABBREV: usize = 4;
+
+/// The rules `git rev-parse` uses to expand a short ref name, in order.
+const REF_RULES: [(&str, &str); 6] = [
+    ("", ""),
+    ("refs/", ""),
+    ("refs/tags/", ""),
+    ("refs/heads/", ""),
+    ("refs/remotes/", ""),
+    ("refs/remotes/", "/HEAD"),
+];
+
+/// Resolves a revision as described in gitrevisions(7):
+///
+/// - a full or unique abbreviated object name
+/// - a ref name, expanded like `main` to `refs/heads/main`, with `@` standing
+///   for `HEAD`
+/// - `<ref>@{<n>}` and `@{<n>}`, the n-th prior value of a ref or of the
+///   current branch
+/// - `<rev>~<n>`, `<rev>^<n>`, `<rev>^{<type>}`, `<rev>^{}` and
+///   `<rev>^{/<text>}`
+/// - `<rev>:<path>` and `:<n>:<path>`, an entry of a tree or of the index
+/// - `:/<text>`, the youngest reachable commit whose message contains
+///   `<text>`
+///
+/// Messages are searched for plain text rather than a regular expression.
+pub fn resolve(root: impl AsRef<Path>, rev: &str) -> Result<Hash> {
+    let root = root.as_ref();
+    resolve_rev(root, rev)?.ok_or_else(|| Error::InvalidObjectName(rev.to_string()))
+}
+
+fn resolve_rev(root: &Path, rev: &str) -> Result<Option<Hash>> {
+    if let Some(pattern) = rev.strip_prefix(":/") {
+        let mut starts: Vec<_> = refs::list(root, "refs/")?
+            .into_iter()
+            .map(|(_, hash)| hash)
+            .collect();
+        starts.extend(refs::resolve(root, "HEAD")?);
+        return search_message(root, starts, pattern);
+    }
+    if let Some(path) = rev.strip_prefix(':') {
+        return lookup_index(root, path).map(Some);
+    }
+    if let Some(colon) = find_outside_braces(rev, |c| c == ':') {
+        let (tree_ish, path) = (&rev[..colon], &rev[colon + 1..]);
+        let Some(hash) = resolve_rev(root, tree_ish)? else {
+            return Ok(None);
+        };
+        let (tree, kind) = peel(root, hash, "tree")?;
+        if kind != "tree" {
+            return Ok(None);
+        }
+        return lookup_path(root, tree, path)
+            .transpose()
+            .unwrap_or_else(|| {
+                Err(Error::fatal(format!(
+                    "path '{}' does not exist in '{}'",
+                    path, tree_ish
+                )))
+            })
+            .map(Some);
+    }
+
+    let end = find_outside_braces(rev, |c| c == '^' || c == '~').unwrap_or(rev.len());
+    let Some(mut hash) = resolve_base(root, &rev[..end])? else {
+        return Ok(None);
+    };
+    let mut rest = &rev[end..];
+    while let Some(op) = rest.chars().next() {
+        rest = &rest[1..];
+        if op == '^' && rest.starts_with('{') {
+            let Some(close) = rest.find('}') else {
+                return Ok(None);
+            };
+            let Some(peeled) = peel_suffix(root, hash, &rest[1..close], rev)? else {
+                return Ok(None);
+            };
+            hash = peeled;
+            rest = &rest[close + 1..];
+            continue;
+        }
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let n = match digits {
+            0 => 1,
+            _ => match rest[..digits].parse() {
+                Ok(n) => n,
+                Err(_) => return Ok(None),
+            },
+        };
+        rest = &rest[digits..];
+        let next = match op {
+            '^' => nth_parent(root, hash, n)?,
+            _ => nth_ancestor(root, hash, n)?,
+        };
+        let Some(next) = next else {
+            return Ok(None);
+        };
+        hash = next;
+    }
+    Ok(Some(hash))
+}
+
+/// Finds the first character matching `pred` that is not inside a `{...}`,
+/// as in `HEAD@{1}` or `v1^{tree}`.
+fn find_outside_braces(rev: &str, pred: impl Fn(char) -> bool) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in rev.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            c if depth == 0 && pred(c) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Resolves a revision without suffixes: an object name, a ref, or a reflog
+/// entry.
+fn resolve_base(root: &Path, name: &str) -> Result<Option<Hash>> {
+    if let Some(at) = name.find("@{") {
+        let Some(selector) = name[at + 2..].strip_suffix('}') else {
+            return Ok(None);
+        };
+        let Ok(n) = selector.parse() else {
+            return Ok(None);
+        };
+        return reflog_entry(root, &name[..at], n);
+    }
+    let name = if name == "@" { "HEAD" } else { name };
+    if name.len() == 40 {
+        if let Ok(hash) = name.parse() {
+            return Ok(Some(hash));
+        }
+    }
+    if let Some(refname) = expand_ref(root, name)? {
+        return refs::resolve(root, &refname);
+    }
+    if name.len() < MIN_ABBREV || !name.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+
+    let candidates = object::find_by_prefix(root, name)?;
+    if candidates.len() > 1 {
+        eprintln!("error: short object ID {} is ambiguous", name);
+        eprintln!("hint: The candidates are:");
+        for candidate in &candidates {
+            let (kind, _) = object::read_raw(root, &candidate.to_string())?;
+            let short = object::abbreviate(root, candidate, name.len() + 1)?;
+            eprintln!("hint:   {} {}", short, kind);
+        }
+        return Ok(None);
+    }
+    Ok(candidates.first().copied())
+}
+
+/// Expands a short ref name to the full name of the first existing ref it
+/// may stand for, trying `<name>`, `refs/<name>`, `refs/tags/<name>`,
+/// `refs/heads/<name>`, `refs/remotes/<name>` and `refs/remotes/<name>/HEAD`.
+pub fn expand_ref(root: impl AsRef<Path>, name: &str) -> Result<Option<String>> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+    for (prefix, suffix) in REF_RULES {
+        let refname = format!("{}{}{}", prefix, name, suffix);
+        // Outside `refs/`, only names like `HEAD` or `ORIG_HEAD` are refs.
+        let valid = if refname.starts_with("refs/") {
+            refs::is_valid_name(&refname)
+        } else {
+            refname.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+        };
+        if valid && refs::read_ref(&root, &refname)?.is_some() {
+            return Ok(Some(refname));
+        }
+    }
+    Ok(None)
+}
+
+/// Looks up `<name>@{<n>}`, where an empty name means the current branch.
+fn reflog_entry(root: &Path, name: &str, n: usize) -> Result<Option<Hash>> {
+    let refname = match name {
+        "" => refs::resolve_name(root, "HEAD")?.0,
+        name => match expand_ref(root, if name == "@" { "HEAD" } else { name })? {
+            Some(refname) => refname,
+            None => return Ok(None),
+        },
+    };
+    let log = refs::read_reflog(root, &refname)?;
+    if log.is_empty() {
+        return Ok(None);
+    }
+    match log.len().checked_sub(n + 1) {
+        Some(i) => Ok(Some(log[i])),
+        None => Err(Error::fatal(format!(
+            "log for '{}' only has {} entries",
+            refs::shorten(&refname),
+            log.len()
+        ))),
+    }
+}
+
+/// Applies a `^{...}` suffix. Reports a mismatched type the way git does and
+/// returns `None`.
+fn peel_suffix(root: &Path, hash: Hash, kind: &str, rev: &str) -> Result<Option<Hash>> {
+    if let Some(pattern) = kind.strip_prefix('/') {
+        return search_message(root, vec![hash], pattern);
+    }
+    match kind {
+        "object" => return Ok(Some(hash)),
+        "" | "tag" | "commit" | "tree" | "blob" => {}
+        _ => return Ok(None),
+    }
+    let (peeled, got) = peel(root, hash, kind)?;
+    if !kind.is_empty() && got != kind {
+        eprintln!(
+            "error: {}: expected {} type, but the object dereferences to {} type",
+            rev, kind, got
+        );
+        return Ok(None);
+    }
+    Ok(Some(peeled))
+}
+
+/// Dereferences tags, and commits to their tree unless `kind` is empty,
+/// until reaching an object of type `kind` or one that cannot be
+/// dereferenced further. Returns that object and its type.
+pub fn peel(root: impl AsRef<Path>, mut hash: Hash, kind: &str) -> Result<(Hash, &'static str)> {
+    loop {
+        let (got, content) = object::read_raw(&root, &hash.to_string())?;
+        let mut reader = BytesReader::new(&content);
+        match got {
+            _ if got == kind => return Ok((hash, got)),
+            "tag" => hash = Tag::parse(&mut reader)?.object,
+            "commit" if !kind.is_empty() => hash = Commit::parse(&mut reader)?.tree,
+            _ => return Ok((hash, got)),
+        }
+    }
+}
+
+/// Reads the commit `hash` refers to, peeling tags.
+fn read_commit(root: &Path, hash: Hash) -> Result<Option<(Hash, Commit)>> {
+    let (hash, kind) = peel(root, hash, "commit")?;
+    if kind != "commit" {
+        return Ok(None);
+    }
+    match Object::read(root, &hash.to_string())? {
+        Object::Commit(commit) => Ok(Some((hash, commit))),
+        _ => Ok(None),
+    }
+}
+
+/// `<rev>^<n>`: the n-th parent, or the commit itself for `n = 0`.
+fn nth_parent(root: &Path, hash: Hash, n: usize) -> Result<Option<Hash>> {
+    let Some((hash, commit)) = read_commit(root, hash)? else {
+        return Ok(None);
+    };
+    match n {
+        0 => Ok(Some(hash)),
+        n => Ok(commit.parents.get(n - 1).copied()),
+    }
+}
+
+/// `<rev>~<n>`: the n-th generation ancestor, following first parents.
+fn nth_ancestor(root: &Path, hash: Hash, n: usize) -> Result<Option<Hash>> {
+    let mut hash = hash;
+    for _ in 0..n {
+        match nth_parent(root, hash, 1)? {
+            Some(parent) => hash = parent,
+            None => return Ok(None),
+        }
+    }
+    nth_parent(root, hash, 0)
+}
+
+/// Walks the commits reachable from `starts`, youngest first, and returns
+/// the first whose message contains `pattern`. A pattern starting with `!-`
+/// matches messages that do not contain the rest, and `!!` stands for a
+/// literal `!`.
+fn search_message(root: &Path, starts: Vec<Hash>, pattern: &str) -> Result<Option<Hash>> {
+    let (pattern, negate) = match pattern.strip_prefix('!') {
+        Some(rest) if rest.starts_with('!') => (rest, false),
+        Some(rest) => match rest.strip_prefix('-') {
+            Some(rest) => (rest, true),
+            None => return Ok(None),
+        },
+        None => (pattern, false),
+    };
+
+    let mut walk = RevWalk::new(root);
+    for start in starts {
+        if read_commit(root, start)?.is_some() {
+            walk.push(start)?;
+        }
+    }
+    while let Some(walked) = walk.next()? {
+        if String::from_utf8_lossy(&walked.commit.message).contains(pattern) != negate {
+            return Ok(Some(walked.hash));
+        }
+    }
+    Ok(None)
+}
+
+/// Finds `path` below the tree `tree`, the empty path naming the tree
+/// itself.
+pub fn lookup_path(root: &Path, tree: Hash, path: &str) -> Result<Option<Hash>> {
+    let mut hash = tree;
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        let Object::TreeNode(tree) = Object::read(root, &hash.to_string())? else {
+            return Ok(None);
+        };
+        match tree.into_iter().find(|entry| entry.name == name) {
+            Some(entry) => hash = entry.hash,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(hash))
+}
+
+/// Looks up `<path>` or `<n>:<path>` in the index, `n` being the stage.
+fn lookup_index(root: &Path, path: &str) -> Result<Hash> {
+    let (stage, path) = match path.split_once(':') {
+        Some((stage, rest)) if stage.len() == 1 => match stage.parse::<u8>() {
+            Ok(stage) if stage <= 3 => (stage, rest),
+            _ => (0, path),
+        },
+        _ => (0, path),
+    };
+    let index = Index::read(root)?;
+    let entry = index
+        .entries()
+        .iter()
+        .find(|entry| entry.path == path && entry.stage == stage);
+    match entry {
+        Some(entry) => Ok(entry.hash),
+        None if root.join(path).exists() => Err(Error::fatal(format!(
+            "path '{}' exists on disk, but not in the index",
+            path
+        ))),
+        None => Err(Error::fatal(format!(
+            "path '{}' does not exist (neither on disk nor in the index)",
+            path
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{input_output, object, reference_impl, refs, test_utils};
+
+    use super::{expand_ref, resolve};
+
+    #[test]
+    fn test_resolve() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+
+        input_output::write(root.join("dir/file.txt"), "one").unwrap();
+        reference_impl::git_add_all(&repository);
+        refs::write_symbolic(&root, "HEAD", "refs/heads/main").unwrap();
+        let first = reference_impl::git_commit(&repository, "first commit", &[]);
+        reference_impl::git_update_ref(&repository, "refs/heads/main", &first);
+        input_output::write(root.join("dir/file.txt"), "two").unwrap();
+        reference_impl::git_add_all(&repository);
+        let second = reference_impl::git_commit(&repository, "second commit", &[&first]);
+        reference_impl::git_update_ref(&repository, "refs/heads/main", &second);
+        let side = reference_impl::git_write_object(
+            &repository,
+            "commit",
+            format!(
+                "tree {}\nauthor A <a@x> 1 +0000\ncommitter C <c@x> 1 +0000\n\nside\n",
+                reference_impl::git_write_tree(&repository)
+            ),
+        );
+        let merge = reference_impl::git_commit(&repository, "merge", &[&second, &side]);
+        reference_impl::git_update_ref(&repository, "refs/heads/main", &merge);
+        reference_impl::git_tag(&repository, "v1", &first, "release\n");
+
+        for rev in [
+            "HEAD",
+            "@",
+            "main",
+            "refs/heads/main",
+            "HEAD~",
+            "HEAD~2",
+            "HEAD^2",
+            "HEAD^^",
+            "HEAD~1^",
+            "v1",
+            "v1^{}",
+            "v1^{commit}",
+            "v1^{tree}",
+            "tags/v1",
+            "HEAD^{tree}",
+            "HEAD:dir",
+            "HEAD:dir/file.txt",
+            "HEAD~2:dir/file.txt",
+            "HEAD:",
+            ":/first",
+            "HEAD^{/second}",
+            "HEAD@{0}",
+            "HEAD@{2}",
+            "main@{1}",
+            &first[..7],
+            &merge,
+        ] {
+            assert_eq!(
+                resolve(&root, rev).unwrap().to_string(),
+                reference_impl::git_rev_parse(&repository, rev),
+                "{}",
+                rev
+            );
+        }
+        assert_eq!(resolve(&root, "HEAD^2").unwrap().to_string(), side);
+        assert_eq!(
+            resolve(&root, ":dir/file.txt").unwrap().to_string(),
+            reference_impl::git_index_hash(&repository, "dir/file.txt")
+        );
+        assert_eq!(
+            expand_ref(&root, "v1").unwrap().as_deref(),
+            Some("refs/tags/v1")
+        );
+
+        for rev in ["nope", "HEAD~5", "HEAD^3", "HEAD^{blob}", ":/absent", "zz"] {
+            let error = resolve(&root, rev).unwrap_err();
+            assert_eq!(
+                error.report().unwrap(),
+                format!("fatal: Not a valid object name {}", rev)
+            );
+        }
+        let error = resolve(&root, "HEAD:missing").unwrap_err();
+        assert_eq!(error.to_string(), "path 'missing' does not exist in 'HEAD'");
+        let error = resolve(&root, "HEAD@{9}").unwrap_err();
+        assert_eq!(error.to_string(), "log for 'HEAD' only has 3 entries");
+    }
+
+    #[test]
+    fn test_abbreviated() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+
+        // Blobs whose hashes share the prefix "8324".
+        let blobs: Vec<_> = ["142", "784"]
+            .iter()
+            .map(|content| reference_impl::git_write_object(&repository, "blob", content))
+            .collect();
+        assert_eq!(&blobs[0][..4], &blobs[1][..4]);
+
+        assert!(resolve(&root, &blobs[0][..4]).is_err());
+        let short = object::abbreviate(&root, &blobs[0].parse().unwrap(), 4).unwrap();
+        assert!(short.len() > 4);
+        assert_eq!(resolve(&root, &short).unwrap().to_string(), blobs[0]);
+        assert_eq!(
+            resolve(&root, &blobs[1][..12].to_uppercase())
+                .unwrap()
+                .to_string(),
+            blobs[1]
+        );
+    }
+}
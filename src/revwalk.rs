@@ -0,0 +1,281 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    commit::Commit,
+    error::{Error, Result},
+    hash::Hash,
+    object::Object,
+    revision,
+};
+
+/// A commit returned by [`RevWalk`].
+pub struct Walked {
+    pub hash: Hash,
+    pub commit: Commit,
+    /// The parents the walk continues with.
+    pub parents: Vec<Hash>,
+    /// Whether the commit changes nothing below the walk's paths, and so
+    /// is left out of path-limited history.
+    pub treesame: bool,
+}
+
+/// Walks the commits reachable from a set of starting points, youngest
+/// committer date first. Commits with the same date come out in the order
+/// they were reached, like git's default revision walk.
+pub struct RevWalk {
+    root: PathBuf,
+    queue: BinaryHeap<(i64, Reverse<usize>, Hash)>,
+    seen: HashSet<Hash>,
+    pushed: usize,
+    first_parent: bool,
+    paths: Vec<String>,
+    topo_order: bool,
+    /// With `topo_order`, the whole walk in reverse order once it started.
+    sorted: Option<Vec<Walked>>,
+}
+
+impl RevWalk {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            pushed: 0,
+            first_parent: false,
+            paths: vec![],
+            topo_order: false,
+            sorted: None,
+        }
+    }
+
+    /// Only follows the first parent of merges.
+    pub fn first_parent(mut self, first_parent: bool) -> Self {
+        self.first_parent = first_parent;
+        self
+    }
+
+    /// Limits history to the commits that change something below `paths`.
+    /// Like git's default history simplification, a merge that matches one
+    /// of its parents there is only followed through that parent.
+    pub fn paths(mut self, paths: Vec<String>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Never shows a commit before all of its children, as `--graph`
+    /// needs. Like git, this walks the whole history before returning the
+    /// first commit, and otherwise keeps branches together: the last parent
+    /// of a merge comes out first.
+    pub fn topo_order(mut self, topo_order: bool) -> Self {
+        self.topo_order = topo_order;
+        self
+    }
+
+    /// Adds a starting point, which must be a commit or a tag pointing at
+    /// one.
+    pub fn push(&mut self, hash: Hash) -> Result<()> {
+        let (hash, commit) = read_commit(&self.root, hash)?;
+        self.enqueue(hash, &commit);
+        Ok(())
+    }
+
+    fn enqueue(&mut self, hash: Hash, commit: &Commit) {
+        if self.seen.insert(hash) {
+            self.queue
+                .push((commit.committer.timestamp, Reverse(self.pushed), hash));
+            self.pushed += 1;
+        }
+    }
+
+    pub fn next(&mut self) -> Result<Option<Walked>> {
+        if !self.topo_order {
+            return self.next_by_date();
+        }
+        if self.sorted.is_none() {
+            let mut walked = vec![];
+            while let Some(commit) = self.next_by_date()? {
+                walked.push(commit);
+            }
+            let mut sorted = sort_topo(walked);
+            sorted.reverse();
+            self.sorted = Some(sorted);
+        }
+        Ok(self.sorted.as_mut().and_then(Vec::pop))
+    }
+
+    fn next_by_date(&mut self) -> Result<Option<Walked>> {
+        let Some((_, _, hash)) = self.queue.pop() else {
+            return Ok(None);
+        };
+        let (_, commit) = read_commit(&self.root, hash)?;
+        let mut parents = commit.parents.clone();
+        if self.first_parent {
+            parents.truncate(1);
+        }
+
+        let mut treesame = false;
+        if !self.paths.is_empty() {
+            if parents.is_empty() {
+                treesame = self.same_at_paths(Some(commit.tree), None)?;
+            }
+            for parent in parents.clone() {
+                let (_, parent_commit) = read_commit(&self.root, parent)?;
+                let tree = parent_commit.tree;
+                if self.same_at_paths(Some(commit.tree), Some(tree))? {
+                    treesame = true;
+                    parents = vec![parent];
+                    break;
+                }
+            }
+        }
+
+        for parent in &parents {
+            if !self.seen.contains(parent) {
+                let (_, commit) = read_commit(&self.root, *parent)?;
+                self.enqueue(*parent, &commit);
+            }
+        }
+        Ok(Some(Walked {
+            hash,
+            commit,
+            parents,
+            treesame,
+        }))
+    }
+
+    /// Whether two trees, `None` standing for the empty tree, have the same
+    /// entries at all of the walk's paths.
+    fn same_at_paths(&self, a: Option<Hash>, b: Option<Hash>) -> Result<bool> {
+        let lookup = |tree: Option<Hash>, path: &str| match tree {
+            Some(tree) => revision::lookup_path(&self.root, tree, path),
+            None => Ok(None),
+        };
+        for path in &self.paths {
+            if lookup(a, path)? != lookup(b, path)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Orders commits so that each comes before its parents, using a stack of
+/// the commits whose children have all been output, like git's
+/// `sort_in_topological_order`.
+fn sort_topo(walked: Vec<Walked>) -> Vec<Walked> {
+    let index: HashMap<Hash, usize> = walked
+        .iter()
+        .enumerate()
+        .map(|(i, commit)| (commit.hash, i))
+        .collect();
+    let mut children = vec![0; walked.len()];
+    for commit in &walked {
+        for parent in &commit.parents {
+            if let Some(&i) = index.get(parent) {
+                children[i] += 1;
+            }
+        }
+    }
+
+    let mut stack: Vec<usize> = (0..walked.len()).filter(|&i| children[i] == 0).collect();
+    stack.reverse();
+    let mut order = vec![];
+    while let Some(i) = stack.pop() {
+        for parent in &walked[i].parents {
+            if let Some(&j) = index.get(parent) {
+                children[j] -= 1;
+                if children[j] == 0 {
+                    stack.push(j);
+                }
+            }
+        }
+        order.push(i);
+    }
+
+    let mut walked: Vec<_> = walked.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| walked[i].take()).collect()
+}
+
+fn read_commit(root: &Path, hash: Hash) -> Result<(Hash, Commit)> {
+    let (hash, kind) = revision::peel(root, hash, "commit")?;
+    match Object::read(root, &hash.to_string())? {
+        Object::Commit(commit) => Ok((hash, commit)),
+        _ => Err(Error::fatal(format!(
+            "object {} is a {}, not a commit",
+            hash, kind
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        commit::Commit, hash::Hash, input_output, object::Object, reference_impl,
+        signature::Signature, test_utils,
+    };
+
+    use super::RevWalk;
+
+    #[test]
+    fn test_walk() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let commit = |timestamp: i64, parents: Vec<Hash>, file: &str| {
+            input_output::write(root.join(file), timestamp.to_string()).unwrap();
+            reference_impl::git_add_all(&repository);
+            let tree = reference_impl::git_write_tree(&repository).parse().unwrap();
+            let signature = Signature::new(
+                String::from("A"),
+                String::from("a@example.com"),
+                timestamp,
+                String::from("+0000"),
+            );
+            let commit = Commit::new(
+                tree,
+                parents,
+                signature.clone(),
+                signature,
+                timestamp.to_string(),
+            );
+            Object::Commit(commit).write(&root).unwrap()
+        };
+        let c1 = commit(1, vec![], "a");
+        let c2 = commit(2, vec![c1], "a");
+        let c3 = commit(4, vec![c2], "a");
+        // A side branch off c1 that only touches b.
+        input_output::write(root.join("a"), "1").unwrap();
+        let s1 = commit(3, vec![c1], "b");
+        let merge = commit(5, vec![c3, s1], "a");
+
+        let walk = |walk: RevWalk, shown_only: bool| {
+            let mut walk = walk;
+            walk.push(merge).unwrap();
+            let mut hashes = vec![];
+            while let Some(walked) = walk.next().unwrap() {
+                if !shown_only || !walked.treesame {
+                    hashes.push(walked.hash);
+                }
+            }
+            hashes
+        };
+
+        let by_date = walk(RevWalk::new(&root), false);
+        assert_eq!(by_date, [merge, c3, s1, c2, c1]);
+        let want = reference_impl::git_rev_list(&repository, &merge.to_string());
+        let got: Vec<_> = by_date.iter().map(Hash::to_string).collect();
+        assert_eq!(got, want);
+
+        let topo = walk(RevWalk::new(&root).topo_order(true), false);
+        assert_eq!(topo, [merge, s1, c3, c2, c1]);
+        let first_parent = walk(RevWalk::new(&root).first_parent(true), false);
+        assert_eq!(first_parent, [merge, c3, c2, c1]);
+        let paths = walk(RevWalk::new(&root).paths(vec![String::from("b")]), true);
+        assert_eq!(paths, [s1]);
+        let paths = walk(RevWalk::new(&root).paths(vec![String::from("a")]), true);
+        assert_eq!(paths, [merge, c3, c2, c1]);
+    }
+}
@@ -0,0 +1,406 @@
+use std::{env, fmt::Display, fs, path::Path};
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    /// The name and email as stored, which an `encoding` header may say are
+    /// not UTF-8.
+    pub name: Vec<u8>,
+    pub email: Vec<u8>,
+    pub timestamp: i64,
+    pub timezone: String,
+}
+
+impl Signature {
+    pub fn new(name: String, email: String, timestamp: i64, timezone: String) -> Self {
+        Self {
+            name: name.into_bytes(),
+            email: email.into_bytes(),
+            timestamp,
+            timezone,
+        }
+    }
+
+    pub fn new_current_time(name: String, email: String) -> Self {
+        let now = Local::now();
+        let timezone = format_offset(now.offset().local_minus_utc());
+        Self::new(name, email, now.timestamp(), timezone)
+    }
+
+    /// The author identity for a new commit in the repository at `root`.
+    pub fn author(root: impl AsRef<Path>) -> Result<Self> {
+        Self::from_identity("author", &Config::read(root)?, |var| env::var(var).ok())
+    }
+
+    /// The committer identity for a new commit in the repository at `root`.
+    pub fn committer(root: impl AsRef<Path>) -> Result<Self> {
+        Self::from_identity("committer", &Config::read(root)?, |var| env::var(var).ok())
+    }
+
+    /// Resolves the identity of `role` (`author` or `committer`) the way git
+    /// does: `GIT_<ROLE>_NAME`, `<role>.name`, `user.name`, and likewise for
+    /// the email, which also falls back to `EMAIL`. The date comes from
+    /// `GIT_<ROLE>_DATE` or the current time.
+    fn from_identity(
+        role: &str,
+        config: &Config,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let prefix = format!("GIT_{}", role.to_uppercase());
+        let lookup = |field: &str| {
+            var(&format!("{}_{}", prefix, field.to_uppercase()))
+                .or_else(|| config.get(&format!("{}.{}", role, field)).map(String::from))
+                .or_else(|| config.get(&format!("user.{}", field)).map(String::from))
+        };
+
+        let user = var("USER")
+            .or_else(|| var("LOGNAME"))
+            .unwrap_or_else(|| String::from("unknown"));
+        let name = lookup("name").unwrap_or_else(|| user.clone());
+        let email = lookup("email")
+            .or_else(|| var("EMAIL"))
+            .unwrap_or_else(|| format!("{}@{}", user, hostname()));
+        let name = sanitize(&name);
+        if name.is_empty() {
+            return Err(Error::fatal(format!(
+                "empty ident name (for <{}>) not allowed",
+                email
+            )));
+        }
+
+        let email = sanitize(&email);
+        match var(&format!("{}_DATE", prefix)) {
+            Some(date) => {
+                let (timestamp, timezone) = parse_date(&date)
+                    .ok_or_else(|| Error::fatal(format!("invalid date format: {}", date)))?;
+                Ok(Self::new(name, email, timestamp, timezone))
+            }
+            None => Ok(Self::new_current_time(name, email)),
+        }
+    }
+
+    /// Parses an identity line as found after `author `, `committer ` or
+    /// `tagger `: `Name <email> 1700000000 +0100`.
+    pub fn parse(line: &[u8]) -> Result<Self> {
+        let bad_ident = || {
+            let line = String::from_utf8_lossy(line);
+            Error::corrupt(format!("bad identity line '{}'", line))
+        };
+        let (name, rest) = split_once(line, b'<').ok_or_else(bad_ident)?;
+        let (email, rest) = split_once(rest, b'>').ok_or_else(bad_ident)?;
+        let (timestamp, timezone) =
+            split_once(rest.trim_ascii_start(), b' ').ok_or_else(bad_ident)?;
+        let timestamp = std::str::from_utf8(timestamp).map_err(|_| bad_ident())?;
+        let timezone = std::str::from_utf8(timezone).map_err(|_| bad_ident())?;
+        Ok(Self {
+            name: name.trim_ascii_end().to_vec(),
+            email: email.to_vec(),
+            timestamp: timestamp.parse().map_err(|_| bad_ident())?,
+            timezone: timezone.to_string(),
+        })
+    }
+
+    /// `Name <email>`, for display.
+    pub fn identity(&self) -> String {
+        format!(
+            "{} <{}>",
+            String::from_utf8_lossy(&self.name),
+            String::from_utf8_lossy(&self.email)
+        )
+    }
+
+    /// The signature as it is stored in a commit or tag.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.name.clone();
+        bytes.extend_from_slice(b" <");
+        bytes.extend_from_slice(&self.email);
+        bytes.extend_from_slice(format!("> {} {}", self.timestamp, self.timezone).as_bytes());
+        bytes
+    }
+
+    /// Formats the date like git's default date format,
+    /// `Thu Apr 7 22:13:13 2005 +0200`, in the signature's own zone.
+    pub fn format_date(&self) -> String {
+        let zone = parse_offset(&self.timezone)
+            .and_then(FixedOffset::east_opt)
+            .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+        match zone.timestamp_opt(self.timestamp, 0).single() {
+            Some(date) => format!("{} {}", date.format("%a %b %-d %H:%M:%S %Y"), self.timezone),
+            None => format!("{} {}", self.timestamp, self.timezone),
+        }
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.identity(),
+            self.timestamp,
+            self.timezone
+        )
+    }
+}
+
+/// The ways of writing a day that [`parse_date`] accepts.
+const DAY_FORMATS: [&str; 4] = ["%Y-%m-%d", "%Y.%m.%d", "%m/%d/%Y", "%d.%m.%Y"];
+
+/// Parses a date in one of the formats git accepts for `GIT_AUTHOR_DATE`
+/// and `GIT_COMMITTER_DATE`:
+///
+/// - git's internal format, `1112904793 +0200` or `@1112904793`
+/// - RFC 2822, `Thu, 07 Apr 2005 22:13:13 +0200`
+/// - ISO 8601, `2005-04-07T22:13:13`, with a space instead of the `T`, an
+///   optional zone and ignored fractional seconds. The date may also be
+///   written `2005.04.07`, `04/07/2005` or `07.04.2005`.
+///
+/// Dates without a zone are taken to be local time.
+pub fn parse_date(date: &str) -> Option<(i64, String)> {
+    let date = date.trim();
+    if let Some(raw) = parse_raw_date(date) {
+        return Some(raw);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(date) {
+        return Some(from_fixed(date));
+    }
+
+    let date = match date.strip_suffix('Z') {
+        Some(date) => format!("{}+0000", date),
+        None => date.to_string(),
+    };
+    for day in DAY_FORMATS {
+        for separator in ["T", " "] {
+            let naive = format!("{}{}%H:%M:%S%.f", day, separator);
+            for zone in ["%z", " %z"] {
+                if let Ok(date) = DateTime::parse_from_str(&date, &format!("{}{}", naive, zone)) {
+                    return Some(from_fixed(date));
+                }
+            }
+            if let Ok(date) = NaiveDateTime::parse_from_str(&date, &naive) {
+                let date = Local.from_local_datetime(&date).earliest()?;
+                return Some(from_fixed(date.fixed_offset()));
+            }
+        }
+    }
+    None
+}
+
+/// Parses a date the way options like `--since` take it: anything
+/// [`parse_date`] accepts, a day alone, taken as its start in local time, or
+/// a relative date like `2 weeks ago` or `3.days.ago`. Returns the
+/// timestamp.
+pub fn parse_approxidate(date: &str) -> Option<i64> {
+    if let Some((timestamp, _)) = parse_date(date) {
+        return Some(timestamp);
+    }
+    let date = date.trim();
+    for day in DAY_FORMATS {
+        if let Ok(day) = NaiveDate::parse_from_str(date, day) {
+            let midnight = Local.from_local_datetime(&day.and_hms_opt(0, 0, 0)?);
+            return midnight.earliest().map(|date| date.timestamp());
+        }
+    }
+
+    let words: Vec<_> = date
+        .split(|c: char| c.is_whitespace() || c == '.')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let [count, unit, "ago"] = words[..] else {
+        return None;
+    };
+    let count: i64 = count.parse().ok()?;
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Local::now().timestamp() - count * seconds)
+}
+
+/// Parses an expiry date like `prune --expire` takes it: `never` keeps
+/// everything, `now` and `all` expire everything, and anything else is a
+/// date for [`parse_approxidate`]. Returns the timestamp at and before which
+/// things expire.
+pub fn parse_expiry_date(date: &str) -> Option<i64> {
+    match date {
+        "never" | "false" => Some(0),
+        "now" | "all" => Some(i64::MAX),
+        _ => parse_approxidate(date),
+    }
+}
+
+fn parse_raw_date(date: &str) -> Option<(i64, String)> {
+    let (timestamp, zone) = match date.split_once(' ') {
+        Some((timestamp, zone)) => (timestamp, Some(zone.trim())),
+        None => (date, None),
+    };
+    let timestamp = match timestamp.strip_prefix('@') {
+        Some(timestamp) => timestamp,
+        // Without the `@`, only numbers too large to be a date are seconds.
+        None if timestamp.len() >= 9 => timestamp,
+        None => return None,
+    };
+    let timestamp = timestamp.parse().ok()?;
+    match zone {
+        Some(zone) if is_offset(zone) => Some((timestamp, zone.to_string())),
+        Some(_) => None,
+        None => Some((timestamp, String::from("+0000"))),
+    }
+}
+
+fn is_offset(zone: &str) -> bool {
+    zone.len() == 5 && zone.starts_with(['+', '-']) && zone[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Parses a `+hhmm` offset into seconds east of UTC.
+fn parse_offset(zone: &str) -> Option<i32> {
+    if !is_offset(zone) {
+        return None;
+    }
+    let hours: i32 = zone[1..3].parse().ok()?;
+    let minutes: i32 = zone[3..].parse().ok()?;
+    let seconds = hours * 3600 + minutes * 60;
+    Some(if zone.starts_with('-') {
+        -seconds
+    } else {
+        seconds
+    })
+}
+
+fn from_fixed(date: DateTime<FixedOffset>) -> (i64, String) {
+    (
+        date.timestamp(),
+        format_offset(date.offset().local_minus_utc()),
+    )
+}
+
+/// Formats an offset in seconds east of UTC as `+hhmm`.
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Strips the characters git refuses in identities: `<`, `>` and newlines
+/// anywhere, and leading or trailing punctuation and whitespace.
+/// Splits `bytes` around the first `c`.
+fn split_once(bytes: &[u8], c: u8) -> Option<(&[u8], &[u8])> {
+    let pos = bytes.iter().position(|&b| b == c)?;
+    Some((&bytes[..pos], &bytes[pos + 1..]))
+}
+
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| !matches!(c, '<' | '>' | '\n'))
+        .collect();
+    value
+        .trim_matches(|c: char| c.is_whitespace() || ".,:;\"'".contains(c))
+        .to_string()
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/etc/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("localhost"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{config::Config, input_output, test_utils};
+
+    use super::{parse_approxidate, parse_date, Signature};
+
+    #[test]
+    fn test_parse_date() {
+        let want = Some((1112904793, String::from("+0200")));
+        for date in [
+            "1112904793 +0200",
+            "@1112904793 +0200",
+            "Thu, 07 Apr 2005 22:13:13 +0200",
+            "2005-04-07T22:13:13+02:00",
+            "2005-04-07 22:13:13 +0200",
+            "2005-04-07T22:13:13.019+0200",
+            "2005.04.07 22:13:13 +0200",
+            "04/07/2005 22:13:13 +0200",
+            "07.04.2005 22:13:13 +0200",
+        ] {
+            assert_eq!(parse_date(date), want, "{}", date);
+        }
+        assert_eq!(
+            parse_date("2005-04-07T20:13:13Z"),
+            Some((1112904793, String::from("+0000")))
+        );
+        assert_eq!(parse_date("@0"), Some((0, String::from("+0000"))));
+        assert!(parse_date("2005-04-07 22:13:13").is_some());
+        assert_eq!(parse_date("yesterday"), None);
+        assert_eq!(parse_date("1112904793 CEST"), None);
+    }
+
+    #[test]
+    fn test_parse_approxidate() {
+        assert_eq!(parse_approxidate("@1112904793"), Some(1112904793));
+        let day = parse_approxidate("2005-04-07").unwrap();
+        // Midnight UTC, give or take the local zone.
+        assert!((1112832000 - 14 * 3600..=1112832000 + 12 * 3600).contains(&day));
+        let ago = parse_approxidate("2.weeks.ago").unwrap();
+        assert!((parse_approxidate("2 weeks ago").unwrap() - ago).abs() <= 1);
+        assert_eq!(parse_approxidate("2 fortnights ago"), None);
+    }
+
+    #[test]
+    fn test_format_date() {
+        let signature = Signature::parse(b"A <a@x> 1112904793 +0200").unwrap();
+        assert_eq!(signature.format_date(), "Thu Apr 7 22:13:13 2005 +0200");
+        let signature = Signature::parse(b"A <a@x> 1112904793 -0130").unwrap();
+        assert_eq!(signature.format_date(), "Thu Apr 7 18:43:13 2005 -0130");
+    }
+
+    #[test]
+    fn test_identity() {
+        let root = test_utils::create_test_dir();
+        let path = root.join("config");
+        input_output::write(
+            &path,
+            concat!(
+                "[user]\n",
+                "\tname = User Name\n",
+                "\temail = user@example.com\n",
+                "[committer]\n",
+                "\temail = committer@example.com\n",
+            ),
+        )
+        .unwrap();
+        let config = Config::read_file(path).unwrap();
+        let env = HashMap::from([
+            ("GIT_AUTHOR_NAME", "Author Name"),
+            ("GIT_AUTHOR_DATE", "1700000000 +0130"),
+        ]);
+        let var = |name: &str| env.get(name).map(|value| value.to_string());
+
+        let author = Signature::from_identity("author", &config, var).unwrap();
+        assert_eq!(
+            author.to_string(),
+            "Author Name <user@example.com> 1700000000 +0130"
+        );
+        let committer = Signature::from_identity("committer", &config, var).unwrap();
+        assert_eq!(committer.name, b"User Name");
+        assert_eq!(committer.email, b"committer@example.com");
+    }
+}
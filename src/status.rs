@@ -0,0 +1,545 @@
+use std::path::Path;
+
+use crate::{
+    error::Result,
+    hash::Hash,
+    object,
+    tree_diff::{Change, DiffEntry, Status},
+};
+
+/// The width of the labels in front of the paths of the long format.
+const LABEL_WIDTH: usize = 12;
+/// The same for unmerged paths.
+const UNMERGED_LABEL_WIDTH: usize = 17;
+
+/// What `status` shows and how.
+pub struct Options {
+    pub format: Format,
+    /// Shows the branch and how it compares with its upstream in the short
+    /// formats, as the long format always does.
+    pub branch: bool,
+    pub untracked: Untracked,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Long,
+    Short,
+    /// `--porcelain`, the short format without color and relative paths.
+    Porcelain,
+    /// `--porcelain=v2`, with the modes and hashes of every file.
+    PorcelainV2,
+}
+
+/// Which untracked files are listed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Untracked {
+    No,
+    /// Untracked directories as a whole.
+    Normal,
+    /// Every untracked file.
+    All,
+}
+
+impl Untracked {
+    /// Parses an `--untracked-files` value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "no" => Some(Self::No),
+            "normal" => Some(Self::Normal),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+}
+
+/// The branch HEAD is on.
+pub struct Branch {
+    /// The branch's short name, or `None` when HEAD is detached.
+    pub name: Option<String>,
+    /// The commit HEAD points at, or `None` before the first commit.
+    pub head: Option<Hash>,
+    pub upstream: Option<Upstream>,
+}
+
+/// The branch a branch is set to track.
+pub struct Upstream {
+    /// The upstream's short name, like `origin/main`.
+    pub name: String,
+    /// The number of commits only on the branch and only on the upstream,
+    /// or `None` if the upstream does not exist.
+    pub counts: Option<(usize, usize)>,
+}
+
+/// A path that shows up in `status`.
+pub enum Entry {
+    /// A tracked file that differs between HEAD and the index, or between
+    /// the index and the working tree.
+    Changed {
+        staged: Option<Change>,
+        unstaged: Option<Change>,
+    },
+    /// A file with conflicting versions in the index.
+    Unmerged {
+        path: String,
+        /// The mode and blob of the base, ours and theirs, where present.
+        stages: [Option<(u32, Hash)>; 3],
+        /// The working tree file's mode, 0 if it is missing.
+        worktree_mode: u32,
+    },
+}
+
+impl Entry {
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Changed { staged, unstaged } => {
+                staged.as_ref().or(unstaged.as_ref()).unwrap().path()
+            }
+            Self::Unmerged { path, .. } => path,
+        }
+    }
+
+    /// The two letters of the short format: the staged status and the
+    /// unstaged one, with `none` for no change.
+    fn codes(&self, none: char) -> String {
+        let code = |change: &Option<Change>| {
+            change
+                .as_ref()
+                .map_or(none, |change| change.status.code().chars().next().unwrap())
+        };
+        match self {
+            Self::Changed { staged, unstaged } => format!("{}{}", code(staged), code(unstaged)),
+            Self::Unmerged { stages, .. } => String::from(unmerged_state(stages).0),
+        }
+    }
+}
+
+/// The short format's letters for an unmerged file and its long format
+/// label, depending on which stages are present.
+fn unmerged_state(stages: &[Option<(u32, Hash)>; 3]) -> (&'static str, &'static str) {
+    match stages.each_ref().map(Option::is_some) {
+        [true, false, false] => ("DD", "both deleted:"),
+        [false, true, false] => ("AU", "added by us:"),
+        [true, true, false] => ("UD", "deleted by them:"),
+        [false, false, true] => ("UA", "added by them:"),
+        [true, false, true] => ("DU", "deleted by us:"),
+        [false, true, true] => ("AA", "both added:"),
+        _ => ("UU", "both modified:"),
+    }
+}
+
+/// Everything `status` reports, with the entries in path order.
+pub struct Report {
+    pub branch: Branch,
+    pub entries: Vec<Entry>,
+    pub untracked: Vec<String>,
+    /// Whether a merge is in progress.
+    pub merging: bool,
+}
+
+/// Formats a report, byte for byte like git.
+pub fn format(root: impl AsRef<Path>, report: &Report, options: &Options) -> Result<String> {
+    let mut lines = vec![];
+    match options.format {
+        Format::Long => format_long(root, report, options.untracked, &mut lines)?,
+        Format::Short | Format::Porcelain => {
+            if options.branch {
+                lines.push(format!("## {}", branch_summary(&report.branch)));
+            }
+            for entry in &report.entries {
+                let path = match entry {
+                    Entry::Changed {
+                        staged: Some(change @ Change { old: Some(old), .. }),
+                        ..
+                    } if matches!(change.status, Status::Renamed(_) | Status::Copied(_)) => {
+                        format!(
+                            "{} -> {}",
+                            quote_path(&old.path, true),
+                            quote_path(change.path(), true)
+                        )
+                    }
+                    entry => quote_path(entry.path(), true),
+                };
+                lines.push(format!("{} {}", entry.codes(' '), path));
+            }
+            for path in &report.untracked {
+                lines.push(format!("?? {}", quote_path(path, true)));
+            }
+        }
+        Format::PorcelainV2 => {
+            if options.branch {
+                format_branch_v2(&report.branch, &mut lines);
+            }
+            // Unmerged files come after the others.
+            let (unmerged, changed): (Vec<_>, Vec<_>) = report
+                .entries
+                .iter()
+                .partition(|entry| matches!(entry, Entry::Unmerged { .. }));
+            for entry in changed.into_iter().chain(unmerged) {
+                lines.push(format_entry_v2(entry));
+            }
+            for path in &report.untracked {
+                lines.push(format!("? {}", quote_path(path, false)));
+            }
+        }
+    }
+    Ok(lines.into_iter().map(|line| line + "\n").collect())
+}
+
+/// The branch line of the short format, without its `## `.
+fn branch_summary(branch: &Branch) -> String {
+    let Some(name) = &branch.name else {
+        return String::from("HEAD (no branch)");
+    };
+    if branch.head.is_none() {
+        return format!("No commits yet on {}", name);
+    }
+    let Some(upstream) = &branch.upstream else {
+        return name.clone();
+    };
+    let counts = match upstream.counts {
+        None => String::from(" [gone]"),
+        Some((0, 0)) => String::new(),
+        Some((ahead, 0)) => format!(" [ahead {}]", ahead),
+        Some((0, behind)) => format!(" [behind {}]", behind),
+        Some((ahead, behind)) => format!(" [ahead {}, behind {}]", ahead, behind),
+    };
+    format!("{}...{}{}", name, upstream.name, counts)
+}
+
+fn format_branch_v2(branch: &Branch, lines: &mut Vec<String>) {
+    let oid = branch
+        .head
+        .map_or(String::from("(initial)"), |head| head.to_string());
+    lines.push(format!("# branch.oid {}", oid));
+    let name = branch.name.as_deref().unwrap_or("(detached)");
+    lines.push(format!("# branch.head {}", name));
+    if let Some(upstream) = &branch.upstream {
+        lines.push(format!("# branch.upstream {}", upstream.name));
+        if let Some((ahead, behind)) = upstream.counts {
+            lines.push(format!("# branch.ab +{} -{}", ahead, behind));
+        }
+    }
+}
+
+fn format_entry_v2(entry: &Entry) -> String {
+    let side = |entry: Option<&DiffEntry>| entry.map_or((0, Hash::zero()), |e| (e.mode, e.hash));
+    match entry {
+        Entry::Changed { staged, unstaged } => {
+            // Without a staged change, HEAD has what the index has.
+            let ((head_mode, head_hash), (index_mode, index_hash)) = match staged {
+                Some(change) => (side(change.old.as_ref()), side(change.new.as_ref())),
+                None => {
+                    let index = side(unstaged.as_ref().and_then(|c| c.old.as_ref()));
+                    (index, index)
+                }
+            };
+            let worktree_mode = match unstaged {
+                Some(change) => side(change.new.as_ref()).0,
+                None => index_mode,
+            };
+            let fields = format!(
+                "{} N... {:06o} {:06o} {:06o} {} {}",
+                entry.codes('.'),
+                head_mode,
+                index_mode,
+                worktree_mode,
+                head_hash,
+                index_hash
+            );
+            match staged {
+                Some(Change {
+                    status: status @ (Status::Renamed(_) | Status::Copied(_)),
+                    old: Some(old),
+                    ..
+                }) => format!(
+                    "2 {} {} {}\t{}",
+                    fields,
+                    status.code(),
+                    quote_path(entry.path(), false),
+                    quote_path(&old.path, false)
+                ),
+                _ => format!("1 {} {}", fields, quote_path(entry.path(), false)),
+            }
+        }
+        Entry::Unmerged {
+            path,
+            stages,
+            worktree_mode,
+        } => {
+            let stage = |i: usize| stages[i].unwrap_or((0, Hash::zero()));
+            let [(m1, h1), (m2, h2), (m3, h3)] = [stage(0), stage(1), stage(2)];
+            format!(
+                "u {} N... {:06o} {:06o} {:06o} {:06o} {} {} {} {}",
+                entry.codes('.'),
+                m1,
+                m2,
+                m3,
+                worktree_mode,
+                h1,
+                h2,
+                h3,
+                quote_path(path, false)
+            )
+        }
+    }
+}
+
+fn format_long(
+    root: impl AsRef<Path>,
+    report: &Report,
+    untracked: Untracked,
+    lines: &mut Vec<String>,
+) -> Result<()> {
+    let branch = &report.branch;
+    match (&branch.name, branch.head) {
+        (Some(name), _) => lines.push(format!("On branch {}", name)),
+        (None, Some(head)) => lines.push(format!(
+            "HEAD detached at {}",
+            object::abbreviate(&root, &head, 7)?
+        )),
+        (None, None) => lines.push(String::from("Not currently on any branch.")),
+    }
+    let is_initial = branch.head.is_none();
+    match &branch.upstream {
+        Some(upstream) if !is_initial => {
+            lines.extend(tracking_lines(upstream));
+            lines.push(String::new());
+        }
+        _ => {}
+    }
+    let has_unmerged = report
+        .entries
+        .iter()
+        .any(|entry| matches!(entry, Entry::Unmerged { .. }));
+    if report.merging {
+        match has_unmerged {
+            true => lines.extend(
+                [
+                    "You have unmerged paths.",
+                    "  (fix conflicts and run \"git commit\")",
+                    "  (use \"git merge --abort\" to abort the merge)",
+                ]
+                .map(String::from),
+            ),
+            false => lines.extend(
+                [
+                    "All conflicts fixed but you are still merging.",
+                    "  (use \"git commit\" to conclude merge)",
+                ]
+                .map(String::from),
+            ),
+        }
+        lines.push(String::new());
+    }
+    if is_initial {
+        lines.push(String::new());
+        lines.push(String::from("No commits yet"));
+        lines.push(String::new());
+    }
+
+    let mut staged = vec![];
+    let mut unstaged = vec![];
+    let mut unmerged = vec![];
+    for entry in &report.entries {
+        match entry {
+            Entry::Changed {
+                staged: staged_change,
+                unstaged: unstaged_change,
+            } => {
+                staged.extend(staged_change);
+                unstaged.extend(unstaged_change);
+            }
+            Entry::Unmerged { path, stages, .. } => unmerged.push((path, stages)),
+        }
+    }
+
+    // While merging, there is no commit to go back to.
+    let unstage_hint = match (report.merging, is_initial) {
+        (true, _) => None,
+        (false, true) => Some("  (use \"git rm --cached <file>...\" to unstage)"),
+        (false, false) => Some("  (use \"git restore --staged <file>...\" to unstage)"),
+    };
+    if !staged.is_empty() {
+        lines.push(String::from("Changes to be committed:"));
+        lines.extend(unstage_hint.map(String::from));
+        lines.extend(staged.iter().map(|change| format_change(change)));
+        lines.push(String::new());
+    }
+    if !unmerged.is_empty() {
+        lines.push(String::from("Unmerged paths:"));
+        lines.extend(unstage_hint.map(String::from));
+        let states: Vec<_> = unmerged
+            .iter()
+            .map(|(_, stages)| unmerged_state(stages).0)
+            .collect();
+        let both_deleted = states.contains(&"DD");
+        let del_mod_conflict = states.iter().any(|state| matches!(*state, "UD" | "DU"));
+        lines.push(String::from(match (both_deleted, del_mod_conflict) {
+            (_, true) => "  (use \"git add/rm <file>...\" as appropriate to mark resolution)",
+            (true, false) => "  (use \"git rm <file>...\" to mark resolution)",
+            (false, false) => "  (use \"git add <file>...\" to mark resolution)",
+        }));
+        for (path, stages) in &unmerged {
+            let label = unmerged_state(stages).1;
+            lines.push(format!(
+                "\t{:<UNMERGED_LABEL_WIDTH$}{}",
+                label,
+                quote_path(path, false)
+            ));
+        }
+        lines.push(String::new());
+    }
+    if !unstaged.is_empty() {
+        lines.push(String::from("Changes not staged for commit:"));
+        let has_deleted = unstaged.iter().any(|c| c.status == Status::Deleted);
+        lines.push(String::from(match has_deleted {
+            true => "  (use \"git add/rm <file>...\" to update what will be committed)",
+            false => "  (use \"git add <file>...\" to update what will be committed)",
+        }));
+        lines.push(String::from(
+            "  (use \"git restore <file>...\" to discard changes in working directory)",
+        ));
+        lines.extend(unstaged.iter().map(|change| format_change(change)));
+        lines.push(String::new());
+    }
+    if untracked != Untracked::No && !report.untracked.is_empty() {
+        lines.push(String::from("Untracked files:"));
+        lines.push(String::from(
+            "  (use \"git add <file>...\" to include in what will be committed)",
+        ));
+        for path in &report.untracked {
+            lines.push(format!("\t{}", quote_path(path, false)));
+        }
+        lines.push(String::new());
+    } else if untracked == Untracked::No && !staged.is_empty() {
+        lines.push(String::from(
+            "Untracked files not listed (use -u option to show untracked files)",
+        ));
+    }
+
+    if staged.is_empty() {
+        let workdir_dirty = !unstaged.is_empty() || !unmerged.is_empty();
+        lines.push(String::from(if workdir_dirty {
+            "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+        } else if !report.untracked.is_empty() {
+            "nothing added to commit but untracked files present (use \"git add\" to track)"
+        } else if is_initial {
+            "nothing to commit (create/copy files and use \"git add\" to track)"
+        } else if untracked == Untracked::No {
+            "nothing to commit (use -u to show untracked files)"
+        } else {
+            "nothing to commit, working tree clean"
+        }));
+    }
+    Ok(())
+}
+
+/// Describes how a branch compares with its upstream.
+pub fn tracking_lines(upstream: &Upstream) -> Vec<String> {
+    let name = &upstream.name;
+    let plural = |n: usize| if n == 1 { "commit" } else { "commits" };
+    let lines = match upstream.counts {
+        None => vec![
+            format!(
+                "Your branch is based on '{}', but the upstream is gone.",
+                name
+            ),
+            String::from("  (use \"git branch --unset-upstream\" to fixup)"),
+        ],
+        Some((0, 0)) => vec![format!("Your branch is up to date with '{}'.", name)],
+        Some((ahead, 0)) => vec![
+            format!(
+                "Your branch is ahead of '{}' by {} {}.",
+                name,
+                ahead,
+                plural(ahead)
+            ),
+            String::from("  (use \"git push\" to publish your local commits)"),
+        ],
+        Some((0, behind)) => vec![
+            format!(
+                "Your branch is behind '{}' by {} {}, and can be fast-forwarded.",
+                name,
+                behind,
+                plural(behind)
+            ),
+            String::from("  (use \"git pull\" to update your local branch)"),
+        ],
+        Some((ahead, behind)) => vec![
+            format!("Your branch and '{}' have diverged,", name),
+            format!(
+                "and have {} and {} different commits each, respectively.",
+                ahead, behind
+            ),
+            String::from("  (use \"git pull\" to merge the remote branch into yours)"),
+        ],
+    };
+    lines
+}
+
+/// A line of the long format's lists of changes.
+fn format_change(change: &Change) -> String {
+    let label = match change.status {
+        Status::Added => "new file:",
+        Status::Deleted => "deleted:",
+        Status::Modified => "modified:",
+        Status::TypeChanged => "typechange:",
+        Status::Renamed(_) => "renamed:",
+        Status::Copied(_) => "copied:",
+    };
+    let path = match (&change.status, &change.old) {
+        (Status::Renamed(_) | Status::Copied(_), Some(old)) => format!(
+            "{} -> {}",
+            quote_path(&old.path, false),
+            quote_path(change.path(), false)
+        ),
+        _ => quote_path(change.path(), false),
+    };
+    format!("\t{:<LABEL_WIDTH$}{}", label, path)
+}
+
+/// Quotes a path the way git shows it: in double quotes with C-style
+/// escapes if it has control characters, quotes, backslashes or non-ASCII
+/// bytes, or with `quote_space` set, spaces.
+pub fn quote_path(path: &str, quote_space: bool) -> String {
+    let needs_quotes = path.bytes().any(|b| {
+        !(0x20..0x7f).contains(&b) || b == b'"' || b == b'\\' || (quote_space && b == b' ')
+    });
+    if !needs_quotes {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'\x07' => quoted.push_str("\\a"),
+            b'\x08' => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\x0b' => quoted.push_str("\\v"),
+            b'\x0c' => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{:03o}", b)),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quote_path;
+
+    #[test]
+    fn test_quote_path() {
+        assert_eq!(quote_path("plain/path", true), "plain/path");
+        assert_eq!(quote_path("sp ace", false), "sp ace");
+        assert_eq!(quote_path("sp ace", true), "\"sp ace\"");
+        assert_eq!(quote_path("tab\there", false), "\"tab\\there\"");
+        assert_eq!(quote_path("q\"b\\", false), "\"q\\\"b\\\\\"");
+        assert_eq!(quote_path("caf\u{e9}", false), "\"caf\\303\\251\"");
+    }
+}
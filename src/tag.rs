@@ -0,0 +1,245 @@
+use std::fmt::Display;
+
+use crate::{
+    bytes_reader::BytesReader,
+    codec,
+    commit::{parse_hash, parse_headers, push_header},
+    error::{Error, Result},
+    hash::Hash,
+    object::Header,
+    signature::Signature,
+};
+
+/// Lines that start the signature git appends to a signed tag's message.
+const SIGNATURE_STARTS: [&str; 3] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN PGP MESSAGE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+];
+
+/// What `tag` does.
+pub enum Action {
+    List {
+        patterns: Vec<String>,
+    },
+    Create {
+        name: String,
+        target: Option<String>,
+        annotate: bool,
+        messages: Vec<String>,
+        file: Option<String>,
+        force: bool,
+    },
+    Delete {
+        names: Vec<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Tag {
+    pub object: Hash,
+    /// The type of the tagged object.
+    pub kind: String,
+    pub name: String,
+    /// Missing in some tags created by very old versions of git.
+    pub tagger: Option<Signature>,
+    /// Headers following `tagger`, in their original order.
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    /// The message as stored, in whatever `encoding` names.
+    pub message: Vec<u8>,
+    /// The signature following the message, starting at its `-----BEGIN`
+    /// line.
+    pub signature: Option<Vec<u8>>,
+}
+
+impl Tag {
+    pub fn new(
+        object: Hash,
+        kind: String,
+        name: String,
+        tagger: Signature,
+        message: String,
+    ) -> Self {
+        Self {
+            object,
+            kind,
+            name,
+            tagger: Some(tagger),
+            extra_headers: vec![],
+            message: message.into_bytes(),
+            signature: None,
+        }
+    }
+
+    pub fn parse(reader: &mut BytesReader) -> Result<Self> {
+        let corrupt = |message: &str| Error::corrupt(format!("corrupt tag: {}", message));
+        let (fields, message) = parse_headers(reader.read_all(), "tag")?;
+
+        let mut object = None;
+        let mut kind = None;
+        let mut name = None;
+        let mut tagger = None;
+        let mut extra_headers = vec![];
+        let text = |value: Vec<u8>, header: &str| {
+            String::from_utf8(value).map_err(|_| corrupt(&format!("invalid {}", header)))
+        };
+        for (key, value) in fields {
+            match key {
+                "object" => object = Some(parse_hash(&value)?),
+                "type" => kind = Some(text(value, "type")?),
+                "tag" => name = Some(text(value, "tag name")?),
+                "tagger" => tagger = Some(Signature::parse(&value)?),
+                _ => extra_headers.push((key.to_string(), value)),
+            }
+        }
+
+        let (message, signature) = split_signature(message);
+        Ok(Self {
+            object: object.ok_or_else(|| corrupt("missing object"))?,
+            kind: kind.ok_or_else(|| corrupt("missing type"))?,
+            name: name.ok_or_else(|| corrupt("missing tag name"))?,
+            tagger,
+            extra_headers,
+            message: message.to_vec(),
+            signature: signature.map(<[u8]>::to_vec),
+        })
+    }
+
+    /// The tag as stored, without the object header.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = format!(
+            "object {}\ntype {}\ntag {}\n",
+            self.object, self.kind, self.name
+        )
+        .into_bytes();
+        if let Some(tagger) = &self.tagger {
+            push_header(&mut payload, "tagger", &tagger.to_bytes());
+        }
+        for (key, value) in &self.extra_headers {
+            push_header(&mut payload, key, value);
+        }
+
+        payload.push(b'\n');
+        payload.extend_from_slice(&self.message);
+        payload.extend_from_slice(self.signature.as_deref().unwrap_or_default());
+        payload
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let payload = self.payload();
+
+        let mut bytes = Header::new("tag", payload.len()).encode();
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    pub fn encode(&self) -> (Hash, Vec<u8>) {
+        let bytes = self.serialize();
+        let hash = Hash::hash(&bytes);
+        let encoded = codec::compress(&bytes);
+        (hash, encoded)
+    }
+}
+
+/// Splits a message at the last line that starts a signature, like git's
+/// `parse_signed_buffer`.
+fn split_signature(message: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let mut start = None;
+    let mut pos = 0;
+    for line in message.split_inclusive(|&b| b == b'\n') {
+        if SIGNATURE_STARTS
+            .iter()
+            .any(|s| line.starts_with(s.as_bytes()))
+        {
+            start = Some(pos);
+        }
+        pos += line.len();
+    }
+    match start {
+        Some(start) => (&message[..start], Some(&message[start..])),
+        None => (message, None),
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.payload()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{codec, input_output, object::Object, reference_impl, test_utils};
+
+    #[test]
+    fn test_read_tag() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+
+        input_output::write(root.join("hello.txt"), "Hello World!").unwrap();
+        reference_impl::git_add_all(&repository);
+        let commit = reference_impl::git_commit(&repository, "first", &[]);
+        let hash = reference_impl::git_tag(&repository, "v1.0", &commit, "release\n\nnotes\n");
+
+        let tag = Object::read(&root, &hash).unwrap().into_tag();
+        assert_eq!(tag.object.to_string(), commit);
+        assert_eq!(tag.kind, "commit");
+        assert_eq!(tag.name, "v1.0");
+        assert_eq!(tag.tagger.as_ref().unwrap().name, b"Tagger Name");
+        assert_eq!(tag.message, b"release\n\nnotes\n");
+        assert_eq!(tag.signature, None);
+
+        let want = codec::decompress(&input_output::read_obj(&root, &hash).unwrap()).unwrap();
+        assert_eq!(tag.serialize(), want);
+    }
+
+    #[test]
+    fn test_read_signed_tag_round_trip() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+
+        input_output::write(root.join("hello.txt"), "Hello World!").unwrap();
+        reference_impl::git_add_all(&repository);
+        let commit = reference_impl::git_commit(&repository, "first", &[]);
+        let signature =
+            "-----BEGIN PGP SIGNATURE-----\n\niQEzBAAB\n=abcd\n-----END PGP SIGNATURE-----\n";
+        let hash = reference_impl::git_write_object(
+            &repository,
+            "tag",
+            format!(
+                "object {}\ntype commit\ntag signed\ntagger T <t@example.com> 1700000000 +0000\n\n\
+                 message\n{}",
+                commit, signature
+            ),
+        );
+
+        let tag = Object::read(&root, &hash).unwrap().into_tag();
+        assert_eq!(tag.message, b"message\n");
+        assert_eq!(tag.signature.as_deref(), Some(signature.as_bytes()));
+        let want = codec::decompress(&input_output::read_obj(&root, &hash).unwrap()).unwrap();
+        assert_eq!(tag.serialize(), want);
+    }
+
+    #[test]
+    fn test_read_tag_not_utf8() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+
+        input_output::write(root.join("hello.txt"), "Hello World!").unwrap();
+        reference_impl::git_add_all(&repository);
+        let commit = reference_impl::git_commit(&repository, "first", &[]);
+        let mut payload = format!("object {}\ntype commit\ntag v1\n", commit).into_bytes();
+        payload.extend_from_slice(
+            b"tagger Jos\xe9 <j@x> 1700000000 +0000\n\
+              encoding ISO-8859-1\n\
+              \n\
+              Caf\xe9\r\n",
+        );
+        let hash = reference_impl::git_write_object(&repository, "tag", &payload);
+
+        let tag = Object::read(&root, &hash).unwrap().into_tag();
+        assert_eq!(tag.tagger.as_ref().unwrap().name, b"Jos\xe9");
+        assert_eq!(tag.message, b"Caf\xe9\r\n");
+        assert_eq!(tag.payload(), payload);
+    }
+}
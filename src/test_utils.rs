@@ -1,9 +1,13 @@
 use std::{
-    fs,
+    env, fs,
     path::{Path, PathBuf},
+    sync::Once,
+    thread,
 };
 
-fn get_test_dir() -> PathBuf {
+use git2::ConfigLevel;
+
+fn get_tests_root() -> PathBuf {
     let manifest_dir = env!("CARGO_MANIFEST_DIR");
     let pkg_name = env!("CARGO_PKG_NAME");
     Path::new(manifest_dir)
@@ -12,9 +16,41 @@ fn get_test_dir() -> PathBuf {
         .join(format!("{}-tests", pkg_name))
 }
 
+fn get_test_dir() -> PathBuf {
+    // The test harness names each thread after its test, which gives every
+    // test its own directory when they run in parallel.
+    let test_name = thread::current()
+        .name()
+        .unwrap_or("main")
+        .replace("::", "-");
+    get_tests_root().join(test_name)
+}
+
+/// Keeps the global and system config of whoever runs the tests, and the
+/// ignore file next to it, away from both our code and libgit2.
+fn isolate_config() {
+    static ISOLATE: Once = Once::new();
+    ISOLATE.call_once(|| {
+        let empty = get_tests_root().join("empty-config");
+        fs::create_dir_all(&empty).unwrap();
+        env::set_var("GIT_CONFIG_GLOBAL", "/dev/null");
+        env::set_var("GIT_CONFIG_NOSYSTEM", "1");
+        env::set_var("XDG_CONFIG_HOME", &empty);
+        for level in [
+            ConfigLevel::System,
+            ConfigLevel::XDG,
+            ConfigLevel::Global,
+            ConfigLevel::ProgramData,
+        ] {
+            unsafe { git2::opts::set_search_path(level, &empty) }.unwrap();
+        }
+    });
+}
+
 pub fn create_test_dir() -> PathBuf {
+    isolate_config();
     let root = get_test_dir();
     let _ = fs::remove_dir_all(&root);
-    fs::create_dir(&root).unwrap();
+    fs::create_dir_all(&root).unwrap();
     root
 }
@@ -1,87 +1,170 @@
-use std::{fs, path::Path};
+use std::path::Path;
 
 use crate::{
-    blob::Blob,
+    error::{Error, Result},
+    file_mode::FileMode,
     hash::Hash,
-    input_output,
+    index::{Index, IndexEntry},
     object::Object,
-    tree_node::{TreeNode, TreeNodeEntry},
+    tree_node::{TreeBuilder, TreeNodeEntry},
+    worktree,
 };
 
-fn build_tree(path: impl AsRef<Path>) -> Vec<Object> {
+/// Builds the trees for a sorted run of index entries that all start with
+/// `prefix`. The tree for `prefix` itself comes last.
+fn build_tree(entries: &[IndexEntry], prefix: &str) -> Result<Vec<Object>> {
     let mut tree = vec![];
-    let mut children = vec![];
-    for path in input_output::read_dir_sorted(path) {
-        let tree_node_entry = if path.is_dir() {
-            if is_dot_git(&path) {
-                continue;
+    let mut children = TreeBuilder::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let entry = &entries[i];
+        let rest = &entry.path[prefix.len()..];
+        let tree_node_entry = match rest.split_once('/') {
+            Some((dir, _)) => {
+                let dir_prefix = format!("{}{}/", prefix, dir);
+                let len = entries[i..]
+                    .iter()
+                    .take_while(|e| e.path.starts_with(&dir_prefix))
+                    .count();
+                tree.append(&mut build_tree(&entries[i..i + len], &dir_prefix)?);
+                i += len;
+                let hash = tree.last().unwrap().hash();
+                TreeNodeEntry::new(FileMode::Tree, dir.to_string(), hash)
+            }
+            None => {
+                i += 1;
+                let mode = FileMode::from_bits(entry.mode)?;
+                TreeNodeEntry::new(mode, rest.to_string(), entry.hash)
             }
-            tree.append(&mut build_tree(&path));
-            let mode = String::from("40000");
-            let name = input_output::basename(&path);
-            let hash = tree.last().unwrap().hash();
-            TreeNodeEntry::new(mode, name, hash)
-        } else {
-            let content = fs::read(&path).unwrap();
-            let obj = Object::Blob(Blob::new(content));
-            let hash = obj.hash();
-            tree.push(obj);
-
-            let mode = format!("{:o}", input_output::get_mode(&path));
-            let name = input_output::basename(&path);
-            TreeNodeEntry::new(mode, name, hash)
         };
-        children.push(tree_node_entry);
+        children.insert(tree_node_entry)?;
     }
-    tree.push(Object::TreeNode(TreeNode::new(children)));
-    tree
+    tree.push(Object::TreeNode(children.build()));
+    Ok(tree)
 }
 
-pub fn write_tree(root: impl AsRef<Path>) -> Option<Hash> {
-    let mut tree = build_tree(&root);
-    let Some(root_node) = tree.pop() else {
-        return None;
-    };
+/// Writes the trees for the current index and returns the root tree's hash.
+pub fn write_tree(root: impl AsRef<Path>) -> Result<Hash> {
+    let index = Index::read(&root)?;
+    if let Some(entry) = index.entries().iter().find(|e| e.stage != 0) {
+        eprintln!("error: {}: unmerged ({})", entry.path, entry.hash);
+        return Err(Error::fatal("git-write-tree: error building trees"));
+    }
+    // Intent-to-add entries have no content yet and are left out.
+    let entries: Vec<_> = index
+        .entries()
+        .iter()
+        .filter(|e| !e.intent_to_add)
+        .cloned()
+        .collect();
+
+    write_entries(root, &entries)
+}
+
+/// Writes the trees for `entries`, which must be sorted like the index
+/// and all at stage 0, and returns the root tree's hash.
+pub fn write_entries(root: impl AsRef<Path>, entries: &[IndexEntry]) -> Result<Hash> {
+    let mut tree = build_tree(entries, "")?;
+    let root_node = tree.pop().unwrap();
     for obj in tree {
-        obj.write(&root);
+        obj.write(&root)?;
+    }
+    root_node.write(&root)
+}
+
+/// Fails on an entry of tree `hash` or its subtrees that would be written
+/// outside of the working tree or into the repository itself, like git's
+/// `verify_path`: one named `.`, `..` or `.git` in any case, or whose name
+/// is empty or holds a slash.
+pub fn verify_tree(root: impl AsRef<Path>, hash: &Hash) -> Result<()> {
+    verify_tree_at(root.as_ref(), hash, "")
+}
+
+fn verify_tree_at(root: &Path, hash: &Hash, prefix: &str) -> Result<()> {
+    let Object::TreeNode(tree) = Object::read(root, &hash.to_string())? else {
+        return Err(Error::fatal(format!("{} is not a tree object", hash)));
+    };
+    for entry in &tree {
+        let name = &entry.name;
+        let path = format!("{}{}", prefix, name);
+        if name.is_empty()
+            || name == "."
+            || name == ".."
+            || name.eq_ignore_ascii_case(".git")
+            || name.contains('/')
+        {
+            return Err(Error::fatal(format!("invalid path '{}'", path)));
+        }
+        if entry.mode == FileMode::Tree {
+            verify_tree_at(root, &entry.hash, &format!("{}/", path))?;
+        }
     }
-    Some(root_node.write(&root))
+    Ok(())
 }
 
-fn is_dot_git(path: impl AsRef<Path>) -> bool {
-    input_output::basename(path) == ".git"
+/// Writes the files of tree `hash` into the working directory and returns an
+/// index describing them. Nothing is written if the tree has a path that
+/// `verify_tree` rejects.
+pub fn checkout_tree(root: impl AsRef<Path>, hash: &Hash) -> Result<Index> {
+    let root = root.as_ref();
+    verify_tree(root, hash)?;
+    let mut index = Index::new();
+    checkout_tree_at(root, hash, "", &mut index)?;
+    Ok(index)
+}
+
+fn checkout_tree_at(root: &Path, hash: &Hash, prefix: &str, index: &mut Index) -> Result<()> {
+    let Object::TreeNode(tree) = Object::read(root, &hash.to_string())? else {
+        return Err(Error::fatal(format!("{} is not a tree object", hash)));
+    };
+    for entry in &tree {
+        let name = format!("{}{}", prefix, entry.name);
+        match entry.mode {
+            FileMode::Tree => checkout_tree_at(root, &entry.hash, &format!("{}/", name), index)?,
+            mode => index.add(worktree::checkout_file(
+                root,
+                &name,
+                mode.canonical().bits(),
+                entry.hash,
+            )?),
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        input_output, reference_impl,
+        blob::Blob,
+        file_mode::FileMode,
+        hash::Hash,
+        index::Index,
+        input_output,
+        object::Object,
+        reference_impl,
         repo::Repo,
         test_utils,
-        tree::{build_tree, write_tree},
+        tree::{build_tree, checkout_tree, write_tree},
+        tree_diff,
+        tree_node::{TreeBuilder, TreeNode, TreeNodeEntry},
     };
 
     #[test]
     fn test_build_tree() {
         let root = test_utils::create_test_dir();
+        let repo = Repo::new(&root);
+        repo.init().unwrap();
 
         let contents = "";
-        input_output::write(root.join("file1"), contents);
-        input_output::write(root.join("dir1/file_in_dir_1"), contents);
-        input_output::write(root.join("dir1/file_in_dir_2"), contents);
-        input_output::write(root.join("dir2/file_in_dir_3"), contents);
-
-        let tree = build_tree(&root);
-        assert_eq!(tree.len(), 7);
+        input_output::write(root.join("file1"), contents).unwrap();
+        input_output::write(root.join("dir1/file_in_dir_1"), contents).unwrap();
+        input_output::write(root.join("dir1/file_in_dir_2"), contents).unwrap();
+        input_output::write(root.join("dir2/file_in_dir_3"), contents).unwrap();
+        repo.add(vec![], true, false, false).unwrap();
 
-        assert_eq!(
-            tree.iter().filter(|obj| obj.get_type() == "file").count(),
-            4
-        );
-        assert_eq!(
-            tree.iter().filter(|obj| obj.get_type() == "tree").count(),
-            3
-        );
+        let tree = build_tree(Index::read(&root).unwrap().entries(), "").unwrap();
+        assert_eq!(tree.len(), 3);
+        assert!(tree.iter().all(|obj| matches!(obj, Object::TreeNode(_))));
     }
 
     #[test]
@@ -91,25 +174,138 @@ mod tests {
         let repository = reference_impl::create_repository(&root);
 
         let contents = "";
-        input_output::write(root.join("file1"), contents);
-        input_output::write(root.join("dir1/file_in_dir_1"), contents);
-        input_output::write(root.join("dir1/file_in_dir_2"), contents);
-        input_output::write(root.join("dir2/file_in_dir_3"), contents);
+        input_output::write(root.join("file1"), contents).unwrap();
+        input_output::write(root.join("dir1/file_in_dir_1"), contents).unwrap();
+        input_output::write(root.join("dir1/file_in_dir_2"), contents).unwrap();
+        input_output::write(root.join("dir2/file_in_dir_3"), contents).unwrap();
+        input_output::write(root.join("dir2.txt"), contents).unwrap();
         reference_impl::git_add_all(&repository);
         let hash_want = reference_impl::git_write_tree(&repository);
 
         // got
         let root = test_utils::create_test_dir();
         let repo = Repo::new(&root);
-        repo.init();
+        repo.init().unwrap();
 
         let contents = "";
-        input_output::write(root.join("file1"), contents);
-        input_output::write(root.join("dir1/file_in_dir_1"), contents);
-        input_output::write(root.join("dir1/file_in_dir_2"), contents);
-        input_output::write(root.join("dir2/file_in_dir_3"), contents);
+        input_output::write(root.join("file1"), contents).unwrap();
+        input_output::write(root.join("dir1/file_in_dir_1"), contents).unwrap();
+        input_output::write(root.join("dir1/file_in_dir_2"), contents).unwrap();
+        input_output::write(root.join("dir2/file_in_dir_3"), contents).unwrap();
+        input_output::write(root.join("dir2.txt"), contents).unwrap();
+        repo.add(vec![String::from(".")], false, false, false)
+            .unwrap();
         let hash_got = write_tree(root).unwrap();
 
         assert_eq!(hash_got.to_string(), hash_want);
     }
+
+    #[test]
+    fn test_tree_builder() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let blob = &reference_impl::git_write_object(&repository, "blob", "content\n");
+        let tree = &reference_impl::git_build_tree(&repository, &[("file", blob, 0o100644)]);
+
+        // A directory sorts as if its name ended with a slash, after
+        // `foo.txt` and `foo-bar` but before `foo0`.
+        let entries = [
+            ("foo0", blob.as_str(), 0o100644),
+            ("foo", tree, 0o40000),
+            ("foo.txt", blob, 0o100644),
+            ("foo-bar", tree, 0o40000),
+            ("bar", blob, 0o100755),
+        ];
+        let want = reference_impl::git_build_tree(&repository, &entries);
+
+        let mut builder = TreeBuilder::new();
+        for (name, hash, mode) in entries {
+            let mode = FileMode::from_bits(mode as u32).unwrap();
+            let entry = TreeNodeEntry::new(mode, name.to_string(), hash.parse().unwrap());
+            builder.insert(entry).unwrap();
+        }
+        let got = Object::TreeNode(builder.build()).write(&root).unwrap();
+        assert_eq!(got.to_string(), want);
+        let written = Object::read(&root, &want).unwrap().into_tree();
+        let names: Vec<_> = written.into_iter().map(|entry| &entry.name).collect();
+        assert_eq!(names, ["bar", "foo-bar", "foo.txt", "foo", "foo0"]);
+
+        // A file and a directory cannot share a name.
+        let mut builder = TreeBuilder::new();
+        let entry =
+            |mode, hash: &str| TreeNodeEntry::new(mode, String::from("foo"), hash.parse().unwrap());
+        builder.insert(entry(FileMode::Blob, blob)).unwrap();
+        assert!(builder.insert(entry(FileMode::Tree, tree)).is_err());
+    }
+
+    #[test]
+    fn test_legacy_mode() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let blob = &reference_impl::git_write_object(&repository, "blob", "content\n");
+        // libgit2 refuses to build one, so the tree is written as it is.
+        let blob_hash: Hash = blob.parse().unwrap();
+        let content = [&b"100664 file\0"[..], &blob_hash.bytes()].concat();
+        let legacy = reference_impl::git_write_object(&repository, "tree", content);
+        let plain = reference_impl::git_build_tree(&repository, &[("file", blob, 0o100644)]);
+
+        // The tree keeps its hash when written back, but shows and diffs
+        // like a plain blob, as with git.
+        let tree = Object::read(&root, &legacy).unwrap().into_tree();
+        let entry = tree.into_iter().next().unwrap();
+        assert_eq!(entry.mode, FileMode::GroupWritable);
+        assert_eq!(entry.to_string(), format!("100644 {}\tfile", blob));
+        assert_eq!(
+            Object::TreeNode(tree).write(&root).unwrap().to_string(),
+            legacy
+        );
+        let (legacy, plain) = (legacy.parse().unwrap(), plain.parse().unwrap());
+        assert!(
+            tree_diff::diff_trees(&root, Some(legacy), Some(plain), true)
+                .unwrap()
+                .is_empty()
+        );
+
+        let index = checkout_tree(&root, &legacy).unwrap();
+        assert_eq!(index.get("file").unwrap().mode, 0o100644);
+    }
+
+    #[test]
+    fn test_checkout_tree_invalid_path() {
+        let dir = test_utils::create_test_dir();
+        let root = dir.join("repo");
+        Repo::new(&root).init().unwrap();
+        let write = |entries: Vec<(FileMode, &str, Hash)>| {
+            let entries = entries
+                .into_iter()
+                .map(|(mode, name, hash)| TreeNodeEntry::new(mode, name.to_string(), hash))
+                .collect();
+            Object::TreeNode(TreeNode::new(entries))
+                .write(&root)
+                .unwrap()
+        };
+        let blob = Object::Blob(Blob::new(b"#!/bin/sh\n".to_vec()))
+            .write(&root)
+            .unwrap();
+        let hooks = write(vec![(FileMode::Executable, "post-checkout", blob)]);
+        let hooks_dir = write(vec![(FileMode::Tree, "hooks", hooks)]);
+
+        let mut trees = vec![];
+        for name in [".git", ".GIT", "..", "."] {
+            trees.push(write(vec![
+                (FileMode::Tree, name, hooks_dir),
+                (FileMode::Blob, "a", blob),
+            ]));
+        }
+        trees.push(write(vec![
+            (FileMode::Blob, ".git/hooks/post-checkout", blob),
+            (FileMode::Blob, "a", blob),
+        ]));
+        for tree in trees {
+            assert!(checkout_tree(&root, &tree).is_err());
+            assert!(!root.join("a").exists());
+            assert!(!root.join(".git/hooks/post-checkout").exists());
+            assert!(!dir.join("hooks").exists());
+        }
+    }
 }
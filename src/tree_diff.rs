@@ -0,0 +1,553 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::HashMap,
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    file_mode::FileMode,
+    hash::Hash,
+    input_output,
+    object::Object,
+};
+
+/// Similarity scores are fractions of this, like in git.
+pub const MAX_SCORE: u32 = 60000;
+/// The similarity `-M` and `-C` require by default, 50%.
+pub const DEFAULT_MIN_SCORE: u32 = MAX_SCORE / 2;
+/// How many of its best matching sources a destination keeps for pairing.
+const CANDIDATES_PER_DST: usize = 4;
+/// The modulus of the chunk hashes used for similarity scoring.
+const HASHBASE: u32 = 107927;
+
+/// What `diff-tree` compares and how it shows the changes.
+pub struct Options {
+    pub trees: Vec<String>,
+    pub recursive: bool,
+    pub output: Output,
+    /// The similarity renames need, if detecting them.
+    pub renames: Option<u32>,
+    /// The similarity copies need, if detecting them.
+    pub copies: Option<u32>,
+    /// Shows a root commit as adding all of its files.
+    pub root: bool,
+}
+
+pub enum Output {
+    Raw,
+    NameStatus,
+    NameOnly,
+}
+
+impl Output {
+    pub fn format(&self, change: &Change) -> String {
+        match self {
+            Self::Raw => change.format_raw(),
+            Self::NameStatus => change.format_name_status(),
+            Self::NameOnly => change.path().to_string(),
+        }
+    }
+}
+
+/// A file or tree on one side of a change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffEntry {
+    pub path: String,
+    pub mode: u32,
+    pub hash: Hash,
+}
+
+impl DiffEntry {
+    fn is_tree(&self) -> bool {
+        FileMode::type_of(self.mode) == Some(FileMode::Tree)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Added,
+    Deleted,
+    Modified,
+    TypeChanged,
+    /// A rename with the similarity of the two files, in percent.
+    Renamed(u32),
+    /// A copy with the similarity of the two files, in percent.
+    Copied(u32),
+}
+
+impl Status {
+    /// The status as `--name-status` and `--raw` show it, like `M` or
+    /// `R086`.
+    pub fn code(&self) -> String {
+        match self {
+            Self::Added => String::from("A"),
+            Self::Deleted => String::from("D"),
+            Self::Modified => String::from("M"),
+            Self::TypeChanged => String::from("T"),
+            Self::Renamed(score) => format!("R{:03}", score),
+            Self::Copied(score) => format!("C{:03}", score),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub status: Status,
+    pub old: Option<DiffEntry>,
+    pub new: Option<DiffEntry>,
+}
+
+impl Change {
+    fn new(status: Status, old: Option<DiffEntry>, new: Option<DiffEntry>) -> Self {
+        Self { status, old, new }
+    }
+
+    /// The change from `old` to `new`, at least one of which is present.
+    pub fn between(old: Option<DiffEntry>, new: Option<DiffEntry>) -> Self {
+        let status = match (&old, &new) {
+            (None, _) => Status::Added,
+            (_, None) => Status::Deleted,
+            (Some(old), Some(new)) if !FileMode::same_type(old.mode, new.mode) => {
+                Status::TypeChanged
+            }
+            _ => Status::Modified,
+        };
+        Self::new(status, old, new)
+    }
+
+    /// The path after the change, or before it for deletions.
+    pub fn path(&self) -> &str {
+        let entry = self.new.as_ref().or(self.old.as_ref()).unwrap();
+        &entry.path
+    }
+
+    /// Formats the change like `--name-status`: the status and the path, or
+    /// both paths for renames and copies.
+    pub fn format_name_status(&self) -> String {
+        format!("{}\t{}", self.status.code(), self.format_paths())
+    }
+
+    /// Formats the change like `--raw`: both modes and object names, the
+    /// missing side written as zeros, followed by the status and paths.
+    pub fn format_raw(&self) -> String {
+        let side = |entry: &Option<DiffEntry>| match entry {
+            Some(entry) => (entry.mode, entry.hash),
+            None => (0, Hash::zero()),
+        };
+        let (old_mode, old_hash) = side(&self.old);
+        let (new_mode, new_hash) = side(&self.new);
+        format!(
+            ":{:06o} {:06o} {} {} {}\t{}",
+            old_mode,
+            new_mode,
+            old_hash,
+            new_hash,
+            self.status.code(),
+            self.format_paths()
+        )
+    }
+
+    fn format_paths(&self) -> String {
+        match (&self.status, &self.old, &self.new) {
+            (Status::Renamed(_) | Status::Copied(_), Some(old), Some(new)) => {
+                format!("{}\t{}", old.path, new.path)
+            }
+            _ => self.path().to_string(),
+        }
+    }
+}
+
+/// Compares two trees, `None` standing for the empty tree, and returns the
+/// changes in path order. Identical subtrees are skipped without reading
+/// them. Unless `recursive`, changed subtrees are reported as a whole
+/// instead of descending into them.
+pub fn diff_trees(
+    root: impl AsRef<Path>,
+    old: Option<Hash>,
+    new: Option<Hash>,
+    recursive: bool,
+) -> Result<Vec<Change>> {
+    let mut changes = vec![];
+    diff_trees_at(root.as_ref(), old, new, "", recursive, &mut changes)?;
+    Ok(changes)
+}
+
+fn read_entries(root: &Path, tree: Option<Hash>, prefix: &str) -> Result<Vec<DiffEntry>> {
+    let Some(tree) = tree else {
+        return Ok(vec![]);
+    };
+    let Object::TreeNode(node) = Object::read(root, &tree.to_string())? else {
+        return Err(Error::fatal(format!("{} is not a tree object", tree)));
+    };
+    Ok(node
+        .into_iter()
+        .map(|entry| DiffEntry {
+            path: format!("{}{}", prefix, entry.name),
+            mode: entry.mode.canonical().bits(),
+            hash: entry.hash,
+        })
+        .collect())
+}
+
+/// Orders entries like git sorts trees: by name, a tree's name followed by
+/// `/`.
+fn tree_order(a: &DiffEntry, b: &DiffEntry) -> Ordering {
+    let key = |entry: &DiffEntry| {
+        let mut key = entry.path.clone().into_bytes();
+        if entry.is_tree() {
+            key.push(b'/');
+        }
+        key
+    };
+    key(a).cmp(&key(b))
+}
+
+fn diff_trees_at(
+    root: &Path,
+    old: Option<Hash>,
+    new: Option<Hash>,
+    prefix: &str,
+    recursive: bool,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let old_entries = read_entries(root, old, prefix)?;
+    let new_entries = read_entries(root, new, prefix)?;
+    for (old, new) in pair_entries(old_entries, new_entries, tree_order) {
+        let entry = old.as_ref().or(new.as_ref()).unwrap();
+        if entry.is_tree() && recursive {
+            let prefix = format!("{}/", entry.path);
+            let (old, new) = (old.map(|e| e.hash), new.map(|e| e.hash));
+            diff_trees_at(root, old, new, &prefix, true, changes)?;
+            continue;
+        }
+        changes.push(Change::between(old, new));
+    }
+    Ok(())
+}
+
+/// Returns the files of a tree and its subtrees in path order, `None`
+/// standing for the empty tree.
+pub fn flatten_tree(root: impl AsRef<Path>, tree: Option<Hash>) -> Result<Vec<DiffEntry>> {
+    let changes = diff_trees(root, None, tree, true)?;
+    Ok(changes
+        .into_iter()
+        .filter_map(|change| change.new)
+        .collect())
+}
+
+/// Compares two lists of files sorted by path, such as a flattened tree,
+/// the index or the working tree, and returns the changes in path order.
+pub fn diff_entries(old: Vec<DiffEntry>, new: Vec<DiffEntry>) -> Vec<Change> {
+    pair_entries(old, new, |a, b| a.path.cmp(&b.path))
+        .into_iter()
+        .map(|(old, new)| Change::between(old, new))
+        .collect()
+}
+
+/// Pairs up the entries of two sorted lists that have the same name,
+/// leaving out the ones that are the same on both sides.
+fn pair_entries(
+    old: Vec<DiffEntry>,
+    new: Vec<DiffEntry>,
+    order: fn(&DiffEntry, &DiffEntry) -> Ordering,
+) -> Vec<(Option<DiffEntry>, Option<DiffEntry>)> {
+    let mut old = old.into_iter().peekable();
+    let mut new = new.into_iter().peekable();
+    let mut pairs = vec![];
+    loop {
+        let pair = match (old.peek(), new.peek()) {
+            (None, None) => return pairs,
+            (Some(_), None) => (old.next(), None),
+            (None, Some(_)) => (None, new.next()),
+            (Some(a), Some(b)) => match order(a, b) {
+                Ordering::Less => (old.next(), None),
+                Ordering::Greater => (None, new.next()),
+                Ordering::Equal => (old.next(), new.next()),
+            },
+        };
+        if let (Some(old), Some(new)) = &pair {
+            if old.hash == new.hash && old.mode == new.mode {
+                continue;
+            }
+        }
+        pairs.push(pair);
+    }
+}
+
+/// Pairs up added files with deleted files, and with `copies` also with
+/// modified files, whose content is at least `min_score` similar, turning
+/// them into renames and copies. A deleted file that several files were
+/// made from counts as copied to all but the last of them. `read` returns
+/// the content of a side of a change.
+pub fn detect_renames(
+    changes: Vec<Change>,
+    min_score: u32,
+    copies: bool,
+    mut read: impl FnMut(&DiffEntry) -> Result<Vec<u8>>,
+) -> Result<Vec<Change>> {
+    let is_file = |entry: &Option<DiffEntry>| entry.as_ref().is_some_and(|e| !e.is_tree());
+    let dsts: Vec<usize> = (0..changes.len())
+        .filter(|&i| changes[i].status == Status::Added && is_file(&changes[i].new))
+        .collect();
+    let srcs: Vec<usize> = (0..changes.len())
+        .filter(|&i| match changes[i].status {
+            Status::Deleted => is_file(&changes[i].old),
+            Status::Modified => copies && is_file(&changes[i].old),
+            _ => false,
+        })
+        .collect();
+
+    // The source and score chosen for each destination.
+    let mut pairs: HashMap<usize, (usize, u32)> = HashMap::new();
+    let mut used = vec![false; changes.len()];
+    let old = |i: usize| changes[i].old.as_ref().unwrap();
+    let new = |i: usize| changes[i].new.as_ref().unwrap();
+    let same_type = |src: usize, dst: usize| FileMode::same_type(old(src).mode, new(dst).mode);
+
+    for &dst in &dsts {
+        let exact = srcs
+            .iter()
+            .filter(|&&src| (copies || !used[src]) && same_type(src, dst))
+            .filter(|&&src| old(src).hash == new(dst).hash)
+            .max_by_key(|&&src| (name_score(old(src), new(dst)), Reverse(src)));
+        if let Some(&src) = exact {
+            pairs.insert(dst, (src, MAX_SCORE));
+            used[src] = true;
+        }
+    }
+
+    let mut contents: HashMap<Hash, Vec<u8>> = HashMap::new();
+    let mut read = |entry: &DiffEntry| -> Result<Vec<u8>> {
+        if let Some(content) = contents.get(&entry.hash) {
+            return Ok(content.clone());
+        }
+        let content = read(entry)?;
+        contents.insert(entry.hash, content.clone());
+        Ok(content)
+    };
+    let mut candidates = vec![];
+    for &dst in dsts.iter().filter(|dst| !pairs.contains_key(dst)) {
+        let dst_content = read(new(dst))?;
+        let mut scores = vec![];
+        for &src in srcs.iter().filter(|&&src| copies || !used[src]) {
+            if !same_type(src, dst) {
+                continue;
+            }
+            let score = similarity(&read(old(src))?, &dst_content, min_score);
+            if score >= min_score {
+                scores.push((score, name_score(old(src), new(dst)), dst, src));
+            }
+        }
+        scores.sort_by_key(|&(score, name_score, ..)| Reverse((score, name_score)));
+        scores.truncate(CANDIDATES_PER_DST);
+        candidates.extend(scores);
+    }
+    candidates.sort_by_key(|&(score, name_score, ..)| Reverse((score, name_score)));
+    for (score, _, dst, src) in candidates {
+        if pairs.contains_key(&dst) || (!copies && used[src]) {
+            continue;
+        }
+        pairs.insert(dst, (src, score));
+        used[src] = true;
+    }
+
+    // How many destinations each source still has to be paired with. A
+    // source that stays counts one extra use, so it only gives copies.
+    let mut uses = vec![0; changes.len()];
+    for &(src, _) in pairs.values() {
+        uses[src] += 1;
+    }
+    for &src in &srcs {
+        if changes[src].status != Status::Deleted && uses[src] > 0 {
+            uses[src] += 1;
+        }
+    }
+
+    let mut result = vec![];
+    for (i, change) in changes.iter().enumerate() {
+        if change.status == Status::Deleted && used[i] {
+            continue;
+        }
+        let Some(&(src, score)) = pairs.get(&i) else {
+            result.push(change.clone());
+            continue;
+        };
+        uses[src] -= 1;
+        let similarity = score * 100 / MAX_SCORE;
+        let status = if uses[src] > 0 {
+            Status::Copied(similarity)
+        } else {
+            Status::Renamed(similarity)
+        };
+        result.push(Change::new(
+            status,
+            changes[src].old.clone(),
+            change.new.clone(),
+        ));
+    }
+    Ok(result)
+}
+
+/// 1 if both files have the same name, which makes them the better pair
+/// when the content alone doesn't decide.
+fn name_score(a: &DiffEntry, b: &DiffEntry) -> u32 {
+    (input_output::basename(&a.path) == input_output::basename(&b.path)) as u32
+}
+
+/// Estimates how similar two files are, from 0 to [`MAX_SCORE`], like git's
+/// `estimate_similarity`: the share of the larger file made of chunks that
+/// also appear in `src`. Files whose sizes alone rule out reaching
+/// `min_score` get 0 without looking at their content.
+pub fn similarity(src: &[u8], dst: &[u8], min_score: u32) -> u32 {
+    let max_size = src.len().max(dst.len()) as u64;
+    let delta_size = max_size - src.len().min(dst.len()) as u64;
+    if dst.is_empty() || max_size * ((MAX_SCORE - min_score) as u64) < delta_size * MAX_SCORE as u64
+    {
+        return 0;
+    }
+
+    let src_chunks = count_chunks(src);
+    let dst_chunks = count_chunks(dst);
+    let copied: u64 = dst_chunks
+        .iter()
+        .filter_map(|(hash, &count)| src_chunks.get(hash).map(|&src| src.min(count)))
+        .sum();
+    (copied * MAX_SCORE as u64 / max_size) as u32
+}
+
+/// Splits content into lines, or 64 byte chunks of long lines, and returns
+/// how many bytes each distinct chunk accounts for, keyed by a hash of the
+/// chunk. A CR before a LF is ignored in text files.
+fn count_chunks(content: &[u8]) -> HashMap<u32, u64> {
+    let is_text = !content.iter().take(8000).any(|&b| b == 0);
+    let mut counts = HashMap::new();
+    let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0u64);
+    for (i, &c) in content.iter().enumerate() {
+        if is_text && c == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old1 >> 25);
+        accum1 = accum1.wrapping_add(c as u32);
+        n += 1;
+        if n < 64 && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x9)) % HASHBASE;
+        *counts.entry(hash).or_default() += n;
+        (accum1, accum2, n) = (0, 0, 0);
+    }
+    if n > 0 {
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x9)) % HASHBASE;
+        *counts.entry(hash).or_default() += n;
+    }
+    counts
+}
+
+/// Parses the value of `-M<n>` or `-C<n>` like git: digits are a fraction,
+/// `5` and `50` meaning 50%, unless followed by `%`.
+pub fn parse_score(value: &str) -> Option<u32> {
+    let (digits, percent) = match value.strip_suffix('%') {
+        Some(digits) => (digits, true),
+        None => (value, false),
+    };
+    let digits = digits.strip_prefix("0.").unwrap_or(digits);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let number: f64 = digits.parse().ok()?;
+    let fraction = match percent {
+        true => number / 100.0,
+        false => number / 10f64.powi(digits.len() as i32),
+    };
+    Some((fraction.min(1.0) * MAX_SCORE as f64) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{input_output, object, reference_impl, test_utils};
+
+    use super::{
+        detect_renames, diff_trees, parse_score, similarity, DiffEntry, Status, MAX_SCORE,
+    };
+
+    #[test]
+    fn test_diff_trees() {
+        let root = test_utils::create_test_dir();
+        let repository = reference_impl::create_repository(&root);
+        let lines: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        input_output::write(root.join("a.txt"), &lines).unwrap();
+        input_output::write(root.join("dir/sub/x"), "x").unwrap();
+        input_output::write(root.join("dir/y"), "y").unwrap();
+        input_output::write(root.join("file"), "file").unwrap();
+        reference_impl::git_add_all(&repository);
+        let old = reference_impl::git_write_tree(&repository);
+
+        fs::remove_file(root.join("a.txt")).unwrap();
+        input_output::write(root.join("b.txt"), format!("{}line 101\n", lines)).unwrap();
+        input_output::write(root.join("dir/sub/x"), "changed").unwrap();
+        fs::remove_file(root.join("file")).unwrap();
+        input_output::write(root.join("file/inner"), "inner").unwrap();
+        let mut index = repository.index().unwrap();
+        index.remove_all(["*"], None).unwrap();
+        index.write().unwrap();
+        reference_impl::git_add_all(&repository);
+        let new = reference_impl::git_write_tree(&repository);
+
+        let (old_hash, new_hash) = (old.parse().unwrap(), new.parse().unwrap());
+        let changes = diff_trees(&root, Some(old_hash), Some(new_hash), true).unwrap();
+        let got: Vec<_> = changes
+            .iter()
+            .map(|change| change.format_name_status())
+            .collect();
+        assert_eq!(
+            got,
+            reference_impl::git_diff_tree(&repository, &old, &new, false)
+        );
+
+        let read = |entry: &DiffEntry| Ok(object::read_raw(&root, &entry.hash.to_string())?.1);
+        let renamed = detect_renames(changes, MAX_SCORE / 2, false, read).unwrap();
+        let got: Vec<_> = renamed
+            .iter()
+            .map(|change| change.format_name_status().replace("R098", "R"))
+            .collect();
+        assert_eq!(
+            got,
+            reference_impl::git_diff_tree(&repository, &old, &new, true)
+        );
+        assert_eq!(renamed[0].status, Status::Renamed(98));
+
+        let shallow = diff_trees(&root, Some(old_hash), Some(new_hash), false).unwrap();
+        let got: Vec<_> = shallow.iter().map(|c| c.format_name_status()).collect();
+        assert_eq!(
+            got,
+            ["D\ta.txt", "A\tb.txt", "M\tdir", "D\tfile", "A\tfile"]
+        );
+        assert!(diff_trees(&root, Some(old_hash), Some(old_hash), true)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_similarity() {
+        let text = b"one\ntwo\nthree\nfour\n";
+        assert_eq!(similarity(text, text, 0), MAX_SCORE);
+        assert_eq!(similarity(text, b"one\ntwo\n", 0), MAX_SCORE * 8 / 19);
+        assert_eq!(similarity(text, b"one\ntwo\n", MAX_SCORE / 2), 0);
+        assert_eq!(similarity(b"a\r\nb\r\n", b"a\nb\n", 0), MAX_SCORE * 4 / 6);
+        assert_eq!(similarity(text, b"", 0), 0);
+    }
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("50%"), Some(MAX_SCORE / 2));
+        assert_eq!(parse_score("5"), Some(MAX_SCORE / 2));
+        assert_eq!(parse_score("90"), Some(MAX_SCORE * 9 / 10));
+        assert_eq!(parse_score("100%"), Some(MAX_SCORE));
+        assert_eq!(parse_score("x"), None);
+    }
+}
@@ -1,35 +1,49 @@
-use std::{fmt::Display, slice::Iter};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    slice::Iter,
+    str::from_utf8,
+};
 
-use crate::{bytes_reader::BytesReader, codec, hash::Hash, object::Header};
+use crate::{
+    bytes_reader::BytesReader,
+    codec,
+    error::{Error, Result},
+    file_mode::FileMode,
+    hash::Hash,
+    object::Header,
+};
 
 #[derive(Debug)]
 pub struct TreeNodeEntry {
-    pub mode: String,
+    pub mode: FileMode,
     pub name: String,
     pub hash: Hash,
 }
 
 impl TreeNodeEntry {
-    pub fn new(mode: String, name: String, hash: Hash) -> Self {
+    pub fn new(mode: FileMode, name: String, hash: Hash) -> Self {
         Self { mode, name, hash }
     }
 
-    fn parse(reader: &mut BytesReader) -> Self {
-        let mode = reader.read_until(b' ');
-        reader.skip();
-        let name = reader.read_until(0);
-        reader.skip();
-        let hash = reader.read_n(20);
+    fn parse(reader: &mut BytesReader) -> Result<Self> {
+        let corrupt = |_| Error::corrupt("corrupt tree file");
+        let mode = reader.read_until(b' ').map_err(corrupt)?;
+        reader.skip()?;
+        let name = reader.read_until(0).map_err(corrupt)?;
+        reader.skip()?;
+        let hash = reader.read_n(20).map_err(corrupt)?;
 
-        Self::new(
-            String::from_utf8(mode.to_vec()).unwrap(),
-            String::from_utf8(name.to_vec()).unwrap(),
+        let mode = from_utf8(mode).map_err(|_| Error::corrupt("corrupt tree file"))?;
+        Ok(Self::new(
+            mode.parse()?,
+            String::from_utf8(name.to_vec()).map_err(|_| Error::corrupt("corrupt tree file"))?,
             Hash::new(hash.try_into().unwrap()),
-        )
+        ))
     }
 
     pub fn encode(&self) -> Vec<u8> {
-        format!("{} {}\0", self.mode, self.name)
+        format!("{:o} {}\0", self.mode.bits(), self.name)
             .bytes()
             .chain(self.hash.bytes())
             .collect()
@@ -52,12 +66,12 @@ impl TreeNode {
         Self { entries }
     }
 
-    pub fn parse(reader: &mut BytesReader) -> Self {
+    pub fn parse(reader: &mut BytesReader) -> Result<Self> {
         let mut entries = vec![];
         while !reader.is_at_end() {
-            entries.push(TreeNodeEntry::parse(reader));
+            entries.push(TreeNodeEntry::parse(reader)?);
         }
-        Self::new(entries)
+        Ok(Self::new(entries))
     }
 
     pub fn serialize(&self) -> Vec<u8> {
@@ -79,12 +93,47 @@ impl TreeNode {
     }
 }
 
+/// Gathers the entries of a tree in any order. Git sorts them by name with
+/// a directory compared as if its name ended with `/`, so `foo.txt` comes
+/// before `foo/`, and the tree's hash depends on it.
+#[derive(Default)]
+pub struct TreeBuilder {
+    entries: BTreeMap<Vec<u8>, TreeNodeEntry>,
+    names: HashSet<String>,
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `entry`, which must not share its name with another entry,
+    /// whether a file or a directory.
+    pub fn insert(&mut self, entry: TreeNodeEntry) -> Result<()> {
+        if !self.names.insert(entry.name.clone()) {
+            return Err(Error::fatal(format!(
+                "duplicate tree entry '{}'",
+                entry.name
+            )));
+        }
+        let mut key = entry.name.clone().into_bytes();
+        if entry.mode == FileMode::Tree {
+            key.push(b'/');
+        }
+        self.entries.insert(key, entry);
+        Ok(())
+    }
+
+    pub fn build(self) -> TreeNode {
+        TreeNode::new(self.entries.into_values().collect())
+    }
+}
+
 impl<'a> IntoIterator for &'a TreeNode {
     type Item = &'a TreeNodeEntry;
     type IntoIter = Iter<'a, TreeNodeEntry>;
     fn into_iter(self) -> Self::IntoIter {
-        let entries = &self.entries;
-        entries.into_iter()
+        self.entries.iter()
     }
 }
 
@@ -0,0 +1,260 @@
+/// Glob matching with git's `wildmatch` semantics: `?`, `*`, `[...]`
+/// classes (including `[:alpha:]` and friends) and backslash escapes. With
+/// `pathname` set, wildcards do not match `/` except in a `**` that is a
+/// whole path component (`**/`, `/**/` or a trailing `/**`).
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool, casefold: bool) -> bool {
+    let flags = Flags { pathname, casefold };
+    matches!(
+        dowild(pattern.as_bytes(), text.as_bytes(), flags),
+        Outcome::Match
+    )
+}
+
+#[derive(Clone, Copy)]
+struct Flags {
+    pathname: bool,
+    casefold: bool,
+}
+
+#[derive(PartialEq)]
+enum Outcome {
+    Match,
+    NoMatch,
+    /// The text ran out, so no shorter match of an enclosing `*` can succeed.
+    AbortAll,
+    /// A `/` was needed, so only an enclosing `**` can still succeed.
+    AbortToStarstar,
+}
+
+fn dowild(pattern: &[u8], text: &[u8], flags: Flags) -> Outcome {
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        let mut p_ch = pattern[p];
+        if t == text.len() && p_ch != b'*' {
+            return Outcome::AbortAll;
+        }
+        let mut t_ch = text.get(t).copied().unwrap_or(0);
+        if flags.casefold {
+            t_ch = t_ch.to_ascii_lowercase();
+            p_ch = p_ch.to_ascii_lowercase();
+        }
+        match p_ch {
+            b'\\' => {
+                p += 1;
+                match pattern.get(p) {
+                    Some(&c) if fold(c, flags) == t_ch => {}
+                    _ => return Outcome::NoMatch,
+                }
+            }
+            b'?' => {
+                if flags.pathname && t_ch == b'/' {
+                    return Outcome::NoMatch;
+                }
+            }
+            b'*' => {
+                let match_slash;
+                p += 1;
+                if pattern.get(p) == Some(&b'*') {
+                    let before = p.checked_sub(2).map(|i| pattern[i]);
+                    while pattern.get(p) == Some(&b'*') {
+                        p += 1;
+                    }
+                    let after = pattern.get(p).copied();
+                    if !flags.pathname {
+                        match_slash = true;
+                    } else if matches!(before, None | Some(b'/'))
+                        && matches!(after, None | Some(b'/'))
+                    {
+                        // `**/` also matches zero directories.
+                        if after == Some(b'/')
+                            && dowild(&pattern[p + 1..], &text[t..], flags) == Outcome::Match
+                        {
+                            return Outcome::Match;
+                        }
+                        match_slash = true;
+                    } else {
+                        match_slash = false;
+                    }
+                } else {
+                    match_slash = !flags.pathname;
+                }
+
+                if p == pattern.len() {
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Outcome::AbortToStarstar;
+                    }
+                    return Outcome::Match;
+                }
+                if !match_slash && pattern[p] == b'/' {
+                    // Skip to the next slash; the loop consumes it on both sides.
+                    match text[t..].iter().position(|&c| c == b'/') {
+                        Some(slash) => t += slash,
+                        None => return Outcome::AbortAll,
+                    }
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                while t < text.len() {
+                    let matched = dowild(&pattern[p..], &text[t..], flags);
+                    if matched != Outcome::NoMatch {
+                        if !match_slash || matched != Outcome::AbortToStarstar {
+                            return matched;
+                        }
+                    } else if !match_slash && text[t] == b'/' {
+                        return Outcome::AbortToStarstar;
+                    }
+                    t += 1;
+                }
+                return Outcome::AbortAll;
+            }
+            b'[' => {
+                let (end, matched) = match match_class(pattern, p, t_ch, flags) {
+                    Some(result) => result,
+                    None => return Outcome::AbortAll,
+                };
+                if !matched || (flags.pathname && t_ch == b'/') {
+                    return Outcome::NoMatch;
+                }
+                p = end;
+            }
+            _ => {
+                if t_ch != p_ch {
+                    return Outcome::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+    if t == text.len() {
+        Outcome::Match
+    } else {
+        Outcome::NoMatch
+    }
+}
+
+fn fold(c: u8, flags: Flags) -> u8 {
+    if flags.casefold {
+        c.to_ascii_lowercase()
+    } else {
+        c
+    }
+}
+
+/// Matches `t_ch` against the bracket expression starting at `pattern[start]`.
+/// Returns the index of the closing `]` and whether the character is in the
+/// set, or `None` if the expression is malformed.
+fn match_class(pattern: &[u8], start: usize, t_ch: u8, flags: Flags) -> Option<(usize, bool)> {
+    let mut p = start + 1;
+    let negated = matches!(pattern.get(p), Some(b'!' | b'^'));
+    if negated {
+        p += 1;
+    }
+    let mut matched = false;
+    let mut prev_ch = 0;
+    loop {
+        let mut p_ch = *pattern.get(p)?;
+        if p_ch == b'\\' {
+            p += 1;
+            p_ch = *pattern.get(p)?;
+            if t_ch == p_ch {
+                matched = true;
+            }
+        } else if p_ch == b'-' && prev_ch != 0 && !matches!(pattern.get(p + 1), None | Some(b']')) {
+            p += 1;
+            p_ch = pattern[p];
+            if p_ch == b'\\' {
+                p += 1;
+                p_ch = *pattern.get(p)?;
+            }
+            let upper = t_ch.to_ascii_uppercase();
+            if (prev_ch..=p_ch).contains(&t_ch)
+                || (flags.casefold && (prev_ch..=p_ch).contains(&upper))
+            {
+                matched = true;
+            }
+            p_ch = 0;
+        } else if p_ch == b'[' && pattern.get(p + 1) == Some(&b':') {
+            let name_start = p + 2;
+            let close = name_start + pattern[name_start..].iter().position(|&c| c == b']')?;
+            if close == name_start || pattern[close - 1] != b':' {
+                // Not a `[:name:]`, so the `[` is an ordinary character.
+                if t_ch == b'[' {
+                    matched = true;
+                }
+            } else {
+                if is_in_class(&pattern[name_start..close - 1], t_ch, flags)? {
+                    matched = true;
+                }
+                p = close;
+                p_ch = 0;
+            }
+        } else if t_ch == p_ch {
+            matched = true;
+        }
+        prev_ch = p_ch;
+        p += 1;
+        if pattern.get(p) == Some(&b']') {
+            return Some((p, matched != negated));
+        }
+    }
+}
+
+fn is_in_class(name: &[u8], c: u8, flags: Flags) -> Option<bool> {
+    Some(match name {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase() || (flags.casefold && c.is_ascii_uppercase()),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace() || c == 0x0b,
+        b"upper" => c.is_ascii_uppercase() || (flags.casefold && c.is_ascii_lowercase()),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wildmatch;
+
+    #[test]
+    fn test_wildmatch() {
+        let cases = [
+            ("foo", "foo", true),
+            ("fo?", "foo", true),
+            ("*.txt", "dir/a.txt", false),
+            ("**/a.txt", "a.txt", true),
+            ("**/a.txt", "x/y/a.txt", true),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**", "a/x/y", true),
+            ("a/*/b", "a/x/y/b", false),
+            ("a**b", "ax/yb", false),
+            ("[a-c]x", "bx", true),
+            ("[!a-c]x", "bx", false),
+            ("[]]", "]", true),
+            ("[[:digit:]]*", "7up", true),
+            ("[[:digit:]]*", "up", false),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("a?b", "a/b", false),
+            ("**/.git/**", "/home/me/.git/x", true),
+        ];
+        for (pattern, text, want) in cases {
+            assert_eq!(
+                wildmatch(pattern, text, true, false),
+                want,
+                "{pattern} {text}"
+            );
+        }
+        assert!(wildmatch("*.txt", "dir/a.txt", false, false));
+        assert!(wildmatch("/HOME/**", "/home/me", true, true));
+    }
+}
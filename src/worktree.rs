@@ -0,0 +1,300 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    os::unix::{ffi::OsStringExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    blob::Blob,
+    error::{Error, Result},
+    file_mode::FileMode,
+    hash::Hash,
+    ignore::Ignore,
+    index::{Index, IndexEntry},
+    input_output,
+    object::Object,
+    tree_diff::DiffEntry,
+};
+
+/// The content of working tree files by hash, for the ones that had to be
+/// read.
+pub type Contents = HashMap<Hash, Vec<u8>>;
+
+/// Turns a path given on the command line into a repository-relative path,
+/// where the empty string stands for the whole working directory.
+pub fn normalize_pathspec(pathspec: &str) -> String {
+    let pathspec = pathspec.trim_start_matches("./").trim_end_matches('/');
+    if pathspec == "." {
+        String::new()
+    } else {
+        pathspec.to_string()
+    }
+}
+
+pub fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+pub fn get_path(root: impl AsRef<Path>, path: &str) -> PathBuf {
+    root.as_ref().join(path)
+}
+
+/// Returns the files at or below `path`, relative to `root`, in sorted
+/// order. With `ignore`, untracked files that it excludes are left out,
+/// and ignored directories are only looked into for tracked files.
+pub fn list_files(
+    root: impl AsRef<Path>,
+    path: &str,
+    index: &Index,
+    ignore: Option<&Ignore>,
+) -> Result<Vec<String>> {
+    let root = root.as_ref();
+    let is_dir = fs::symlink_metadata(get_path(root, path)).is_ok_and(|m| m.is_dir());
+    let excluded = ignore.is_some_and(|ignore| ignore.is_excluded(path, is_dir));
+    let mut files = vec![];
+    collect_files(root, path, excluded, index, ignore, &mut files)?;
+    Ok(files)
+}
+
+fn collect_files(
+    root: &Path,
+    path: &str,
+    excluded: bool,
+    index: &Index,
+    ignore: Option<&Ignore>,
+    files: &mut Vec<String>,
+) -> Result<()> {
+    let full_path = get_path(root, path);
+    let Ok(metadata) = fs::symlink_metadata(&full_path) else {
+        return Ok(());
+    };
+    if !metadata.is_dir() {
+        if !excluded || index.get(path).is_some() {
+            files.push(path.to_string());
+        }
+        return Ok(());
+    }
+    if excluded && index.matching(path).is_empty() {
+        return Ok(());
+    }
+
+    for child in input_output::read_dir_sorted(&full_path)? {
+        if is_dot_git(&child) {
+            continue;
+        }
+        let name = join_path(path, &input_output::basename(&child));
+        let excluded = excluded
+            || ignore.is_some_and(|ignore| {
+                let is_dir = fs::symlink_metadata(&child).is_ok_and(|m| m.is_dir());
+                ignore.is_ignored(&name, is_dir)
+            });
+        collect_files(root, &name, excluded, index, ignore, files)?;
+    }
+    Ok(())
+}
+
+/// Reads a working tree file the way git stores it: symlinks as their
+/// target, everything else as its content.
+pub fn read_content(root: impl AsRef<Path>, path: &str) -> Result<(u32, Vec<u8>)> {
+    let full_path = get_path(root, path);
+    let mode = input_output::get_mode(&full_path)?;
+    let content = if mode == FileMode::Symlink {
+        fs::read_link(&full_path).map(|target| target.into_os_string().into_vec())
+    } else {
+        fs::read(&full_path)
+    };
+    let content = content.map_err(|e| Error::io("unable to read", &full_path, e))?;
+    Ok((mode.bits(), content))
+}
+
+/// Writes the file at `path` to the object store and returns its index entry.
+pub fn stage_file(root: impl AsRef<Path>, path: &str) -> Result<IndexEntry> {
+    let (mode, content) = read_content(&root, path)?;
+    let hash = Object::Blob(Blob::new(content)).write(&root)?;
+    let full_path = get_path(&root, path);
+    let metadata =
+        fs::symlink_metadata(&full_path).map_err(|e| Error::io("unable to stat", &full_path, e))?;
+    Ok(IndexEntry::from_metadata(
+        path.to_string(),
+        hash,
+        mode,
+        &metadata,
+    ))
+}
+
+/// Writes blob `hash` to the working tree file at `path` with the given
+/// mode and returns its index entry. A submodule only gets its directory.
+pub fn checkout_file(
+    root: impl AsRef<Path>,
+    path: &str,
+    mode: u32,
+    hash: Hash,
+) -> Result<IndexEntry> {
+    let full_path = get_path(&root, path);
+    let file_mode = FileMode::from_bits(mode)?;
+    if file_mode == FileMode::Gitlink {
+        fs::create_dir_all(&full_path).map_err(|e| Error::io("cannot mkdir", &full_path, e))?;
+        return Ok(IndexEntry::new(path.to_string(), hash, mode));
+    }
+    let Object::Blob(blob) = Object::read(&root, &hash.to_string())? else {
+        return Err(Error::fatal(format!("{} is not a blob object", hash)));
+    };
+    input_output::write_with_mode(&full_path, &blob.content, file_mode)?;
+    let metadata =
+        fs::symlink_metadata(&full_path).map_err(|e| Error::io("unable to stat", &full_path, e))?;
+    Ok(IndexEntry::from_metadata(
+        path.to_string(),
+        hash,
+        mode,
+        &metadata,
+    ))
+}
+
+/// Returns the tracked files as they are in the working tree, in path
+/// order, leaving out the missing ones. A file whose stat data matches its
+/// index entry is taken to be unchanged without reading it, unless it was
+/// modified no earlier than the index was written, when that cannot be told
+/// apart from a change in the same instant.
+pub fn tracked_entries(
+    root: impl AsRef<Path>,
+    index: &Index,
+) -> Result<(Vec<DiffEntry>, Contents)> {
+    let root = root.as_ref();
+    let index_mtime = fs::metadata(Index::get_path(root))
+        .map(|m| (m.mtime() as u32, m.mtime_nsec() as u32))
+        .ok();
+    let mut entries = vec![];
+    let mut contents = Contents::new();
+    for entry in index.entries().iter().filter(|e| e.stage == 0) {
+        let full_path = get_path(root, &entry.path);
+        let Ok(metadata) = fs::symlink_metadata(&full_path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            // A submodule's checkout, which is not looked into.
+            if entry.mode == FileMode::Gitlink.bits() {
+                entries.push(DiffEntry {
+                    path: entry.path.clone(),
+                    mode: entry.mode,
+                    hash: entry.hash,
+                });
+            }
+            continue;
+        }
+        let racy = index_mtime.map_or(true, |mtime| entry.mtime >= mtime);
+        let mode = input_output::get_mode(&full_path)?.bits();
+        let hash = if entry.matches_stat(&metadata) && !racy && !entry.intent_to_add {
+            entry.hash
+        } else {
+            let (_, content) = read_content(root, &entry.path)?;
+            let hash = Object::Blob(Blob::new(content.clone())).hash();
+            contents.insert(hash, content);
+            hash
+        };
+        entries.push(DiffEntry {
+            path: entry.path.clone(),
+            mode,
+            hash,
+        });
+    }
+    Ok((entries, contents))
+}
+
+/// Returns the untracked files in path order, leaving out the ignored
+/// ones. Unless `all` is set, a directory without tracked files is listed
+/// as a whole, as `dir/`, if anything in it is not ignored. A nested
+/// repository is always listed as a whole.
+pub fn untracked_files(
+    root: impl AsRef<Path>,
+    index: &Index,
+    ignore: &Ignore,
+    all: bool,
+) -> Result<Vec<String>> {
+    let tracked: HashSet<&str> = index.entries().iter().map(|e| e.path.as_str()).collect();
+    let mut tracked_dirs = HashSet::new();
+    for path in &tracked {
+        let mut path = *path;
+        while let Some((dir, _)) = path.rsplit_once('/') {
+            if !tracked_dirs.insert(dir) {
+                break;
+            }
+            path = dir;
+        }
+    }
+    let walk = UntrackedWalk {
+        root: root.as_ref(),
+        tracked,
+        tracked_dirs,
+        ignore,
+        all,
+    };
+    let mut files = vec![];
+    walk.collect("", &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+struct UntrackedWalk<'a> {
+    root: &'a Path,
+    tracked: HashSet<&'a str>,
+    tracked_dirs: HashSet<&'a str>,
+    ignore: &'a Ignore,
+    all: bool,
+}
+
+impl UntrackedWalk<'_> {
+    fn collect(&self, dir: &str, files: &mut Vec<String>) -> Result<()> {
+        for child in input_output::read_dir_sorted(get_path(self.root, dir))? {
+            if is_dot_git(&child) {
+                continue;
+            }
+            let path = join_path(dir, &input_output::basename(&child));
+            if self.tracked.contains(path.as_str()) {
+                continue;
+            }
+            let is_dir = fs::symlink_metadata(&child).is_ok_and(|m| m.is_dir());
+            if self.ignore.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if !is_dir {
+                files.push(path);
+            } else if self.tracked_dirs.contains(path.as_str()) {
+                self.collect(&path, files)?;
+            } else if child.join(".git").exists() {
+                files.push(format!("{}/", path));
+            } else if self.all {
+                self.collect(&path, files)?;
+            } else {
+                let mut inside = vec![];
+                self.collect(&path, &mut inside)?;
+                if !inside.is_empty() {
+                    files.push(format!("{}/", path));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deletes a working tree file along with the directories it leaves empty.
+pub fn remove_file(root: impl AsRef<Path>, path: &str) {
+    let root = root.as_ref();
+    let full_path = get_path(root, path);
+    let _ = fs::remove_file(&full_path);
+    let mut dir = full_path.parent();
+    while let Some(parent) = dir {
+        if parent == root || fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+pub fn is_dot_git(path: impl AsRef<Path>) -> bool {
+    input_output::basename(path) == ".git"
+}